@@ -22,8 +22,9 @@ pub(crate) enum ResultReceiver<'r> {
     StrBuf(&'r mut String),
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, utoipa::ToSchema)]
 #[serde(tag = "id", content = "model")]
+#[schema(as = ChatProviderKind)]
 pub(crate) enum ChatProvider {
     HuggingFace(HuggingFaceModel),
     OpenAI(String),
@@ -37,37 +38,37 @@ pub(crate) fn replace_model_cache(robot_id: &str, m: &HuggingFaceModel) -> Resul
     Ok(())
 }
 
+// `chat_history` ends with the input of the user
 pub(crate) async fn chat(
     robot_id: &str,
-    prompt: &str,
-    chat_history: Option<Vec<Prompt>>,
+    system: &str,
+    chat_history: &[Prompt],
     connect_timeout: Option<u32>,
     read_timeout: Option<u32>,
     result_receiver: ResultReceiver<'_>,
 ) -> Result<()> {
     if let Some(settings) = settings::get_settings(robot_id)? {
         // log::info!("{:?}", &settings.chat_provider.provider);
-        match settings.chat_provider.provider {
+        let p = settings.chat_provider;
+        match &p.provider {
             ChatProvider::HuggingFace(m) => {
                 huggingface(
                     robot_id,
-                    &m,
-                    prompt,
+                    m,
+                    system,
                     chat_history,
-                    settings.chat_provider.max_response_token_length as usize,
+                    p.max_response_token_length as usize,
                     result_receiver,
                 )?;
                 Ok(())
             }
             ChatProvider::OpenAI(m) => {
                 open_ai(
-                    &m,
-                    prompt,
-                    chat_history,
-                    connect_timeout
-                        .unwrap_or(settings.text_generation_provider.connect_timeout_millis),
-                    read_timeout.unwrap_or(settings.text_generation_provider.read_timeout_millis),
-                    &settings.text_generation_provider.proxy_url,
+                    &p,
+                    m,
+                    messages(system, chat_history),
+                    connect_timeout,
+                    read_timeout,
                     result_receiver,
                 )
                 .await?;
@@ -75,15 +76,11 @@ pub(crate) async fn chat(
             }
             ChatProvider::Ollama(m) => {
                 ollama(
-                    &settings.text_generation_provider.api_url,
-                    &m,
-                    prompt,
-                    chat_history,
-                    connect_timeout
-                        .unwrap_or(settings.text_generation_provider.connect_timeout_millis),
-                    read_timeout.unwrap_or(settings.text_generation_provider.read_timeout_millis),
-                    &settings.text_generation_provider.proxy_url,
-                    settings.text_generation_provider.max_response_token_length,
+                    &p,
+                    m,
+                    messages(system, chat_history),
+                    connect_timeout,
+                    read_timeout,
                     result_receiver,
                 )
                 .await?;
@@ -97,17 +94,75 @@ pub(crate) async fn chat(
     }
 }
 
+fn messages(system: &str, chat_history: &[Prompt]) -> Vec<Value> {
+    let mut messages = Vec::with_capacity(chat_history.len() + 1);
+    if !system.is_empty() {
+        messages.push(serde_json::json!({"role": "system", "content": system}));
+    }
+    for h in chat_history.iter() {
+        messages.push(serde_json::json!({"role": &h.role, "content": &h.content}));
+    }
+    messages
+}
+
+// Models of OpenAI unknown to tiktoken and models of Ollama are counted with cl100k,
+// which takes more tokens than newer tokenizers, so the history still fits in their windows
+fn bpe(provider: &ChatProvider) -> &'static tiktoken_rs::CoreBPE {
+    match provider {
+        ChatProvider::OpenAI(m)
+            if tiktoken_rs::tokenizer::get_tokenizer(m)
+                == Some(tiktoken_rs::tokenizer::Tokenizer::O200kBase) =>
+        {
+            tiktoken_rs::o200k_base_singleton()
+        }
+        _ => tiktoken_rs::cl100k_base_singleton(),
+    }
+}
+
+// Local models are counted with their tokenizers, others with the tokenizers of OpenAI models
+pub(crate) fn count_tokens(
+    robot_id: &str,
+    provider: &ChatProvider,
+    texts: &[&str],
+) -> Result<Vec<usize>> {
+    let ChatProvider::HuggingFace(m) = provider else {
+        let bpe = bpe(provider);
+        return Ok(texts.iter().map(|t| bpe.encode_ordinary(t).len()).collect());
+    };
+    let mut model = LOADED_MODELS.lock().unwrap_or_else(|e| {
+        log::warn!("{:#?}", &e);
+        e.into_inner()
+    });
+    if !model.contains_key(robot_id) {
+        let r = LoadedHuggingFaceModel::load(m)?;
+        model.insert(String::from(robot_id), r);
+    };
+    let tokenizer = match model.get(robot_id).unwrap() {
+        LoadedHuggingFaceModel::Llama(m) => &m.3,
+        LoadedHuggingFaceModel::Gemma(m) => &m.2,
+        LoadedHuggingFaceModel::Phi3(m) => &m.2,
+        LoadedHuggingFaceModel::Bert(m) => &m.1,
+    };
+    texts
+        .iter()
+        .map(|t| match tokenizer.encode(*t, false) {
+            Ok(e) => Ok(e.len()),
+            Err(e) => Err(Error::ErrorWithMessage(format!("{}", &e))),
+        })
+        .collect()
+}
+
 fn huggingface(
     robot_id: &str,
     m: &HuggingFaceModel,
-    prompt: &str,
-    chat_history: Option<Vec<Prompt>>,
+    system: &str,
+    chat_history: &[Prompt],
     sample_len: usize,
     mut result_receiver: ResultReceiver<'_>,
 ) -> Result<()> {
     let info = m.get_info();
     // log::info!("model_type={:?}", &info.model_type);
-    let new_prompt = info.convert_prompt(prompt, chat_history)?;
+    let new_prompt = info.convert_prompt(system, chat_history)?;
     let mut model = LOADED_MODELS.lock().unwrap_or_else(|e| {
         log::warn!("{:#?}", &e);
         e.into_inner()
@@ -122,7 +177,7 @@ fn huggingface(
             &m.0,
             &m.1,
             &m.2,
-            prompt,
+            &new_prompt,
             sample_len,
             Some(0.5),
             &mut result_receiver,
@@ -143,7 +198,7 @@ fn huggingface(
             &m.0,
             &m.1,
             &m.2,
-            prompt,
+            &new_prompt,
             sample_len,
             Some(0.5),
             &mut result_receiver,
@@ -156,113 +211,120 @@ fn huggingface(
     // Ok(())
 }
 
-async fn open_ai(
-    m: &str,
-    s: &str,
-    chat_history: Option<Vec<Prompt>>,
-    connect_timeout_millis: u32,
-    read_timeout_millis: u32,
-    proxy_url: &str,
-    result_receiver: ResultReceiver<'_>,
-) -> Result<()> {
-    // let client = HTTP_CLIENTS.lock()?.entry(String::from("value")).or_insert(crate::external::http::get_client(connect_timeout_millis.into(), read_timeout_millis.into(), proxy_url)?);
+fn chat_request(
+    p: &settings::ChatProvider,
+    default_url: &str,
+    body: Map<String, Value>,
+    connect_timeout: Option<u32>,
+    read_timeout: Option<u32>,
+) -> Result<reqwest::RequestBuilder> {
     let client = crate::external::http::get_client(
-        connect_timeout_millis.into(),
-        read_timeout_millis.into(),
-        proxy_url,
+        connect_timeout.unwrap_or(p.connect_timeout_millis).into(),
+        read_timeout.unwrap_or(p.read_timeout_millis).into(),
+        &p.proxy_url,
     )?;
-    let mut req_body = Map::new();
-    req_body.insert(String::from("model"), Value::from(m));
+    let url = if p.api_url.is_empty() {
+        default_url
+    } else {
+        &p.api_url
+    };
+    let mut req = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&Value::Object(body))?);
+    if !p.api_key.is_empty() {
+        req = req.bearer_auth(&p.api_key);
+    }
+    Ok(req)
+}
 
-    let mut sys_message = Map::new();
-    sys_message.insert(String::from("role"), Value::from("system"));
-    sys_message.insert(
-        String::from("content"),
-        Value::from("You are a helpful assistant."),
-    );
-    let mut messages: Vec<Value> = match chat_history {
-        Some(h) if !h.is_empty() => {
-            let mut d = Vec::with_capacity(h.len() + 1);
-            d.push(Value::Null);
-            for p in h.into_iter() {
-                let mut map = Map::new();
-                map.insert(p.role, Value::String(p.content));
-                d.push(Value::from(map));
+// Streamed responses are lines of JSON, which may be split across chunks
+async fn stream_lines(
+    res: reqwest::Response,
+    mut f: impl FnMut(&str) -> Result<bool>,
+) -> Result<()> {
+    let mut stream = res.bytes_stream();
+    let mut buf: Vec<u8> = Vec::with_capacity(1024);
+    while let Some(item) = stream.next().await {
+        buf.extend_from_slice(item?.as_ref());
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.is_empty() && !f(line)? {
+                return Ok(());
             }
-            d
         }
-        _ => Vec::with_capacity(1),
-    };
-    messages[0] = Value::Object(sys_message);
-    let mut user_message = Map::new();
-    user_message.insert(String::from("role"), Value::from("user"));
-    user_message.insert(String::from("content"), Value::from(s));
-    messages.push(Value::Object(user_message));
-    let messages = Value::Array(messages);
-    req_body.insert(String::from("messages"), messages);
+    }
+    let line = String::from_utf8_lossy(&buf);
+    if !line.trim().is_empty() {
+        f(line.trim())?;
+    }
+    Ok(())
+}
 
+async fn open_ai(
+    p: &settings::ChatProvider,
+    m: &str,
+    messages: Vec<Value>,
+    connect_timeout: Option<u32>,
+    read_timeout: Option<u32>,
+    result_receiver: ResultReceiver<'_>,
+) -> Result<()> {
+    let mut req_body = Map::new();
+    req_body.insert(String::from("model"), Value::from(m));
+    req_body.insert(String::from("messages"), Value::Array(messages));
     let stream = match result_receiver {
         ResultReceiver::SseSender(_) => true,
         ResultReceiver::StrBuf(_) => false,
     };
     req_body.insert(String::from("stream"), Value::Bool(stream));
-    let obj = Value::Object(req_body);
-    let req = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .header("Content-Type", "application/json")
-        .header("Authorization", "Bearer ")
-        .body(serde_json::to_string(&obj)?);
+    let req = chat_request(
+        p,
+        "https://api.openai.com/v1/chat/completions",
+        req_body,
+        connect_timeout,
+        read_timeout,
+    )?;
     let res = req.send().await?;
+    if !res.status().is_success() {
+        return Err(Error::ErrorWithMessage(format!(
+            "OpenAI request failed, status: {}, body: {}",
+            res.status(),
+            res.text().await?
+        )));
+    }
     match result_receiver {
         ResultReceiver::SseSender(sender) => {
-            let mut stream = res.bytes_stream();
-            while let Some(item) = stream.next().await {
-                let chunk = item?;
-                let v: Value = serde_json::from_slice(chunk.as_ref())?;
-                if let Some(choices) = v.get("choices") {
-                    if choices.is_array() {
-                        if let Some(choices) = choices.as_array() {
-                            if !choices.is_empty() {
-                                if let Some(item) = choices.get(0) {
-                                    if let Some(delta) = item.get("delta") {
-                                        if let Some(content) = delta.get("content") {
-                                            if content.is_string() {
-                                                if let Some(s) = content.as_str() {
-                                                    let m = String::from(s);
-                                                    log::info!("OpenAI push {}", &m);
-                                                    crate::sse_send!(sender, m);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+            stream_lines(res, |line| {
+                let Some(data) = line.strip_prefix("data:") else {
+                    return Ok(true);
+                };
+                let data = data.trim();
+                if data.eq("[DONE]") {
+                    return Ok(false);
                 }
-            }
+                let v: Value = serde_json::from_str(data)?;
+                if let Some(s) = v
+                    .pointer("/choices/0/delta/content")
+                    .and_then(Value::as_str)
+                {
+                    let m = String::from(s);
+                    log::info!("OpenAI push {}", &m);
+                    crate::sse_send!(sender, m);
+                }
+                Ok(true)
+            })
+            .await?;
         }
         ResultReceiver::StrBuf(sb) => {
             let v: Value = serde_json::from_slice(res.text().await?.as_ref())?;
-            if let Some(choices) = v.get("choices") {
-                if choices.is_array() {
-                    if let Some(choices) = choices.as_array() {
-                        if !choices.is_empty() {
-                            if let Some(item) = choices.get(0) {
-                                if let Some(message) = item.get("message") {
-                                    if let Some(content) = message.get("content") {
-                                        if content.is_string() {
-                                            if let Some(s) = content.as_str() {
-                                                log::info!("OpenAI push {}", s);
-                                                sb.push_str(s);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+            if let Some(s) = v
+                .pointer("/choices/0/message/content")
+                .and_then(Value::as_str)
+            {
+                log::info!("OpenAI push {}", s);
+                sb.push_str(s);
             }
         }
     }
@@ -270,97 +332,273 @@ async fn open_ai(
 }
 
 async fn ollama(
-    u: &str,
+    p: &settings::ChatProvider,
     m: &str,
-    s: &str,
-    chat_history: Option<Vec<Prompt>>,
-    connect_timeout_millis: u32,
-    read_timeout_millis: u32,
-    proxy_url: &str,
-    sample_len: u32,
+    messages: Vec<Value>,
+    connect_timeout: Option<u32>,
+    read_timeout: Option<u32>,
     result_receiver: ResultReceiver<'_>,
 ) -> Result<()> {
-    // log::info!("s1=|{}|", s);
-    let prompts: Vec<super::completion::Prompt> = serde_json::from_str(s)?;
-    // log::info!("s2=|{}|", s);
-    let mut prompt = String::with_capacity(32);
-    for p in prompts.iter() {
-        if p.role.eq("user") {
-            prompt.push_str(&p.content);
-            break;
-        }
-    }
-    if prompt.is_empty() {
-        return Ok(());
-    }
-    let client = crate::external::http::get_client(
-        connect_timeout_millis.into(),
-        read_timeout_millis.into(),
-        proxy_url,
-    )?;
     let mut req_body = Map::new();
-    req_body.insert(String::from("prompt"), Value::String(prompt));
     req_body.insert(String::from("model"), Value::String(String::from(m)));
+    req_body.insert(String::from("messages"), Value::Array(messages));
     let stream = match result_receiver {
         ResultReceiver::SseSender(_) => true,
         ResultReceiver::StrBuf(_) => false,
     };
     req_body.insert(String::from("stream"), Value::Bool(stream));
 
-    let mut messages: Vec<Value> = match chat_history {
-        Some(h) if !h.is_empty() => {
-            let mut d = Vec::with_capacity(h.len() + 1);
-            for p in h.into_iter() {
-                let mut map = Map::new();
-                map.insert(p.role, Value::String(p.content));
-                d.push(Value::from(map));
-            }
-            d
-        }
-        _ => Vec::with_capacity(1),
-    };
-    let mut m = Map::new();
-    m.insert(String::from("user"), Value::String(String::from(s)));
-    messages.push(Value::from(m));
-    req_body.insert(String::from("messages"), Value::Array(messages));
-
     let mut num_predict = Map::new();
-    num_predict.insert(String::from("num_predict"), Value::from(sample_len));
+    num_predict.insert(
+        String::from("num_predict"),
+        Value::from(p.max_response_token_length),
+    );
     req_body.insert(String::from("options"), Value::from(num_predict));
 
-    let obj = Value::Object(req_body);
-    let body = serde_json::to_string(&obj)?;
-    // log::info!("Request Ollama body {}", &body);
-    let req = client.post(u).body(body);
+    // log::info!("Request Ollama body {:?}", &req_body);
+    let req = chat_request(
+        p,
+        "http://localhost:11434/api/chat",
+        req_body,
+        connect_timeout,
+        read_timeout,
+    )?;
     let res = req.send().await?;
+    if !res.status().is_success() {
+        return Err(Error::ErrorWithMessage(format!(
+            "Ollama request failed, status: {}, body: {}",
+            res.status(),
+            res.text().await?
+        )));
+    }
     match result_receiver {
         ResultReceiver::SseSender(sender) => {
-            let mut stream = res.bytes_stream();
-            while let Some(item) = stream.next().await {
-                let chunk = item?;
-                let v: Value = serde_json::from_slice(chunk.as_ref())?;
-                if let Some(res) = v.get("response") {
-                    if res.is_string() {
-                        if let Some(s) = res.as_str() {
-                            let m = String::from(s);
-                            log::info!("Ollama push {}", &m);
-                            crate::sse_send!(sender, m);
-                        }
-                    }
+            stream_lines(res, |line| {
+                let v: Value = serde_json::from_str(line)?;
+                if let Some(s) = v.pointer("/message/content").and_then(Value::as_str) {
+                    let m = String::from(s);
+                    log::info!("Ollama push {}", &m);
+                    crate::sse_send!(sender, m);
                 }
-            }
+                Ok(!v.get("done").and_then(Value::as_bool).unwrap_or(false))
+            })
+            .await?;
         }
         ResultReceiver::StrBuf(sb) => {
             let v: Value = serde_json::from_slice(res.bytes().await?.as_ref())?;
-            if let Some(res) = v.get("response") {
-                if res.is_string() {
-                    if let Some(s) = res.as_str() {
-                        log::info!("Ollama returned {}", s);
-                        sb.push_str(s);
-                    }
-                }
+            if let Some(s) = v.pointer("/message/content").and_then(Value::as_str) {
+                log::info!("Ollama returned {}", s);
+                sb.push_str(s);
             }
         }
     }
     Ok(())
 }
+
+// Function calling and structured outputs of OpenAI `/v1/chat/completions` and Ollama `/api/chat`
+#[derive(Clone, Copy, PartialEq)]
+enum ToolChatApi {
+    OpenAI,
+    Ollama,
+}
+
+pub(crate) struct ToolCall {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) arguments: Map<String, Value>,
+}
+
+pub(crate) enum ToolChatTurn {
+    Answer(String),
+    ToolCalls(Vec<ToolCall>),
+}
+
+// Tools and structured replies need the chat API of OpenAI or Ollama
+pub(crate) fn supports_tools(robot_id: &str) -> Result<bool> {
+    Ok(settings::get_settings(robot_id)?
+        .is_some_and(|s| !matches!(s.chat_provider.provider, ChatProvider::HuggingFace(_))))
+}
+
+pub(crate) struct ToolChat {
+    api: ToolChatApi,
+    url: String,
+    api_key: String,
+    model: String,
+    max_response_token_length: u32,
+    client: reqwest::Client,
+    messages: Vec<Value>,
+    tools: Vec<Value>,
+    output_schema: Option<Value>,
+}
+
+impl ToolChat {
+    // `tools` are name, description and JSON schema of parameters,
+    // `chat_history` ends with the input of the user
+    pub(crate) fn new(
+        robot_id: &str,
+        system: &str,
+        chat_history: &[Prompt],
+        tools: Vec<(String, String, Value)>,
+        connect_timeout: Option<u32>,
+        read_timeout: Option<u32>,
+    ) -> Result<Self> {
+        let Some(settings) = settings::get_settings(robot_id)? else {
+            return Err(Error::ErrorWithMessage(format!(
+                "Can NOT retrieve settings from robot_id: {robot_id}"
+            )));
+        };
+        let p = settings.chat_provider;
+        let (api, model, default_url) = match p.provider {
+            ChatProvider::OpenAI(m) => (
+                ToolChatApi::OpenAI,
+                m,
+                "https://api.openai.com/v1/chat/completions",
+            ),
+            ChatProvider::Ollama(m) => (ToolChatApi::Ollama, m, "http://localhost:11434/api/chat"),
+            ChatProvider::HuggingFace(m) => {
+                return Err(Error::ErrorWithMessage(format!(
+                    "Model {:?} does not support tools, please choose OpenAI or Ollama.",
+                    &m
+                )))
+            }
+        };
+        let client = crate::external::http::get_client(
+            connect_timeout.unwrap_or(p.connect_timeout_millis).into(),
+            read_timeout.unwrap_or(p.read_timeout_millis).into(),
+            &p.proxy_url,
+        )?;
+        let messages = messages(system, chat_history);
+        let tools = tools
+            .into_iter()
+            .map(|(name, description, parameters)| {
+                serde_json::json!({
+                    "type": "function",
+                    "function": {"name": name, "description": description, "parameters": parameters},
+                })
+            })
+            .collect();
+        Ok(Self {
+            api,
+            url: if p.api_url.is_empty() {
+                String::from(default_url)
+            } else {
+                p.api_url
+            },
+            api_key: p.api_key,
+            model,
+            max_response_token_length: p.max_response_token_length,
+            client,
+            messages,
+            tools,
+            output_schema: None,
+        })
+    }
+
+    // Makes the model reply with JSON matching the schema
+    pub(crate) fn set_output_schema(&mut self, schema: Value) {
+        self.output_schema = Some(schema);
+    }
+
+    pub(crate) fn add_user_message(&mut self, content: &str) {
+        self.messages
+            .push(serde_json::json!({"role": "user", "content": content}));
+    }
+
+    // Sends the conversation, the reply of the model is kept in it
+    pub(crate) async fn next(&mut self) -> Result<ToolChatTurn> {
+        let mut body = serde_json::json!({
+            "model": &self.model,
+            "messages": &self.messages,
+            "stream": false,
+        });
+        // OpenAI rejects empty tools
+        if !self.tools.is_empty() {
+            body["tools"] = Value::from(self.tools.clone());
+        }
+        if let Some(schema) = &self.output_schema {
+            match self.api {
+                ToolChatApi::OpenAI => {
+                    body["response_format"] = serde_json::json!({
+                        "type": "json_schema",
+                        "json_schema": {"name": "output", "schema": schema},
+                    })
+                }
+                ToolChatApi::Ollama => body["format"] = schema.clone(),
+            }
+        }
+        if self.api == ToolChatApi::Ollama {
+            body["options"] = serde_json::json!({"num_predict": self.max_response_token_length});
+        }
+        let mut req = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&body)?);
+        if !self.api_key.is_empty() {
+            req = req.bearer_auth(&self.api_key);
+        }
+        let res = req.send().await?;
+        let status = res.status();
+        let text = res.text().await?;
+        if !status.is_success() {
+            return Err(Error::ErrorWithMessage(format!(
+                "Chat request failed, status: {}, body: {}",
+                status, &text
+            )));
+        }
+        let v: Value = serde_json::from_str(&text)?;
+        let message = match self.api {
+            ToolChatApi::OpenAI => v.pointer("/choices/0/message"),
+            ToolChatApi::Ollama => v.get("message"),
+        }
+        .cloned()
+        .ok_or_else(|| Error::ErrorWithMessage(format!("Invalid chat response: {}", &text)))?;
+        let mut calls = Vec::new();
+        if let Some(tool_calls) = message.get("tool_calls").and_then(Value::as_array) {
+            for (i, c) in tool_calls.iter().enumerate() {
+                let name = c
+                    .pointer("/function/name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                // OpenAI sends arguments as a JSON string, Ollama as an object
+                let arguments = match c.pointer("/function/arguments") {
+                    Some(Value::String(s)) if !s.trim().is_empty() => serde_json::from_str(s)?,
+                    Some(Value::Object(o)) => o.clone(),
+                    _ => Map::new(),
+                };
+                calls.push(ToolCall {
+                    id: c
+                        .get("id")
+                        .and_then(Value::as_str)
+                        .map_or_else(|| format!("call_{}", i), String::from),
+                    name: String::from(name),
+                    arguments,
+                });
+            }
+        }
+        let answer = message
+            .get("content")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or_default();
+        self.messages.push(message);
+        if calls.is_empty() {
+            log::info!("Tool chat returned {}", &answer);
+            Ok(ToolChatTurn::Answer(answer))
+        } else {
+            Ok(ToolChatTurn::ToolCalls(calls))
+        }
+    }
+
+    pub(crate) fn add_tool_result(&mut self, call: &ToolCall, content: &str) {
+        let message = match self.api {
+            ToolChatApi::OpenAI => {
+                serde_json::json!({"role": "tool", "tool_call_id": &call.id, "content": content})
+            }
+            ToolChatApi::Ollama => {
+                serde_json::json!({"role": "tool", "tool_name": &call.name, "content": content})
+            }
+        };
+        self.messages.push(message);
+    }
+}
@@ -16,8 +16,9 @@ pub(crate) const TEMPERATURE: f64 = 0.7;
 pub(crate) const REPEAT_PENALTY: f32 = 1.1;
 pub(crate) const REPEAT_LAST_N: usize = 64;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, utoipa::ToSchema)]
 #[serde(tag = "id", content = "model")]
+#[schema(as = TextGenerationProviderKind)]
 pub(crate) enum TextGenerationProvider {
     HuggingFace(HuggingFaceModel),
     OpenAI(String),
@@ -183,7 +184,12 @@ async fn huggingface(
 ) -> Result<()> {
     let info = m.get_info();
     // log::info!("model_type={:?}", &info.model_type);
-    let new_prompt = info.convert_prompt(prompt, None)?;
+    let (system, prompts): (Vec<Prompt>, Vec<Prompt>) =
+        serde_json::from_str::<Vec<Prompt>>(prompt)?
+            .into_iter()
+            .partition(|p| p.role.eq("system"));
+    let system: Vec<String> = system.into_iter().map(|p| p.content).collect();
+    let new_prompt = info.convert_prompt(&system.join("\n"), &prompts)?;
     let mut model = LOADED_MODELS.lock().unwrap_or_else(|e| {
         log::warn!("{:#?}", &e);
         e.into_inner()
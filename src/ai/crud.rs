@@ -14,7 +14,8 @@ use tokio_stream::StreamExt as _;
 
 use crate::ai::completion;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
+#[schema(as = TextGenerationRequest)]
 pub(crate) struct Request {
     pub(crate) robot_id: String,
     pub(crate) prompt: String,
@@ -27,6 +28,14 @@ pub(crate) struct Request {
 //     }
 // }
 
+#[utoipa::path(
+    post,
+    path = "/ai/text/generation",
+    tag = "ai",
+    summary = "Generate text",
+    request_body = Request,
+    responses((status = 200, content_type = "text/event-stream", body = String))
+)]
 pub(crate) async fn gen_text(bytes: Bytes) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     let q: Request = serde_json::from_slice(bytes.as_ref()).unwrap();
     // let _guard = Guard;
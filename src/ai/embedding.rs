@@ -13,8 +13,9 @@ use super::huggingface::{load_bert_model_files, HuggingFaceModel, HuggingFaceMod
 use crate::man::settings;
 use crate::result::{Error, Result};
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 #[serde(tag = "id", content = "model")]
+#[schema(as = SentenceEmbeddingProviderKind)]
 pub(crate) enum SentenceEmbeddingProvider {
     HuggingFace(HuggingFaceModel),
     OpenAI(String),
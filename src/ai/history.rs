@@ -0,0 +1,143 @@
+use super::chat::{self, ResultReceiver};
+use super::completion::Prompt;
+use crate::man::settings;
+use crate::result::{Error, Result};
+
+// Turns left out of the window are folded into a summary, which is kept as the first message of the history
+const SUMMARY_ROLE: &str = "system";
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation:\n";
+const SUMMARIZE_PROMPT: &str = "Summarize the following conversation between a user and an assistant in a few sentences. Keep names, numbers, dates and what the user asked for. Reply with only the summary.";
+// Role and separators of a message
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+// Prompts saved by the flow editor are JSON arrays of messages
+pub(crate) fn system_prompt(prompt: &str) -> String {
+    match serde_json::from_str::<Vec<Prompt>>(prompt) {
+        Ok(prompts) => prompts
+            .into_iter()
+            .map(|p| p.content)
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Err(_) => String::from(prompt),
+    }
+}
+
+// Returns the summary and the last `context_len` turns before the input of the user,
+// as many as fit in the context window of the model along with `system`.
+// A turn is a message of the user and the answers to it.
+pub(crate) async fn window(
+    robot_id: &str,
+    system: &str,
+    history: &mut Vec<Prompt>,
+    context_len: u8,
+    connect_timeout: Option<u32>,
+    read_timeout: Option<u32>,
+) -> Result<Vec<Prompt>> {
+    let offset = usize::from(history.first().is_some_and(|p| p.role.eq(SUMMARY_ROLE)));
+    let turns: Vec<usize> = history
+        .iter()
+        .enumerate()
+        .skip(offset)
+        .filter(|(_, p)| p.role.eq("user"))
+        .map(|(i, _)| i)
+        .collect();
+    let Some(&current) = turns.last() else {
+        return Ok(history[offset..].to_vec());
+    };
+    if context_len == 0 {
+        return Ok(history[current..].to_vec());
+    }
+    let Some(settings) = settings::get_settings(robot_id)? else {
+        return Err(Error::ErrorWithMessage(format!(
+            "Can NOT retrieve settings from robot_id: {robot_id}"
+        )));
+    };
+    let p = &settings.chat_provider;
+    let texts: Vec<&str> = std::iter::once(system)
+        .chain(history.iter().map(|h| h.content.as_str()))
+        .collect();
+    let tokens = chat::count_tokens(robot_id, &p.provider, &texts)?;
+    let cost = |i: usize| tokens[i + 1] + MESSAGE_OVERHEAD_TOKENS;
+    let mut budget = (p.max_context_token_length as usize)
+        .saturating_sub(p.max_response_token_length as usize + tokens[0]);
+    if offset > 0 {
+        budget = budget.saturating_sub(cost(0));
+    }
+    // Start of the last turns fitting in the budget, the input of the user is always sent
+    let fit = |budget: usize, max_turns: usize| -> usize {
+        let mut used: usize = (current..history.len()).map(cost).sum();
+        let mut start = current;
+        for &t in turns.iter().rev().skip(1).take(max_turns) {
+            let c: usize = (t..start).map(cost).sum();
+            if used + c > budget {
+                break;
+            }
+            used += c;
+            start = t;
+        }
+        start
+    };
+    let start = fit(budget, context_len as usize);
+    let mut kept = history.len() - start;
+    if start > offset {
+        // Folds half of the window as well, so the following turns fit without summarizing again
+        let start = fit(budget / 2, context_len as usize / 2);
+        match summarize(robot_id, &history[..start], connect_timeout, read_timeout).await {
+            Ok(s) => {
+                kept = history.len() - start;
+                history.splice(
+                    ..start,
+                    [Prompt {
+                        role: String::from(SUMMARY_ROLE),
+                        content: format!("{}{}", SUMMARY_PREFIX, s),
+                    }],
+                );
+            }
+            Err(e) => log::warn!("Summarizing chat history failed, err: {:?}", &e),
+        }
+    }
+    let mut w = Vec::with_capacity(kept + 1);
+    if history.first().is_some_and(|p| p.role.eq(SUMMARY_ROLE)) {
+        w.push(history[0].clone());
+    }
+    w.extend_from_slice(&history[history.len() - kept..]);
+    Ok(w)
+}
+
+async fn summarize(
+    robot_id: &str,
+    history: &[Prompt],
+    connect_timeout: Option<u32>,
+    read_timeout: Option<u32>,
+) -> Result<String> {
+    let mut text = String::with_capacity(1024);
+    for p in history.iter() {
+        if !p.role.eq(SUMMARY_ROLE) {
+            text.push_str(&p.role);
+            text.push_str(": ");
+        }
+        text.push_str(&p.content);
+        text.push('\n');
+    }
+    let mut s = String::with_capacity(512);
+    chat::chat(
+        robot_id,
+        SUMMARIZE_PROMPT,
+        &[Prompt {
+            role: String::from("user"),
+            content: text,
+        }],
+        connect_timeout,
+        read_timeout,
+        ResultReceiver::StrBuf(&mut s),
+    )
+    .await?;
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "The model returned an empty summary",
+        )));
+    }
+    log::info!("Chat history summary {}", s);
+    Ok(String::from(s))
+}
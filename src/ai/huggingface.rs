@@ -21,7 +21,7 @@ use tokio::io::AsyncWriteExt;
 
 use crate::result::{Error, Result};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) enum HuggingFaceModel {
     AllMiniLML6V2,
     ParaphraseMLMiniLML12V2,
@@ -96,42 +96,34 @@ pub(crate) struct HuggingFaceModelInfo {
 }
 
 impl HuggingFaceModelInfo {
+    // The last message of `history` is the input of the user
     pub(super) fn convert_prompt(
         &self,
-        s: &str,
-        history: Option<Vec<crate::ai::completion::Prompt>>,
+        system: &str,
+        history: &[crate::ai::completion::Prompt],
     ) -> Result<String> {
-        let mut prompts: Vec<super::completion::Prompt> = serde_json::from_str(s)?;
-        let mut system = String::new();
-        let mut user = String::new();
-        for p in prompts.iter_mut() {
-            if p.role.eq("system") {
-                std::mem::swap(&mut system, &mut p.content);
-            } else if p.role.eq("user") {
-                std::mem::swap(&mut user, &mut p.content);
-            }
-        }
+        let mut p = String::with_capacity(1024);
         match self.model_type {
-            HuggingFaceModelType::Bert => todo!(),
+            HuggingFaceModelType::Bert => {
+                return Err(Error::ErrorWithMessage(format!(
+                    "Unsupported model type {:?}.",
+                    &self.model_type
+                )))
+            }
             HuggingFaceModelType::Llama => {
-                let mut p = String::with_capacity(s.len());
                 if !system.is_empty() {
                     p.push_str("<|system|>\n");
-                    p.push_str(&system);
+                    p.push_str(system);
                     p.push_str("</s>\n");
                 }
-                if let Some(h) = history {
-                    for i in h.iter() {
-                        p.push_str("<|");
-                        p.push_str(&i.role);
-                        p.push_str("|>\n");
-                        p.push_str(&i.content);
-                        p.push_str("</s>\n");
-                    }
+                for i in history.iter() {
+                    p.push_str("<|");
+                    p.push_str(&i.role);
+                    p.push_str("|>\n");
+                    p.push_str(&i.content);
+                    p.push_str("</s>\n");
                 }
-                p.push_str("<|user|>\n");
-                p.push_str(&user);
-                p.push_str("</s>\n<|assistant|>");
+                p.push_str("<|assistant|>");
                 // p.push_str("<|begin_of_text|>");
                 // if !system.is_empty() {
                 //     p.push_str("<|start_header_id|>system<|end_header_id|>");
@@ -141,52 +133,46 @@ impl HuggingFaceModelInfo {
                 // p.push_str("<|start_header_id|>user<|end_header_id|>");
                 // p.push_str(&user);
                 // p.push_str("<|eot_id|><|start_header_id|>assistant<|end_header_id|><|eot_id|>");
-                Ok(p)
             }
             HuggingFaceModelType::Gemma => {
-                let mut p = String::with_capacity(s.len());
                 // p.push_str("<bos>");
-                if let Some(h) = history {
-                    for i in h.iter() {
-                        p.push_str("<start_of_turn>");
-                        if i.role.eq("assistant") {
-                            p.push_str("model");
-                        } else {
-                            p.push_str(&i.role);
+                // Gemma has no system role, so instructions go into the first user turn
+                let mut system = system;
+                for i in history.iter() {
+                    p.push_str("<start_of_turn>");
+                    if i.role.eq("assistant") {
+                        p.push_str("model\n");
+                    } else {
+                        p.push_str("user\n");
+                        if !system.is_empty() {
+                            p.push_str(system);
+                            p.push_str("\n\n");
+                            system = "";
                         }
-                        p.push_str("\n");
-                        p.push_str(&i.content);
-                        p.push_str("<end_of_turn>\n");
                     }
+                    p.push_str(&i.content);
+                    p.push_str("<end_of_turn>\n");
                 }
-                p.push_str("<start_of_turn>user\n");
-                p.push_str(&user);
-                p.push_str("<end_of_turn>\n<start_of_turn>model");
-                Ok(p)
+                p.push_str("<start_of_turn>model");
             }
             HuggingFaceModelType::Phi3 => {
-                let mut p = String::with_capacity(s.len());
                 p.push_str("<s>");
                 if !system.is_empty() {
                     p.push_str("<|system|>\n");
-                    p.push_str(&system);
+                    p.push_str(system);
                     p.push_str("<|end|>\n");
                 }
-                if let Some(h) = history {
-                    for i in h.iter() {
-                        p.push_str("<|");
-                        p.push_str(&i.role);
-                        p.push_str("|>\n");
-                        p.push_str(&i.content);
-                        p.push_str("<|end|>\n");
-                    }
+                for i in history.iter() {
+                    p.push_str("<|");
+                    p.push_str(&i.role);
+                    p.push_str("|>\n");
+                    p.push_str(&i.content);
+                    p.push_str("<|end|>\n");
                 }
-                p.push_str("<|user|>\n");
-                p.push_str(&user);
-                p.push_str("<|end|>\n<|assistant|>");
-                Ok(p)
+                p.push_str("<|assistant|>");
             }
         }
+        Ok(p)
     }
 }
 
@@ -424,7 +410,7 @@ impl std::fmt::Display for HuggingFaceModel {
 
 const HUGGING_FACE_MODEL_ROOT: &str = "./data/hf_hub/";
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, utoipa::ToSchema)]
 pub(crate) struct DownloadStatus {
     pub(crate) downloading: bool,
     #[serde(rename = "totalLen")]
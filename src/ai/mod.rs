@@ -6,6 +6,7 @@ pub(crate) mod completion;
 pub(crate) mod crud;
 pub(crate) mod embedding;
 pub(crate) mod gemma;
+pub(crate) mod history;
 pub(super) mod huggingface;
 pub(super) mod llama;
 pub(super) mod phi3;
@@ -2,8 +2,9 @@ use serde::{Deserialize, Serialize};
 
 use super::huggingface::{load_bert_model_files, HuggingFaceModel, HuggingFaceModelInfo};
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 #[serde(tag = "id", content = "model")]
+#[schema(as = TtsProviderKind)]
 pub(crate) enum TtsProvider {
     HuggingFace(HuggingFaceModel),
 }
@@ -87,6 +87,7 @@ pub(crate) async fn init() -> Result<GlobalSettings> {
 
     // Settings
     settings::init_table()?;
+    crate::robot::template::init_table()?;
     mainflow::init_default_names(is_en)?;
     if settings::exists()? {
         return Ok(settings::get_global_settings()?.unwrap());
@@ -205,6 +206,31 @@ where
     }
 }
 
+// Reads, changes and writes the value in one transaction, returns false if it does not exist
+pub(crate) fn update<V, D, F>(table: TableDefinition<&str, V>, key: &str, f: F) -> Result<bool>
+where
+    V: for<'a> redb::Value<SelfType<'a> = &'a [u8]>,
+    D: serde::Serialize + serde::de::DeserializeOwned,
+    F: FnOnce(&mut D),
+{
+    let write_txn = DB.begin_write()?;
+    {
+        let mut table = write_txn.open_table(table)?;
+        let current: Option<D> = match table.get(key)? {
+            Some(r) => Some(serde_json::from_slice(r.value())?),
+            None => None,
+        };
+        let Some(mut d) = current else {
+            return Ok(false);
+        };
+        f(&mut d);
+        let r = serde_json::to_vec(&d)?;
+        table.insert(key, r.as_slice())?;
+    }
+    write_txn.commit()?;
+    Ok(true)
+}
+
 /*
 pub(crate) fn read<'a, D>(key: impl Borrow<&'a str>) -> Result<Option<D>>
 where
@@ -357,6 +383,22 @@ where
     Ok(())
 }
 
+// Removes the keys in one transaction
+pub(crate) fn remove_keys<V>(table: TableDefinition<&str, V>, keys: &[String]) -> Result<()>
+where
+    for<'b> V: redb::Value<SelfType<'b> = &'b [u8]>,
+{
+    let write_txn = DB.begin_write()?;
+    {
+        let mut table = write_txn.open_table(table)?;
+        for key in keys.iter() {
+            table.remove(key.as_str())?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
 pub(crate) fn delete_table<'a, K, V>(table: redb::TableDefinition<K, V>) -> Result<()>
 where
     K: redb::Key,
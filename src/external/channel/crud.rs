@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use utoipa::TupleUnit;
+
+use super::dto::{ChannelAdapter, ChannelPlatform};
+use crate::db;
+use crate::db_executor;
+use crate::result::{Error, Result};
+use crate::web::openapi::Binary;
+use crate::web::server::{to_res, ResponseData};
+
+pub(crate) const TABLE_SUFFIX: &str = "channelAdapters";
+
+fn robot_id(q: &HashMap<String, String>) -> Result<&String> {
+    q.get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))
+}
+
+fn err(m: &str) -> Error {
+    Error::ErrorWithMessage(String::from(m))
+}
+
+pub(crate) fn list_adapters(robot_id: &str) -> Result<Vec<ChannelAdapter>> {
+    match db_executor!(db::get_all, robot_id, TABLE_SUFFIX,) {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Ok(Vec::new()),
+        r => r,
+    }
+}
+
+pub(crate) fn get_adapter(robot_id: &str, id: &str) -> Result<Option<ChannelAdapter>> {
+    match db_executor!(db::query, robot_id, TABLE_SUFFIX, id) {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Ok(None),
+        r => r,
+    }
+}
+
+pub(crate) fn delete_tables(robot_id: &str) -> Result<()> {
+    db_executor!(db::delete_table, robot_id, TABLE_SUFFIX,)
+}
+
+#[utoipa::path(
+    get,
+    path = "/channel/adapter",
+    tag = "channel",
+    summary = "List channel adapters",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<ChannelAdapter>>))
+)]
+pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(robot_id(&q).and_then(|robot_id| list_adapters(robot_id)))
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+fn check(a: &ChannelAdapter) -> Result<()> {
+    if a.main_flow_id.is_empty() {
+        return Err(err("Please choose the main flow of the channel."));
+    }
+    match &a.platform {
+        ChannelPlatform::Telegram(s) => {
+            if s.bot_token.is_empty() || !is_http_url(&s.api_base_url) {
+                return Err(err("Bot token and API URL of Telegram are required."));
+            }
+            // Rules of `secret_token` of `setWebhook`
+            if s.secret_token.is_empty()
+                || s.secret_token.len() > 256
+                || !s
+                    .secret_token
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(err(
+                    "Secret token must be 1-256 characters of A-Z, a-z, 0-9, _ and -.",
+                ));
+            }
+        }
+        ChannelPlatform::Slack(s) => {
+            if s.bot_token.is_empty()
+                || s.signing_secret.is_empty()
+                || !is_http_url(&s.api_base_url)
+            {
+                return Err(err(
+                    "Bot token, signing secret and API URL of Slack are required.",
+                ));
+            }
+        }
+        ChannelPlatform::GenericJson(s) => {
+            if s.secret.is_empty() {
+                return Err(err("Secret of the JSON webhook is required."));
+            }
+            if s.user_id_pointer.is_empty() || s.text_pointer.is_empty() {
+                return Err(err("Pointers of user id and text are required."));
+            }
+            for p in [
+                &s.user_id_pointer,
+                &s.text_pointer,
+                &s.user_name_pointer,
+                &s.intent_pointer,
+                &s.locale_pointer,
+            ] {
+                if !p.is_empty() && !p.starts_with('/') {
+                    return Err(Error::ErrorWithMessage(format!(
+                        "Invalid JSON pointer: {}, it must start with /",
+                        p
+                    )));
+                }
+            }
+            if !s.reply_url.is_empty() && !is_http_url(&s.reply_url) {
+                return Err(err("The reply URL must start with http:// or https://"));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn save_adapter(q: &HashMap<String, String>, mut a: ChannelAdapter) -> Result<String> {
+    let robot_id = robot_id(q)?;
+    check(&a)?;
+    if a.id.is_empty() || a.id.eq("new") {
+        a.id = scru128::new_string();
+    }
+    db_executor!(db::write, robot_id, TABLE_SUFFIX, &a.id, &a)?;
+    Ok(a.id)
+}
+
+#[utoipa::path(
+    post,
+    path = "/channel/adapter",
+    tag = "channel",
+    summary = "Create or update a channel adapter",
+    params(("robotId" = String, Query)),
+    request_body = ChannelAdapter,
+    responses((status = 200, body = ResponseData<String>))
+)]
+pub(crate) async fn save(
+    Query(q): Query<HashMap<String, String>>,
+    Json(a): Json<ChannelAdapter>,
+) -> impl IntoResponse {
+    to_res(save_adapter(&q, a))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/channel/adapter/{id}",
+    tag = "channel",
+    summary = "Delete a channel adapter",
+    params(("id" = String, Path), ("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn remove(
+    Query(q): Query<HashMap<String, String>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    to_res(
+        robot_id(&q)
+            .and_then(|robot_id| db_executor!(db::remove, robot_id, TABLE_SUFFIX, id.as_str())),
+    )
+}
+
+// Webhook of the platform, it is answered in the format the platform expects instead of `to_res`
+#[utoipa::path(
+    post,
+    path = "/channel/adapter/{id}/webhook",
+    tag = "channel",
+    summary = "Receive a message from a messaging platform",
+    params(("id" = String, Path), ("robotId" = String, Query)),
+    request_body(content = inline(Binary), content_type = "application/json"),
+    responses((status = 200, content_type = "application/json", body = serde_json::Value))
+)]
+pub(crate) async fn receive(
+    Query(q): Query<HashMap<String, String>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let Some(robot_id) = q.get("robotId") else {
+        return (StatusCode::BAD_REQUEST, "Parameter: robotId is missing.").into_response();
+    };
+    match get_adapter(robot_id, &id) {
+        Ok(Some(a)) if a.enabled => super::inbound::receive(robot_id, a, &headers, &body).await,
+        Ok(_) => (StatusCode::NOT_FOUND, "Channel adapter was not found").into_response(),
+        Err(e) => {
+            log::error!("Loading channel adapter {} failed: {:?}", &id, &e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
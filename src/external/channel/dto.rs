@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct ChannelAdapter {
+    #[serde(default)]
+    pub(crate) id: String,
+    pub(crate) name: String,
+    #[serde(default = "default_enabled")]
+    pub(crate) enabled: bool,
+    // Flow started by new conversations
+    #[serde(rename = "mainFlowId")]
+    pub(crate) main_flow_id: String,
+    pub(crate) platform: ChannelPlatform,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) enum ChannelPlatform {
+    Telegram(TelegramSettings),
+    Slack(SlackSettings),
+    GenericJson(GenericJsonSettings),
+}
+
+impl ChannelPlatform {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Telegram(_) => "telegram",
+            Self::Slack(_) => "slack",
+            Self::GenericJson(_) => "json",
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct TelegramSettings {
+    #[serde(rename = "botToken")]
+    pub(crate) bot_token: String,
+    // Passed as `secret_token` of `setWebhook`, Telegram sends it back in `X-Telegram-Bot-Api-Secret-Token`
+    #[serde(rename = "secretToken")]
+    pub(crate) secret_token: String,
+    #[serde(rename = "apiBaseUrl", default = "default_telegram_api")]
+    pub(crate) api_base_url: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct SlackSettings {
+    #[serde(rename = "botToken")]
+    pub(crate) bot_token: String,
+    // Verifies `X-Slack-Signature` of events and interactions
+    #[serde(rename = "signingSecret")]
+    pub(crate) signing_secret: String,
+    #[serde(rename = "apiBaseUrl", default = "default_slack_api")]
+    pub(crate) api_base_url: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct GenericJsonSettings {
+    // Requests are signed like outgoing webhooks, `X-Dialogflow-Signature` is HMAC-SHA256 of `{timestamp}.{body}`
+    pub(crate) secret: String,
+    // JSON pointers (RFC 6901) into the request body, e.g. `/user/id`
+    #[serde(rename = "userIdPointer")]
+    pub(crate) user_id_pointer: String,
+    #[serde(rename = "textPointer")]
+    pub(crate) text_pointer: String,
+    #[serde(rename = "userNamePointer", default)]
+    pub(crate) user_name_pointer: String,
+    #[serde(rename = "intentPointer", default)]
+    pub(crate) intent_pointer: String,
+    #[serde(rename = "localePointer", default)]
+    pub(crate) locale_pointer: String,
+    // Answers are posted here when it is not empty, otherwise they are the response of the request
+    #[serde(rename = "replyUrl", default)]
+    pub(crate) reply_url: String,
+    #[serde(rename = "timeoutMilliseconds", default = "default_timeout")]
+    pub(crate) timeout_milliseconds: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_telegram_api() -> String {
+    String::from("https://api.telegram.org")
+}
+
+fn default_slack_api() -> String {
+    String::from("https://slack.com/api")
+}
+
+fn default_timeout() -> u64 {
+    5000
+}
+
+// A message translated from a platform webhook
+pub(crate) struct InboundMessage {
+    pub(crate) user_id: String,
+    pub(crate) user_name: String,
+    // Chat or channel the answers are sent to
+    pub(crate) conversation_id: String,
+    pub(crate) text: String,
+    pub(crate) intent: Option<String>,
+    pub(crate) locale: String,
+    // Telegram button clicks must be acknowledged
+    pub(crate) callback_id: Option<String>,
+}
+
+pub(crate) enum Inbound {
+    Message(InboundMessage),
+    // Slack URL verification handshake
+    Challenge(String),
+    // Bot messages, edits and other updates which are not answered
+    Ignored,
+}
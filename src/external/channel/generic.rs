@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use serde_json::Value;
+
+use super::dto::{ChannelAdapter, GenericJsonSettings, Inbound, InboundMessage};
+use super::inbound::{fresh, header, hmac_matches};
+use crate::external::webhook::delivery::{now, sign};
+use crate::flow::rt::dto::Response;
+use crate::result::{Error, Result};
+
+pub(super) fn verify(s: &GenericJsonSettings, headers: &HeaderMap, body: &[u8]) -> bool {
+    let (Some(timestamp), Some(signature)) = (
+        header(headers, "X-Dialogflow-Timestamp"),
+        header(headers, "X-Dialogflow-Signature"),
+    ) else {
+        return false;
+    };
+    fresh(timestamp)
+        && signature
+            .strip_prefix("sha256=")
+            .is_some_and(|sig| hmac_matches(&s.secret, &[timestamp.as_bytes(), b".", body], sig))
+}
+
+// Numbers and booleans are accepted too, e.g. numeric user ids
+fn value_at(v: &Value, pointer: &str) -> String {
+    if pointer.is_empty() {
+        return String::new();
+    }
+    match v.pointer(pointer) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(v) => v.to_string(),
+    }
+}
+
+pub(super) fn parse(s: &GenericJsonSettings, body: &[u8]) -> Result<Inbound> {
+    let v: Value = serde_json::from_slice(body)?;
+    let user_id = value_at(&v, &s.user_id_pointer);
+    if user_id.is_empty() {
+        return Err(Error::ErrorWithMessage(format!(
+            "User id was not found at {}",
+            &s.user_id_pointer
+        )));
+    }
+    let intent = value_at(&v, &s.intent_pointer);
+    Ok(Inbound::Message(InboundMessage {
+        conversation_id: user_id.clone(),
+        user_id,
+        user_name: value_at(&v, &s.user_name_pointer),
+        text: value_at(&v, &s.text_pointer),
+        intent: Some(intent).filter(|i| !i.is_empty()),
+        locale: value_at(&v, &s.locale_pointer),
+        callback_id: None,
+    }))
+}
+
+// The response of the flow, rich answers are left to the receiver
+pub(super) fn payload(adapter: &ChannelAdapter, m: &InboundMessage, res: &Response) -> Value {
+    let mut v = serde_json::to_value(res).unwrap_or_default();
+    v["adapterId"] = Value::from(adapter.id.as_str());
+    v["userId"] = Value::from(m.user_id.as_str());
+    v
+}
+
+pub(super) async fn reply(
+    s: &GenericJsonSettings,
+    adapter: &ChannelAdapter,
+    m: &InboundMessage,
+    res: &Response,
+) -> Result<()> {
+    let body = payload(adapter, m, res).to_string();
+    let timestamp = now();
+    let client =
+        crate::external::http::get_client(s.timeout_milliseconds, s.timeout_milliseconds, "")?;
+    let r = client
+        .post(&s.reply_url)
+        .timeout(Duration::from_millis(s.timeout_milliseconds))
+        .header("Content-Type", "application/json")
+        .header("X-Dialogflow-Timestamp", timestamp)
+        .header("X-Dialogflow-Signature", sign(&s.secret, timestamp, &body)?)
+        .body(body)
+        .send()
+        .await?;
+    if r.status().is_success() {
+        Ok(())
+    } else {
+        Err(Error::ErrorWithMessage(format!(
+            "Reply URL responded {}",
+            r.status()
+        )))
+    }
+}
@@ -0,0 +1,173 @@
+use axum::body::Bytes;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::dto::{ChannelAdapter, ChannelPlatform, Inbound, InboundMessage};
+use super::{generic, slack, telegram};
+use crate::flow::rt::dto::{
+    AnswerData, AnswerType, QuickReply, Request, Response, UserInputResult,
+};
+use crate::flow::rt::executor;
+use crate::result::Result;
+use crate::variable::dto::{SimpleVariable, VariableType};
+
+// Signed requests older than this are rejected as replays
+const MAX_CLOCK_SKEW_SECS: u64 = 300;
+
+pub(super) fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0u8, |r, (x, y)| r | (x ^ y)) == 0
+}
+
+pub(super) fn fresh(timestamp: &str) -> bool {
+    timestamp
+        .parse::<u64>()
+        .is_ok_and(|t| crate::external::webhook::delivery::now().abs_diff(t) <= MAX_CLOCK_SKEW_SECS)
+}
+
+// Checks hex encoded HMAC-SHA256 of the concatenated parts
+pub(super) fn hmac_matches(secret: &str, parts: &[&[u8]], signature: &str) -> bool {
+    let Ok(expected) = hex::decode(signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    for p in parts.iter() {
+        mac.update(p);
+    }
+    mac.verify_slice(&expected).is_ok()
+}
+
+// Buttons with an intent carry both, e.g. `["yes","confirm"]`, otherwise only the value
+pub(super) fn button_payload(r: &QuickReply) -> String {
+    if r.intent_name.is_empty() {
+        r.value.clone()
+    } else {
+        serde_json::json!([&r.value, &r.intent_name]).to_string()
+    }
+}
+
+// Returns the user input and the intent of a clicked button
+pub(super) fn parse_button_payload(payload: &str) -> (String, Option<String>) {
+    if payload.starts_with('[') {
+        if let Ok((value, intent)) = serde_json::from_str::<(String, String)>(payload) {
+            return (value, Some(intent).filter(|i| !i.is_empty()));
+        }
+    }
+    (String::from(payload), None)
+}
+
+pub(super) fn answer_text(a: &AnswerData) -> String {
+    match a.answer_type {
+        AnswerType::TextHtml => crate::external::email::smtp::html_to_text(&a.text),
+        _ => a.text.trim().to_string(),
+    }
+}
+
+// Every platform user talks in their own session
+fn session_id(robot_id: &str, adapter_id: &str, user_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    for p in [robot_id, adapter_id, user_id] {
+        hasher.update(p.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("channel-{}", &hex::encode(hasher.finalize())[..24])
+}
+
+fn variable(name: &str, value: &str) -> SimpleVariable {
+    SimpleVariable {
+        var_name: String::from(name),
+        var_type: VariableType::Str,
+        var_val: String::from(value),
+    }
+}
+
+pub(super) async fn converse(
+    robot_id: &str,
+    adapter: &ChannelAdapter,
+    m: &InboundMessage,
+) -> Result<Response> {
+    let mut req = Request {
+        robot_id: String::from(robot_id),
+        main_flow_id: adapter.main_flow_id.clone(),
+        session_id: session_id(robot_id, &adapter.id, &m.user_id),
+        user_input_result: UserInputResult::Successful,
+        user_input: m.text.clone(),
+        import_variables: vec![
+            variable("channel_platform", adapter.platform.name()),
+            variable("channel_user_id", &m.user_id),
+            variable("channel_user_name", &m.user_name),
+        ],
+        user_input_intent: m.intent.clone(),
+        locale: m.locale.clone(),
+        // Ids of different platforms may collide
+        user_id: format!("{}:{}", &adapter.id, &m.user_id),
+    };
+    executor::process(&mut req).await
+}
+
+async fn answer(robot_id: String, adapter: ChannelAdapter, m: InboundMessage) {
+    let r = match converse(&robot_id, &adapter, &m).await {
+        Ok(res) => match &adapter.platform {
+            ChannelPlatform::Telegram(s) => telegram::reply(s, &m, &res).await,
+            ChannelPlatform::Slack(s) => slack::reply(s, &m, &res).await,
+            ChannelPlatform::GenericJson(s) => generic::reply(s, &adapter, &m, &res).await,
+        },
+        Err(e) => Err(e),
+    };
+    if let Err(e) = r {
+        log::error!(
+            "Answering message of channel adapter {} failed: {:?}",
+            &adapter.id,
+            &e
+        );
+    }
+}
+
+pub(crate) async fn receive(
+    robot_id: &str,
+    adapter: ChannelAdapter,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> HttpResponse {
+    let verified = match &adapter.platform {
+        ChannelPlatform::Telegram(s) => telegram::verify(s, headers),
+        ChannelPlatform::Slack(s) => slack::verify(s, headers, body),
+        ChannelPlatform::GenericJson(s) => generic::verify(s, headers, body),
+    };
+    if !verified {
+        log::warn!(
+            "Rejected a request of channel adapter {} with invalid signature",
+            &adapter.id
+        );
+        return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response();
+    }
+    let inbound = match &adapter.platform {
+        ChannelPlatform::Telegram(_) => telegram::parse(body),
+        ChannelPlatform::Slack(_) => slack::parse(headers, body),
+        ChannelPlatform::GenericJson(s) => generic::parse(s, body),
+    };
+    let m = match inbound {
+        Ok(Inbound::Message(m)) => m,
+        Ok(Inbound::Challenge(c)) => return c.into_response(),
+        Ok(Inbound::Ignored) => return StatusCode::OK.into_response(),
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{:?}", e)).into_response(),
+    };
+    if let ChannelPlatform::GenericJson(s) = &adapter.platform {
+        if s.reply_url.is_empty() {
+            return match converse(robot_id, &adapter, &m).await {
+                Ok(res) => axum::Json(generic::payload(&adapter, &m, &res)).into_response(),
+                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)).into_response(),
+            };
+        }
+    }
+    // Platforms resend webhooks which are not acknowledged in a few seconds, so answers are sent later
+    tokio::spawn(answer(String::from(robot_id), adapter, m));
+    StatusCode::OK.into_response()
+}
@@ -0,0 +1,6 @@
+pub(crate) mod crud;
+pub(crate) mod dto;
+pub(crate) mod generic;
+pub(crate) mod inbound;
+pub(crate) mod slack;
+pub(crate) mod telegram;
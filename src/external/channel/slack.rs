@@ -0,0 +1,230 @@
+use axum::http::HeaderMap;
+use serde_json::{json, Value};
+
+use super::dto::{Inbound, InboundMessage, SlackSettings};
+use super::inbound::{
+    answer_text, button_payload, fresh, header, hmac_matches, parse_button_payload,
+};
+use crate::flow::rt::dto::{AnswerData, Card, QuickReply, Response, RichContent};
+use crate::result::{Error, Result};
+
+const CONNECT_TIMEOUT_MILLIS: u64 = 5000;
+const READ_TIMEOUT_MILLIS: u64 = 10000;
+
+// `X-Slack-Signature` is `v0=` and HMAC-SHA256 of `v0:{timestamp}:{body}`
+pub(super) fn verify(s: &SlackSettings, headers: &HeaderMap, body: &[u8]) -> bool {
+    let (Some(timestamp), Some(signature)) = (
+        header(headers, "X-Slack-Request-Timestamp"),
+        header(headers, "X-Slack-Signature"),
+    ) else {
+        return false;
+    };
+    fresh(timestamp)
+        && signature.strip_prefix("v0=").is_some_and(|sig| {
+            hmac_matches(
+                &s.signing_secret,
+                &[b"v0:", timestamp.as_bytes(), b":", body],
+                sig,
+            )
+        })
+}
+
+fn str_at<'a>(v: &'a Value, pointer: &str) -> &'a str {
+    v.pointer(pointer)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+// Button clicks are posted as a form with a JSON `payload` field
+fn parse_interaction(body: &[u8]) -> Result<Inbound> {
+    let Some((_, payload)) = form_urlencoded::parse(body).find(|(k, _)| k == "payload") else {
+        return Ok(Inbound::Ignored);
+    };
+    let p: Value = serde_json::from_str(&payload)?;
+    // Link buttons have no value
+    let value = str_at(&p, "/actions/0/value");
+    if str_at(&p, "/type") != "block_actions" || value.is_empty() {
+        return Ok(Inbound::Ignored);
+    }
+    let (text, intent) = parse_button_payload(value);
+    Ok(Inbound::Message(InboundMessage {
+        user_id: String::from(str_at(&p, "/user/id")),
+        user_name: String::from(str_at(&p, "/user/username")),
+        conversation_id: String::from(str_at(&p, "/channel/id")),
+        text,
+        intent,
+        locale: String::new(),
+        callback_id: None,
+    }))
+}
+
+pub(super) fn parse(headers: &HeaderMap, body: &[u8]) -> Result<Inbound> {
+    // Answers of the first delivery are sent already
+    if header(headers, "X-Slack-Retry-Num").is_some() {
+        return Ok(Inbound::Ignored);
+    }
+    if body.starts_with(b"payload=") {
+        return parse_interaction(body);
+    }
+    let v: Value = serde_json::from_slice(body)?;
+    match str_at(&v, "/type") {
+        "url_verification" => Ok(Inbound::Challenge(String::from(str_at(&v, "/challenge")))),
+        "event_callback" => {
+            let e = &v["event"];
+            // Messages of bots, including this one, and edits have `bot_id` or `subtype`
+            if str_at(e, "/type") != "message"
+                || e.get("bot_id").is_some()
+                || e.get("subtype").is_some()
+            {
+                return Ok(Inbound::Ignored);
+            }
+            Ok(Inbound::Message(InboundMessage {
+                user_id: String::from(str_at(e, "/user")),
+                user_name: String::new(),
+                conversation_id: String::from(str_at(e, "/channel")),
+                text: String::from(str_at(e, "/text")),
+                intent: None,
+                locale: String::new(),
+                callback_id: None,
+            }))
+        }
+        _ => Ok(Inbound::Ignored),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn plain_text(text: &str) -> Value {
+    json!({"type": "plain_text", "text": text})
+}
+
+fn section(text: &str) -> Value {
+    json!({"type": "section", "text": plain_text(text)})
+}
+
+fn buttons(replies: &[QuickReply]) -> Vec<Value> {
+    replies
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            json!({
+                "type": "button",
+                "action_id": format!("reply_{}", i),
+                "text": plain_text(&r.label),
+                "value": button_payload(r),
+            })
+        })
+        .collect()
+}
+
+fn card(c: &Card, blocks: &mut Vec<Value>) {
+    let mut text = format!("*{}*", escape(&c.title));
+    if !c.description.is_empty() {
+        text.push('\n');
+        text.push_str(&escape(&c.description));
+    }
+    let mut s = json!({"type": "section", "text": {"type": "mrkdwn", "text": text}});
+    if !c.image_url.is_empty() {
+        s["accessory"] = json!({"type": "image", "image_url": &c.image_url, "alt_text": &c.title});
+    }
+    blocks.push(s);
+    let mut elements = buttons(&c.buttons);
+    if !c.link_url.is_empty() {
+        elements.push(json!({
+            "type": "button",
+            "action_id": "link",
+            "text": plain_text(&c.title),
+            "url": &c.link_url,
+        }));
+    }
+    if !elements.is_empty() {
+        blocks.push(json!({"type": "actions", "elements": elements}));
+    }
+}
+
+// Parameters of `chat.postMessage` except `channel`, `text` is shown in notifications
+fn message(a: &AnswerData) -> Option<Value> {
+    let Some(rich) = &a.rich_content else {
+        let text = answer_text(a);
+        return (!text.is_empty()).then(|| json!({ "text": text }));
+    };
+    let text = a.text.trim();
+    let mut blocks = Vec::new();
+    if !text.is_empty() {
+        blocks.push(section(text));
+    }
+    match &rich.content {
+        RichContent::QuickReplies(replies) => {
+            blocks.push(json!({"type": "actions", "elements": buttons(replies)}));
+        }
+        RichContent::Card(c) => card(c, &mut blocks),
+        RichContent::Carousel(cards) => {
+            for (i, c) in cards.iter().enumerate() {
+                if i > 0 {
+                    blocks.push(json!({"type": "divider"}));
+                }
+                card(c, &mut blocks);
+            }
+        }
+        RichContent::Attachment(attachment) => {
+            if attachment.mime_type.starts_with("image/") {
+                blocks.push(json!({
+                    "type": "image",
+                    "image_url": &attachment.url,
+                    "alt_text": &attachment.name,
+                }));
+            } else {
+                blocks.push(json!({
+                    "type": "section",
+                    "text": {
+                        "type": "mrkdwn",
+                        "text": format!("<{}|{}>", &attachment.url, escape(&attachment.name)),
+                    },
+                }));
+            }
+        }
+        // Slack can not share locations, users type them instead
+        RichContent::LocationRequest => {}
+    }
+    Some(json!({"text": &rich.fallback_text, "blocks": blocks}))
+}
+
+async fn post_message(s: &SlackSettings, payload: &Value) -> Result<()> {
+    let client =
+        crate::external::http::get_client(CONNECT_TIMEOUT_MILLIS, READ_TIMEOUT_MILLIS, "")?;
+    let body = client
+        .post(format!(
+            "{}/chat.postMessage",
+            s.api_base_url.trim_end_matches('/')
+        ))
+        .header("Content-Type", "application/json; charset=utf-8")
+        .bearer_auth(&s.bot_token)
+        .body(payload.to_string())
+        .send()
+        .await?
+        .text()
+        .await?;
+    let r: Value = serde_json::from_str(&body)?;
+    if r.get("ok").and_then(Value::as_bool).unwrap_or(false) {
+        Ok(())
+    } else {
+        Err(Error::ErrorWithMessage(format!(
+            "Slack chat.postMessage failed: {}",
+            r.get("error").and_then(Value::as_str).unwrap_or(&body)
+        )))
+    }
+}
+
+pub(super) async fn reply(s: &SlackSettings, m: &InboundMessage, res: &Response) -> Result<()> {
+    for a in res.answers.iter() {
+        if let Some(mut payload) = message(a) {
+            payload["channel"] = json!(&m.conversation_id);
+            post_message(s, &payload).await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use axum::http::HeaderMap;
+use serde_json::{json, Value};
+
+use super::dto::{Inbound, InboundMessage, TelegramSettings};
+use super::inbound::{answer_text, button_payload, constant_time_eq, header, parse_button_payload};
+use crate::flow::rt::dto::{AnswerData, Card, QuickReply, Response, RichContent};
+use crate::result::{Error, Result};
+
+const CONNECT_TIMEOUT_MILLIS: u64 = 5000;
+const READ_TIMEOUT_MILLIS: u64 = 10000;
+// Limit of `callback_data` of inline keyboard buttons
+const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+pub(super) fn verify(s: &TelegramSettings, headers: &HeaderMap) -> bool {
+    header(headers, "X-Telegram-Bot-Api-Secret-Token")
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), s.secret_token.as_bytes()))
+}
+
+fn id_str(v: Option<&Value>) -> String {
+    match v {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(v) => v.to_string(),
+    }
+}
+
+fn message(from: &Value, chat_id: Option<&Value>, text: String) -> InboundMessage {
+    let user_name = ["username", "first_name"]
+        .iter()
+        .find_map(|k| from.get(k).and_then(Value::as_str))
+        .unwrap_or_default();
+    InboundMessage {
+        user_id: id_str(from.get("id")),
+        user_name: String::from(user_name),
+        conversation_id: id_str(chat_id),
+        text,
+        intent: None,
+        locale: id_str(from.get("language_code")),
+        callback_id: None,
+    }
+}
+
+pub(super) fn parse(body: &[u8]) -> Result<Inbound> {
+    let update: Value = serde_json::from_slice(body)?;
+    if let Some(q) = update.get("callback_query") {
+        let Some(from) = q.get("from") else {
+            return Ok(Inbound::Ignored);
+        };
+        let data = q.get("data").and_then(Value::as_str).unwrap_or_default();
+        let (text, intent) = parse_button_payload(data);
+        let mut m = message(from, q.pointer("/message/chat/id"), text);
+        m.intent = intent;
+        m.callback_id = Some(id_str(q.get("id")));
+        return Ok(Inbound::Message(m));
+    }
+    let Some(msg) = update.get("message") else {
+        return Ok(Inbound::Ignored);
+    };
+    let Some(from) = msg.get("from") else {
+        return Ok(Inbound::Ignored);
+    };
+    if from.get("is_bot").and_then(Value::as_bool).unwrap_or(false) {
+        return Ok(Inbound::Ignored);
+    }
+    let text = if let Some(t) = msg.get("text").and_then(Value::as_str) {
+        String::from(t)
+    } else if let Some(l) = msg.get("location") {
+        // Answer of location requests
+        format!("{},{}", &l["latitude"], &l["longitude"])
+    } else if let Some(t) = msg.get("caption").and_then(Value::as_str) {
+        String::from(t)
+    } else {
+        return Ok(Inbound::Ignored);
+    };
+    Ok(Inbound::Message(message(
+        from,
+        msg.pointer("/chat/id"),
+        text,
+    )))
+}
+
+fn callback_data(r: &QuickReply) -> String {
+    let payload = button_payload(r);
+    if payload.len() <= MAX_CALLBACK_DATA_LEN {
+        return payload;
+    }
+    // Long values lose the intent and are cut
+    let mut end = r.value.len().min(MAX_CALLBACK_DATA_LEN);
+    while !r.value.is_char_boundary(end) {
+        end -= 1;
+    }
+    String::from(&r.value[..end])
+}
+
+fn buttons(replies: &[QuickReply]) -> Vec<Vec<Value>> {
+    replies
+        .iter()
+        .map(|r| vec![json!({"text": &r.label, "callback_data": callback_data(r)})])
+        .collect()
+}
+
+fn card(c: &Card) -> (&'static str, Value) {
+    let mut text = c.title.clone();
+    if !c.description.is_empty() {
+        text.push('\n');
+        text.push_str(&c.description);
+    }
+    let mut rows = buttons(&c.buttons);
+    if !c.link_url.is_empty() {
+        rows.push(vec![json!({"text": &c.title, "url": &c.link_url})]);
+    }
+    let (method, mut payload) = if c.image_url.is_empty() {
+        ("sendMessage", json!({"text": text}))
+    } else {
+        ("sendPhoto", json!({"photo": &c.image_url, "caption": text}))
+    };
+    if !rows.is_empty() {
+        payload["reply_markup"] = json!({"inline_keyboard": rows});
+    }
+    (method, payload)
+}
+
+// Bot API methods and their parameters, except `chat_id`
+fn messages(a: &AnswerData) -> Vec<(&'static str, Value)> {
+    let Some(rich) = &a.rich_content else {
+        let text = answer_text(a);
+        if text.is_empty() {
+            return Vec::new();
+        }
+        return vec![("sendMessage", json!({ "text": text }))];
+    };
+    let text = a.text.trim();
+    let mut r = Vec::new();
+    match &rich.content {
+        RichContent::QuickReplies(replies) => {
+            // Telegram rejects empty messages
+            let text = if text.is_empty() {
+                rich.fallback_text.as_str()
+            } else {
+                text
+            };
+            r.push((
+                "sendMessage",
+                json!({"text": text, "reply_markup": {"inline_keyboard": buttons(replies)}}),
+            ));
+        }
+        RichContent::Card(c) => {
+            if !text.is_empty() {
+                r.push(("sendMessage", json!({ "text": text })));
+            }
+            r.push(card(c));
+        }
+        RichContent::Carousel(cards) => {
+            if !text.is_empty() {
+                r.push(("sendMessage", json!({ "text": text })));
+            }
+            r.extend(cards.iter().map(card));
+        }
+        RichContent::Attachment(attachment) => {
+            let (method, field) = if attachment.mime_type.starts_with("image/") {
+                ("sendPhoto", "photo")
+            } else {
+                ("sendDocument", "document")
+            };
+            let mut payload = json!({ field: &attachment.url });
+            if !text.is_empty() {
+                payload["caption"] = json!(text);
+            }
+            r.push((method, payload));
+        }
+        RichContent::LocationRequest => {
+            let text = if text.is_empty() { "📍" } else { text };
+            r.push((
+                "sendMessage",
+                json!({
+                    "text": text,
+                    "reply_markup": {
+                        "keyboard": [[{"text": "📍", "request_location": true}]],
+                        "one_time_keyboard": true,
+                        "resize_keyboard": true,
+                    },
+                }),
+            ));
+        }
+    }
+    r
+}
+
+async fn call(s: &TelegramSettings, method: &str, payload: &Value) -> Result<()> {
+    let client =
+        crate::external::http::get_client(CONNECT_TIMEOUT_MILLIS, READ_TIMEOUT_MILLIS, "")?;
+    let url = format!(
+        "{}/bot{}/{}",
+        s.api_base_url.trim_end_matches('/'),
+        &s.bot_token,
+        method
+    );
+    // Errors of reqwest contain the URL, which contains the bot token
+    let body = client
+        .post(&url)
+        .header("Content-Type", "application/json")
+        .body(payload.to_string())
+        .send()
+        .await
+        .map_err(|e| {
+            Error::ErrorWithMessage(format!("Calling {} failed: {}", method, e.without_url()))
+        })?
+        .text()
+        .await
+        .map_err(|e| {
+            Error::ErrorWithMessage(format!("Reading {} failed: {}", method, e.without_url()))
+        })?;
+    let r: Value = serde_json::from_str(&body)?;
+    if r.get("ok").and_then(Value::as_bool).unwrap_or(false) {
+        Ok(())
+    } else {
+        Err(Error::ErrorWithMessage(format!(
+            "Telegram {} failed: {}",
+            method,
+            r.get("description")
+                .and_then(Value::as_str)
+                .unwrap_or(&body)
+        )))
+    }
+}
+
+pub(super) async fn reply(s: &TelegramSettings, m: &InboundMessage, res: &Response) -> Result<()> {
+    if let Some(id) = &m.callback_id {
+        // Stops the loading indicator of the button
+        if let Err(e) = call(
+            s,
+            "answerCallbackQuery",
+            &json!({ "callback_query_id": id }),
+        )
+        .await
+        {
+            log::warn!("Answering Telegram callback query failed: {:?}", &e);
+        }
+    }
+    for a in res.answers.iter() {
+        for (method, mut payload) in messages(a) {
+            payload["chat_id"] = json!(&m.conversation_id);
+            call(s, method, &payload).await?;
+        }
+    }
+    Ok(())
+}
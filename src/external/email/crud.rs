@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use axum::extract::{Multipart, Query};
+use axum::response::IntoResponse;
+use utoipa::TupleUnit;
+
+use super::dto::{
+    AttachmentData, EmailOutboxEntry, EmailPollResult, EmailStatus, UploadedAttachment,
+};
+use super::outbox::TABLE_SUFFIX;
+use crate::db;
+use crate::db_executor;
+use crate::result::{Error, Result};
+use crate::web::openapi::FileUpload;
+use crate::web::server::{to_res, ResponseData};
+
+const DEFAULT_LOG_LIMIT: usize = 100;
+
+fn robot_id(q: &HashMap<String, String>) -> Result<&String> {
+    q.get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))
+}
+
+fn attachments_dir(robot_id: &str) -> Result<PathBuf> {
+    if !crate::robot::crud::is_robot_id(robot_id) {
+        return Err(Error::ErrorWithMessage(format!(
+            "Invalid robot id: {}",
+            robot_id
+        )));
+    }
+    Ok(Path::new(".")
+        .join("data")
+        .join(robot_id)
+        .join("email")
+        .join("attachments"))
+}
+
+// Ids are generated by us, so they never contain path separators
+fn attachment_dir(robot_id: &str, id: &str) -> Result<PathBuf> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error::ErrorWithMessage(format!(
+            "Invalid attachment id: {}",
+            id
+        )));
+    }
+    Ok(attachments_dir(robot_id)?.join(id))
+}
+
+fn uploaded(dir: &Path, id: &str) -> Result<Option<UploadedAttachment>> {
+    if !dir.exists() {
+        return Ok(None);
+    }
+    let Some(entry) = std::fs::read_dir(dir)?.next().transpose()? else {
+        return Ok(None);
+    };
+    Ok(Some(UploadedAttachment {
+        id: String::from(id),
+        file_name: entry.file_name().to_string_lossy().to_string(),
+        size: entry.metadata()?.len(),
+    }))
+}
+
+pub(crate) fn read_attachment(robot_id: &str, id: &str, file_name: &str) -> Result<AttachmentData> {
+    let dir = attachment_dir(robot_id, id)?;
+    let Some(a) = uploaded(&dir, id)? else {
+        return Err(Error::ErrorWithMessage(format!(
+            "Attachment {} was not found",
+            id
+        )));
+    };
+    let name = if file_name.is_empty() {
+        a.file_name.clone()
+    } else {
+        String::from(file_name)
+    };
+    Ok(AttachmentData {
+        content_type: String::from(super::smtp::guess_content_type(&name)),
+        content: std::fs::read(dir.join(&a.file_name))?,
+        file_name: name,
+    })
+}
+
+async fn save_attachment(robot_id: &str, mut multipart: Multipart) -> Result<UploadedAttachment> {
+    let Some(field) = multipart.next_field().await? else {
+        return Err(Error::ErrorWithMessage(String::from("File not found.")));
+    };
+    let Some(file_name) = field.file_name() else {
+        return Err(Error::ErrorWithMessage(String::from(
+            "File name is missing.",
+        )));
+    };
+    // Drops any directory part sent by the browser
+    let file_name = Path::new(file_name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.is_empty() && n != "..")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Invalid file name.")))?;
+    let data = field.bytes().await?;
+    let id = scru128::new_string();
+    let dir = attachment_dir(robot_id, &id)?;
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(&file_name), &data)?;
+    Ok(UploadedAttachment {
+        id,
+        file_name,
+        size: data.len() as u64,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/email/attachment",
+    tag = "email",
+    summary = "Upload an attachment",
+    params(("robotId" = String, Query)),
+    request_body(content = inline(FileUpload), content_type = "multipart/form-data"),
+    responses((status = 200, body = ResponseData<UploadedAttachment>))
+)]
+pub(crate) async fn upload_attachment(
+    Query(q): Query<HashMap<String, String>>,
+    multipart: Multipart,
+) -> impl IntoResponse {
+    match robot_id(&q) {
+        Ok(robot_id) => to_res(save_attachment(robot_id, multipart).await),
+        Err(e) => to_res(Err(e)),
+    }
+}
+
+fn list_uploaded(robot_id: &str) -> Result<Vec<UploadedAttachment>> {
+    let root = attachments_dir(robot_id)?;
+    let mut r = Vec::new();
+    if !root.exists() {
+        return Ok(r);
+    }
+    for entry in std::fs::read_dir(&root)? {
+        let entry = entry?;
+        let id = entry.file_name().to_string_lossy().to_string();
+        if let Some(a) = uploaded(&entry.path(), &id)? {
+            r.push(a);
+        }
+    }
+    r.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(r)
+}
+
+#[utoipa::path(
+    get,
+    path = "/email/attachment",
+    tag = "email",
+    summary = "List uploaded attachments",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<UploadedAttachment>>))
+)]
+pub(crate) async fn list_attachments(
+    Query(q): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    to_res(robot_id(&q).and_then(|robot_id| list_uploaded(robot_id)))
+}
+
+fn remove_uploaded(q: &HashMap<String, String>) -> Result<()> {
+    let robot_id = robot_id(q)?;
+    let id = q
+        .get("id")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: id is missing.")))?;
+    let dir = attachment_dir(robot_id, id)?;
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/email/attachment",
+    tag = "email",
+    summary = "Delete an uploaded attachment",
+    params(("robotId" = String, Query), ("id" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn delete_attachment(
+    Query(q): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    to_res(remove_uploaded(&q))
+}
+
+pub(crate) fn delete_all(robot_id: &str) -> Result<()> {
+    super::outbox::delete_tables(robot_id)?;
+    let dir = attachments_dir(robot_id)?;
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+fn list_outbox(q: &HashMap<String, String>) -> Result<Vec<EmailOutboxEntry>> {
+    let robot_id = robot_id(q)?;
+    let status = match q.get("status").map(|s| s.as_str()) {
+        Some("Pending") => Some(EmailStatus::Pending),
+        Some("Sent") => Some(EmailStatus::Sent),
+        Some("Failed") => Some(EmailStatus::Failed),
+        Some(s) if !s.is_empty() => {
+            return Err(Error::ErrorWithMessage(format!("Unknown status: {}", s)));
+        }
+        _ => None,
+    };
+    let limit = q
+        .get("limit")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LOG_LIMIT);
+    let r: Vec<EmailOutboxEntry> = match db_executor!(db::get_all, robot_id, TABLE_SUFFIX,) {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Vec::new(),
+        r => r?,
+    };
+    // Ids are time ordered, so the newest emails come first
+    Ok(r.into_iter()
+        .rev()
+        .filter(|e| q.get("sessionId").is_none_or(|id| e.session_id.eq(id)))
+        .filter(|e| status.is_none_or(|s| e.status == s))
+        .take(limit)
+        .collect())
+}
+
+#[utoipa::path(
+    post,
+    path = "/email/channel/poll",
+    tag = "email",
+    summary = "Poll the mailbox of the robot now",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<EmailPollResult>))
+)]
+pub(crate) async fn poll(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    match robot_id(&q) {
+        Ok(robot_id) => to_res(super::inbound::poll(robot_id).await),
+        Err(e) => to_res(Err(e)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/email/outbox",
+    tag = "email",
+    summary = "List sent and queued emails, newest first",
+    params(
+        ("robotId" = String, Query),
+        ("sessionId" = Option<String>, Query),
+        ("status" = Option<String>, Query),
+        ("limit" = Option<String>, Query),
+    ),
+    responses((status = 200, body = ResponseData<Vec<EmailOutboxEntry>>))
+)]
+pub(crate) async fn outbox(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(list_outbox(&q))
+}
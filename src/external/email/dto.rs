@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct AttachmentData {
+    pub(crate) file_name: String,
+    pub(crate) content_type: String,
+    pub(crate) content: Vec<u8>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct OutgoingEmail {
+    pub(crate) from: String,
+    pub(crate) to: Vec<String>,
+    pub(crate) cc: Vec<String>,
+    pub(crate) bcc: Vec<String>,
+    pub(crate) subject: String,
+    pub(crate) text_body: String,
+    // Sent as multipart/alternative together with the text body
+    pub(crate) html_body: Option<String>,
+    pub(crate) attachments: Vec<AttachmentData>,
+    // Threading headers of replies, message ids are without angle brackets
+    #[serde(default)]
+    pub(crate) in_reply_to: Option<String>,
+    #[serde(default)]
+    pub(crate) references: Vec<String>,
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub(crate) enum EmailStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct EmailOutboxEntry {
+    pub(crate) id: String,
+    #[serde(rename = "sessionId")]
+    pub(crate) session_id: String,
+    pub(crate) to: Vec<String>,
+    pub(crate) subject: String,
+    // File names
+    pub(crate) attachments: Vec<String>,
+    #[serde(rename = "asyncSend")]
+    pub(crate) async_send: bool,
+    pub(crate) status: EmailStatus,
+    pub(crate) attempts: u32,
+    // Unix timestamps in seconds
+    #[serde(rename = "nextAttemptAt")]
+    pub(crate) next_attempt_at: u64,
+    #[serde(rename = "lastError")]
+    pub(crate) last_error: String,
+    #[serde(rename = "createdAt")]
+    pub(crate) created_at: u64,
+    #[serde(rename = "updatedAt")]
+    pub(crate) updated_at: u64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct UploadedAttachment {
+    pub(crate) id: String,
+    #[serde(rename = "fileName")]
+    pub(crate) file_name: String,
+    pub(crate) size: u64,
+}
+
+// Robots answer emails of the mailbox when it is enabled, replies are sent by the SMTP settings
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct EmailChannel {
+    pub(crate) enabled: bool,
+    #[serde(rename = "imapHost")]
+    pub(crate) imap_host: String,
+    #[serde(rename = "imapPort")]
+    pub(crate) imap_port: u16,
+    #[serde(rename = "imapTls")]
+    pub(crate) imap_tls: bool,
+    #[serde(rename = "imapUsername")]
+    pub(crate) imap_username: String,
+    #[serde(rename = "imapPassword")]
+    pub(crate) imap_password: String,
+    pub(crate) mailbox: String,
+    // Flow started by a new thread
+    #[serde(rename = "mainFlowId")]
+    pub(crate) main_flow_id: String,
+    #[serde(rename = "pollIntervalSec")]
+    pub(crate) poll_interval_sec: u32,
+    // SMTP username is used when it is empty
+    #[serde(rename = "replyFrom", default)]
+    pub(crate) reply_from: String,
+}
+
+impl Default for EmailChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            imap_host: String::new(),
+            imap_port: 993,
+            imap_tls: true,
+            imap_username: String::new(),
+            imap_password: String::new(),
+            mailbox: String::from("INBOX"),
+            main_flow_id: String::new(),
+            poll_interval_sec: 60,
+            reply_from: String::new(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, utoipa::ToSchema)]
+pub(crate) struct EmailPollResult {
+    pub(crate) received: u32,
+    pub(crate) answered: u32,
+    // Auto replies, messages sent by the robot itself and empty messages
+    pub(crate) skipped: u32,
+    pub(crate) failed: u32,
+}
@@ -0,0 +1,263 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+
+use super::dto::EmailChannel;
+use crate::result::{Error, Result};
+
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+// Protects from a broken server sending endless lines
+const MAX_LINE_LEN: usize = 64 * 1024;
+const MAX_LITERAL_LEN: usize = 32 * 1024 * 1024;
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+// An untagged response line, the contents of its literals are moved to `literals`
+pub(crate) struct ImapResponse {
+    pub(crate) line: String,
+    pub(crate) literals: Vec<Vec<u8>>,
+}
+
+// Minimal IMAP4rev1 client, only commands used by the email channel are supported
+pub(crate) struct ImapSession {
+    stream: BufStream<Box<dyn Stream>>,
+    tag: u32,
+}
+
+fn err(m: impl Into<String>) -> Error {
+    Error::ErrorWithMessage(m.into())
+}
+
+// Quoted strings can not hold line breaks, which would also let a value inject further commands
+fn quote(s: &str) -> Result<String> {
+    if s.chars().any(|c| c.is_control()) {
+        return Err(err("IMAP arguments can not contain control characters"));
+    }
+    Ok(format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    ))
+}
+
+// Size of the literal at the end of the line, e.g. `* 1 FETCH (UID 5 BODY[] {1024}`
+fn literal_len(line: &str) -> Option<usize> {
+    let s = line.strip_suffix('}')?;
+    let start = s.rfind('{')?;
+    s[start + 1..].trim_end_matches('+').parse().ok()
+}
+
+impl ImapSession {
+    pub(crate) async fn connect(c: &EmailChannel) -> Result<Self> {
+        let addr = format!("{}:{}", &c.imap_host, c.imap_port);
+        let tcp = tokio::time::timeout(
+            Duration::from_secs(CONNECT_TIMEOUT_SECS),
+            TcpStream::connect(&addr),
+        )
+        .await
+        .map_err(|_| err(format!("Connecting {} timed out", &addr)))??;
+        let stream: Box<dyn Stream> = if c.imap_tls {
+            let connector = tokio_native_tls::native_tls::TlsConnector::new()
+                .map_err(|e| err(format!("TLS failed: {:?}", e)))?;
+            let tls = tokio_native_tls::TlsConnector::from(connector)
+                .connect(&c.imap_host, tcp)
+                .await
+                .map_err(|e| err(format!("TLS handshake with {} failed: {:?}", &addr, e)))?;
+            Box::new(tls)
+        } else {
+            Box::new(tcp)
+        };
+        let mut s = Self {
+            stream: BufStream::new(stream),
+            tag: 0,
+        };
+        let greeting = s.read_response().await?;
+        if !greeting.line.starts_with("* OK") && !greeting.line.starts_with("* PREAUTH") {
+            return Err(err(format!("Unexpected IMAP greeting: {}", &greeting.line)));
+        }
+        Ok(s)
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut buf = Vec::with_capacity(256);
+        let n = (&mut self.stream)
+            .take(MAX_LINE_LEN as u64)
+            .read_until(b'\n', &mut buf)
+            .await?;
+        if n == 0 {
+            return Err(err("IMAP connection was closed"));
+        }
+        if buf.last() != Some(&b'\n') {
+            return Err(err("IMAP response line was too long"));
+        }
+        Ok(String::from_utf8_lossy(&buf).trim_end().to_string())
+    }
+
+    async fn read_response(&mut self) -> Result<ImapResponse> {
+        let mut r = ImapResponse {
+            line: String::new(),
+            literals: Vec::new(),
+        };
+        loop {
+            let line = self.read_line().await?;
+            let len = literal_len(&line);
+            r.line.push_str(&line);
+            let Some(len) = len else {
+                return Ok(r);
+            };
+            if len > MAX_LITERAL_LEN {
+                return Err(err(format!("IMAP literal of {} bytes was too large", len)));
+            }
+            let mut literal = vec![0u8; len];
+            self.stream.read_exact(&mut literal).await?;
+            r.literals.push(literal);
+        }
+    }
+
+    // Returns untagged responses, fails when the server does not respond OK
+    pub(crate) async fn command(&mut self, command: &str) -> Result<Vec<ImapResponse>> {
+        self.tag += 1;
+        let tag = format!("A{}", self.tag);
+        self.stream
+            .write_all(format!("{} {}\r\n", &tag, command).as_bytes())
+            .await?;
+        self.stream.flush().await?;
+        let mut responses = Vec::new();
+        loop {
+            let r = self.read_response().await?;
+            if let Some(status) = r.line.strip_prefix(&tag) {
+                let status = status.trim_start();
+                if status.starts_with("OK") {
+                    return Ok(responses);
+                }
+                // Passwords are never logged
+                let name = command.split(' ').next().unwrap_or(command);
+                return Err(err(format!("IMAP {} failed: {}", name, status)));
+            }
+            responses.push(r);
+        }
+    }
+
+    pub(crate) async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        self.command(&format!("LOGIN {} {}", quote(username)?, quote(password)?))
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn select(&mut self, mailbox: &str) -> Result<()> {
+        self.command(&format!("SELECT {}", quote(mailbox)?)).await?;
+        Ok(())
+    }
+
+    pub(crate) async fn search_unseen(&mut self) -> Result<Vec<u32>> {
+        let mut uids = Vec::new();
+        for r in self.command("UID SEARCH UNSEEN").await? {
+            if let Some(ids) = r.line.strip_prefix("* SEARCH") {
+                uids.extend(
+                    ids.split_whitespace()
+                        .filter_map(|id| id.parse::<u32>().ok()),
+                );
+            }
+        }
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    // Raw RFC 5322 message, `PEEK` leaves it unseen until it is processed
+    pub(crate) async fn fetch(&mut self, uid: u32) -> Result<Option<Vec<u8>>> {
+        let mut responses = self
+            .command(&format!("UID FETCH {} BODY.PEEK[]", uid))
+            .await?;
+        Ok(responses
+            .iter_mut()
+            .find(|r| r.line.contains("FETCH") && !r.literals.is_empty())
+            .map(|r| r.literals.swap_remove(0)))
+    }
+
+    pub(crate) async fn mark_seen(&mut self, uid: u32) -> Result<()> {
+        self.command(&format!("UID STORE {} +FLAGS.SILENT (\\Seen)", uid))
+            .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn logout(mut self) {
+        let _ = self.command("LOGOUT").await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // Replies to each expected command with its lines, fails on anything else
+    async fn stub(script: Vec<(&'static str, &'static str)>) -> EmailChannel {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (r, mut w) = socket.into_split();
+            let mut r = BufReader::new(r);
+            w.write_all(b"* OK stub ready\r\n").await.unwrap();
+            for (command, reply) in script {
+                let mut line = String::new();
+                r.read_line(&mut line).await.unwrap();
+                assert_eq!(line, format!("{}\r\n", command));
+                w.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        EmailChannel {
+            imap_host: String::from("127.0.0.1"),
+            imap_port: port,
+            imap_tls: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quote_rejects_control_characters() {
+        assert_eq!(quote(r#"a"b\c"#).unwrap(), r#""a\"b\\c""#);
+        assert!(quote("INBOX\r\nA2 DELETE INBOX").is_err());
+        assert!(quote("pass\0word").is_err());
+    }
+
+    #[tokio::test]
+    async fn fetch_unseen() -> Result<()> {
+        let c = stub(vec![
+            (
+                r#"A1 LOGIN "bot@example.com" "p\"w""#,
+                "A1 OK LOGIN completed\r\n",
+            ),
+            (
+                r#"A2 SELECT "INBOX""#,
+                "* 2 EXISTS\r\nA2 OK [READ-WRITE] SELECT completed\r\n",
+            ),
+            (
+                "A3 UID SEARCH UNSEEN",
+                "* SEARCH 9 4\r\nA3 OK SEARCH completed\r\n",
+            ),
+            (
+                "A4 UID FETCH 4 BODY.PEEK[]",
+                "* 1 FETCH (UID 4 BODY[] {7}\r\nHi\r\nBye)\r\nA4 OK FETCH completed\r\n",
+            ),
+            (
+                "A5 UID STORE 4 +FLAGS.SILENT (\\Seen)",
+                "A5 OK STORE completed\r\n",
+            ),
+            ("A6 SELECT \"Missing\"", "A6 NO Mailbox does not exist\r\n"),
+        ])
+        .await;
+        let mut s = ImapSession::connect(&c).await?;
+        s.login("bot@example.com", "p\"w").await?;
+        s.select("INBOX").await?;
+        assert_eq!(s.search_unseen().await?, vec![4, 9]);
+        assert_eq!(s.fetch(4).await?.as_deref(), Some(&b"Hi\r\nBye"[..]));
+        s.mark_seen(4).await?;
+        assert!(s.select("INBOX\r\nA7 LOGOUT").await.is_err());
+        assert!(s.select("Missing").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use mail_parser::MessageParser;
+use sha2::{Digest, Sha256};
+
+use super::dto::{EmailPollResult, OutgoingEmail};
+use super::imap::ImapSession;
+use crate::db;
+use crate::flow::rt::dto::{AnswerType, Request, UserInputResult};
+use crate::flow::rt::executor;
+use crate::man::settings::{self, Settings};
+use crate::result::{Error, Result};
+use crate::robot::dto::RobotData;
+use crate::variable::dto::{SimpleVariable, VariableType};
+
+const TICK_SECS: u64 = 10;
+const MIN_POLL_INTERVAL_SECS: u64 = 10;
+const POLL_TIMEOUT_SECS: u64 = 300;
+// The rest are fetched by the next poll
+const MAX_MESSAGES_PER_POLL: usize = 50;
+
+// Robots whose mailbox is being polled, the poll API and the poller never read a mailbox at the same time
+static POLLING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+struct PollingGuard(String);
+
+impl PollingGuard {
+    fn acquire(robot_id: &str) -> Result<Self> {
+        if !POLLING.lock()?.insert(String::from(robot_id)) {
+            return Err(Error::ErrorWithMessage(String::from(
+                "The mailbox is being polled",
+            )));
+        }
+        Ok(Self(String::from(robot_id)))
+    }
+}
+
+impl Drop for PollingGuard {
+    fn drop(&mut self) {
+        if let Ok(mut l) = POLLING.lock() {
+            l.remove(&self.0);
+        }
+    }
+}
+
+struct InboundEmail {
+    sender: String,
+    subject: String,
+    message_id: Option<String>,
+    // Ids of the earlier messages of the thread, the first one is the root
+    references: Vec<String>,
+    body: String,
+    auto_submitted: bool,
+}
+
+// Drops quoted text of replies, e.g. "> ..." lines and "On Mon, Bob wrote:" trailers
+fn strip_quoted(body: &str) -> String {
+    let mut lines = Vec::new();
+    for line in body.lines() {
+        let t = line.trim();
+        if t.starts_with("-----Original Message-----")
+            || (t.starts_with("On ") && t.ends_with("wrote:"))
+        {
+            break;
+        }
+        if !t.starts_with('>') {
+            lines.push(line.trim_end());
+        }
+    }
+    lines.join("\n").trim().to_string()
+}
+
+fn parse(raw: &[u8]) -> Option<InboundEmail> {
+    let m = MessageParser::default().parse(raw)?;
+    let sender = m
+        .reply_to()
+        .or_else(|| m.from())
+        .and_then(|a| a.first())
+        .and_then(|a| a.address())?
+        .to_string();
+    let mut references: Vec<String> = m
+        .references()
+        .as_text_list()
+        .map(|l| l.iter().map(|id| id.to_string()).collect())
+        .unwrap_or_default();
+    if references.is_empty() {
+        if let Some(id) = m.in_reply_to().as_text() {
+            references.push(String::from(id));
+        }
+    }
+    let auto_submitted = m
+        .header("Auto-Submitted")
+        .and_then(|h| h.as_text())
+        .is_some_and(|v| !v.trim().eq_ignore_ascii_case("no"));
+    Some(InboundEmail {
+        sender,
+        subject: m.subject().unwrap_or_default().to_string(),
+        message_id: m.message_id().map(String::from),
+        references,
+        // HTML only messages are converted to text by the parser
+        body: m.body_text(0).map(|b| strip_quoted(&b)).unwrap_or_default(),
+        auto_submitted,
+    })
+}
+
+// Every thread is a session, replies keep the root message id in `References`
+fn thread_session_id(robot_id: &str, email: &InboundEmail) -> String {
+    let root = email
+        .references
+        .first()
+        .or(email.message_id.as_ref())
+        .map_or_else(
+            || format!("{}\n{}", &email.sender, &email.subject),
+            String::from,
+        );
+    let mut hasher = Sha256::new();
+    hasher.update(robot_id.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(root.as_bytes());
+    format!("email-{}", &hex::encode(hasher.finalize())[..24])
+}
+
+fn reply_subject(subject: &str) -> String {
+    if subject
+        .get(..3)
+        .is_some_and(|p| p.eq_ignore_ascii_case("re:"))
+    {
+        String::from(subject)
+    } else {
+        format!("Re: {}", subject)
+    }
+}
+
+fn reply_from(settings: &Settings) -> &str {
+    if settings.email_channel.reply_from.is_empty() {
+        &settings.smtp_username
+    } else {
+        &settings.email_channel.reply_from
+    }
+}
+
+fn variable(name: &str, value: &str) -> SimpleVariable {
+    SimpleVariable {
+        var_name: String::from(name),
+        var_type: VariableType::Str,
+        var_val: String::from(value),
+    }
+}
+
+// Returns false when the robot had nothing to say
+async fn answer(robot_id: &str, settings: &Settings, email: InboundEmail) -> Result<bool> {
+    let session_id = thread_session_id(robot_id, &email);
+    let mut req = Request {
+        robot_id: String::from(robot_id),
+        main_flow_id: settings.email_channel.main_flow_id.clone(),
+        session_id,
+        user_input_result: UserInputResult::Successful,
+        user_input: email.body,
+        import_variables: vec![
+            variable("email_sender", &email.sender),
+            variable("email_subject", &email.subject),
+        ],
+        user_input_intent: None,
+        locale: String::new(),
+        user_id: email.sender.clone(),
+    };
+    let res = executor::process(&mut req).await?;
+    let texts: Vec<String> = res
+        .answers
+        .iter()
+        .map(|a| match a.answer_type {
+            AnswerType::TextHtml => super::smtp::html_to_text(&a.text),
+            _ => a.text.trim().to_string(),
+        })
+        .filter(|t| !t.is_empty())
+        .collect();
+    if texts.is_empty() {
+        return Ok(false);
+    }
+    let mut references = email.references;
+    if let Some(id) = &email.message_id {
+        references.push(id.clone());
+    }
+    let reply = OutgoingEmail {
+        from: String::from(reply_from(settings)),
+        to: vec![email.sender],
+        cc: Vec::new(),
+        bcc: Vec::new(),
+        subject: reply_subject(&email.subject),
+        text_body: texts.join("\n\n"),
+        html_body: None,
+        attachments: Vec::new(),
+        in_reply_to: email.message_id,
+        references,
+    };
+    // Outbox retries replies when SMTP server is unavailable
+    super::outbox::enqueue(robot_id, &req.session_id, &reply)?;
+    Ok(true)
+}
+
+pub(crate) async fn poll(robot_id: &str) -> Result<EmailPollResult> {
+    let Some(settings) = settings::get_settings(robot_id)? else {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Settings were not found",
+        )));
+    };
+    let c = &settings.email_channel;
+    if c.imap_host.is_empty() || c.main_flow_id.is_empty() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Email channel is not configured",
+        )));
+    }
+    let _guard = PollingGuard::acquire(robot_id)?;
+    let mut session = ImapSession::connect(c).await?;
+    if !c.imap_username.is_empty() {
+        session.login(&c.imap_username, &c.imap_password).await?;
+    }
+    session.select(&c.mailbox).await?;
+    let own_addresses = [reply_from(&settings), &c.imap_username];
+    let mut r = EmailPollResult::default();
+    for uid in session
+        .search_unseen()
+        .await?
+        .into_iter()
+        .take(MAX_MESSAGES_PER_POLL)
+    {
+        let Some(raw) = session.fetch(uid).await? else {
+            continue;
+        };
+        r.received += 1;
+        match parse(&raw) {
+            Some(email)
+                if !email.auto_submitted
+                    && !email.body.is_empty()
+                    && !own_addresses
+                        .iter()
+                        .any(|a| a.eq_ignore_ascii_case(&email.sender)) =>
+            {
+                match answer(robot_id, &settings, email).await {
+                    Ok(true) => r.answered += 1,
+                    Ok(false) => r.skipped += 1,
+                    Err(e) => {
+                        r.failed += 1;
+                        log::error!("Answering email {} failed: {:?}", uid, &e);
+                    }
+                }
+            }
+            _ => r.skipped += 1,
+        }
+        // Failed messages are not processed again, otherwise every poll would answer them
+        session.mark_seen(uid).await?;
+    }
+    session.logout().await;
+    Ok(r)
+}
+
+pub(crate) async fn poll_mailboxes(mut recv: tokio::sync::oneshot::Receiver<()>) {
+    let mut last_polls: HashMap<String, Instant> = HashMap::new();
+    loop {
+        tokio::select! {
+          _ = tokio::time::sleep(Duration::from_secs(TICK_SECS)) => {}
+          _ = &mut recv => {
+            break;
+          }
+        }
+        let robots: Vec<RobotData> = match db::get_all(crate::robot::crud::TABLE) {
+            Ok(r) => r,
+            Err(e) => {
+                log::error!("Loading robots failed: {:?}", &e);
+                continue;
+            }
+        };
+        for robot in robots.iter() {
+            let interval = match settings::get_settings(&robot.robot_id) {
+                Ok(Some(s)) if s.email_channel.enabled => {
+                    (s.email_channel.poll_interval_sec as u64).max(MIN_POLL_INTERVAL_SECS)
+                }
+                _ => continue,
+            };
+            if last_polls
+                .get(&robot.robot_id)
+                .is_some_and(|t| t.elapsed() < Duration::from_secs(interval))
+            {
+                continue;
+            }
+            last_polls.insert(robot.robot_id.clone(), Instant::now());
+            let r = tokio::time::timeout(
+                Duration::from_secs(POLL_TIMEOUT_SECS),
+                poll(&robot.robot_id),
+            )
+            .await;
+            match r {
+                Ok(Ok(r)) if r.received > 0 => log::info!(
+                    "Polled mailbox of robot {}: {} received, {} answered",
+                    &robot.robot_id,
+                    r.received,
+                    r.answered
+                ),
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => log::error!(
+                    "Polling mailbox of robot {} failed: {:?}",
+                    &robot.robot_id,
+                    &e
+                ),
+                Err(_) => log::error!("Polling mailbox of robot {} timed out", &robot.robot_id),
+            }
+        }
+    }
+}
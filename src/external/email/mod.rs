@@ -0,0 +1,6 @@
+pub(crate) mod crud;
+pub(crate) mod dto;
+pub(crate) mod imap;
+pub(crate) mod inbound;
+pub(crate) mod outbox;
+pub(crate) mod smtp;
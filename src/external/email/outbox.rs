@@ -0,0 +1,193 @@
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::Notify;
+
+use super::dto::{EmailOutboxEntry, EmailStatus, OutgoingEmail};
+use crate::db;
+use crate::db_executor;
+use crate::man::settings;
+use crate::result::{Error, Result};
+
+pub(crate) const TABLE_SUFFIX: &str = "emailOutbox";
+// Messages waiting to be sent, removed once the entry is sent or failed
+pub(crate) const MESSAGE_TABLE_SUFFIX: &str = "emailOutboxMessages";
+// Pending emails of all robots, values are (entry id, robot id)
+const QUEUE_TABLE: redb::TableDefinition<&str, &[u8]> = redb::TableDefinition::new("emailQueue");
+const POLL_INTERVAL_SECS: u64 = 10;
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY_SECS: u64 = 30;
+const MAX_RETRY_DELAY_SECS: u64 = 3600;
+
+static NOTIFY: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn new_entry(session_id: &str, email: &OutgoingEmail, async_send: bool) -> EmailOutboxEntry {
+    let now = now();
+    EmailOutboxEntry {
+        id: scru128::new_string(),
+        session_id: String::from(session_id),
+        to: email.to.clone(),
+        subject: email.subject.clone(),
+        attachments: email
+            .attachments
+            .iter()
+            .map(|a| a.file_name.clone())
+            .collect(),
+        async_send,
+        status: EmailStatus::Pending,
+        attempts: 0,
+        next_attempt_at: now,
+        last_error: String::new(),
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+fn update_entry(entry: &mut EmailOutboxEntry, r: &Result<()>) {
+    entry.attempts += 1;
+    entry.updated_at = now();
+    match r {
+        Ok(_) => {
+            entry.status = EmailStatus::Sent;
+            entry.last_error.clear();
+        }
+        Err(e) => entry.last_error = format!("{:?}", e),
+    }
+}
+
+// Records the outcome of a synchronous send
+pub(crate) fn record(
+    robot_id: &str,
+    session_id: &str,
+    email: &OutgoingEmail,
+    r: &Result<()>,
+) -> Result<()> {
+    let mut entry = new_entry(session_id, email, false);
+    update_entry(&mut entry, r);
+    if entry.status == EmailStatus::Pending {
+        entry.status = EmailStatus::Failed;
+    }
+    db_executor!(db::write, robot_id, TABLE_SUFFIX, &entry.id, &entry)
+}
+
+pub(crate) fn enqueue(robot_id: &str, session_id: &str, email: &OutgoingEmail) -> Result<()> {
+    let entry = new_entry(session_id, email, true);
+    db_executor!(db::write, robot_id, MESSAGE_TABLE_SUFFIX, &entry.id, email)?;
+    db_executor!(db::write, robot_id, TABLE_SUFFIX, &entry.id, &entry)?;
+    db::write(QUEUE_TABLE, &entry.id, &(&entry.id, robot_id))?;
+    NOTIFY.notify_one();
+    Ok(())
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    let factor = 1u64 << attempts.saturating_sub(1).min(16);
+    FIRST_RETRY_DELAY_SECS
+        .saturating_mul(factor)
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+async fn attempt(robot_id: String, mut entry: EmailOutboxEntry) -> Result<()> {
+    let email: Option<OutgoingEmail> = db_executor!(
+        db::query,
+        &robot_id,
+        MESSAGE_TABLE_SUFFIX,
+        entry.id.as_str()
+    )?;
+    let r = match (email, settings::get_settings(&robot_id)?) {
+        (Some(email), Some(settings)) if !settings.smtp_host.is_empty() => {
+            super::smtp::send(&settings, &email).await
+        }
+        (Some(_), _) => Err(Error::ErrorWithMessage(String::from(
+            "SMTP is not configured",
+        ))),
+        (None, _) => {
+            entry.attempts = MAX_ATTEMPTS;
+            Err(Error::ErrorWithMessage(String::from(
+                "The message was removed",
+            )))
+        }
+    };
+    update_entry(&mut entry, &r);
+    if entry.status == EmailStatus::Pending {
+        if entry.attempts >= MAX_ATTEMPTS {
+            entry.status = EmailStatus::Failed;
+            log::warn!(
+                "Email {} failed after {} attempts: {}",
+                &entry.id,
+                entry.attempts,
+                &entry.last_error
+            );
+        } else {
+            entry.next_attempt_at = entry.updated_at + retry_delay(entry.attempts);
+        }
+    }
+    db_executor!(db::write, &robot_id, TABLE_SUFFIX, &entry.id, &entry)?;
+    if entry.status != EmailStatus::Pending {
+        db_executor!(
+            db::remove,
+            &robot_id,
+            MESSAGE_TABLE_SUFFIX,
+            entry.id.as_str()
+        )?;
+        db::remove(QUEUE_TABLE, entry.id.as_str())?;
+    }
+    Ok(())
+}
+
+async fn send_due() -> Result<()> {
+    let queue: Vec<(String, String)> = match db::get_all(QUEUE_TABLE) {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => return Ok(()),
+        r => r?,
+    };
+    let now = now();
+    let mut attempts = Vec::new();
+    for (id, robot_id) in queue.into_iter() {
+        let entry: Option<EmailOutboxEntry> =
+            match db_executor!(db::query, &robot_id, TABLE_SUFFIX, id.as_str()) {
+                Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => None,
+                r => r?,
+            };
+        match entry {
+            Some(e) if e.status == EmailStatus::Pending => {
+                if e.next_attempt_at <= now {
+                    attempts.push(attempt(robot_id, e));
+                }
+            }
+            // The robot was removed
+            _ => db::remove(QUEUE_TABLE, id.as_str())?,
+        }
+    }
+    for r in futures::future::join_all(attempts).await {
+        if let Err(e) = r {
+            log::error!("Sending queued email failed: {:?}", &e);
+        }
+    }
+    Ok(())
+}
+
+// Queue is persisted, so emails left by the last run are sent after restarting
+pub(crate) async fn send_pending(mut recv: tokio::sync::oneshot::Receiver<()>) {
+    loop {
+        tokio::select! {
+          _ = NOTIFY.notified() => {}
+          _ = tokio::time::sleep(Duration::from_secs(POLL_INTERVAL_SECS)) => {}
+          _ = &mut recv => {
+            break;
+          }
+        }
+        if let Err(e) = send_due().await {
+            log::error!("Sending queued emails failed: {:?}", &e);
+        }
+    }
+}
+
+pub(crate) fn delete_tables(robot_id: &str) -> Result<()> {
+    db_executor!(db::delete_table, robot_id, TABLE_SUFFIX,)?;
+    db_executor!(db::delete_table, robot_id, MESSAGE_TABLE_SUFFIX,)
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use lettre::message::header::{Bcc, Cc, ContentType, To};
+use lettre::message::{Attachment, Mailboxes, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::PoolConfig;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use super::dto::OutgoingEmail;
+use crate::man::settings::Settings;
+use crate::result::{Error, Result};
+
+type Transport = AsyncSmtpTransport<Tokio1Executor>;
+// Host, username, password and timeout
+type TransportKey = (String, String, String, u16);
+
+// Transports own their connection pools, so they are shared by all sends with the same settings
+static TRANSPORTS: LazyLock<Mutex<HashMap<TransportKey, Transport>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// `smtp.example.com` uses implicit TLS on port 465, URLs like `smtp://localhost:2525` are supported as well
+pub(crate) fn transport(settings: &Settings) -> Result<Transport> {
+    let key = (
+        settings.smtp_host.clone(),
+        settings.smtp_username.clone(),
+        settings.smtp_password.clone(),
+        settings.smtp_timeout_sec,
+    );
+    if let Some(t) = TRANSPORTS.lock()?.get(&key) {
+        return Ok(t.clone());
+    }
+    let mut builder = if settings.smtp_host.contains("://") {
+        Transport::from_url(&settings.smtp_host)?
+    } else {
+        Transport::relay(&settings.smtp_host)?
+    };
+    if !settings.smtp_username.is_empty() {
+        builder = builder.credentials(Credentials::new(
+            settings.smtp_username.to_owned(),
+            settings.smtp_password.to_owned(),
+        ));
+    }
+    let pool = PoolConfig::new()
+        .min_idle(0)
+        .max_size(4)
+        .idle_timeout(Duration::from_secs(300));
+    let t = builder
+        .timeout(Some(Duration::from_secs(settings.smtp_timeout_sec as u64)))
+        .pool_config(pool)
+        .build();
+    TRANSPORTS.lock()?.insert(key, t.clone());
+    Ok(t)
+}
+
+fn mailboxes(addresses: &[String]) -> Result<Mailboxes> {
+    Ok(addresses.join(",").parse()?)
+}
+
+fn content_type(s: &str) -> Result<ContentType> {
+    ContentType::parse(s)
+        .map_err(|e| Error::ErrorWithMessage(format!("Invalid content type {}: {:?}", s, e)))
+}
+
+pub(crate) fn build_message(email: &OutgoingEmail) -> Result<Message> {
+    let to: To = mailboxes(&email.to)?.into();
+    let mut builder = Message::builder()
+        .from(email.from.parse()?)
+        .mailbox(to)
+        .subject(&email.subject)
+        // Lets replies of the recipient refer to this message
+        .message_id(None);
+    if !email.cc.is_empty() {
+        let cc: Cc = mailboxes(&email.cc)?.into();
+        builder = builder.mailbox(cc);
+    }
+    if !email.bcc.is_empty() {
+        let bcc: Bcc = mailboxes(&email.bcc)?.into();
+        builder = builder.mailbox(bcc);
+    }
+    if let Some(id) = &email.in_reply_to {
+        builder = builder.in_reply_to(format!("<{}>", id));
+    }
+    if !email.references.is_empty() {
+        let references: Vec<String> = email
+            .references
+            .iter()
+            .map(|id| format!("<{}>", id))
+            .collect();
+        builder = builder.references(references.join(" "));
+    }
+    let body = match &email.html_body {
+        Some(html) => MultiPart::alternative_plain_html(email.text_body.clone(), html.clone()),
+        None if email.attachments.is_empty() => {
+            return Ok(builder.singlepart(SinglePart::plain(email.text_body.clone()))?);
+        }
+        None => MultiPart::mixed().singlepart(SinglePart::plain(email.text_body.clone())),
+    };
+    if email.attachments.is_empty() {
+        return Ok(builder.multipart(body)?);
+    }
+    let mut mixed = if email.html_body.is_some() {
+        MultiPart::mixed().multipart(body)
+    } else {
+        body
+    };
+    for a in email.attachments.iter() {
+        mixed = mixed.singlepart(
+            Attachment::new(a.file_name.clone())
+                .body(a.content.clone(), content_type(&a.content_type)?),
+        );
+    }
+    Ok(builder.multipart(mixed)?)
+}
+
+pub(crate) async fn send(settings: &Settings, email: &OutgoingEmail) -> Result<()> {
+    let message = build_message(email)?;
+    let r = transport(settings)?.send(message).await?;
+    log::info!("Sent email response: {:?}", r);
+    Ok(())
+}
+
+pub(crate) fn guess_content_type(file_name: &str) -> &'static str {
+    let ext = file_name
+        .rsplit_once('.')
+        .map_or(String::new(), |(_, e)| e.to_lowercase());
+    match ext.as_str() {
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "htm" | "html" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "zip" => "application/zip",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        _ => "application/octet-stream",
+    }
+}
+
+// Plain text alternative of a HTML body
+pub(crate) fn html_to_text(html: &str) -> String {
+    let doc = scraper::Html::parse_fragment(html);
+    let mut text = String::with_capacity(html.len());
+    for n in doc.root_element().descendants() {
+        match n.value() {
+            scraper::Node::Text(t) => text.push_str(t),
+            scraper::Node::Element(e) => match e.name() {
+                "br" | "p" | "div" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    text.push('\n')
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // Replies to each command starting with the expected prefix, returns the message of DATA
+    async fn stub(
+        script: Vec<(&'static str, &'static str)>,
+    ) -> (u16, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (r, mut w) = socket.into_split();
+            let mut r = BufReader::new(r);
+            w.write_all(b"220 stub ESMTP\r\n").await.unwrap();
+            let mut data = String::new();
+            for (prefix, reply) in script {
+                let mut line = String::new();
+                r.read_line(&mut line).await.unwrap();
+                if prefix.is_empty() {
+                    // Message lines up to the terminating dot
+                    while line != ".\r\n" {
+                        data.push_str(&line);
+                        line.clear();
+                        r.read_line(&mut line).await.unwrap();
+                    }
+                } else {
+                    assert!(line.starts_with(prefix), "unexpected command {}", line);
+                }
+                w.write_all(reply.as_bytes()).await.unwrap();
+            }
+            data
+        });
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn send_reply() -> Result<()> {
+        let (port, handle) = stub(vec![
+            ("EHLO ", "250-stub\r\n250 8BITMIME\r\n"),
+            ("MAIL FROM:<bot@example.com>", "250 OK\r\n"),
+            ("RCPT TO:<alice@example.com>", "250 OK\r\n"),
+            ("RCPT TO:<audit@example.com>", "250 OK\r\n"),
+            ("DATA", "354 Go ahead\r\n"),
+            ("", "250 Queued\r\n"),
+        ])
+        .await;
+        let mut settings = Settings::default();
+        settings.smtp_host = format!("smtp://127.0.0.1:{}", port);
+        settings.smtp_timeout_sec = 5;
+        let email = OutgoingEmail {
+            from: String::from("bot@example.com"),
+            to: vec![String::from("alice@example.com")],
+            cc: Vec::new(),
+            bcc: vec![String::from("audit@example.com")],
+            subject: String::from("Re: Order"),
+            text_body: String::from("Shipped"),
+            html_body: None,
+            attachments: Vec::new(),
+            in_reply_to: Some(String::from("1@example.com")),
+            references: vec![String::from("1@example.com")],
+        };
+        send(&settings, &email).await?;
+        let data = handle.await.unwrap();
+        assert!(data.contains("In-Reply-To: <1@example.com>\r\n"));
+        assert!(data.contains("Subject: Re: Order\r\n"));
+        assert!(!data.contains("audit@example.com"));
+        Ok(())
+    }
+}
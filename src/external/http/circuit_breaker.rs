@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::result::{Error, Result};
+
+struct State {
+    failures: u32,
+    open_until: Option<Instant>,
+    open_for: Duration,
+}
+
+// Keyed by robot id and HTTP API id, since ids of APIs are copied when robots are cloned or imported
+static STATES: LazyLock<Mutex<HashMap<(String, String), State>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn key(robot_id: &str, api_id: &str) -> (String, String) {
+    (String::from(robot_id), String::from(api_id))
+}
+
+// Fails fast while the circuit is open. Once it is due, the circuit is half-open:
+// one call is let through to probe the endpoint and the others keep failing fast
+// until the probe is recorded, or another open period passes without a result.
+pub(crate) fn check(robot_id: &str, api_id: &str) -> Result<()> {
+    if let Some(s) = STATES.lock()?.get_mut(&key(robot_id, api_id)) {
+        if let Some(open_until) = s.open_until {
+            let now = Instant::now();
+            if now < open_until {
+                return Err(Error::ErrorWithMessage(format!(
+                    "Circuit breaker of HTTP API {} is open",
+                    api_id
+                )));
+            }
+            s.open_until = Some(now + s.open_for);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn record(
+    robot_id: &str,
+    api_id: &str,
+    successful: bool,
+    threshold: u32,
+    open_seconds: u64,
+) -> Result<()> {
+    let mut states = STATES.lock()?;
+    if successful {
+        states.remove(&key(robot_id, api_id));
+        return Ok(());
+    }
+    let s = states.entry(key(robot_id, api_id)).or_insert(State {
+        failures: 0,
+        open_until: None,
+        open_for: Duration::from_secs(open_seconds),
+    });
+    s.failures = s.failures.saturating_add(1);
+    if s.failures >= threshold {
+        log::warn!("Circuit breaker of HTTP API {} opened", api_id);
+        s.open_for = Duration::from_secs(open_seconds);
+        s.open_until = Some(Instant::now() + s.open_for);
+    }
+    Ok(())
+}
+
+pub(crate) fn reset(robot_id: &str, api_id: &str) -> Result<()> {
+    STATES.lock()?.remove(&key(robot_id, api_id));
+    Ok(())
+}
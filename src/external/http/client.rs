@@ -1,59 +1,174 @@
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Client;
 use reqwest::RequestBuilder;
 
-use super::dto::{HttpReqInfo, Method, PostContentType, Protocol, ResponseData, ValueSource};
-use crate::result::Result;
+use super::circuit_breaker;
+use super::dto::{
+    HttpAuth, HttpReqInfo, HttpReqParam, HttpResponse, HttpTestRequest, HttpTestResult,
+    HttpTestTiming, Method, PostContentType, Protocol, ResponseData, ValueSource,
+};
+use crate::flow::rt::template;
+use crate::result::{Error, Result};
 use crate::variable::dto::VariableValue;
 
+// Connect timeout, read timeout and proxy URL
+type ClientKey = (u64, u64, String);
+
+static CLIENTS: LazyLock<Mutex<HashMap<ClientKey, Client>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Access tokens of OAuth2 client credentials grants, keyed by token URL, client id and scope
+static OAUTH2_TOKENS: LazyLock<Mutex<HashMap<String, (String, Instant)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Clients keep connection pools, so they are shared by timeouts and proxy
 pub(crate) fn get_client(
     connect_timeout_millis: u64,
     read_timeout_millis: u64,
     proxy_url: &str,
 ) -> Result<Client> {
+    let key = (
+        connect_timeout_millis,
+        read_timeout_millis,
+        String::from(proxy_url),
+    );
+    if let Some(c) = CLIENTS.lock()?.get(&key) {
+        return Ok(c.clone());
+    }
     let mut client = reqwest::Client::builder()
         .connect_timeout(Duration::from_millis(connect_timeout_millis))
-        .read_timeout(Duration::from_millis(read_timeout_millis))
-        // Since can not reuse Client currently, so set pool size to 0
-        .pool_max_idle_per_host(0)
-        .pool_idle_timeout(Duration::from_secs(1));
+        .read_timeout(Duration::from_millis(read_timeout_millis));
     if proxy_url.is_empty() {
         client = client.no_proxy();
     } else {
-        let proxy = reqwest::Proxy::http(proxy_url)?;
+        let proxy = reqwest::Proxy::all(proxy_url)?;
         client = client.proxy(proxy);
     }
     let client = client.build()?;
+    CLIENTS.lock()?.insert(key, client.clone());
     Ok(client)
 }
 
 pub(crate) async fn req_async(
+    robot_id: String,
     info: HttpReqInfo,
     vars: HashMap<String, VariableValue>,
     ignore_response: bool,
-) -> reqwest::Result<ResponseData> {
-    req(info, &vars, ignore_response).await
+) -> Result<ResponseData> {
+    req(&robot_id, info, &vars, ignore_response).await
 }
 
 pub(crate) async fn req(
+    robot_id: &str,
     info: HttpReqInfo,
     vars: &HashMap<String, VariableValue>,
     ignore_response: bool,
-) -> reqwest::Result<ResponseData> {
-    let req = build_req(&info, vars)?;
-    let res = req.send().await?;
+) -> Result<ResponseData> {
+    let res = execute(robot_id, &info, vars).await?;
     // println!("http status code {}", res.status().as_str());
     if ignore_response || res.status().as_u16() != 200 {
         return Ok(ResponseData::None);
     }
+    Ok(read_body(res).await?)
+}
+
+// Unlike `req`, keeps the status code and headers of responses with any status
+pub(crate) async fn send(
+    robot_id: &str,
+    info: &HttpReqInfo,
+    vars: &HashMap<String, VariableValue>,
+) -> Result<HttpResponse> {
+    let res = execute(robot_id, info, vars).await?;
+    let status = res.status().as_u16();
+    let headers = res.headers().clone();
+    let data = read_body(res).await.map_err(send_err)?;
+    Ok(HttpResponse {
+        status,
+        headers,
+        data,
+    })
+}
+
+fn is_idempotent(m: &Method) -> bool {
+    matches!(m, Method::GET | Method::PUT | Method::DELETE)
+}
+
+fn should_retry(r: &Result<reqwest::Response>) -> bool {
+    match r {
+        Ok(res) => matches!(res.status().as_u16(), 429 | 502 | 503 | 504),
+        Err(_) => true,
+    }
+}
+
+// Applies the circuit breaker and the retry policy of the API
+async fn execute(
+    robot_id: &str,
+    info: &HttpReqInfo,
+    vars: &HashMap<String, VariableValue>,
+) -> Result<reqwest::Response> {
+    let breaker_enabled = info.circuit_breaker_threshold > 0;
+    if breaker_enabled {
+        circuit_breaker::check(robot_id, &info.id)?;
+    }
+    let retry_times = if is_idempotent(&info.method) {
+        info.retry_times
+    } else {
+        0
+    };
+    let mut backoff = Duration::from_millis(info.retry_backoff_milliseconds);
+    let mut attempt = 0u8;
+    let r = loop {
+        // Bodies like multipart forms can not be cloned, so the request is built again
+        let r = match build_req(info, vars).await {
+            Ok(req) => req.send().await.map_err(send_err),
+            Err(e) => return Err(e),
+        };
+        if attempt >= retry_times || !should_retry(&r) {
+            break r;
+        }
+        attempt += 1;
+        log::warn!(
+            "Retrying HTTP API {} ({}/{})",
+            &info.name,
+            attempt,
+            retry_times
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = backoff.saturating_mul(2);
+    };
+    if breaker_enabled {
+        let successful = r.as_ref().is_ok_and(|res| res.status().as_u16() < 500);
+        circuit_breaker::record(
+            robot_id,
+            &info.id,
+            successful,
+            info.circuit_breaker_threshold,
+            info.circuit_breaker_open_seconds,
+        )?;
+    }
+    r
+}
+
+fn send_err(e: reqwest::Error) -> Error {
+    if e.is_timeout() && e.is_connect() {
+        Error::NetworkConnectTimeout(e)
+    } else if e.is_timeout() {
+        Error::NetworkReadTimeout(e)
+    } else {
+        e.into()
+    }
+}
+
+async fn read_body(res: reqwest::Response) -> reqwest::Result<ResponseData> {
     let content_type = res
         .headers()
         .get(reqwest::header::CONTENT_TYPE)
-        .map_or("", |h| h.to_str().unwrap());
+        .map_or("", |h| h.to_str().unwrap_or(""));
     let data = if content_type.find("text/").is_some()
         || content_type.find("/json").is_some()
         || content_type.find("/xml").is_some()
@@ -67,62 +182,131 @@ pub(crate) async fn req(
     Ok(data)
 }
 
-fn build_req(
+fn render(text: &str, vars: &HashMap<String, VariableValue>) -> Result<String> {
+    template::render_placeholders(text, vars)
+}
+
+fn param_value(p: &HttpReqParam, vars: &HashMap<String, VariableValue>) -> Result<String> {
+    match p.value_source {
+        ValueSource::Val => render(&p.value, vars),
+        ValueSource::Var => Ok(vars
+            .get(&p.value)
+            .map_or(String::new(), |v| v.val_to_string())),
+    }
+}
+
+fn header(name: &str, value: &str) -> Result<(HeaderName, HeaderValue)> {
+    let n = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|e| Error::ErrorWithMessage(format!("Invalid header name {}: {}", name, e)))?;
+    let v = HeaderValue::from_str(value)
+        .map_err(|e| Error::ErrorWithMessage(format!("Invalid value of header {}: {}", name, e)))?;
+    Ok((n, v))
+}
+
+async fn oauth2_token(
+    token_url: &str,
+    client_id: &str,
+    client_secret: &str,
+    scope: &str,
+) -> Result<String> {
+    let key = format!("{}|{}|{}", token_url, client_id, scope);
+    if let Some((token, expires_at)) = OAUTH2_TOKENS.lock()?.get(&key) {
+        if Instant::now() < *expires_at {
+            return Ok(token.clone());
+        }
+    }
+    let mut form = vec![("grant_type", "client_credentials")];
+    if !scope.is_empty() {
+        form.push(("scope", scope));
+    }
+    let res = get_client(1000, 10000, "")?
+        .post(token_url)
+        .basic_auth(client_id, Some(client_secret))
+        .form(&form)
+        .send()
+        .await
+        .map_err(send_err)?;
+    if !res.status().is_success() {
+        return Err(Error::ErrorWithMessage(format!(
+            "Requesting OAuth2 token failed, status: {}",
+            res.status()
+        )));
+    }
+    let v: serde_json::Value = serde_json::from_str(&res.text().await?)?;
+    let token = v
+        .get("access_token")
+        .and_then(|t| t.as_str())
+        .ok_or_else(|| {
+            Error::ErrorWithMessage(String::from("OAuth2 token response has no access_token"))
+        })?
+        .to_string();
+    let expires_in = v.get("expires_in").and_then(|e| e.as_u64()).unwrap_or(3600);
+    // Renew a bit earlier, so the token will not expire in flight
+    let expires_at = Instant::now() + Duration::from_secs(expires_in.saturating_sub(30));
+    OAUTH2_TOKENS
+        .lock()?
+        .insert(key, (token.clone(), expires_at));
+    Ok(token)
+}
+
+async fn build_req(
     info: &HttpReqInfo,
     vars: &HashMap<String, VariableValue>,
-) -> reqwest::Result<RequestBuilder> {
-    let client = reqwest::Client::builder()
-        .connect_timeout(Duration::from_millis(1000))
-        .read_timeout(Duration::from_millis(info.timeout_milliseconds))
-        .build()?;
+) -> Result<RequestBuilder> {
+    let client = get_client(
+        info.connect_timeout_milliseconds,
+        info.timeout_milliseconds,
+        &info.proxy_url,
+    )?;
     let mut url = String::with_capacity(512);
     match info.protocol {
         Protocol::HTTP => url.push_str("http"),
         Protocol::HTTPS => url.push_str("https"),
     }
     url.push_str("://");
-    url.push_str(&info.address);
+    url.push_str(&template::render_url(&info.address, vars)?);
     let mut req = match info.method {
         Method::GET => client.get(&url),
-        Method::POST => {
-            let r = client.post(&url);
-            if !info.request_body.is_empty() {
-                r.body(info.request_body.clone())
-            } else {
-                r
+        Method::POST => client.post(&url),
+        Method::PUT => client.put(&url),
+        Method::PATCH => client.patch(&url),
+        Method::DELETE => client.delete(&url),
+    };
+    if !matches!(info.method, Method::GET) {
+        match info.post_content_type {
+            PostContentType::UrlEncoded if !info.form_data.is_empty() => {
+                let mut form: Vec<(&str, String)> = Vec::with_capacity(info.form_data.len());
+                for p in info.form_data.iter() {
+                    form.push((&p.name, param_value(p, vars)?));
+                }
+                req = req.form(&form);
+            }
+            PostContentType::Multipart => {
+                let mut form = reqwest::multipart::Form::new();
+                for p in info.form_data.iter() {
+                    form = form.text(p.name.clone(), param_value(p, vars)?);
+                }
+                req = req.multipart(form);
+            }
+            _ => {
+                if !info.request_body.is_empty() {
+                    req = req.body(render(&info.request_body, vars)?);
+                }
             }
         }
-    };
+    }
     if !info.headers.is_empty() {
         let mut headers: HeaderMap<HeaderValue> = HeaderMap::with_capacity(info.headers.len());
         for p in info.headers.iter() {
-            match p.value_source {
-                ValueSource::Val => headers.insert(
-                    HeaderName::from_bytes(p.name.as_bytes()).unwrap(),
-                    p.value.parse().unwrap(),
-                ),
-                ValueSource::Var => headers.insert(
-                    HeaderName::from_bytes(p.name.as_bytes()).unwrap(),
-                    vars.get(&p.value)
-                        .map_or(String::new(), |v| v.val_to_string())
-                        .parse()
-                        .unwrap(),
-                ),
-            };
+            let (n, v) = header(&p.name, &param_value(p, vars)?)?;
+            headers.insert(n, v);
         }
         req = req.headers(headers);
     }
     if !info.query_params.is_empty() {
         let mut queries: Vec<(&str, String)> = Vec::with_capacity(info.query_params.len());
         for p in info.query_params.iter() {
-            match p.value_source {
-                ValueSource::Val => queries.push((&p.name, p.value.clone())),
-                ValueSource::Var => queries.push((
-                    &p.name,
-                    vars.get(&p.value)
-                        .map_or(String::new(), |v| v.val_to_string()),
-                )),
-            }
+            queries.push((&p.name, param_value(p, vars)?));
         }
         req = req.query(&queries);
     }
@@ -132,6 +316,119 @@ fn build_req(
     if !info.user_agent.is_empty() {
         req = req.header("User-Agent", &info.user_agent);
     }
+    req = match &info.auth {
+        HttpAuth::None => req,
+        HttpAuth::Basic { username, password } => {
+            req.basic_auth(render(username, vars)?, Some(render(password, vars)?))
+        }
+        HttpAuth::Bearer { var_name } => {
+            let token = vars
+                .get(var_name)
+                .map(|v| v.val_to_string())
+                .filter(|t| !t.is_empty())
+                .ok_or_else(|| {
+                    Error::ErrorWithMessage(format!("Bearer token variable {} is empty", var_name))
+                })?;
+            req.bearer_auth(token)
+        }
+        HttpAuth::ApiKey { header_name, value } => {
+            let (n, v) = header(header_name, &render(value, vars)?)?;
+            req.header(n, v)
+        }
+        HttpAuth::OAuth2ClientCredentials {
+            token_url,
+            client_id,
+            client_secret,
+            scope,
+        } => {
+            let token = oauth2_token(token_url, client_id, client_secret, scope).await?;
+            req.bearer_auth(token)
+        }
+    };
     // Ok(req.timeout(Duration::from_millis(info.timeout_milliseconds)))
     Ok(req)
 }
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(n, v)| {
+            (
+                n.to_string(),
+                String::from_utf8_lossy(v.as_bytes()).to_string(),
+            )
+        })
+        .collect()
+}
+
+fn elapsed_millis(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+// Sends the request once, without retries and circuit breaker, and reports every detail
+pub(crate) async fn test(
+    info: &HttpReqInfo,
+    vars: &HashMap<String, VariableValue>,
+) -> Result<(HttpTestResult, Option<HttpResponse>)> {
+    let begin = Instant::now();
+    let (client, req) = build_req(info, vars).await?.build_split();
+    let req = req?;
+    let mut timing = HttpTestTiming {
+        build_millis: elapsed_millis(begin),
+        ..Default::default()
+    };
+    let request = HttpTestRequest {
+        method: req.method().to_string(),
+        url: req.url().to_string(),
+        headers: header_pairs(req.headers()),
+        body: req
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| String::from_utf8_lossy(b).to_string()),
+    };
+    let mut result = HttpTestResult {
+        request,
+        status: None,
+        response_headers: Vec::new(),
+        timing: HttpTestTiming::default(),
+        body: None,
+        error: None,
+        extractions: Vec::new(),
+        mappings: Vec::new(),
+    };
+    let sent_at = Instant::now();
+    let mut response = None;
+    match client.execute(req).await {
+        Ok(res) => {
+            timing.response_millis = elapsed_millis(sent_at);
+            let status = res.status().as_u16();
+            let headers = res.headers().clone();
+            result.status = Some(status);
+            result.response_headers = header_pairs(&headers);
+            let body_begin = Instant::now();
+            match read_body(res).await {
+                Ok(data) => {
+                    result.body = Some(match &data {
+                        ResponseData::Str(s) => s.clone(),
+                        ResponseData::Bin(b) => format!("<{} bytes binary data>", b.len()),
+                        ResponseData::None => String::new(),
+                    });
+                    response = Some(HttpResponse {
+                        status,
+                        headers,
+                        data,
+                    });
+                }
+                Err(e) => result.error = Some(format!("{:?}", send_err(e))),
+            }
+            timing.body_millis = elapsed_millis(body_begin);
+        }
+        Err(e) => {
+            timing.response_millis = elapsed_millis(sent_at);
+            result.error = Some(format!("{:?}", send_err(e)));
+        }
+    }
+    timing.total_millis = elapsed_millis(begin);
+    result.timing = timing;
+    Ok((result, response))
+}
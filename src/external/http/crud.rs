@@ -3,12 +3,18 @@ use std::collections::HashMap;
 use axum::extract::{Path, Query};
 use axum::response::IntoResponse;
 use axum::Json;
+use utoipa::TupleUnit;
 
-use super::dto::HttpReqInfo;
+use super::dto::{
+    HttpReqInfo, HttpResponse, HttpTestData, HttpTestExtraction, HttpTestResult, ResponseData,
+};
 use crate::db;
 use crate::db_executor;
+use crate::flow::rt::node::{ExternalHttpCallNode, HttpResponseMapping};
 use crate::result::{Error, Result};
-use crate::web::server::to_res;
+use crate::variable::dto::{Variable, VariableValue, VariableValueSource};
+use crate::variable::extract;
+use crate::web::server::{self, to_res};
 
 // pub(crate) const TABLE: redb::TableDefinition<&str, &[u8]> =
 //     redb::TableDefinition::new("externalHttpApis");
@@ -19,6 +25,14 @@ pub(crate) fn init(robot_id: &str) -> Result<()> {
     db_executor!(db::init_table, robot_id, TABLE_SUFFIX,)
 }
 
+#[utoipa::path(
+    get,
+    path = "/external/http",
+    tag = "external",
+    summary = "List HTTP APIs",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = server::ResponseData<Vec<HttpReqInfo>>))
+)]
 pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
     // let r: Result<Vec<HttpReqInfo>> = db::get_all(TABLE);
     if let Some(robot_id) = q.get("robotId") {
@@ -36,6 +50,14 @@ pub(crate) fn get_detail(robot_id: &str, id: &str) -> Result<Option<HttpReqInfo>
     db_executor!(db::query, robot_id, TABLE_SUFFIX, id)
 }
 
+#[utoipa::path(
+    get,
+    path = "/external/http/{id}",
+    tag = "external",
+    summary = "Get an HTTP API",
+    params(("id" = String, Path), ("robotId" = String, Query)),
+    responses((status = 200, body = server::ResponseData<HttpReqInfo>))
+)]
 pub(crate) async fn detail(
     Query(q): Query<HashMap<String, String>>,
     Path(id): Path<String>,
@@ -50,6 +72,15 @@ pub(crate) async fn detail(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/external/http/{id}",
+    tag = "external",
+    summary = "Create or update an HTTP API",
+    params(("id" = String, Path), ("robotId" = String, Query)),
+    request_body = HttpReqInfo,
+    responses((status = 200, body = server::ResponseData<TupleUnit>))
+)]
 pub(crate) async fn save(
     Query(q): Query<HashMap<String, String>>,
     Json(mut params): Json<HttpReqInfo>,
@@ -58,7 +89,8 @@ pub(crate) async fn save(
         if params.id.is_empty() || params.id.eq("new") {
             params.id = scru128::new_string();
         }
-        let r = db_executor!(db::write, robot_id, TABLE_SUFFIX, &params.id, &params);
+        let r = db_executor!(db::write, robot_id, TABLE_SUFFIX, &params.id, &params)
+            .and_then(|_| super::circuit_breaker::reset(robot_id, &params.id));
         to_res(r)
     } else {
         to_res(Err(Error::ErrorWithMessage(String::from(
@@ -67,12 +99,21 @@ pub(crate) async fn save(
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/external/http/{id}",
+    tag = "external",
+    summary = "Delete an HTTP API",
+    params(("id" = String, Path), ("robotId" = String, Query)),
+    responses((status = 200, body = server::ResponseData<TupleUnit>))
+)]
 pub(crate) async fn remove(
     Query(q): Query<HashMap<String, String>>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     if let Some(robot_id) = q.get("robotId") {
-        let r = db_executor!(db::remove, &robot_id, TABLE_SUFFIX, id.as_str());
+        let r = db_executor!(db::remove, &robot_id, TABLE_SUFFIX, id.as_str())
+            .and_then(|_| super::circuit_breaker::reset(robot_id, &id));
         to_res(r)
     } else {
         to_res(Err(Error::ErrorWithMessage(String::from(
@@ -80,3 +121,125 @@ pub(crate) async fn remove(
         ))))
     }
 }
+
+fn sample_vars(
+    robot_id: &str,
+    samples: HashMap<String, serde_json::Value>,
+) -> Result<HashMap<String, VariableValue>> {
+    let mut vars = HashMap::with_capacity(samples.len());
+    for (k, v) in samples.into_iter() {
+        let t = match crate::variable::crud::get(robot_id, &k)? {
+            Some(var) => var.var_type,
+            None => crate::variable::dto::json_type(&v),
+        };
+        let v = VariableValue::from_json(v, &t)
+            .map_err(|e| Error::ErrorWithMessage(format!("Variable {}: {:?}", k, e)))?;
+        vars.insert(k, v);
+    }
+    Ok(vars)
+}
+
+fn test_extraction(var_name: String, r: Result<VariableValue>) -> HttpTestExtraction {
+    match r {
+        Ok(value) => HttpTestExtraction {
+            var_name,
+            value: Some(value.to_json()),
+            error: None,
+        },
+        Err(e) => HttpTestExtraction {
+            var_name,
+            value: None,
+            error: Some(match e {
+                Error::ErrorWithMessage(m) => m,
+                e => format!("{:?}", e),
+            }),
+        },
+    }
+}
+
+fn extractions(
+    robot_id: &str,
+    api_id: &str,
+    data: &ResponseData,
+) -> Result<Vec<HttpTestExtraction>> {
+    let variables: Vec<Variable> =
+        db_executor!(db::get_all, robot_id, crate::variable::crud::TABLE_SUFFIX,)?;
+    let extractions = variables
+        .into_iter()
+        .filter(|v| {
+            matches!(v.var_val_source, VariableValueSource::ExternalHttp)
+                && v.var_associate_data.eq(api_id)
+        })
+        .map(|v| {
+            let r = match data {
+                ResponseData::Str(s) => extract::extract(
+                    &v.obtain_value_expression_type,
+                    &v.obtain_value_expression,
+                    s,
+                )
+                .and_then(|values| extract::to_variable_value(values, &v.var_type)),
+                _ => Err(Error::ErrorWithMessage(String::from(
+                    "Response has no text body",
+                ))),
+            };
+            test_extraction(v.var_name, r)
+        })
+        .collect();
+    Ok(extractions)
+}
+
+fn mappings(
+    robot_id: &str,
+    mappings: &[HttpResponseMapping],
+    res: Option<&HttpResponse>,
+) -> Vec<HttpTestExtraction> {
+    mappings
+        .iter()
+        .map(|m| {
+            let r = match res {
+                Some(res) => ExternalHttpCallNode::mapped_value(m, res, robot_id),
+                None => Err(Error::ErrorWithMessage(String::from(
+                    "No response was received",
+                ))),
+            };
+            test_extraction(m.var_name.clone(), r)
+        })
+        .collect()
+}
+
+async fn try_test(robot_id: &str, d: HttpTestData) -> Result<HttpTestResult> {
+    let info = match d.info {
+        Some(info) => info,
+        None => get_detail(robot_id, &d.http_api_id)?.ok_or_else(|| {
+            Error::ErrorWithMessage(format!("HTTP API {} not found", &d.http_api_id))
+        })?,
+    };
+    let vars = sample_vars(robot_id, d.variables)?;
+    let (mut result, res) = super::client::test(&info, &vars).await?;
+    let data = res.as_ref().map_or(&ResponseData::None, |r| &r.data);
+    result.extractions = extractions(robot_id, &info.id, data)?;
+    result.mappings = mappings(robot_id, &d.response_mappings, res.as_ref());
+    Ok(result)
+}
+
+#[utoipa::path(
+    post,
+    path = "/external/http/test",
+    tag = "external",
+    summary = "Call an HTTP API with sample variables",
+    params(("robotId" = String, Query)),
+    request_body = HttpTestData,
+    responses((status = 200, body = server::ResponseData<HttpTestResult>))
+)]
+pub(crate) async fn test(
+    Query(q): Query<HashMap<String, String>>,
+    Json(d): Json<HttpTestData>,
+) -> impl IntoResponse {
+    if let Some(robot_id) = q.get("robotId") {
+        to_res(try_test(robot_id, d).await)
+    } else {
+        to_res(Err(Error::ErrorWithMessage(String::from(
+            "Parameter: robotId is missing.",
+        ))))
+    }
+}
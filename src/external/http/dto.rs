@@ -1,32 +1,37 @@
+use std::collections::HashMap;
 use std::vec::Vec;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) enum Protocol {
     HTTP,
     HTTPS,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) enum Method {
     GET,
     POST,
+    PUT,
+    PATCH,
+    DELETE,
 }
 
-#[derive(Clone, Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Clone, Deserialize, PartialEq, Eq, Serialize, utoipa::ToSchema)]
 pub(crate) enum PostContentType {
     UrlEncoded,
     JSON,
+    Multipart,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) enum ValueSource {
     Val,
     Var,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct HttpReqParam {
     pub(crate) name: String,
     pub(crate) value: String,
@@ -34,7 +39,37 @@ pub(crate) struct HttpReqParam {
     pub(crate) value_source: ValueSource,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+// Texts, except the bearer variable name, can contain variables like {{ api_key }}
+#[derive(Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) enum HttpAuth {
+    #[default]
+    None,
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        #[serde(rename = "varName")]
+        var_name: String,
+    },
+    ApiKey {
+        #[serde(rename = "headerName")]
+        header_name: String,
+        value: String,
+    },
+    OAuth2ClientCredentials {
+        #[serde(rename = "tokenUrl")]
+        token_url: String,
+        #[serde(rename = "clientId")]
+        client_id: String,
+        #[serde(rename = "clientSecret")]
+        client_secret: String,
+        #[serde(default)]
+        scope: String,
+    },
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct HttpReqInfo {
     pub(crate) id: String,
     pub(crate) name: String,
@@ -57,6 +92,38 @@ pub(crate) struct HttpReqInfo {
     pub(crate) user_agent: String,
     #[serde(rename = "asyncReq")]
     pub(crate) async_req: bool,
+    #[serde(default)]
+    pub(crate) auth: HttpAuth,
+    #[serde(
+        rename = "connectTimeoutMilliseconds",
+        default = "default_connect_timeout"
+    )]
+    pub(crate) connect_timeout_milliseconds: u64,
+    #[serde(rename = "proxyUrl", default)]
+    pub(crate) proxy_url: String,
+    // Only GET, PUT and DELETE requests are retried
+    #[serde(rename = "retryTimes", default)]
+    pub(crate) retry_times: u8,
+    // Doubled after each retry
+    #[serde(rename = "retryBackoffMilliseconds", default = "default_retry_backoff")]
+    pub(crate) retry_backoff_milliseconds: u64,
+    // Consecutive failures opening the circuit, 0 disables the circuit breaker
+    #[serde(rename = "circuitBreakerThreshold", default)]
+    pub(crate) circuit_breaker_threshold: u32,
+    #[serde(rename = "circuitBreakerOpenSeconds", default = "default_circuit_open")]
+    pub(crate) circuit_breaker_open_seconds: u64,
+}
+
+fn default_connect_timeout() -> u64 {
+    1000
+}
+
+fn default_retry_backoff() -> u64 {
+    200
+}
+
+fn default_circuit_open() -> u64 {
+    30
 }
 
 pub(crate) enum ResponseData {
@@ -64,3 +131,73 @@ pub(crate) enum ResponseData {
     Bin(Vec<u8>),
     None,
 }
+
+pub(crate) struct HttpResponse {
+    pub(crate) status: u16,
+    pub(crate) headers: reqwest::header::HeaderMap,
+    pub(crate) data: ResponseData,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub(crate) struct HttpTestData {
+    // Unsaved definition, the saved one of `httpApiId` is tested when absent
+    #[serde(default)]
+    pub(crate) info: Option<HttpReqInfo>,
+    #[serde(rename = "httpApiId", default)]
+    pub(crate) http_api_id: String,
+    // Sample values of the variables used by the definition
+    #[serde(default)]
+    #[schema(value_type = HashMap<String, Object>)]
+    pub(crate) variables: HashMap<String, serde_json::Value>,
+    // Response mappings of the HTTP node being edited
+    #[serde(rename = "responseMappings", default)]
+    pub(crate) response_mappings: Vec<crate::flow::rt::node::HttpResponseMapping>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct HttpTestRequest {
+    pub(crate) method: String,
+    pub(crate) url: String,
+    pub(crate) headers: Vec<(String, String)>,
+    // None when the body is streamed, e.g. multipart forms
+    pub(crate) body: Option<String>,
+}
+
+#[derive(Default, Serialize, utoipa::ToSchema)]
+pub(crate) struct HttpTestTiming {
+    // Rendering templates and fetching OAuth2 tokens
+    #[serde(rename = "buildMillis")]
+    pub(crate) build_millis: u64,
+    // Until the response headers were received
+    #[serde(rename = "responseMillis")]
+    pub(crate) response_millis: u64,
+    #[serde(rename = "bodyMillis")]
+    pub(crate) body_millis: u64,
+    #[serde(rename = "totalMillis")]
+    pub(crate) total_millis: u64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct HttpTestExtraction {
+    #[serde(rename = "varName")]
+    pub(crate) var_name: String,
+    #[schema(value_type = Option<Object>)]
+    pub(crate) value: Option<serde_json::Value>,
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct HttpTestResult {
+    pub(crate) request: HttpTestRequest,
+    pub(crate) status: Option<u16>,
+    #[serde(rename = "responseHeaders")]
+    pub(crate) response_headers: Vec<(String, String)>,
+    pub(crate) timing: HttpTestTiming,
+    pub(crate) body: Option<String>,
+    // Set when the request could not be completed
+    pub(crate) error: Option<String>,
+    // Variables obtaining their values from this API
+    pub(crate) extractions: Vec<HttpTestExtraction>,
+    // Values the response mappings of the node would assign
+    pub(crate) mappings: Vec<HttpTestExtraction>,
+}
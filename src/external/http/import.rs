@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+
+use axum::body::Bytes;
+use axum::extract::Query;
+use axum::response::IntoResponse;
+use serde_json::Value;
+
+use super::crud::TABLE_SUFFIX;
+use super::dto::{
+    HttpAuth, HttpReqInfo, HttpReqParam, Method, PostContentType, Protocol, ValueSource,
+};
+use crate::db;
+use crate::db_executor;
+use crate::result::{Error, Result};
+use crate::web::openapi::Binary;
+use crate::web::server::{to_res, ResponseData};
+
+const DEFAULT_TIMEOUT_MILLIS: u64 = 3000;
+const MAX_SAMPLE_DEPTH: usize = 8;
+
+fn new_info(name: String, method: Method) -> HttpReqInfo {
+    HttpReqInfo {
+        id: scru128::new_string(),
+        name,
+        description: String::new(),
+        protocol: Protocol::HTTPS,
+        method,
+        address: String::new(),
+        timeout_milliseconds: DEFAULT_TIMEOUT_MILLIS,
+        post_content_type: PostContentType::JSON,
+        headers: Vec::new(),
+        query_params: Vec::new(),
+        form_data: Vec::new(),
+        request_body: String::new(),
+        user_agent: String::new(),
+        async_req: false,
+        auth: HttpAuth::None,
+        connect_timeout_milliseconds: 1000,
+        proxy_url: String::new(),
+        retry_times: 0,
+        retry_backoff_milliseconds: 200,
+        circuit_breaker_threshold: 0,
+        circuit_breaker_open_seconds: 30,
+    }
+}
+
+fn param(name: &str, value: &str) -> HttpReqParam {
+    HttpReqParam {
+        name: String::from(name),
+        value: String::from(value),
+        value_source: ValueSource::Val,
+    }
+}
+
+fn parse_method(m: &str) -> Option<Method> {
+    match m.to_uppercase().as_str() {
+        "GET" => Some(Method::GET),
+        "POST" => Some(Method::POST),
+        "PUT" => Some(Method::PUT),
+        "PATCH" => Some(Method::PATCH),
+        "DELETE" => Some(Method::DELETE),
+        _ => None,
+    }
+}
+
+// Splits `https://host:8080/v1/items?a=b` into the protocol, the address and the query params
+fn set_url(info: &mut HttpReqInfo, url: &str) {
+    let (protocol, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_lowercase(), rest),
+        None => (String::from("https"), url),
+    };
+    info.protocol = if protocol == "http" {
+        Protocol::HTTP
+    } else {
+        Protocol::HTTPS
+    };
+    let (address, query) = match rest.split_once('?') {
+        Some((a, q)) => (a, Some(q)),
+        None => (rest, None),
+    };
+    info.address = String::from(address);
+    if let Some(q) = query {
+        for (k, v) in reqwest::Url::parse(&format!("http://h/?{}", q))
+            .iter()
+            .flat_map(|u| u.query_pairs().into_owned().collect::<Vec<_>>())
+        {
+            info.query_params.push(param(&k, &v));
+        }
+    }
+}
+
+struct OpenApi<'a> {
+    doc: &'a Value,
+}
+
+impl OpenApi<'_> {
+    // Resolves local references like `#/components/schemas/Pet`
+    fn resolve<'b>(&'b self, v: &'b Value) -> &'b Value {
+        let mut v = v;
+        for _ in 0..MAX_SAMPLE_DEPTH {
+            match v.get("$ref").and_then(|r| r.as_str()) {
+                Some(r) => match r.strip_prefix('#').and_then(|p| self.doc.pointer(p)) {
+                    Some(target) => v = target,
+                    None => return &Value::Null,
+                },
+                None => return v,
+            }
+        }
+        v
+    }
+
+    fn base_url(&self) -> String {
+        let Some(server) = self.doc.pointer("/servers/0") else {
+            return String::new();
+        };
+        let mut url = server
+            .get("url")
+            .and_then(|u| u.as_str())
+            .unwrap_or("")
+            .to_string();
+        if let Some(vars) = server.get("variables").and_then(|v| v.as_object()) {
+            for (k, v) in vars.iter() {
+                let default = v.get("default").and_then(|d| d.as_str()).unwrap_or("");
+                url = url.replace(&format!("{{{}}}", k), default);
+            }
+        }
+        String::from(url.trim_end_matches('/'))
+    }
+
+    fn sample(&self, schema: &Value, depth: usize) -> Value {
+        let schema = self.resolve(schema);
+        if let Some(e) = schema.get("example") {
+            return e.clone();
+        }
+        if let Some(d) = schema.get("default") {
+            return d.clone();
+        }
+        if depth >= MAX_SAMPLE_DEPTH {
+            return Value::Null;
+        }
+        for key in ["allOf", "oneOf", "anyOf"] {
+            if let Some(first) = schema.get(key).and_then(|s| s.get(0)) {
+                return self.sample(first, depth + 1);
+            }
+        }
+        match schema.get("type").and_then(|t| t.as_str()) {
+            Some("object") | None if schema.get("properties").is_some() => {
+                let mut o = serde_json::Map::new();
+                if let Some(props) = schema.get("properties").and_then(|p| p.as_object()) {
+                    for (k, v) in props.iter() {
+                        o.insert(k.clone(), self.sample(v, depth + 1));
+                    }
+                }
+                Value::Object(o)
+            }
+            Some("object") => Value::Object(serde_json::Map::new()),
+            Some("array") => match schema.get("items") {
+                Some(items) => Value::Array(vec![self.sample(items, depth + 1)]),
+                None => Value::Array(Vec::new()),
+            },
+            Some("integer") | Some("number") => Value::from(0),
+            Some("boolean") => Value::Bool(false),
+            Some("string") => Value::from(""),
+            _ => Value::Null,
+        }
+    }
+
+    fn apply_security(&self, info: &mut HttpReqInfo, op: &Value) {
+        let requirements = op.get("security").or_else(|| self.doc.get("security"));
+        let Some(name) = requirements
+            .and_then(|r| r.get(0))
+            .and_then(|r| r.as_object())
+            .and_then(|r| r.keys().next())
+        else {
+            return;
+        };
+        let Some(scheme) = self
+            .doc
+            .pointer(&format!("/components/securitySchemes/{}", name))
+            .map(|s| self.resolve(s))
+        else {
+            return;
+        };
+        let s = |k: &str| {
+            scheme
+                .get(k)
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string()
+        };
+        match (s("type").as_str(), s("scheme").to_lowercase().as_str()) {
+            ("http", "basic") => {
+                info.auth = HttpAuth::Basic {
+                    username: String::new(),
+                    password: String::new(),
+                }
+            }
+            ("http", "bearer") => {
+                info.auth = HttpAuth::Bearer {
+                    var_name: String::from("access_token"),
+                }
+            }
+            ("apiKey", _) => match s("in").as_str() {
+                "header" => {
+                    info.auth = HttpAuth::ApiKey {
+                        header_name: s("name"),
+                        value: String::new(),
+                    }
+                }
+                "query" => info.query_params.push(param(&s("name"), "")),
+                _ => {}
+            },
+            ("oauth2", _) => {
+                if let Some(flow) = scheme.pointer("/flows/clientCredentials") {
+                    info.auth = HttpAuth::OAuth2ClientCredentials {
+                        token_url: flow
+                            .get("tokenUrl")
+                            .and_then(|u| u.as_str())
+                            .unwrap_or("")
+                            .to_string(),
+                        client_id: String::new(),
+                        client_secret: String::new(),
+                        scope: flow
+                            .get("scopes")
+                            .and_then(|s| s.as_object())
+                            .map(|s| s.keys().cloned().collect::<Vec<_>>().join(" "))
+                            .unwrap_or_default(),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_body(&self, info: &mut HttpReqInfo, op: &Value) {
+        let Some(content) = op
+            .get("requestBody")
+            .map(|b| self.resolve(b))
+            .and_then(|b| b.get("content"))
+            .and_then(|c| c.as_object())
+        else {
+            return;
+        };
+        let Some((media_type, media)) = content
+            .iter()
+            .find(|(t, _)| t.contains("json"))
+            .or_else(|| content.iter().next())
+        else {
+            return;
+        };
+        let sample = match media.get("example") {
+            Some(e) => e.clone(),
+            None => media
+                .get("schema")
+                .map_or(Value::Null, |s| self.sample(s, 0)),
+        };
+        if media_type.contains("json") {
+            info.post_content_type = PostContentType::JSON;
+            if !sample.is_null() {
+                info.request_body = serde_json::to_string_pretty(&sample).unwrap_or_default();
+            }
+        } else {
+            info.post_content_type = if media_type.starts_with("multipart/") {
+                PostContentType::Multipart
+            } else {
+                PostContentType::UrlEncoded
+            };
+            if let Some(o) = sample.as_object() {
+                for (k, v) in o.iter() {
+                    let v = match v {
+                        Value::String(s) => s.clone(),
+                        Value::Null => String::new(),
+                        v => v.to_string(),
+                    };
+                    info.form_data.push(param(k, &v));
+                }
+            }
+        }
+    }
+
+    fn to_info(&self, path: &str, method: Method, path_item: &Value, op: &Value) -> HttpReqInfo {
+        let name = op.get("operationId").and_then(|o| o.as_str()).map_or_else(
+            || format!("{} {}", op_key_method(&method), path),
+            String::from,
+        );
+        let mut info = new_info(name, method);
+        info.description = op
+            .get("summary")
+            .or_else(|| op.get("description"))
+            .and_then(|s| s.as_str())
+            .unwrap_or("")
+            .to_string();
+        let mut url = self.base_url();
+        // Path params become template variables, e.g. /pets/{{ petId }}
+        let mut p = String::with_capacity(path.len() + 16);
+        for seg in path.split('/').filter(|s| !s.is_empty()) {
+            p.push('/');
+            match seg.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(var) => {
+                    p.push_str("{{ ");
+                    p.push_str(var);
+                    p.push_str(" }}");
+                }
+                None => p.push_str(seg),
+            }
+        }
+        url.push_str(&p);
+        set_url(&mut info, &url);
+        let params = path_item
+            .get("parameters")
+            .and_then(|p| p.as_array())
+            .into_iter()
+            .chain(op.get("parameters").and_then(|p| p.as_array()))
+            .flatten()
+            .map(|p| self.resolve(p));
+        for p in params {
+            let name = p.get("name").and_then(|n| n.as_str()).unwrap_or("");
+            if name.is_empty() {
+                continue;
+            }
+            let value = match p
+                .get("example")
+                .cloned()
+                .or_else(|| p.get("schema").map(|s| self.sample(s, 0)))
+            {
+                Some(Value::String(s)) => s,
+                Some(Value::Null) | None => String::new(),
+                Some(v) => v.to_string(),
+            };
+            match p.get("in").and_then(|i| i.as_str()) {
+                Some("query") if !info.query_params.iter().any(|q| q.name == name) => {
+                    info.query_params.push(param(name, &value));
+                }
+                Some("header") => info.headers.push(param(name, &value)),
+                _ => {}
+            }
+        }
+        self.apply_body(&mut info, op);
+        self.apply_security(&mut info, op);
+        info
+    }
+
+    // Operations are selected by operationId or by `METHOD /path`, none selected means all
+    fn convert(&self, selected: &[&str]) -> Result<Vec<HttpReqInfo>> {
+        let Some(paths) = self.doc.get("paths").and_then(|p| p.as_object()) else {
+            return Err(Error::ErrorWithMessage(String::from(
+                "The document has no paths",
+            )));
+        };
+        let mut infos = Vec::new();
+        for (path, item) in paths.iter() {
+            let item = self.resolve(item);
+            let Some(ops) = item.as_object() else {
+                continue;
+            };
+            for (m, op) in ops.iter() {
+                let Some(method) = parse_method(m) else {
+                    continue;
+                };
+                let key = format!("{} {}", m.to_uppercase(), path);
+                let op_id = op.get("operationId").and_then(|o| o.as_str()).unwrap_or("");
+                if selected.is_empty()
+                    || selected
+                        .iter()
+                        .any(|s| s.eq_ignore_ascii_case(&key) || (!op_id.is_empty() && *s == op_id))
+                {
+                    infos.push(self.to_info(path, method, item, op));
+                }
+            }
+        }
+        Ok(infos)
+    }
+}
+
+fn op_key_method(m: &Method) -> &'static str {
+    match m {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::PATCH => "PATCH",
+        Method::DELETE => "DELETE",
+    }
+}
+
+pub(crate) fn from_openapi(doc: &[u8], selected: &[&str]) -> Result<Vec<HttpReqInfo>> {
+    let doc: Value = serde_json::from_slice(doc).map_err(|e| {
+        Error::ErrorWithMessage(format!(
+            "Only OpenAPI 3 documents in JSON format are supported: {}",
+            e
+        ))
+    })?;
+    if !doc
+        .get("openapi")
+        .and_then(|v| v.as_str())
+        .is_some_and(|v| v.starts_with('3'))
+    {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Only OpenAPI 3 documents are supported",
+        )));
+    }
+    OpenApi { doc: &doc }.convert(selected)
+}
+
+fn option_value<'a>(
+    args: &'a [String],
+    idx: &mut usize,
+    arg: &'a str,
+    long: &str,
+) -> Result<&'a str> {
+    // --data=abc
+    if let Some(v) = arg.strip_prefix(long).and_then(|v| v.strip_prefix('=')) {
+        return Ok(v);
+    }
+    *idx += 1;
+    args.get(*idx)
+        .map(|s| s.as_str())
+        .ok_or_else(|| Error::ErrorWithMessage(format!("Option {} needs a value", arg)))
+}
+
+fn name_of(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(n, _)| n)
+}
+
+pub(crate) fn from_curl(command: &str) -> Result<HttpReqInfo> {
+    let command = command.replace("\\\r\n", " ").replace("\\\n", " ");
+    let args = shlex::split(&command)
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Invalid cURL command")))?;
+    let mut idx = match args.first() {
+        Some(c) if c == "curl" => 1,
+        _ => 0,
+    };
+    let mut info = new_info(String::new(), Method::GET);
+    let mut method: Option<Method> = None;
+    let mut url = String::new();
+    let mut data: Vec<String> = Vec::new();
+    let mut as_query = false;
+    let mut content_type = String::new();
+    while idx < args.len() {
+        let arg = args[idx].as_str();
+        match name_of(arg) {
+            "-X" | "--request" => {
+                let m = option_value(&args, &mut idx, arg, "--request")?;
+                method =
+                    Some(parse_method(m).ok_or_else(|| {
+                        Error::ErrorWithMessage(format!("Unsupported method {}", m))
+                    })?);
+            }
+            "-H" | "--header" => {
+                let h = option_value(&args, &mut idx, arg, "--header")?;
+                if let Some((n, v)) = h.split_once(':') {
+                    let (n, v) = (n.trim(), v.trim());
+                    if n.eq_ignore_ascii_case("content-type") {
+                        content_type = v.to_lowercase();
+                    } else if n.eq_ignore_ascii_case("user-agent") {
+                        info.user_agent = String::from(v);
+                    } else {
+                        info.headers.push(param(n, v));
+                    }
+                }
+            }
+            "-d" | "--data" | "--data-raw" | "--data-binary" | "--data-ascii" => {
+                let long = name_of(arg);
+                data.push(String::from(option_value(&args, &mut idx, arg, long)?));
+            }
+            "--data-urlencode" => {
+                let v = option_value(&args, &mut idx, arg, "--data-urlencode")?;
+                let (n, v) = v.split_once('=').unwrap_or(("", v));
+                info.form_data.push(param(n, v));
+                info.post_content_type = PostContentType::UrlEncoded;
+            }
+            "-F" | "--form" => {
+                let v = option_value(&args, &mut idx, arg, "--form")?;
+                let (n, v) = v.split_once('=').unwrap_or((v, ""));
+                info.form_data.push(param(n, v));
+                info.post_content_type = PostContentType::Multipart;
+            }
+            "-u" | "--user" => {
+                let v = option_value(&args, &mut idx, arg, "--user")?;
+                let (u, p) = v.split_once(':').unwrap_or((v, ""));
+                info.auth = HttpAuth::Basic {
+                    username: String::from(u),
+                    password: String::from(p),
+                };
+            }
+            "-A" | "--user-agent" => {
+                info.user_agent = String::from(option_value(&args, &mut idx, arg, "--user-agent")?);
+            }
+            "-b" | "--cookie" => {
+                let v = option_value(&args, &mut idx, arg, "--cookie")?;
+                info.headers.push(param("Cookie", v));
+            }
+            "-x" | "--proxy" => {
+                info.proxy_url = String::from(option_value(&args, &mut idx, arg, "--proxy")?);
+            }
+            "-m" | "--max-time" => {
+                let v = option_value(&args, &mut idx, arg, "--max-time")?;
+                if let Ok(secs) = v.parse::<f64>() {
+                    info.timeout_milliseconds = (secs * 1000f64) as u64;
+                }
+            }
+            "--connect-timeout" => {
+                let v = option_value(&args, &mut idx, arg, "--connect-timeout")?;
+                if let Ok(secs) = v.parse::<f64>() {
+                    info.connect_timeout_milliseconds = (secs * 1000f64) as u64;
+                }
+            }
+            "--url" => url = String::from(option_value(&args, &mut idx, arg, "--url")?),
+            "-G" | "--get" => as_query = true,
+            // Output and transfer options which do not change the request
+            "-o" | "--output" | "-w" | "--write-out" | "-e" | "--referer" => {
+                option_value(&args, &mut idx, arg, name_of(arg))?;
+            }
+            a if a.starts_with('-') => {}
+            _ => url = String::from(arg),
+        }
+        idx += 1;
+    }
+    if url.is_empty() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "The cURL command has no URL",
+        )));
+    }
+    if as_query && !data.is_empty() {
+        let sep = if url.contains('?') { '&' } else { '?' };
+        url = format!("{}{}{}", url, sep, data.join("&"));
+        data.clear();
+    }
+    set_url(&mut info, &url);
+    let has_body = !data.is_empty() || !info.form_data.is_empty();
+    info.method = method.unwrap_or(if has_body { Method::POST } else { Method::GET });
+    if !data.is_empty() {
+        let body = data.join("&");
+        let looks_like_json = body.trim_start().starts_with(['{', '[']);
+        if content_type.contains("json") || (content_type.is_empty() && looks_like_json) {
+            info.post_content_type = PostContentType::JSON;
+        } else {
+            info.post_content_type = PostContentType::UrlEncoded;
+        }
+        info.request_body = body;
+    } else if content_type.contains("json") {
+        info.post_content_type = PostContentType::JSON;
+    } else if !content_type.is_empty() && info.form_data.is_empty() {
+        info.headers.push(param("Content-Type", &content_type));
+    }
+    info.name = format!(
+        "{} /{}",
+        op_key_method(&info.method),
+        info.address.split_once('/').map_or("", |(_, p)| p)
+    );
+    Ok(info)
+}
+
+fn save(robot_id: &str, infos: &[HttpReqInfo]) -> Result<()> {
+    for info in infos.iter() {
+        db_executor!(db::write, robot_id, TABLE_SUFFIX, &info.id, info)?;
+    }
+    Ok(())
+}
+
+fn import(
+    q: &HashMap<String, String>,
+    f: impl FnOnce() -> Result<Vec<HttpReqInfo>>,
+) -> Result<Vec<HttpReqInfo>> {
+    let robot_id = q
+        .get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))?;
+    let infos = f()?;
+    if q.get("dryRun").is_none_or(|d| d != "true") {
+        save(robot_id, &infos)?;
+    }
+    Ok(infos)
+}
+
+#[utoipa::path(
+    post,
+    path = "/external/http/import/openapi",
+    tag = "external",
+    summary = "Import HTTP APIs from an OpenAPI document",
+    params(
+        ("robotId" = String, Query),
+        ("operations" = Option<String>, Query),
+        ("dryRun" = Option<String>, Query),
+    ),
+    request_body(content = inline(Binary), content_type = "application/json"),
+    responses((status = 200, body = ResponseData<Vec<HttpReqInfo>>))
+)]
+pub(crate) async fn import_openapi(
+    Query(q): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let selected: Vec<&str> = q
+        .get("operations")
+        .map(|o| {
+            o.split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    to_res(import(&q, || from_openapi(body.as_ref(), &selected)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/external/http/import/curl",
+    tag = "external",
+    summary = "Import an HTTP API from a cURL command",
+    params(
+        ("robotId" = String, Query),
+        ("name" = Option<String>, Query),
+        ("dryRun" = Option<String>, Query),
+    ),
+    request_body(content = inline(Binary), content_type = "text/plain"),
+    responses((status = 200, body = ResponseData<Vec<HttpReqInfo>>))
+)]
+pub(crate) async fn import_curl(
+    Query(q): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> impl IntoResponse {
+    to_res(import(&q, || {
+        let command = String::from_utf8_lossy(body.as_ref());
+        let mut info = from_curl(&command)?;
+        if let Some(name) = q.get("name").filter(|n| !n.is_empty()) {
+            info.name = name.clone();
+        }
+        Ok(vec![info])
+    }))
+}
@@ -1,5 +1,7 @@
+pub(crate) mod circuit_breaker;
 pub(crate) mod client;
 pub(crate) mod crud;
 pub(crate) mod dto;
+pub(crate) mod import;
 
 pub(crate) use client::get_client;
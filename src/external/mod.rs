@@ -1 +1,5 @@
+pub(crate) mod channel;
+pub(crate) mod email;
 pub(crate) mod http;
+pub(crate) mod notification;
+pub(crate) mod webhook;
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct NotificationProvider {
+    // Referenced by send notification nodes
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) channel: NotificationChannel,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) enum NotificationChannel {
+    // Gateway is called once for every recipient
+    Sms(HttpNotificationTemplate),
+    // Incoming webhook of a chat app, called once when there is no recipient
+    InstantMessaging(HttpNotificationTemplate),
+    // Sent with the SMTP settings of the robot, from the SMTP username when `from` is empty
+    Email {
+        #[serde(default)]
+        from: String,
+    },
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct NotificationHeader {
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+// URL, header values and body can use {{ recipient }}, {{ subject }}, {{ content }}
+// and session variables, e.g. {"to": "{{ recipient }}", "text": {{ content|tojson }}}
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct HttpNotificationTemplate {
+    #[serde(default = "default_method")]
+    pub(crate) method: String,
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) headers: Vec<NotificationHeader>,
+    // Instant messaging sends {"text": content} when it is empty
+    #[serde(default)]
+    pub(crate) body: String,
+    #[serde(rename = "contentType", default = "default_content_type")]
+    pub(crate) content_type: String,
+    #[serde(rename = "timeoutMilliseconds", default = "default_timeout")]
+    pub(crate) timeout_milliseconds: u64,
+    // Some gateways respond 200 for rejected messages, so the body must contain it when not empty
+    #[serde(rename = "successKeyword", default)]
+    pub(crate) success_keyword: String,
+}
+
+fn default_method() -> String {
+    String::from("POST")
+}
+
+fn default_content_type() -> String {
+    String::from("application/json")
+}
+
+fn default_timeout() -> u64 {
+    5000
+}
+
+pub(crate) struct Notification {
+    pub(crate) recipients: Vec<String>,
+    pub(crate) subject: String,
+    pub(crate) content: String,
+}
@@ -0,0 +1,2 @@
+pub(crate) mod dto;
+pub(crate) mod sender;
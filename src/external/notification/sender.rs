@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Method;
+
+use super::dto::{
+    HttpNotificationTemplate, Notification, NotificationChannel, NotificationProvider,
+};
+use crate::external::email;
+use crate::external::http::client::get_client;
+use crate::flow::rt::template;
+use crate::man::settings::Settings;
+use crate::result::{Error, Result};
+use crate::variable::dto::VariableValue;
+
+const MAX_ERROR_BODY_LEN: usize = 200;
+
+pub(crate) fn find_provider<'a>(
+    settings: &'a Settings,
+    provider_id: &str,
+) -> Result<&'a NotificationProvider> {
+    settings
+        .notification_providers
+        .iter()
+        .find(|p| p.id.eq(provider_id))
+        .ok_or_else(|| {
+            Error::ErrorWithMessage(format!(
+                "Notification provider {} was not found",
+                provider_id
+            ))
+        })
+}
+
+fn method(t: &HttpNotificationTemplate) -> Result<Method> {
+    Method::from_bytes(t.method.to_uppercase().as_bytes())
+        .map_err(|_| Error::ErrorWithMessage(format!("Invalid HTTP method: {}", &t.method)))
+}
+
+// Called when saving settings
+pub(crate) fn check_providers(providers: &[NotificationProvider]) -> Result<()> {
+    for (i, p) in providers.iter().enumerate() {
+        if p.id.is_empty() || p.name.is_empty() {
+            return Err(Error::ErrorWithMessage(String::from(
+                "Notification provider id and name are required",
+            )));
+        }
+        if providers[..i].iter().any(|o| o.id.eq(&p.id)) {
+            return Err(Error::ErrorWithMessage(format!(
+                "Duplicate notification provider id: {}",
+                &p.id
+            )));
+        }
+        let t = match &p.channel {
+            NotificationChannel::Sms(t) | NotificationChannel::InstantMessaging(t) => t,
+            NotificationChannel::Email { .. } => continue,
+        };
+        if t.url.is_empty() {
+            return Err(Error::ErrorWithMessage(format!(
+                "URL of notification provider {} is missing",
+                &p.name
+            )));
+        }
+        method(t)?;
+        // Only placeholders are replaced in the URL, so it is not a template
+        for text in std::iter::once(&t.body).chain(t.headers.iter().map(|h| &h.value)) {
+            template::check(text).map_err(|e| match e {
+                Error::ErrorWithMessage(m) => {
+                    Error::ErrorWithMessage(format!("Notification provider {}: {}", &p.name, m))
+                }
+                e => e,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+async fn request(
+    t: &HttpNotificationTemplate,
+    default_body: Option<&str>,
+    vars: &HashMap<String, VariableValue>,
+) -> Result<()> {
+    let url = template::render_url(&t.url, vars)?;
+    let client = get_client(t.timeout_milliseconds, t.timeout_milliseconds, "")?;
+    let mut req = client.request(method(t)?, url);
+    for h in t.headers.iter() {
+        req = req.header(&h.name, template::render_with_vars(&h.value, vars, false)?);
+    }
+    let body = if t.body.is_empty() {
+        default_body.map(String::from)
+    } else {
+        Some(template::render_with_vars(&t.body, vars, false)?)
+    };
+    if let Some(body) = body {
+        req = req.header(CONTENT_TYPE, &t.content_type).body(body);
+    }
+    let res = req.send().await?;
+    let status = res.status();
+    let text = res.text().await?;
+    if !status.is_success() {
+        let text: String = text.chars().take(MAX_ERROR_BODY_LEN).collect();
+        return Err(Error::ErrorWithMessage(format!(
+            "Notification provider responded status {}: {}",
+            status.as_u16(),
+            text
+        )));
+    }
+    if !t.success_keyword.is_empty() && !text.contains(&t.success_keyword) {
+        let text: String = text.chars().take(MAX_ERROR_BODY_LEN).collect();
+        return Err(Error::ErrorWithMessage(format!(
+            "Notification was rejected: {}",
+            text
+        )));
+    }
+    Ok(())
+}
+
+async fn send_http(
+    t: &HttpNotificationTemplate,
+    default_body: Option<String>,
+    n: &Notification,
+    mut vars: HashMap<String, VariableValue>,
+) -> Result<()> {
+    vars.insert(
+        String::from("subject"),
+        VariableValue::Str(n.subject.clone()),
+    );
+    vars.insert(
+        String::from("content"),
+        VariableValue::Str(n.content.clone()),
+    );
+    if n.recipients.is_empty() {
+        vars.insert(String::from("recipient"), VariableValue::Str(String::new()));
+        return request(t, default_body.as_deref(), &vars).await;
+    }
+    for r in n.recipients.iter() {
+        vars.insert(String::from("recipient"), VariableValue::Str(r.clone()));
+        request(t, default_body.as_deref(), &vars).await?;
+    }
+    Ok(())
+}
+
+fn outgoing_email(from: &str, settings: &Settings, n: &Notification) -> email::dto::OutgoingEmail {
+    email::dto::OutgoingEmail {
+        from: if from.is_empty() {
+            settings.smtp_username.clone()
+        } else {
+            String::from(from)
+        },
+        to: n.recipients.clone(),
+        cc: Vec::new(),
+        bcc: Vec::new(),
+        subject: n.subject.clone(),
+        text_body: n.content.clone(),
+        html_body: None,
+        attachments: Vec::new(),
+        in_reply_to: None,
+        references: Vec::new(),
+    }
+}
+
+// Asynchronous emails go to the outbox, other asynchronous notifications are sent in background
+pub(crate) fn send(
+    robot_id: &str,
+    session_id: &str,
+    settings: &Settings,
+    provider: &NotificationProvider,
+    n: Notification,
+    vars: &HashMap<String, VariableValue>,
+    async_send: bool,
+) -> Result<()> {
+    let (t, default_body) = match &provider.channel {
+        NotificationChannel::Email { from } => {
+            if settings.smtp_host.is_empty() {
+                return Err(Error::ErrorWithMessage(String::from(
+                    "SMTP is not configured",
+                )));
+            }
+            if n.recipients.is_empty() {
+                return Err(Error::ErrorWithMessage(String::from(
+                    "No email recipient after rendering",
+                )));
+            }
+            let email = outgoing_email(from, settings, &n);
+            if email.from.is_empty() {
+                return Err(Error::ErrorWithMessage(String::from(
+                    "Sender address of the email provider is missing",
+                )));
+            }
+            if async_send {
+                return email::outbox::enqueue(robot_id, session_id, &email);
+            }
+            let r = tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(email::smtp::send(settings, &email))
+            });
+            email::outbox::record(robot_id, session_id, &email, &r)?;
+            return r;
+        }
+        NotificationChannel::Sms(t) => {
+            if n.recipients.is_empty() {
+                return Err(Error::ErrorWithMessage(String::from(
+                    "No phone number after rendering",
+                )));
+            }
+            (t, None)
+        }
+        NotificationChannel::InstantMessaging(t) => (
+            t,
+            Some(serde_json::json!({ "text": &n.content }).to_string()),
+        ),
+    };
+    if async_send {
+        let t = t.clone();
+        let vars = vars.clone();
+        let name = provider.name.clone();
+        tokio::spawn(async move {
+            if let Err(e) = send_http(&t, default_body, &n, vars).await {
+                log::error!("Sending notification by {} failed: {:?}", &name, &e);
+            }
+        });
+        return Ok(());
+    }
+    tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(send_http(t, default_body, &n, vars.clone()))
+    })
+}
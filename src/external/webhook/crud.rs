@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use axum::extract::{Path, Query};
+use axum::response::IntoResponse;
+use axum::Json;
+use utoipa::TupleUnit;
+
+use super::dto::{Webhook, WebhookDelivery, WebhookDeliveryStatus};
+use crate::db;
+use crate::db_executor;
+use crate::result::{Error, Result};
+use crate::web::server::{to_res, ResponseData};
+
+pub(crate) const TABLE_SUFFIX: &str = "webhooks";
+pub(crate) const DELIVERY_TABLE_SUFFIX: &str = "webhookDeliveries";
+const DEFAULT_LOG_LIMIT: usize = 100;
+
+fn robot_id(q: &HashMap<String, String>) -> Result<&String> {
+    q.get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))
+}
+
+pub(crate) fn list_webhooks(robot_id: &str) -> Result<Vec<Webhook>> {
+    match db_executor!(db::get_all, robot_id, TABLE_SUFFIX,) {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Ok(Vec::new()),
+        r => r,
+    }
+}
+
+pub(crate) fn get_webhook(robot_id: &str, id: &str) -> Result<Option<Webhook>> {
+    match db_executor!(db::query, robot_id, TABLE_SUFFIX, id) {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Ok(None),
+        r => r,
+    }
+}
+
+pub(crate) fn delete_tables(robot_id: &str) -> Result<()> {
+    db_executor!(db::delete_table, robot_id, TABLE_SUFFIX,)?;
+    db_executor!(db::delete_table, robot_id, DELIVERY_TABLE_SUFFIX,)
+}
+
+#[utoipa::path(
+    get,
+    path = "/external/webhook",
+    tag = "external",
+    summary = "List webhooks",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<Webhook>>))
+)]
+pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(robot_id(&q).and_then(|robot_id| list_webhooks(robot_id)))
+}
+
+fn save_webhook(q: &HashMap<String, String>, mut w: Webhook) -> Result<String> {
+    let robot_id = robot_id(q)?;
+    if !w.url.starts_with("http://") && !w.url.starts_with("https://") {
+        return Err(Error::ErrorWithMessage(String::from(
+            "The URL of webhook must start with http:// or https://",
+        )));
+    }
+    if w.events.is_empty() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Please subscribe at least one event.",
+        )));
+    }
+    if w.id.is_empty() || w.id.eq("new") {
+        w.id = scru128::new_string();
+    }
+    db_executor!(db::write, robot_id, TABLE_SUFFIX, &w.id, &w)?;
+    Ok(w.id)
+}
+
+#[utoipa::path(
+    post,
+    path = "/external/webhook",
+    tag = "external",
+    summary = "Create or update a webhook",
+    params(("robotId" = String, Query)),
+    request_body = Webhook,
+    responses((status = 200, body = ResponseData<String>))
+)]
+pub(crate) async fn save(
+    Query(q): Query<HashMap<String, String>>,
+    Json(w): Json<Webhook>,
+) -> impl IntoResponse {
+    to_res(save_webhook(&q, w))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/external/webhook/{id}",
+    tag = "external",
+    summary = "Delete a webhook",
+    params(("id" = String, Path), ("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn remove(
+    Query(q): Query<HashMap<String, String>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    // Queued deliveries of the webhook are marked failed by the delivery task
+    to_res(
+        robot_id(&q)
+            .and_then(|robot_id| db_executor!(db::remove, robot_id, TABLE_SUFFIX, id.as_str())),
+    )
+}
+
+fn list_deliveries(q: &HashMap<String, String>) -> Result<Vec<WebhookDelivery>> {
+    let robot_id = robot_id(q)?;
+    let status = match q.get("status").map(|s| s.as_str()) {
+        Some("Pending") => Some(WebhookDeliveryStatus::Pending),
+        Some("Delivered") => Some(WebhookDeliveryStatus::Delivered),
+        Some("Failed") => Some(WebhookDeliveryStatus::Failed),
+        Some(s) if !s.is_empty() => {
+            return Err(Error::ErrorWithMessage(format!("Unknown status: {}", s)));
+        }
+        _ => None,
+    };
+    let limit = q
+        .get("limit")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LOG_LIMIT);
+    let r: Vec<WebhookDelivery> = match db_executor!(db::get_all, robot_id, DELIVERY_TABLE_SUFFIX,)
+    {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Vec::new(),
+        r => r?,
+    };
+    // Ids are time ordered, so the newest deliveries come first
+    Ok(r.into_iter()
+        .rev()
+        .filter(|d| q.get("webhookId").is_none_or(|id| d.webhook_id.eq(id)))
+        .filter(|d| status.is_none_or(|s| d.status == s))
+        .take(limit)
+        .collect())
+}
+
+#[utoipa::path(
+    get,
+    path = "/external/webhook/delivery",
+    tag = "external",
+    summary = "List webhook deliveries, newest first",
+    params(
+        ("robotId" = String, Query),
+        ("webhookId" = Option<String>, Query),
+        ("status" = Option<String>, Query),
+        ("limit" = Option<String>, Query),
+    ),
+    responses((status = 200, body = ResponseData<Vec<WebhookDelivery>>))
+)]
+pub(crate) async fn deliveries(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(list_deliveries(&q))
+}
+
+fn redeliver_one(q: &HashMap<String, String>, id: &str) -> Result<()> {
+    let robot_id = robot_id(q)?;
+    let d: Option<WebhookDelivery> = db_executor!(db::query, robot_id, DELIVERY_TABLE_SUFFIX, id)?;
+    match d {
+        Some(mut d) => super::delivery::requeue(robot_id, &mut d),
+        None => Err(Error::ErrorWithMessage(format!(
+            "Webhook delivery {} was not found",
+            id
+        ))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/external/webhook/delivery/{id}/redeliver",
+    tag = "external",
+    summary = "Queue a webhook delivery again",
+    params(("id" = String, Path), ("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn redeliver(
+    Query(q): Query<HashMap<String, String>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    to_res(redeliver_one(&q, &id))
+}
@@ -0,0 +1,287 @@
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::Notify;
+
+use super::crud::{self, DELIVERY_TABLE_SUFFIX};
+use super::dto::{Webhook, WebhookDelivery, WebhookDeliveryStatus, WebhookEvent};
+use crate::db;
+use crate::db_executor;
+use crate::result::{Error, Result};
+use crate::robot::dto::RobotData;
+
+// Pending deliveries of all robots, values are (delivery id, robot id)
+const QUEUE_TABLE: redb::TableDefinition<&str, &[u8]> = redb::TableDefinition::new("webhookQueue");
+const POLL_INTERVAL_SECS: u64 = 5;
+const FIRST_RETRY_DELAY_SECS: u64 = 10;
+const MAX_RETRY_DELAY_SECS: u64 = 3600;
+// Delivered and failed log entries are kept for a week
+const LOG_RETENTION_SECS: u64 = 7 * 86400;
+const PRUNE_INTERVAL_SECS: u64 = 3600;
+
+static NOTIFY: LazyLock<Notify> = LazyLock::new(Notify::new);
+// Ids of deliveries being attempted, so a slow webhook is not attempted again by the next poll
+static IN_FLIGHT: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::with_capacity(32)));
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// Queues the event for every enabled webhook which subscribes it, failures never break the conversation
+pub(crate) fn emit(robot_id: &str, session_id: &str, event: WebhookEvent, data: serde_json::Value) {
+    if let Err(e) = enqueue(robot_id, session_id, event, data) {
+        log::error!("Queueing webhook event {:?} failed: {:?}", event, &e);
+    }
+}
+
+fn enqueue(
+    robot_id: &str,
+    session_id: &str,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) -> Result<()> {
+    let webhooks = crud::list_webhooks(robot_id)?;
+    let now = now();
+    let mut queued = false;
+    for w in webhooks
+        .iter()
+        .filter(|w| w.enabled && w.events.contains(&event))
+    {
+        let id = scru128::new_string();
+        let payload = serde_json::json!({
+            "id": &id,
+            "event": event,
+            "robotId": robot_id,
+            "sessionId": session_id,
+            "timestamp": now,
+            "data": &data,
+        });
+        let d = WebhookDelivery {
+            id,
+            webhook_id: w.id.clone(),
+            event,
+            payload: payload.to_string(),
+            status: WebhookDeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_status_code: 0,
+            last_error: String::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        push(robot_id, &d)?;
+        queued = true;
+    }
+    if queued {
+        NOTIFY.notify_one();
+    }
+    Ok(())
+}
+
+fn push(robot_id: &str, d: &WebhookDelivery) -> Result<()> {
+    db_executor!(db::write, robot_id, DELIVERY_TABLE_SUFFIX, &d.id, d)?;
+    db::write(QUEUE_TABLE, &d.id, &(&d.id, robot_id))
+}
+
+pub(crate) fn requeue(robot_id: &str, d: &mut WebhookDelivery) -> Result<()> {
+    d.status = WebhookDeliveryStatus::Pending;
+    d.attempts = 0;
+    d.next_attempt_at = now();
+    d.updated_at = d.next_attempt_at;
+    push(robot_id, d)?;
+    NOTIFY.notify_one();
+    Ok(())
+}
+
+// `X-Dialogflow-Signature` is HMAC-SHA256 of `{timestamp}.{body}`, receivers should also reject stale timestamps
+pub(crate) fn sign(secret: &str, timestamp: u64, payload: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|e| Error::ErrorWithMessage(e.to_string()))?;
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+async fn send(w: &Webhook, d: &WebhookDelivery) -> std::result::Result<u16, String> {
+    let client =
+        crate::external::http::get_client(w.timeout_milliseconds, w.timeout_milliseconds, "")
+            .map_err(|e| format!("{:?}", e))?;
+    let timestamp = now();
+    let mut req = client
+        .post(&w.url)
+        .timeout(Duration::from_millis(w.timeout_milliseconds))
+        .header("Content-Type", "application/json")
+        .header("X-Dialogflow-Event", format!("{:?}", d.event))
+        .header("X-Dialogflow-Delivery", &d.id)
+        .header("X-Dialogflow-Timestamp", timestamp);
+    if !w.secret.is_empty() {
+        let signature = sign(&w.secret, timestamp, &d.payload).map_err(|e| format!("{:?}", e))?;
+        req = req.header("X-Dialogflow-Signature", signature);
+    }
+    let res = req
+        .body(d.payload.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(res.status().as_u16())
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    let factor = 1u64 << attempts.saturating_sub(1).min(16);
+    FIRST_RETRY_DELAY_SECS
+        .saturating_mul(factor)
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+async fn attempt(robot_id: String, mut d: WebhookDelivery) -> Result<()> {
+    let webhook = crud::get_webhook(&robot_id, &d.webhook_id)?;
+    d.attempts += 1;
+    let max_attempts = match &webhook {
+        Some(w) => {
+            match send(w, &d).await {
+                Ok(code) => {
+                    d.last_status_code = code;
+                    if (200..300).contains(&code) {
+                        d.status = WebhookDeliveryStatus::Delivered;
+                        d.last_error.clear();
+                    } else {
+                        d.last_error = format!("Unexpected status code {}", code);
+                    }
+                }
+                Err(e) => {
+                    d.last_status_code = 0;
+                    d.last_error = e;
+                }
+            }
+            w.max_attempts
+        }
+        None => {
+            d.last_error = String::from("The webhook was removed");
+            0
+        }
+    };
+    d.updated_at = now();
+    if d.status == WebhookDeliveryStatus::Pending {
+        if d.attempts >= max_attempts {
+            d.status = WebhookDeliveryStatus::Failed;
+            log::warn!(
+                "Webhook delivery {} failed after {} attempts: {}",
+                &d.id,
+                d.attempts,
+                &d.last_error
+            );
+        } else {
+            d.next_attempt_at = d.updated_at + retry_delay(d.attempts);
+        }
+    }
+    db_executor!(db::write, &robot_id, DELIVERY_TABLE_SUFFIX, &d.id, &d)?;
+    if d.status != WebhookDeliveryStatus::Pending {
+        db::remove(QUEUE_TABLE, d.id.as_str())?;
+    }
+    Ok(())
+}
+
+// Every attempt runs in its own task, so one slow webhook never delays the others
+async fn deliver_due() -> Result<()> {
+    let queue: Vec<(String, String)> = match db::get_all(QUEUE_TABLE) {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => return Ok(()),
+        r => r?,
+    };
+    let now = now();
+    for (id, robot_id) in queue.into_iter() {
+        if IN_FLIGHT.lock()?.contains(&id) {
+            continue;
+        }
+        let d: Option<WebhookDelivery> =
+            match db_executor!(db::query, &robot_id, DELIVERY_TABLE_SUFFIX, id.as_str()) {
+                Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => None,
+                r => r?,
+            };
+        match d {
+            Some(d) if d.status == WebhookDeliveryStatus::Pending => {
+                if d.next_attempt_at <= now {
+                    IN_FLIGHT.lock()?.insert(id.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = attempt(robot_id, d).await {
+                            log::error!("Webhook delivery failed: {:?}", &e);
+                        }
+                        match IN_FLIGHT.lock() {
+                            Ok(mut l) => {
+                                l.remove(&id);
+                            }
+                            Err(e) => log::error!("{:?}", &e),
+                        }
+                    });
+                }
+            }
+            // The robot or the log entry was removed
+            _ => db::remove(QUEUE_TABLE, id.as_str())?,
+        }
+    }
+    Ok(())
+}
+
+// Removes delivered and failed log entries older than LOG_RETENTION_SECS
+fn prune_logs() -> Result<()> {
+    let robots: Vec<RobotData> = db::get_all(crate::robot::crud::TABLE)?;
+    let expired_before = now().saturating_sub(LOG_RETENTION_SECS);
+    for robot in robots.iter() {
+        let deliveries: Vec<WebhookDelivery> =
+            match db_executor!(db::get_all, &robot.robot_id, DELIVERY_TABLE_SUFFIX,) {
+                Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => continue,
+                r => r?,
+            };
+        let expired: Vec<String> = deliveries
+            .into_iter()
+            .filter(|d| d.status != WebhookDeliveryStatus::Pending && d.updated_at < expired_before)
+            .map(|d| d.id)
+            .collect();
+        if !expired.is_empty() {
+            log::info!(
+                "Pruning {} webhook delivery logs of robot {}",
+                expired.len(),
+                &robot.robot_id
+            );
+            db_executor!(
+                db::remove_keys,
+                &robot.robot_id,
+                DELIVERY_TABLE_SUFFIX,
+                &expired
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Queue is persisted, so deliveries left by the last run are resumed here
+pub(crate) async fn deliver_pending(mut recv: tokio::sync::oneshot::Receiver<()>) {
+    let mut pruned_at = 0u64;
+    loop {
+        tokio::select! {
+          _ = NOTIFY.notified() => {}
+          _ = tokio::time::sleep(Duration::from_secs(POLL_INTERVAL_SECS)) => {}
+          _ = &mut recv => {
+            break;
+          }
+        }
+        if let Err(e) = deliver_due().await {
+            log::error!("Delivering webhooks failed: {:?}", &e);
+        }
+        if now() >= pruned_at + PRUNE_INTERVAL_SECS {
+            pruned_at = now();
+            if let Err(e) = prune_logs() {
+                log::error!("Pruning webhook delivery logs failed: {:?}", &e);
+            }
+        }
+    }
+}
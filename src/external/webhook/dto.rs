@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub(crate) enum WebhookEvent {
+    SessionStarted,
+    SessionEnded,
+    VariableCollected,
+    IntentDetected,
+    HandOffRequested,
+    FlowTerminated,
+    FallbackTriggered,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct Webhook {
+    #[serde(default)]
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) url: String,
+    // Deliveries are signed with HMAC-SHA256 when it is not empty
+    #[serde(default)]
+    pub(crate) secret: String,
+    pub(crate) events: Vec<WebhookEvent>,
+    #[serde(default = "default_enabled")]
+    pub(crate) enabled: bool,
+    #[serde(rename = "timeoutMilliseconds", default = "default_timeout")]
+    pub(crate) timeout_milliseconds: u64,
+    // Including the first one
+    #[serde(rename = "maxAttempts", default = "default_max_attempts")]
+    pub(crate) max_attempts: u32,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_timeout() -> u64 {
+    3000
+}
+
+fn default_max_attempts() -> u32 {
+    6
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub(crate) enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct WebhookDelivery {
+    pub(crate) id: String,
+    #[serde(rename = "webhookId")]
+    pub(crate) webhook_id: String,
+    pub(crate) event: WebhookEvent,
+    // The exact body which was signed and posted
+    pub(crate) payload: String,
+    pub(crate) status: WebhookDeliveryStatus,
+    pub(crate) attempts: u32,
+    // Unix timestamps in seconds
+    #[serde(rename = "nextAttemptAt")]
+    pub(crate) next_attempt_at: u64,
+    #[serde(rename = "lastStatusCode")]
+    pub(crate) last_status_code: u16,
+    #[serde(rename = "lastError")]
+    pub(crate) last_error: String,
+    #[serde(rename = "createdAt")]
+    pub(crate) created_at: u64,
+    #[serde(rename = "updatedAt")]
+    pub(crate) updated_at: u64,
+}
@@ -0,0 +1,5 @@
+pub(crate) mod crud;
+pub(crate) mod delivery;
+pub(crate) mod dto;
+
+pub(crate) use delivery::emit;
@@ -5,13 +5,14 @@ use axum::extract::Query;
 use axum::{response::IntoResponse, Json};
 // use redb::TableDefinition;
 use std::sync::Mutex;
+use utoipa::TupleUnit;
 
 use super::dto::MainFlowDetail;
 use crate::db;
 use crate::db_executor;
 use crate::flow::subflow::crud as subflow;
 use crate::result::{Error, Result};
-use crate::web::server::to_res;
+use crate::web::server::{to_res, ResponseData};
 
 // const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("mainflows");
 pub(crate) const TABLE_SUFFIX: &str = "mainflows";
@@ -46,6 +47,14 @@ pub(crate) fn init(robot_id: &str) -> Result<MainFlowDetail> {
     create_main_flow(robot_id, &DEFAULT_NAMES.get().unwrap().0)
 }
 
+#[utoipa::path(
+    get,
+    path = "/mainflow",
+    tag = "flow",
+    summary = "List main flows",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<MainFlowDetail>>))
+)]
 pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
     // to_res::<Vec<MainFlowDetail>>(db::get_all(TABLE))
     if let Some(robot_id) = q.get("robotId") {
@@ -57,6 +66,15 @@ pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoR
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/mainflow",
+    tag = "flow",
+    summary = "Create a main flow",
+    params(("robotId" = String, Query)),
+    request_body = MainFlowDetail,
+    responses((status = 200, body = ResponseData<MainFlowDetail>))
+)]
 pub(crate) async fn new(
     Query(q): Query<HashMap<String, String>>,
     Json(data): Json<MainFlowDetail>,
@@ -94,6 +112,15 @@ fn create_main_flow(robot_id: &str, name: &str) -> Result<MainFlowDetail> {
     Ok(main_flow)
 }
 
+#[utoipa::path(
+    put,
+    path = "/mainflow",
+    tag = "flow",
+    summary = "Update a main flow",
+    params(("robotId" = String, Query)),
+    request_body = MainFlowDetail,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn save(
     Query(q): Query<HashMap<String, String>>,
     Json(data): Json<MainFlowDetail>,
@@ -118,6 +145,15 @@ pub(crate) async fn save(
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/mainflow",
+    tag = "flow",
+    summary = "Delete a main flow",
+    params(("robotId" = String, Query)),
+    request_body = MainFlowDetail,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn delete(
     Query(q): Query<HashMap<String, String>>,
     Json(data): Json<MainFlowDetail>,
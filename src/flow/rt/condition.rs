@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::str::FromStr;
 
 use bigdecimal::BigDecimal;
@@ -7,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use crate::flow::rt::context::Context;
 use crate::flow::rt::dto::{Request, UserInputResult};
 use crate::variable::crud as variable;
-use crate::variable::dto::VariableType;
+use crate::variable::dto::{parse_bool, parse_datetime, VariableValue};
 
 #[derive(
     Clone, Copy, Deserialize, Serialize, rkyv::Archive, rkyv::Deserialize, rkyv::Serialize,
@@ -50,45 +51,6 @@ pub(crate) enum TargetDataVariant {
     ZeroShotTextClassification,
 }
 
-// #[macro_export]
-macro_rules! compare_numbers {
-    ($req: expr, $ctx: expr, $ref_data: expr, $comparsion: tt, $self: ident) => ({
-        let r = variable::get(&$req.robot_id, $ref_data);
-        if r.is_err() {
-            log::error!("err");
-            return false;
-        }
-        let v = r.unwrap();
-        if v.is_none() {
-            return false;
-        }
-        let v = v.unwrap();
-        if v.var_type == VariableType::Str {
-            return false;
-        }
-        let val = v.get_value($req, $ctx);
-        if val.is_none() {
-            return false;
-        }
-        let val = val.unwrap();
-        let n1 = match BigDecimal::from_str(&val.val_to_string()) {
-            Ok(n) => n,
-            Err(e) => {
-                log::warn!("{:?}",&e);
-                return false;
-            }
-        };
-        let n2 = match BigDecimal::from_str(&$self.get_target_data($req, $ctx)) {
-            Ok(n) => n,
-            Err(e) => {
-                log::warn!("{:?}",&e);
-                return false;
-            }
-        };
-        n1 $comparsion n2
-    });
-}
-
 #[derive(Clone, Deserialize, Serialize, rkyv::Archive, rkyv::Deserialize, rkyv::Serialize)]
 #[rkyv(compare(PartialEq))]
 pub(crate) struct ConditionData {
@@ -101,6 +63,66 @@ pub(crate) struct ConditionData {
 }
 
 impl ConditionData {
+    fn get_ref_value(&self, req: &Request, ctx: &mut Context) -> Option<VariableValue> {
+        variable::get_path_value(&self.ref_data, req, ctx)
+    }
+    fn equals(&self, val: &VariableValue, target: &str) -> bool {
+        match val {
+            VariableValue::Num(n) => target.trim().parse::<f64>().is_ok_and(|t| t == *n),
+            VariableValue::Bool(b) => parse_bool(target) == Some(*b),
+            VariableValue::DateTime(t) => parse_datetime(target) == Some(*t),
+            _ => {
+                if self.case_sensitive_comparison {
+                    val.val_to_string().eq(target)
+                } else {
+                    unicase::eq(val.val_to_string().as_str(), target)
+                }
+            }
+        }
+    }
+    // None means the value can not contain anything
+    fn contains(&self, val: &VariableValue, target: &str) -> Option<bool> {
+        match val {
+            VariableValue::Str(s) => Some(if self.case_sensitive_comparison {
+                s.contains(target)
+            } else {
+                s.to_lowercase().contains(&target.to_lowercase())
+            }),
+            VariableValue::Array(arr) => Some(arr.iter().any(|v| self.equals(v, target))),
+            VariableValue::Object(o) => Some(o.contains_key(target)),
+            _ => None,
+        }
+    }
+    // Numbers and date times can be ordered, dates can also be compared with "now"
+    fn compare_order(&self, req: &Request, ctx: &mut Context) -> Option<Ordering> {
+        let val = self.get_ref_value(req, ctx)?;
+        let target = self.get_target_data(req, ctx);
+        match val {
+            VariableValue::Num(_) => {
+                let n1 = BigDecimal::from_str(&val.val_to_string()).ok()?;
+                let n2 = match BigDecimal::from_str(target.trim()) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        log::warn!("{:?}", &e);
+                        return None;
+                    }
+                };
+                n1.partial_cmp(&n2)
+            }
+            VariableValue::DateTime(t1) => {
+                let t2 = if target.trim().eq_ignore_ascii_case("now") {
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .ok()?
+                        .as_secs() as i64
+                } else {
+                    parse_datetime(&target)?
+                };
+                Some(t1.cmp(&t2))
+            }
+            _ => None,
+        }
+    }
     fn get_target_data(&self, req: &Request, ctx: &mut Context) -> String {
         match self.target_data_variant {
             TargetDataVariant::Const => self.target_data.clone(),
@@ -155,140 +177,47 @@ impl ConditionData {
                         .eq(req.user_input_intent.as_ref().unwrap())
             }
             ConditionType::FlowVariable => match self.compare_type {
-                CompareType::HasValue => {
-                    if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
-                        if let Some(v) = op {
-                            v.get_value(req, ctx).is_some()
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                }
-                CompareType::DoesNotHaveValue => {
-                    if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
-                        if let Some(v) = op {
-                            v.get_value(req, ctx).is_none()
-                        } else {
-                            true
-                        }
-                    } else {
-                        true
-                    }
-                }
-                CompareType::EmptyString => {
-                    if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
-                        if let Some(v) = op {
-                            if v.var_type == VariableType::Num {
-                                false
-                            } else {
-                                let val = v.get_value(req, ctx);
-                                val.is_none() || val.as_ref().unwrap().val_to_string().is_empty()
-                            }
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                }
+                CompareType::HasValue => self.get_ref_value(req, ctx).is_some(),
+                CompareType::DoesNotHaveValue => self.get_ref_value(req, ctx).is_none(),
+                CompareType::EmptyString => match self.get_ref_value(req, ctx) {
+                    Some(VariableValue::Str(s)) => s.is_empty(),
+                    Some(VariableValue::Array(arr)) => arr.is_empty(),
+                    Some(VariableValue::Object(o)) => o.is_empty(),
+                    Some(_) => false,
+                    None => true,
+                },
                 CompareType::Eq => {
-                    if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
-                        if let Some(v) = op {
-                            if let Some(val) = v.get_value(req, ctx) {
-                                if self.case_sensitive_comparison {
-                                    val.val_to_string().eq(&self.get_target_data(req, ctx))
-                                } else {
-                                    unicase::eq(
-                                        &val.val_to_string(),
-                                        &self.get_target_data(req, ctx),
-                                    )
-                                }
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
-                        }
+                    if let Some(val) = self.get_ref_value(req, ctx) {
+                        self.equals(&val, &self.get_target_data(req, ctx))
                     } else {
                         false
                     }
                 }
                 CompareType::NotEq => {
-                    if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
-                        if let Some(v) = op {
-                            if let Some(val) = v.get_value(req, ctx) {
-                                if self.case_sensitive_comparison {
-                                    !val.val_to_string().eq(&self.get_target_data(req, ctx))
-                                } else {
-                                    !unicase::eq(
-                                        &val.val_to_string(),
-                                        &self.get_target_data(req, ctx),
-                                    )
-                                }
-                            } else {
-                                true
-                            }
-                        } else {
-                            true
-                        }
+                    if let Some(val) = self.get_ref_value(req, ctx) {
+                        !self.equals(&val, &self.get_target_data(req, ctx))
                     } else {
                         true
                     }
                 }
                 CompareType::Contains => {
-                    if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
-                        if let Some(v) = op {
-                            if v.var_type == VariableType::Num {
-                                false
-                            } else {
-                                if let Some(val) = v.get_value(req, ctx) {
-                                    if self.case_sensitive_comparison {
-                                        val.val_to_string()
-                                            .contains(&self.get_target_data(req, ctx))
-                                    } else {
-                                        let mut s = val.val_to_string();
-                                        s.make_ascii_lowercase();
-                                        s.contains(&self.get_target_data(req, ctx).to_lowercase())
-                                    }
-                                    // val.val_to_string()
-                                    //     .find(&self.get_target_data(req, ctx))
-                                    //     .is_some()
-                                } else {
-                                    true
-                                }
-                            }
-                        } else {
-                            true
-                        }
+                    if let Some(val) = self.get_ref_value(req, ctx) {
+                        self.contains(&val, &self.get_target_data(req, ctx))
+                            .unwrap_or(false)
                     } else {
                         true
                     }
                 }
                 CompareType::NotContains => {
-                    if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
-                        if let Some(v) = op {
-                            if v.var_type == VariableType::Num {
-                                false
-                            } else {
-                                if let Some(val) = v.get_value(req, ctx) {
-                                    val.val_to_string()
-                                        .find(&self.get_target_data(req, ctx))
-                                        .is_none()
-                                } else {
-                                    true
-                                }
-                            }
-                        } else {
-                            true
-                        }
+                    if let Some(val) = self.get_ref_value(req, ctx) {
+                        self.contains(&val, &self.get_target_data(req, ctx))
+                            .is_some_and(|r| !r)
                     } else {
                         true
                     }
                 }
                 CompareType::NGT => {
-                    compare_numbers!(req, ctx, &self.ref_data, >, self)
+                    self.compare_order(req, ctx).is_some_and(|o| o.is_gt())
                     // if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
                     //     if let Some(v) = op {
                     //         if v.var_type == VariableType::Str {
@@ -320,7 +249,7 @@ impl ConditionData {
                     // }
                 }
                 CompareType::NGTE => {
-                    compare_numbers!(req, ctx, &self.ref_data, >=, self)
+                    self.compare_order(req, ctx).is_some_and(|o| o.is_ge())
                     // if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
                     //     if let Some(v) = op {
                     //         if v.var_type == VariableType::Str {
@@ -350,7 +279,7 @@ impl ConditionData {
                     // }
                 }
                 CompareType::NLT => {
-                    compare_numbers!(req, ctx, &self.ref_data, <, self)
+                    self.compare_order(req, ctx).is_some_and(|o| o.is_lt())
                     // if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
                     //     if let Some(v) = op {
                     //         if v.var_type == VariableType::Str {
@@ -380,7 +309,7 @@ impl ConditionData {
                     // }
                 }
                 CompareType::NLTE => {
-                    compare_numbers!(req, ctx, &self.ref_data, <=, self)
+                    self.compare_order(req, ctx).is_some_and(|o| o.is_le())
                     // if let Ok(op) = variable::get(&req.robot_id, &self.ref_data) {
                     //     if let Some(v) = op {
                     //         if v.var_type == VariableType::Str {
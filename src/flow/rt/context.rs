@@ -1,4 +1,4 @@
-use std::collections::{HashMap, LinkedList};
+use std::collections::{HashMap, HashSet, LinkedList};
 // use std::rc::Rc;
 // use std::sync::{Mutex, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -11,6 +11,7 @@ use tokio::time::{interval, Duration};
 use super::node::RuntimeNnodeEnum;
 use crate::ai::completion::Prompt;
 use crate::db;
+use crate::external::webhook::dto::WebhookEvent;
 use crate::man::settings;
 use crate::result::Result;
 use crate::variable::dto::VariableValue;
@@ -32,12 +33,20 @@ pub(crate) struct Context {
     pub(in crate::flow::rt) node: Option<Vec<u8>>,
     pub(in crate::flow::rt) nodes: LinkedList<String>,
     pub(crate) vars: HashMap<String, VariableValue>,
+    // Assigned by set variables nodes, so `vars` wins over the value sources of these variables
+    #[serde(default)]
+    pub(crate) assigned_vars: HashSet<String>,
     #[serde(skip)]
     pub(crate) none_persistent_vars: HashMap<String, VariableValue>,
     #[serde(skip)]
     pub(crate) none_persistent_data: HashMap<String, String>,
     last_active_time: u64,
     pub(crate) chat_history: Vec<Prompt>,
+    #[serde(default)]
+    pub(crate) locale: String,
+    // Created by this request
+    #[serde(skip)]
+    pub(in crate::flow::rt) new_session: bool,
 }
 
 impl Context {
@@ -68,6 +77,7 @@ impl Context {
             node: None,
             nodes: LinkedList::new(),
             vars: HashMap::with_capacity(16),
+            assigned_vars: HashSet::new(),
             none_persistent_vars: HashMap::with_capacity(16),
             none_persistent_data: HashMap::with_capacity(16),
             last_active_time: SystemTime::now()
@@ -75,6 +85,8 @@ impl Context {
                 .unwrap()
                 .as_secs(),
             chat_history: Vec::with_capacity(16),
+            locale: String::new(),
+            new_session: true,
         };
         ctx
     }
@@ -83,6 +95,13 @@ impl Context {
         db::write(TABLE, self.session_id.as_str(), self)
     }
 
+    // Changes only the chat history of the stored context, so requests of the session saved meanwhile are kept
+    pub(crate) fn add_chat_history(session_id: &str, prompt: Prompt) -> Result<bool> {
+        db::update(TABLE, session_id, |ctx: &mut Context| {
+            ctx.chat_history.push(prompt)
+        })
+    }
+
     // pub(crate) fn clear(&mut self) -> Result<()> {
     //     self.nodes.clear();
     //     self.vars.clear();
@@ -161,6 +180,12 @@ fn session_not_expired(d: &mut Vec<String>, idx: usize, now: u64) -> Result<bool
             log::warn!("Discarding expired session {} failed {:?}", session_id, e);
         } else {
             log::info!("Discarded expired session: {}", session_id);
+            crate::external::webhook::emit(
+                &c.robot_id,
+                session_id,
+                WebhookEvent::SessionEnded,
+                serde_json::json!({"reason": "expired", "lastActiveTime": c.last_active_time}),
+            );
             d.remove(idx);
         }
         return Ok(false);
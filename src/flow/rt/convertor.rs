@@ -4,7 +4,8 @@ use std::vec::Vec;
 use super::condition::ConditionData;
 use super::node::{
     CollectNode, ConditionNode, ExternalHttpCallNode, GotoAnotherNode, GotoMainFlowNode,
-    KnowledgeBaseAnswerNode, LlmChatNode, RuntimeNnodeEnum, SendEmailNode, TerminateNode, TextNode,
+    KnowledgeBaseAnswerNode, LlmChatNode, LlmExtractNode, RuntimeNnodeEnum, SendEmailNode,
+    SendNotificationNode, SetVariablesNode, TerminateNode, TextNode,
 };
 use crate::db;
 use crate::db_executor;
@@ -32,15 +33,15 @@ pub(crate) fn convert_flow(is_en: bool, robot_id: &str, mainflow_id: &str) -> Re
         //         f.name
         //     )));
         // }
-        convert_subflow(mainflow_id, idx, f)?;
+        convert_subflow(robot_id, mainflow_id, idx, f)?;
         idx = idx + 1;
     }
     Ok(())
 }
 
-fn validate_nodes(f: &SubFlowDetail, nodes: &Vec<&mut Node>) -> Result<()> {
+fn validate_nodes(robot_id: &str, f: &SubFlowDetail, nodes: &Vec<&mut Node>) -> Result<()> {
     for node in nodes.iter() {
-        node.is_valid(f)?;
+        node.is_valid(robot_id, f)?;
     }
     Ok(())
 }
@@ -76,8 +77,11 @@ fn check_first_node(
                     Node::GotoNode(n) => n.node_id = String::from(first_node_id),
                     Node::ExternalHttpNode(n) => n.node_id = String::from(first_node_id),
                     Node::SendEmailNode(n) => n.node_id = String::from(first_node_id),
+                    Node::SendNotificationNode(n) => n.node_id = String::from(first_node_id),
                     Node::EndNode(n) => n.node_id = String::from(first_node_id),
                     Node::KnowledgeBaseAnswerNode(n) => n.node_id = String::from(first_node_id),
+                    Node::SetVariablesNode(n) => n.node_id = String::from(first_node_id),
+                    Node::LlmExtractNode(n) => n.node_id = String::from(first_node_id),
                 };
             }
         }
@@ -90,7 +94,12 @@ fn check_first_node(
     }
 }
 
-fn convert_subflow(mainflow_id: &str, flow_idx: usize, f: &SubFlowDetail) -> Result<()> {
+fn convert_subflow(
+    robot_id: &str,
+    mainflow_id: &str,
+    flow_idx: usize,
+    f: &SubFlowDetail,
+) -> Result<()> {
     // println!("{}", &f.nodes);
     let mut cells: CanvasCells = serde_json::from_str(&f.canvas)?;
     let mut branches_link: HashMap<String, String> = HashMap::with_capacity(32);
@@ -142,7 +151,7 @@ fn convert_subflow(mainflow_id: &str, flow_idx: usize, f: &SubFlowDetail) -> Res
     }
 
     // let mut nodes: Vec<Node> = serde_json::from_str(&f.nodes)?;
-    validate_nodes(f, &nodes)?;
+    validate_nodes(robot_id, f, &nodes)?;
     check_first_node(mainflow_id, flow_idx, f, &mut nodes)?;
     for node in nodes {
         convert_node(mainflow_id, node)?;
@@ -156,7 +165,13 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
         Node::DialogNode(n) => {
             let node = TextNode {
                 text: n.dialog_text.clone(),
+                texts: n
+                    .dialog_text_i18n
+                    .iter()
+                    .map(|(l, t)| (l.clone(), t.clone()))
+                    .collect(),
                 text_type: n.dialog_text_type.clone(),
+                rich_content: n.rich_content.clone(),
                 ret: NextActionType::WaitUserResponse == n.next_step,
                 next_node_id: n.branches[0].target_node_id.clone(),
             };
@@ -177,6 +192,7 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
                 streaming: n.response_streaming,
                 connect_timeout: n.connect_timeout,
                 read_timeout: n.read_timeout,
+                tools: n.tools.clone(),
                 next_node_id: n.branches[0].target_node_id.clone(),
             };
             let r = RuntimeNnodeEnum::LlmChatNode(node);
@@ -252,6 +268,12 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
             let node = CollectNode {
                 var_name: n.collect_save_var_name.clone(),
                 collect_type: n.collect_type.clone(),
+                prompt: n.collect_prompt.clone(),
+                prompts: n
+                    .collect_prompt_i18n
+                    .iter()
+                    .map(|(l, t)| (l.clone(), t.clone()))
+                    .collect(),
                 successful_node_id: successful_node_id,
                 failed_node_id: failed_node_id,
             };
@@ -261,9 +283,18 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
             nodes.push((n.node_id.clone(), bytes));
         }
         Node::ExternalHttpNode(n) => {
+            let mut branch_ids = n
+                .branches
+                .iter_mut()
+                .map(|b| std::mem::take(&mut b.target_node_id));
             let node = ExternalHttpCallNode {
-                next_node_id: n.branches[0].target_node_id.clone(),
+                next_node_id: branch_ids.next().unwrap_or_default(),
                 http_api_id: n.http_api_id.clone(),
+                response_mappings: std::mem::take(&mut n.response_mappings),
+                status_code_var_name: std::mem::take(&mut n.status_code_var_name),
+                client_error_node_id: branch_ids.next(),
+                server_error_node_id: branch_ids.next(),
+                timeout_node_id: branch_ids.next(),
             };
             let r = RuntimeNnodeEnum::ExternalHttpCallNode(node);
             let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&r).unwrap();
@@ -291,6 +322,8 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
                 subject: std::mem::replace(&mut n.subject, String::new()),
                 content: std::mem::replace(&mut n.content, String::new()),
                 content_type: std::mem::replace(&mut n.content_type, String::new()),
+                plain_text_content: std::mem::take(&mut n.plain_text_content),
+                attachments: std::mem::take(&mut n.attachments),
                 async_send: n.async_send,
                 successful_node_id: successful_node_id,
                 goto_node_id: goto_node_id,
@@ -300,6 +333,24 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
             // bytes.push(RuntimeNodeTypeId::CollectNode as u8);
             nodes.push((n.node_id.clone(), bytes));
         }
+        Node::SendNotificationNode(n) => {
+            let mut branch_ids = n
+                .branches
+                .iter_mut()
+                .map(|b| std::mem::take(&mut b.target_node_id));
+            let node = SendNotificationNode {
+                provider_id: std::mem::take(&mut n.provider_id),
+                recipients: std::mem::take(&mut n.recipients),
+                subject: std::mem::take(&mut n.subject),
+                content: std::mem::take(&mut n.content),
+                async_send: n.async_send,
+                successful_node_id: branch_ids.next().unwrap_or_default(),
+                failed_node_id: branch_ids.next(),
+            };
+            let r = RuntimeNnodeEnum::SendNotificationNode(node);
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&r).unwrap();
+            nodes.push((n.node_id.clone(), bytes));
+        }
         Node::GotoNode(n) => {
             // println!("GotoNode {}", &n.node_id);
             match n.goto_type {
@@ -327,7 +378,9 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
         }
         Node::EndNode(n) => {
             // log::info!("EndNode {}", &n.node_id);
-            let node = TerminateNode {};
+            let node = TerminateNode {
+                hand_off: n.hand_off,
+            };
             let r = RuntimeNnodeEnum::TerminateNode(node);
             let ter_bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&r).unwrap();
             if n.ending_text.is_empty() {
@@ -337,7 +390,13 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
                 let end_node_id = format!("{}-2", &n.node_id);
                 let node = TextNode {
                     text: n.ending_text.clone(),
+                    texts: n
+                        .ending_text_i18n
+                        .iter()
+                        .map(|(l, t)| (l.clone(), t.clone()))
+                        .collect(),
                     text_type: super::dto::AnswerType::TextPlain,
+                    rich_content: None,
                     ret: false,
                     next_node_id: end_node_id.clone(),
                 };
@@ -352,6 +411,7 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
                 recall_distance: 1f64 - n.recall_thresholds as f64 / 100f64,
                 retrieve_answer_sources: n.retrieve_answer_sources.clone(),
                 no_recall_then: n.no_answer_then.clone(),
+                rag: n.rag.clone(),
                 next_node_id: n.branches[0].target_node_id.clone(),
             };
             let r = RuntimeNnodeEnum::KnowledgeBaseAnswerNode(node);
@@ -359,6 +419,48 @@ fn convert_node(main_flow_id: &str, node: &mut Node) -> Result<()> {
             // bytes.push(RuntimeNodeTypeId::CollectNode as u8);
             nodes.push((n.node_id.clone(), bytes));
         }
+        Node::LlmExtractNode(n) => {
+            let mut successful_node_id = String::new();
+            // The first of the other branches is for missing fields, the second for failures
+            let mut goto_node_ids = Vec::with_capacity(2);
+            for b in n.branches.iter_mut() {
+                match b.branch_type {
+                    BranchType::InfoCollectedSuccessfully => {
+                        successful_node_id = std::mem::take(&mut b.target_node_id)
+                    }
+                    BranchType::GotoAnotherNode => {
+                        goto_node_ids.push(std::mem::take(&mut b.target_node_id))
+                    }
+                    _ => {
+                        return Err(Error::ErrorWithMessage(String::from(
+                            "Unknown extraction branch type",
+                        )))
+                    }
+                };
+            }
+            let node = LlmExtractNode {
+                instructions: std::mem::take(&mut n.instructions),
+                fields: std::mem::take(&mut n.fields),
+                retry_times: n.retry_times,
+                connect_timeout: n.connect_timeout,
+                read_timeout: n.read_timeout,
+                successful_node_id,
+                missing_node_id: goto_node_ids.first().cloned().unwrap_or_default(),
+                failed_node_id: goto_node_ids.get(1).cloned(),
+            };
+            let r = RuntimeNnodeEnum::LlmExtractNode(node);
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&r).unwrap();
+            nodes.push((n.node_id.clone(), bytes));
+        }
+        Node::SetVariablesNode(n) => {
+            let node = SetVariablesNode {
+                assignments: std::mem::take(&mut n.assignments),
+                next_node_id: n.branches[0].target_node_id.clone(),
+            };
+            let r = RuntimeNnodeEnum::SetVariablesNode(node);
+            let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&r).unwrap();
+            nodes.push((n.node_id.clone(), bytes));
+        }
     };
     // let mut nodes: Vec<(&str, &[u8])> = Vec::with_capacity(box_nodes.len());
     // for n in box_nodes.iter() {
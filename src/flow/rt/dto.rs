@@ -4,13 +4,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::{flow::subflow::dto::NextActionType, variable::dto::SimpleVariable};
 
-#[derive(Deserialize, PartialEq, Eq)]
+#[derive(Deserialize, PartialEq, Eq, utoipa::ToSchema)]
 pub(crate) enum UserInputResult {
     Successful,
     Timeout,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub(crate) struct Request {
     #[serde(rename = "robotId")]
     pub(crate) robot_id: String,
@@ -26,30 +26,193 @@ pub(crate) struct Request {
     pub(crate) import_variables: Vec<SimpleVariable>,
     #[serde(rename = "userInputIntent")]
     pub(crate) user_input_intent: Option<String>,
+    #[serde(default)]
+    pub(crate) locale: String,
+    // Stable id of the end user, used by user scoped variables
+    #[serde(rename = "userId", default)]
+    pub(crate) user_id: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 pub(crate) struct CollectData {
     #[serde(rename = "varName")]
     pub(crate) var_name: String,
     pub(crate) value: String,
 }
 
-#[derive(Clone, Deserialize, Serialize, rkyv::Archive, rkyv::Deserialize, rkyv::Serialize)]
+#[derive(
+    Clone,
+    Deserialize,
+    Serialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    utoipa::ToSchema,
+)]
 #[rkyv(compare(PartialEq))]
 pub(crate) enum AnswerType {
     TextPlain,
     TextHtml,
+    QuickReplies,
+    Card,
+    Carousel,
+    Attachment,
+    LocationRequest,
 }
 
-#[derive(Serialize)]
+#[derive(
+    Clone,
+    Deserialize,
+    Serialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    utoipa::ToSchema,
+)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct QuickReply {
+    pub(crate) label: String,
+    // Sent back as user input when the button is clicked
+    pub(crate) value: String,
+    // Sent back as `userInputIntent`, skips intent detection
+    #[serde(rename = "intentName", default)]
+    pub(crate) intent_name: String,
+}
+
+#[derive(
+    Clone,
+    Deserialize,
+    Serialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    utoipa::ToSchema,
+)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct Card {
+    pub(crate) title: String,
+    #[serde(default)]
+    pub(crate) description: String,
+    #[serde(rename = "imageUrl", default)]
+    pub(crate) image_url: String,
+    #[serde(rename = "linkUrl", default)]
+    pub(crate) link_url: String,
+    #[serde(default)]
+    pub(crate) buttons: Vec<QuickReply>,
+}
+
+#[derive(
+    Clone,
+    Deserialize,
+    Serialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    utoipa::ToSchema,
+)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct Attachment {
+    pub(crate) name: String,
+    pub(crate) url: String,
+    #[serde(rename = "mimeType", default)]
+    pub(crate) mime_type: String,
+}
+
+#[derive(
+    Clone,
+    Deserialize,
+    Serialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    utoipa::ToSchema,
+)]
+#[rkyv(compare(PartialEq))]
+pub(crate) enum RichContent {
+    QuickReplies(Vec<QuickReply>),
+    Card(Card),
+    Carousel(Vec<Card>),
+    Attachment(Attachment),
+    LocationRequest,
+}
+
+impl RichContent {
+    pub(crate) fn answer_type(&self) -> AnswerType {
+        match self {
+            Self::QuickReplies(_) => AnswerType::QuickReplies,
+            Self::Card(_) => AnswerType::Card,
+            Self::Carousel(_) => AnswerType::Carousel,
+            Self::Attachment(_) => AnswerType::Attachment,
+            Self::LocationRequest => AnswerType::LocationRequest,
+        }
+    }
+
+    fn push_card(s: &mut String, c: &Card) {
+        s.push_str(&c.title);
+        for t in [&c.description, &c.link_url] {
+            if !t.is_empty() {
+                s.push('\n');
+                s.push_str(t);
+            }
+        }
+        Self::push_replies(s, &c.buttons);
+    }
+
+    fn push_replies(s: &mut String, replies: &[QuickReply]) {
+        for (i, r) in replies.iter().enumerate() {
+            s.push_str(&format!("\n{}. {}", i + 1, &r.label));
+        }
+    }
+
+    // Renders the content for channels which can only display plain text
+    pub(crate) fn to_plain_text(&self, text: &str) -> String {
+        let mut s = String::with_capacity(128);
+        s.push_str(text);
+        match self {
+            Self::QuickReplies(replies) => Self::push_replies(&mut s, replies),
+            Self::Card(c) => {
+                if !s.is_empty() {
+                    s.push('\n');
+                }
+                Self::push_card(&mut s, c);
+            }
+            Self::Carousel(cards) => {
+                for c in cards.iter() {
+                    if !s.is_empty() {
+                        s.push_str("\n\n");
+                    }
+                    Self::push_card(&mut s, c);
+                }
+            }
+            Self::Attachment(a) => {
+                if !s.is_empty() {
+                    s.push('\n');
+                }
+                s.push_str(&format!("{}: {}", &a.name, &a.url));
+            }
+            Self::LocationRequest => {}
+        }
+        s
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct RichAnswer {
+    pub(crate) content: RichContent,
+    #[serde(rename = "fallbackText")]
+    pub(crate) fallback_text: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
 pub(crate) struct AnswerData {
     pub(crate) text: String,
     #[serde(rename = "answerType")]
     pub(crate) answer_type: AnswerType,
+    #[serde(rename = "richContent", skip_serializing_if = "Option::is_none")]
+    pub(crate) rich_content: Option<RichAnswer>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 pub(crate) struct Response {
     #[serde(rename = "sessionId")]
     pub(crate) session_id: String,
@@ -75,14 +238,34 @@ impl Response {
             next_action: NextActionType::None,
             extra_data: ExtraData {
                 external_link: String::new(),
+                hand_off: false,
+                sources: Vec::new(),
             },
             sse_receiver_ticket: String::new(),
         }
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 pub(crate) struct ExtraData {
     #[serde(rename = "externalLink")]
     pub(crate) external_link: String,
+    #[serde(rename = "handOff")]
+    pub(crate) hand_off: bool,
+    // Knowledge the answers are generated from
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) sources: Vec<AnswerSource>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct AnswerSource {
+    // Number cited in the answer, e.g. [1]
+    pub(crate) index: usize,
+    // QnA or Doc
+    #[serde(rename = "sourceType")]
+    pub(crate) source_type: String,
+    pub(crate) id: String,
+    // Question of the QnA pair or file name of the document
+    pub(crate) title: String,
+    pub(crate) distance: f64,
 }
@@ -1,17 +1,19 @@
 use super::context::Context;
 use super::dto::{Request, Response};
 use crate::ai::completion::Prompt;
+use crate::external::webhook::{self, dto::WebhookEvent};
 use crate::flow::rt::dto::UserInputResult;
 use crate::flow::rt::node::RuntimeNode;
 use crate::intent::detector;
 use crate::result::{Error, Result};
 
-pub(in crate::flow::rt) async fn process(req: &mut Request) -> Result<Response> {
+pub(crate) async fn process(req: &mut Request) -> Result<Response> {
     // let now = std::time::Instant::now();
     if req.session_id.is_empty() {
         req.session_id = scru128::new_string();
     }
     let mut ctx = Context::get(&req.robot_id, &req.session_id);
+    crate::robot::i18n::resolve_locale(req, &mut ctx)?;
     // log::info!("get ctx {:?}", now.elapsed());
     // let now = std::time::Instant::now();
     if ctx.no_node() {
@@ -20,20 +22,45 @@ pub(in crate::flow::rt) async fn process(req: &mut Request) -> Result<Response>
         }
         ctx.add_node(&req.main_flow_id);
     }
+    if ctx.new_session {
+        webhook::emit(
+            &req.robot_id,
+            &req.session_id,
+            WebhookEvent::SessionStarted,
+            serde_json::json!({"mainFlowId": &req.main_flow_id, "userId": &req.user_id}),
+        );
+    }
     // log::info!("add_node time {:?}", now.elapsed());
     // let now = std::time::Instant::now();
     if req.user_input_intent.is_none()
         && req.user_input_result == UserInputResult::Successful
         && !req.user_input.is_empty()
     {
-        req.user_input_intent = detector::detect(&req.robot_id, &req.user_input).await?;
+        req.user_input_intent =
+            detector::detect(&req.robot_id, &req.user_input, &req.locale).await?;
         // println!("{:?}", req.user_input_intent);
+        if let Some(intent) = &req.user_input_intent {
+            webhook::emit(
+                &req.robot_id,
+                &req.session_id,
+                WebhookEvent::IntentDetected,
+                serde_json::json!({"intent": intent, "userInput": &req.user_input}),
+            );
+        }
     }
     // log::info!("Intent detection took {:?}", now.elapsed());
     if !req.import_variables.is_empty() {
         for v in req.import_variables.iter_mut() {
             let k = std::mem::take(&mut v.var_name);
-            let v = crate::variable::dto::VariableValue::new(&v.var_val, &v.var_type);
+            let v =
+                crate::variable::dto::VariableValue::new(&v.var_val, &v.var_type).map_err(|e| {
+                    match e {
+                        Error::ErrorWithMessage(m) => {
+                            Error::ErrorWithMessage(format!("Import variable {}: {}", &k, m))
+                        }
+                        e => e,
+                    }
+                })?;
             ctx.vars.insert(k, v);
         }
     }
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Map, Value};
+
+use super::node::ExtractField;
+use crate::ai::chat::{ToolChat, ToolChatTurn};
+use crate::ai::completion::Prompt;
+use crate::result::{Error, Result};
+use crate::variable::dto::VariableValue;
+
+// Names of required fields the user did not tell, e.g. for asking them in the follow-up branch
+pub(crate) const MISSING_FIELDS_VAR_NAME: &str = "missing_fields";
+
+fn output_schema(fields: &[ExtractField]) -> Value {
+    let mut properties = Map::new();
+    for f in fields.iter() {
+        let mut schema = f.var_type.json_schema();
+        if !f.description.is_empty() {
+            schema["description"] = Value::from(f.description.as_str());
+        }
+        properties.insert(f.var_name.clone(), schema);
+    }
+    json!({"type": "object", "properties": properties})
+}
+
+// Models may wrap JSON in a Markdown code block
+fn json_text(s: &str) -> &str {
+    let s = s.trim();
+    s.strip_prefix("```json")
+        .or_else(|| s.strip_prefix("```"))
+        .and_then(|s| s.strip_suffix("```"))
+        .map_or(s, str::trim)
+}
+
+fn err_message(e: Error) -> String {
+    match e {
+        Error::ErrorWithMessage(m) => m,
+        e => format!("{:?}", e),
+    }
+}
+
+// Valid values and problems of the reply
+fn parse(reply: &str, fields: &[ExtractField]) -> (HashMap<String, VariableValue>, Vec<String>) {
+    let mut values = HashMap::with_capacity(fields.len());
+    let obj = match serde_json::from_str(json_text(reply)) {
+        Ok(Value::Object(o)) => o,
+        Ok(_) => {
+            return (
+                values,
+                vec![String::from("The reply is not a JSON object.")],
+            )
+        }
+        Err(e) => return (values, vec![format!("The reply is not valid JSON: {}", e)]),
+    };
+    let mut errors = Vec::new();
+    for f in fields.iter() {
+        match obj.get(&f.var_name) {
+            None | Some(Value::Null) => {}
+            Some(Value::String(s)) if s.trim().is_empty() => {}
+            Some(v) => match VariableValue::from_json(v.clone(), &f.var_type) {
+                Ok(v) => {
+                    values.insert(f.var_name.clone(), v);
+                }
+                Err(e) => errors.push(format!("{}: {}", &f.var_name, err_message(e))),
+            },
+        }
+    }
+    (values, errors)
+}
+
+// Asks the chat provider for the fields mentioned in the user input,
+// invalid replies are sent back to the model until `retry_times` runs out
+pub(super) fn extract(
+    robot_id: &str,
+    user_input: &str,
+    instructions: &str,
+    fields: &[ExtractField],
+    retry_times: u8,
+    connect_timeout: Option<u32>,
+    read_timeout: Option<u32>,
+) -> Result<HashMap<String, VariableValue>> {
+    let schema = output_schema(fields);
+    let mut system = format!(
+        "Extract information from the message of the user. Reply with only a JSON object matching the following JSON schema, leave out the fields which are not mentioned.\n{}",
+        &schema
+    );
+    if !instructions.is_empty() {
+        system.push_str("\n\n");
+        system.push_str(instructions);
+    }
+    let mut chat = ToolChat::new(
+        robot_id,
+        &system,
+        &[Prompt {
+            role: String::from("user"),
+            content: String::from(user_input),
+        }],
+        Vec::new(),
+        connect_timeout,
+        read_timeout,
+    )?;
+    chat.set_output_schema(schema);
+    let mut attempt = 0u8;
+    loop {
+        let reply = match tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(chat.next())
+        })? {
+            ToolChatTurn::Answer(s) => s,
+            ToolChatTurn::ToolCalls(_) => String::new(),
+        };
+        let (values, errors) = parse(&reply, fields);
+        if errors.is_empty() || attempt >= retry_times {
+            if !errors.is_empty() {
+                log::warn!("Extracted fields are invalid: {}", errors.join(" "));
+            }
+            return Ok(values);
+        }
+        attempt += 1;
+        log::info!(
+            "Retrying extraction ({}/{}): {}",
+            attempt,
+            retry_times,
+            errors.join(" ")
+        );
+        chat.add_user_message(&format!(
+            "{}\nPlease reply with only the JSON object.",
+            errors.join("\n")
+        ));
+    }
+}
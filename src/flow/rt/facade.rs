@@ -1,18 +1,34 @@
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
 
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::IntoResponse;
 use axum::Json;
+use futures::stream::Stream;
 use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt as _;
 
-use super::dto::Request;
+use super::context::Context;
+use super::dto::{Request, Response};
 use super::executor;
+use crate::ai::completion::Prompt;
 use crate::result::Result;
-use crate::web::server::to_res;
+use crate::web::server::{to_res, ResponseData};
 
 static ANSWER_SSE_SESSIONS: LazyLock<Mutex<HashMap<String, Sender<String>>>> =
     LazyLock::new(|| Mutex::new(HashMap::with_capacity(128)));
 
+#[utoipa::path(
+    post,
+    path = "/flow/answer",
+    tag = "flow",
+    summary = "Talk to a robot",
+    request_body = Request,
+    responses((status = 200, body = ResponseData<Response>))
+)]
 pub(crate) async fn answer(Json(mut req): Json<Request>) -> impl IntoResponse {
     let now = std::time::Instant::now();
     let r = executor::process(&mut req).await;
@@ -22,13 +38,73 @@ pub(crate) async fn answer(Json(mut req): Json<Request>) -> impl IntoResponse {
     res
 }
 
-pub(crate) async fn answer_sse(Json(req): Json<Request>) -> impl IntoResponse {
-    let now = std::time::Instant::now();
-    let (s, r) = tokio::sync::mpsc::channel::<String>(1);
-    let mut l = ANSWER_SSE_SESSIONS.lock().unwrap();
-    l.insert(String::from(req.session_id), s);
-    log::info!("Response used time:{:?}", now.elapsed());
-    ""
+// Sends the response as an `answer` event, then texts of streaming LLM nodes as `chunk` events and a `done` event at last
+#[utoipa::path(
+    post,
+    path = "/flow/answer/sse",
+    tag = "flow",
+    summary = "Talk to a robot with streaming answer",
+    request_body = Request,
+    responses((status = 200, content_type = "text/event-stream", body = String))
+)]
+pub(crate) async fn answer_sse(
+    Json(mut req): Json<Request>,
+) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
+    let (events, events_receiver) = tokio::sync::mpsc::channel::<Event>(8);
+    tokio::spawn(async move {
+        if req.session_id.is_empty() {
+            req.session_id = scru128::new_string();
+        }
+        let (s, mut r) = tokio::sync::mpsc::channel::<String>(8);
+        if let Ok(mut l) = ANSWER_SSE_SESSIONS.lock() {
+            l.insert(req.session_id.clone(), s.clone());
+        }
+        let res = executor::process(&mut req).await;
+        // Streaming nodes started by this request keep their own senders, so chunks end when they finish
+        if let Ok(mut l) = ANSWER_SSE_SESSIONS.lock() {
+            if l.get(&req.session_id).is_some_and(|e| e.same_channel(&s)) {
+                l.remove(&req.session_id);
+            }
+        }
+        drop(s);
+        let event = match res.and_then(|res| Ok(serde_json::to_string(&res)?)) {
+            Ok(res) => Event::default().event("answer").data(res),
+            Err(e) => Event::default().event("error").data(format!("{:?}", e)),
+        };
+        // Chunks are still read after the client left, so the whole answer is kept in the chat history
+        let mut connected = events.send(event).await.is_ok();
+        let mut streamed = String::new();
+        while let Some(chunk) = r.recv().await {
+            streamed.push_str(&chunk);
+            if connected {
+                connected = events
+                    .send(Event::default().event("chunk").data(chunk))
+                    .await
+                    .is_ok();
+            }
+        }
+        // Streamed answers are not in the response, so they are added to the chat history here
+        if !streamed.is_empty() {
+            let prompt = Prompt {
+                role: String::from("assistant"),
+                content: streamed,
+            };
+            match Context::add_chat_history(&req.session_id, prompt) {
+                Ok(true) => {}
+                Ok(false) => log::warn!("Session {} of streamed answer is gone", &req.session_id),
+                Err(e) => log::error!("Saving streamed answer failed: {:?}", &e),
+            }
+        }
+        if !connected {
+            return;
+        }
+        let _ = events.send(Event::default().event("done").data("")).await;
+    });
+    Sse::new(ReceiverStream::new(events_receiver).map(Ok)).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(15))
+            .text("keep-alive"),
+    )
 }
 
 pub(super) fn get_sender(session_id: &str) -> Result<Option<Sender<String>>> {
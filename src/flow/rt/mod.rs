@@ -5,8 +5,12 @@ pub(crate) mod convertor;
 pub(crate) mod crud;
 pub(crate) mod dto;
 pub(crate) mod executor;
+pub(crate) mod extractor;
 pub(crate) mod facade;
 pub(crate) mod node;
+pub(crate) mod rag;
+pub(crate) mod template;
+pub(crate) mod tool;
 // pub(crate) mod node_impl;
 // pub(crate) mod request;
 // pub(crate) mod response;
@@ -1,23 +1,28 @@
-use core::time::Duration;
 // use std::ops::DerefMut;
 
 use enum_dispatch::enum_dispatch;
-use lettre::transport::smtp::PoolConfig;
 use rkyv::{util::AlignedVec, Archive, Deserialize, Serialize};
 
 use super::condition::ConditionData;
 use super::context::Context;
-use super::dto::{AnswerData, AnswerType, CollectData, Request, Response};
+use super::dto::{AnswerData, AnswerType, CollectData, Request, Response, RichAnswer, RichContent};
+use super::template;
 use crate::ai::chat::ResultReceiver;
+use crate::ai::completion::Prompt;
+use crate::external::email;
 use crate::external::http::client as http;
+use crate::external::http::dto::{HttpResponse, ResponseData};
+use crate::external::notification;
+use crate::external::webhook::{self, dto::WebhookEvent};
 use crate::flow::rt::collector;
 use crate::flow::subflow::dto::NextActionType;
 use crate::man::settings::get_settings;
-use crate::result::Result;
-use crate::variable::crud as variable;
-use crate::variable::dto::{VariableType, VariableValue};
-
-const VAR_WRAP_SYMBOL: char = '`';
+use crate::result::{Error, Result};
+use crate::variable::dto::{
+    VariableObtainValueExpressionType, VariableScope, VariableType, VariableValue,
+};
+use crate::variable::extract;
+use crate::variable::store;
 
 // #[repr(u8)]
 // #[derive(PartialEq)]
@@ -41,8 +46,11 @@ pub(crate) enum RuntimeNnodeEnum {
     ExternalHttpCallNode,
     TerminateNode,
     SendEmailNode,
+    SendNotificationNode,
     LlmChatNode,
     KnowledgeBaseAnswerNode,
+    SetVariablesNode,
+    LlmExtractNode,
 }
 
 #[enum_dispatch(RuntimeNnodeEnum)]
@@ -50,37 +58,12 @@ pub(crate) trait RuntimeNode {
     fn exec(&mut self, req: &Request, ctx: &mut Context, response: &mut Response) -> bool;
 }
 
-fn replace_vars(text: &str, req: &Request, ctx: &mut Context) -> Result<String> {
-    let mut new_str = String::with_capacity(128);
-    let mut start = 0usize;
-    loop {
-        if let Some(mut begin) = text[start..].find(VAR_WRAP_SYMBOL) {
-            begin = start + begin;
-            new_str.push_str(&text[start..begin]);
-            if let Some(mut end) = text[begin + 1..].find(VAR_WRAP_SYMBOL) {
-                end = begin + end + 1;
-                // println!("{} {} {} {}", &text[begin + 1..],start, begin,end);
-                let var = variable::get(&req.robot_id, &text[begin + 1..end])?;
-                if let Some(v) = var {
-                    if let Some(value) = v.get_value(req, ctx) {
-                        new_str.push_str(&value.val_to_string());
-                    }
-                    start = end + 1;
-                } else {
-                    new_str.push_str(&text[begin..end]);
-                    start = end;
-                }
-                // new_str.push_str(&variable::get_value(&text[begin + 1..end - 1], req, ctx));
-            } else {
-                start = begin;
-                break;
-            }
-        } else {
-            break;
-        }
+// Also saved into the robot or user scope of the variable
+pub(super) fn save_var(req: &Request, ctx: &mut Context, name: &str, v: VariableValue) {
+    if let Err(e) = store::persist_scoped(req, name, &v) {
+        log::error!("Saving variable {} failed: {:?}", name, &e);
     }
-    new_str.push_str(&text[start..]);
-    Ok(new_str)
+    ctx.vars.insert(String::from(name), v);
 }
 
 #[inline]
@@ -92,7 +75,10 @@ fn add_next_node(ctx: &mut Context, next_node_id: &str) {
 #[rkyv(compare(PartialEq))]
 pub(crate) struct TextNode {
     pub(super) text: String,
+    // Locale and text pairs
+    pub(super) texts: Vec<(String, String)>,
     pub(crate) text_type: AnswerType,
+    pub(super) rich_content: Option<RichContent>,
     pub(super) ret: bool,
     pub(super) next_node_id: String,
 }
@@ -101,11 +87,32 @@ impl RuntimeNode for TextNode {
     fn exec(&mut self, req: &Request, ctx: &mut Context, response: &mut Response) -> bool {
         // log::info!("Into TextNode");
         // let now = std::time::Instant::now();
-        match replace_vars(&self.text, &req, ctx) {
-            Ok(answer) => response.answers.push(AnswerData {
-                text: answer,
-                answer_type: self.text_type.clone(),
-            }),
+        let text = crate::robot::i18n::select(
+            self.texts.iter().map(|(l, t)| (l, t)),
+            &self.text,
+            &req.locale,
+        );
+        let escape_html = matches!(self.text_type, AnswerType::TextHtml);
+        match template::render(text, req, ctx, escape_html) {
+            Ok(answer) => {
+                let answer = if let Some(c) = &self.rich_content {
+                    AnswerData {
+                        answer_type: c.answer_type(),
+                        rich_content: Some(RichAnswer {
+                            content: c.clone(),
+                            fallback_text: c.to_plain_text(&answer),
+                        }),
+                        text: answer,
+                    }
+                } else {
+                    AnswerData {
+                        text: answer,
+                        answer_type: self.text_type.clone(),
+                        rich_content: None,
+                    }
+                };
+                response.answers.push(answer)
+            }
             Err(e) => log::error!("{:?}", e),
         };
         // log::info!("add {}", &self.next_node_id);
@@ -151,6 +158,8 @@ impl RuntimeNode for GotoAnotherNode {
 pub(crate) struct CollectNode {
     pub(super) var_name: String,
     pub(super) collect_type: collector::CollectType,
+    pub(super) prompt: String,
+    pub(super) prompts: Vec<(String, String)>,
     pub(super) successful_node_id: String,
     pub(super) failed_node_id: String,
 }
@@ -160,8 +169,17 @@ impl RuntimeNode for CollectNode {
         // println!("Into CollectNode");
         if let Some(r) = collector::collect(&req.user_input, &self.collect_type) {
             // println!("{} {}", &self.var_name, r);
-            let v = VariableValue::new(r, &VariableType::Str);
+            let v = VariableValue::Str(String::from(r));
+            if let Err(e) = store::persist_scoped(req, &self.var_name, &v) {
+                log::error!("Saving variable {} failed: {:?}", &self.var_name, &e);
+            }
             ctx.vars.insert(self.var_name.clone(), v);
+            webhook::emit(
+                &req.robot_id,
+                &req.session_id,
+                WebhookEvent::VariableCollected,
+                serde_json::json!({"varName": &self.var_name, "value": r}),
+            );
             let collect_data = CollectData {
                 var_name: self.var_name.clone(),
                 value: String::from(r),
@@ -170,6 +188,21 @@ impl RuntimeNode for CollectNode {
             add_next_node(ctx, &self.successful_node_id);
             // println!("{} {}", r, &self.successful_node_id);
         } else {
+            let prompt = crate::robot::i18n::select(
+                self.prompts.iter().map(|(l, t)| (l, t)),
+                &self.prompt,
+                &req.locale,
+            );
+            if !prompt.is_empty() {
+                match template::render(prompt, req, ctx, false) {
+                    Ok(text) => response.answers.push(AnswerData {
+                        text,
+                        answer_type: AnswerType::TextPlain,
+                        rich_content: None,
+                    }),
+                    Err(e) => log::error!("{:?}", e),
+                }
+            }
             add_next_node(ctx, &self.failed_node_id);
         }
         false
@@ -207,53 +240,210 @@ impl RuntimeNode for ConditionNode {
 
 #[derive(Archive, Deserialize, Serialize)]
 #[rkyv(compare(PartialEq))]
-pub(crate) struct TerminateNode {}
+pub(crate) struct TerminateNode {
+    // Asks for a human agent to take over the conversation
+    pub(super) hand_off: bool,
+}
 
 impl RuntimeNode for TerminateNode {
-    fn exec(&mut self, _req: &Request, _ctx: &mut Context, response: &mut Response) -> bool {
+    fn exec(&mut self, req: &Request, ctx: &mut Context, response: &mut Response) -> bool {
         // log::info!("Into TerminateNode");
         response.next_action = NextActionType::Terminate;
+        if self.hand_off {
+            response.extra_data.hand_off = true;
+            webhook::emit(
+                &req.robot_id,
+                &req.session_id,
+                WebhookEvent::HandOffRequested,
+                serde_json::json!({"userId": &req.user_id, "chatHistory": &ctx.chat_history}),
+            );
+        }
+        webhook::emit(
+            &req.robot_id,
+            &req.session_id,
+            WebhookEvent::FlowTerminated,
+            serde_json::json!({"mainFlowId": &ctx.main_flow_id, "handOff": self.hand_off}),
+        );
         true
     }
 }
 
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[rkyv(compare(PartialEq))]
+pub(crate) enum HttpResponseSource {
+    Body,
+    Header,
+}
+
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct HttpResponseMapping {
+    #[serde(rename = "varName")]
+    pub(crate) var_name: String,
+    pub(crate) source: HttpResponseSource,
+    // Ignored when reading headers
+    #[serde(rename = "expressionType")]
+    pub(crate) expression_type: VariableObtainValueExpressionType,
+    // Header name, or the expression applied to the body
+    pub(crate) expression: String,
+}
+
 #[derive(Archive, Deserialize, Serialize)]
 #[rkyv(compare(PartialEq))]
 pub(crate) struct ExternalHttpCallNode {
     pub(super) next_node_id: String,
     pub(super) http_api_id: String,
+    pub(super) response_mappings: Vec<HttpResponseMapping>,
+    pub(super) status_code_var_name: String,
+    // Following are None when the node does not branch on status, then `next_node_id` is used
+    pub(super) client_error_node_id: Option<String>,
+    pub(super) server_error_node_id: Option<String>,
+    pub(super) timeout_node_id: Option<String>,
+}
+
+impl ExternalHttpCallNode {
+    pub(crate) fn mapped_value(
+        m: &HttpResponseMapping,
+        res: &HttpResponse,
+        robot_id: &str,
+    ) -> Result<VariableValue> {
+        let values: Vec<serde_json::Value> = match m.source {
+            HttpResponseSource::Header => res
+                .headers
+                .get_all(m.expression.as_str())
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .map(serde_json::Value::from)
+                .collect(),
+            HttpResponseSource::Body => {
+                let body = match &res.data {
+                    ResponseData::Str(s) => std::borrow::Cow::Borrowed(s.as_str()),
+                    ResponseData::Bin(b) => String::from_utf8_lossy(b),
+                    ResponseData::None => std::borrow::Cow::Borrowed(""),
+                };
+                extract::extract(&m.expression_type, &m.expression, &body)?
+            }
+        };
+        let var_type = match crate::variable::crud::get(robot_id, &m.var_name)? {
+            Some(v) => v.var_type,
+            None if values.len() > 1 => {
+                VariableType::Array(Box::new(crate::variable::dto::json_type(&values[0])))
+            }
+            None => values
+                .first()
+                .map_or(VariableType::Str, crate::variable::dto::json_type),
+        };
+        extract::to_variable_value(values, &var_type)
+    }
+
+    fn next_node_id(&self, r: &Result<HttpResponse>) -> &str {
+        let id = match r {
+            Ok(res) if (200..300).contains(&res.status) => None,
+            Ok(res) if (400..500).contains(&res.status) => self.client_error_node_id.as_ref(),
+            // 5xx and any other unexpected status
+            Ok(_) => self.server_error_node_id.as_ref(),
+            Err(Error::NetworkConnectTimeout(e) | Error::NetworkReadTimeout(e))
+                if e.is_timeout() =>
+            {
+                self.timeout_node_id.as_ref()
+            }
+            // Connection failures, open circuits, invalid requests and missing APIs
+            Err(_) => self.server_error_node_id.as_ref(),
+        };
+        id.unwrap_or(&self.next_node_id)
+    }
 }
 
 impl RuntimeNode for ExternalHttpCallNode {
     fn exec(&mut self, req: &Request, ctx: &mut Context, _response: &mut Response) -> bool {
         // println!("Into ExternalHttpCallNode");
-        if let Ok(op) =
-            crate::external::http::crud::get_detail(&req.robot_id, self.http_api_id.as_str())
-        {
-            if let Some(api) = op {
-                if api.async_req {
-                    tokio::spawn(http::req_async(api, ctx.vars.clone(), true));
-                } else {
-                    tokio::task::block_in_place(/*move*/ || {
-                        match tokio::runtime::Handle::current()
-                            .block_on(http::req(api, &ctx.vars, true))
-                        {
-                            Ok(r) => match r {
-                                crate::external::http::dto::ResponseData::Str(_) => {}
-                                crate::external::http::dto::ResponseData::Bin(_) => {}
-                                crate::external::http::dto::ResponseData::None => {}
-                            },
-                            Err(e) => log::error!("{:?}", e),
-                        }
-                    });
+        let api = match crate::external::http::crud::get_detail(&req.robot_id, &self.http_api_id) {
+            Ok(Some(api)) => api,
+            r => {
+                let e = r.err().unwrap_or_else(|| {
+                    Error::ErrorWithMessage(format!(
+                        "HTTP API {} was not found.",
+                        &self.http_api_id
+                    ))
+                });
+                log::error!("{:?}", e);
+                let next_node_id = String::from(self.next_node_id(&Err(e)));
+                add_next_node(ctx, &next_node_id);
+                return false;
+            }
+        };
+        if api.async_req {
+            tokio::spawn(http::req_async(
+                req.robot_id.clone(),
+                api,
+                ctx.vars.clone(),
+                true,
+            ));
+            add_next_node(ctx, &self.next_node_id);
+            return false;
+        }
+        let r = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(http::send(&req.robot_id, &api, &ctx.vars))
+        });
+        match &r {
+            Ok(res) => {
+                if !self.status_code_var_name.is_empty() {
+                    let v = VariableValue::Num(res.status as f64);
+                    save_var(req, ctx, &self.status_code_var_name, v);
+                }
+                for m in self.response_mappings.iter() {
+                    match Self::mapped_value(m, res, &req.robot_id) {
+                        Ok(v) => save_var(req, ctx, &m.var_name, v),
+                        Err(e) => log::warn!("Mapping variable {} failed: {:?}", &m.var_name, e),
+                    }
                 }
             }
+            Err(e) => log::error!("{:?}", e),
         }
-        add_next_node(ctx, &self.next_node_id);
+        let next_node_id = String::from(self.next_node_id(&r));
+        add_next_node(ctx, &next_node_id);
         false
     }
 }
 
+// File names can contain variables like {{ order_id }}.pdf
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) enum EmailAttachment {
+    // Uploaded through `/email/attachment`
+    File {
+        id: String,
+        #[serde(rename = "fileName", default)]
+        file_name: String,
+    },
+    // Body of the HTTP API response, e.g. a generated PDF
+    HttpResponse {
+        #[serde(rename = "httpApiId")]
+        http_api_id: String,
+        #[serde(rename = "fileName")]
+        file_name: String,
+    },
+}
+
+// Every recipient can be a variable holding several addresses separated by `,` or `;`
+fn render_recipients(
+    recipients: &[String],
+    req: &Request,
+    ctx: &mut Context,
+) -> Result<Vec<String>> {
+    let mut r = Vec::with_capacity(recipients.len());
+    for recipient in recipients.iter() {
+        let s = template::render(recipient, req, ctx, false)?;
+        r.extend(
+            s.split([',', ';'])
+                .map(|a| a.trim())
+                .filter(|a| !a.is_empty())
+                .map(String::from),
+        );
+    }
+    Ok(r)
+}
+
 #[derive(Archive, Deserialize, Serialize)]
 #[rkyv(compare(PartialEq))]
 pub(crate) struct SendEmailNode {
@@ -264,99 +454,153 @@ pub(crate) struct SendEmailNode {
     pub(super) subject: String,
     pub(super) content: String,
     pub(super) content_type: String,
+    // Text alternative of a HTML content, generated from the HTML when it is empty
+    pub(super) plain_text_content: String,
+    pub(super) attachments: Vec<EmailAttachment>,
     pub(super) async_send: bool,
     pub(super) successful_node_id: String,
     pub(super) goto_node_id: Option<String>,
 }
 
 impl SendEmailNode {
-    fn send_email(&self, settings: &crate::man::settings::Settings) -> Result<()> {
-        use lettre::transport::smtp::authentication::Credentials;
-        use lettre::{
-            message::{
-                header::{self, Bcc, Cc, ContentType, To},
-                Mailboxes, MessageBuilder, SinglePart,
-            },
-            AsyncSmtpTransport, AsyncTransport, Message, SmtpTransport, Tokio1Executor, Transport,
-        };
-        let mailboxes: Mailboxes = self.to_recipients.join(",").parse()?;
-        let to_header: To = mailboxes.into();
-        let mut builder = MessageBuilder::new().mailbox(to_header);
-        if !self.cc_recipients.is_empty() {
-            let mailboxes: Mailboxes = self.cc_recipients.join(",").parse()?;
-            let cc_header: Cc = mailboxes.into();
-            builder = builder.mailbox(cc_header);
-        }
-        if !self.bcc_recipients.is_empty() {
-            let mailboxes: Mailboxes = self.bcc_recipients.join(",").parse()?;
-            let bcc_header: Bcc = mailboxes.into();
-            builder = builder.mailbox(bcc_header);
+    fn attachment(
+        a: &EmailAttachment,
+        req: &Request,
+        ctx: &mut Context,
+    ) -> Result<email::dto::AttachmentData> {
+        match a {
+            EmailAttachment::File { id, file_name } => {
+                let file_name = template::render(file_name, req, ctx, false)?;
+                email::crud::read_attachment(&req.robot_id, id, &file_name)
+            }
+            EmailAttachment::HttpResponse {
+                http_api_id,
+                file_name,
+            } => {
+                let file_name = template::render(file_name, req, ctx, false)?;
+                let Some(api) =
+                    crate::external::http::crud::get_detail(&req.robot_id, http_api_id)?
+                else {
+                    return Err(Error::ErrorWithMessage(format!(
+                        "HTTP API {} was not found",
+                        http_api_id
+                    )));
+                };
+                let res = tokio::task::block_in_place(|| {
+                    tokio::runtime::Handle::current().block_on(http::send(
+                        &req.robot_id,
+                        &api,
+                        &ctx.vars,
+                    ))
+                })?;
+                if !(200..300).contains(&res.status) {
+                    return Err(Error::ErrorWithMessage(format!(
+                        "HTTP API {} responded status {}",
+                        &api.name, res.status
+                    )));
+                }
+                let content_type = res
+                    .headers
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map_or_else(
+                        || email::smtp::guess_content_type(&file_name),
+                        |v| v.split(';').next().unwrap_or(v).trim(),
+                    )
+                    .to_string();
+                let content = match res.data {
+                    ResponseData::Bin(b) => b,
+                    ResponseData::Str(s) => s.into_bytes(),
+                    ResponseData::None => Vec::new(),
+                };
+                Ok(email::dto::AttachmentData {
+                    file_name,
+                    content_type,
+                    content,
+                })
+            }
         }
+    }
 
-        let content_type: ContentType = if self.content_type.eq("HTML") {
-            ContentType::TEXT_HTML
+    fn compose(
+        &self,
+        settings: &crate::man::settings::Settings,
+        req: &Request,
+        ctx: &mut Context,
+    ) -> Result<email::dto::OutgoingEmail> {
+        let mut from = template::render(&self.from, req, ctx, false)?;
+        if from.is_empty() {
+            from.push_str(&settings.smtp_username);
+        }
+        let to = render_recipients(&self.to_recipients, req, ctx)?;
+        if to.is_empty() {
+            return Err(Error::ErrorWithMessage(String::from(
+                "No email recipient after rendering",
+            )));
+        }
+        let subject = template::render(&self.subject, req, ctx, false)?;
+        let (text_body, html_body) = if self.content_type.eq("HTML") {
+            let html = template::render(&self.content, req, ctx, true)?;
+            let text = if self.plain_text_content.is_empty() {
+                email::smtp::html_to_text(&html)
+            } else {
+                template::render(&self.plain_text_content, req, ctx, false)?
+            };
+            (text, Some(html))
         } else {
-            ContentType::TEXT_PLAIN
+            (template::render(&self.content, req, ctx, false)?, None)
         };
+        let mut attachments = Vec::with_capacity(self.attachments.len());
+        for a in self.attachments.iter() {
+            attachments.push(Self::attachment(a, req, ctx)?);
+        }
+        Ok(email::dto::OutgoingEmail {
+            from,
+            to,
+            cc: render_recipients(&self.cc_recipients, req, ctx)?,
+            bcc: render_recipients(&self.bcc_recipients, req, ctx)?,
+            subject,
+            text_body,
+            html_body,
+            attachments,
+            in_reply_to: None,
+            references: Vec::new(),
+        })
+    }
 
-        let email = builder
-            .from(self.from.parse()?)
-            .subject(&self.subject)
-            .header(content_type)
-            .body(self.content.clone())
-            // .singlepart(SinglePart::html(&self.content))
-            ?;
-        let creds = Credentials::new(
-            settings.smtp_username.to_owned(),
-            settings.smtp_password.to_owned(),
-        );
-        let pool = PoolConfig::new()
-            .min_idle(1)
-            .max_size(2)
-            .idle_timeout(Duration::from_secs(300));
-        if self.async_send {
-            let builder = AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.smtp_host)?;
-            let mailer = builder
-                .credentials(creds)
-                .timeout(Some(core::time::Duration::from_secs(
-                    settings.smtp_timeout_sec as u64,
+    fn send(&self, req: &Request, ctx: &mut Context) -> Result<()> {
+        let settings = match get_settings(&req.robot_id)? {
+            Some(s) if !s.smtp_host.is_empty() => s,
+            _ => {
+                return Err(Error::ErrorWithMessage(String::from(
+                    "SMTP is not configured",
                 )))
-                .pool_config(pool)
-                .build();
-            tokio::spawn(async move {
-                // mailer.send(email) // will be wrong
-                if let Err(e) = mailer.send(email).await {
-                    log::error!("Failed to send email, failure reason is: {:?}", e);
-                }
-            });
-            Ok(())
-        } else {
-            let mailer = SmtpTransport::relay(&settings.smtp_host)?
-                .credentials(creds)
-                .timeout(Some(core::time::Duration::from_secs(
-                    settings.smtp_timeout_sec as u64,
-                )))
-                .pool_config(pool)
-                .build();
-
-            Ok(mailer.send(&email).map(|r| {
-                log::info!("Sent email response: {:?}", r);
-                ()
-            })?)
+            }
+        };
+        let email = self.compose(&settings, req, ctx)?;
+        if self.async_send {
+            return email::outbox::enqueue(&req.robot_id, &req.session_id, &email);
         }
+        let r = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(email::smtp::send(&settings, &email))
+        });
+        email::outbox::record(&req.robot_id, &req.session_id, &email, &r)?;
+        r
     }
 }
 
 impl RuntimeNode for SendEmailNode {
     fn exec(&mut self, req: &Request, ctx: &mut Context, _response: &mut Response) -> bool {
         // println!("Into SendEmailNode");
-        if let Ok(op) = get_settings(&req.robot_id) {
-            if let Some(settings) = op {
-                if !settings.smtp_host.is_empty() {
-                    match self.send_email(&settings) {
-                        Ok(_) => add_next_node(ctx, &self.successful_node_id),
-                        Err(_) => add_next_node(ctx, self.goto_node_id.as_ref().unwrap()),
-                    }
+        match self.send(req, ctx) {
+            Ok(_) => add_next_node(ctx, &self.successful_node_id),
+            Err(e) => {
+                log::error!("Sending email failed: {:?}", &e);
+                // Asynchronous sending has no failure branch, so the flow stops here
+                // instead of going on as if the email was queued
+                match self.goto_node_id.as_ref() {
+                    Some(id) => add_next_node(ctx, id),
+                    None => return true,
                 }
             }
         }
@@ -364,6 +608,60 @@ impl RuntimeNode for SendEmailNode {
     }
 }
 
+#[derive(Archive, Deserialize, Serialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct SendNotificationNode {
+    pub(super) provider_id: String,
+    pub(super) recipients: Vec<String>,
+    pub(super) subject: String,
+    pub(super) content: String,
+    pub(super) async_send: bool,
+    pub(super) successful_node_id: String,
+    pub(super) failed_node_id: Option<String>,
+}
+
+impl SendNotificationNode {
+    fn send(&self, req: &Request, ctx: &mut Context) -> Result<()> {
+        let Some(settings) = get_settings(&req.robot_id)? else {
+            return Err(Error::ErrorWithMessage(String::from(
+                "Settings were not found",
+            )));
+        };
+        let provider = notification::sender::find_provider(&settings, &self.provider_id)?;
+        let n = notification::dto::Notification {
+            recipients: render_recipients(&self.recipients, req, ctx)?,
+            subject: template::render(&self.subject, req, ctx, false)?,
+            content: template::render(&self.content, req, ctx, false)?,
+        };
+        notification::sender::send(
+            &req.robot_id,
+            &req.session_id,
+            &settings,
+            provider,
+            n,
+            &ctx.vars,
+            self.async_send,
+        )
+    }
+}
+
+impl RuntimeNode for SendNotificationNode {
+    fn exec(&mut self, req: &Request, ctx: &mut Context, _response: &mut Response) -> bool {
+        match self.send(req, ctx) {
+            Ok(_) => add_next_node(ctx, &self.successful_node_id),
+            Err(e) => {
+                log::error!("Sending notification failed: {:?}", &e);
+                let next_node_id = self
+                    .failed_node_id
+                    .as_ref()
+                    .unwrap_or(&self.successful_node_id);
+                add_next_node(ctx, next_node_id);
+            }
+        }
+        false
+    }
+}
+
 #[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
 #[rkyv(compare(PartialEq))]
 pub(crate) enum LlmChatNodeExitCondition {
@@ -380,6 +678,27 @@ pub(crate) enum LlmChatAnswerTimeoutThen {
     DoNothing,
 }
 
+// Functions the model can call while answering
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) enum LlmTool {
+    // Parameters are the variables used by the request which are listed in `params`,
+    // others are only read from the context
+    HttpApi {
+        #[serde(rename = "httpApiId")]
+        http_api_id: String,
+        #[serde(default)]
+        params: Vec<String>,
+        #[serde(rename = "responseMappings", default)]
+        response_mappings: Vec<HttpResponseMapping>,
+    },
+    // Returns the value of the variable
+    Variable {
+        #[serde(rename = "varName")]
+        var_name: String,
+    },
+}
+
 #[derive(Archive, Clone, Deserialize, Serialize)]
 #[rkyv(compare(PartialEq))]
 pub(crate) struct LlmChatNode {
@@ -391,6 +710,7 @@ pub(crate) struct LlmChatNode {
     pub(super) streaming: bool,
     pub(crate) connect_timeout: Option<u32>,
     pub(crate) read_timeout: Option<u32>,
+    pub(super) tools: Vec<LlmTool>,
     pub(super) next_node_id: String,
 }
 
@@ -420,10 +740,39 @@ impl RuntimeNode for LlmChatNode {
                 }
             }
         }
+        // The prompt instructs the model, the input of the user is the last message of the history
+        let system = crate::ai::history::system_prompt(&self.prompt);
+        let system = match template::render(&system, req, ctx, false) {
+            Ok(p) => p,
+            Err(e) => {
+                log::error!("Rendering LLM prompt failed: {:?}", &e);
+                add_next_node(ctx, &self.next_node_id);
+                return false;
+            }
+        };
+        let chat_history = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(crate::ai::history::window(
+                &req.robot_id,
+                &system,
+                &mut ctx.chat_history,
+                self.context_len,
+                self.connect_timeout,
+                self.read_timeout,
+            ))
+        });
+        let chat_history = match chat_history {
+            Ok(h) => h,
+            Err(e) => {
+                log::error!("Windowing chat history failed: {:?}", &e);
+                add_next_node(ctx, &self.next_node_id);
+                return false;
+            }
+        };
         let r = RuntimeNnodeEnum::LlmChatNode(self.clone());
         let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&r).unwrap();
         ctx.node = Some(bytes.into_vec());
-        if self.streaming {
+        // Tools write their outputs into the context, so answers using tools are not streamed
+        if self.streaming && self.tools.is_empty() {
             let r = super::facade::get_sender(&req.session_id);
             if r.is_err() {
                 add_next_node(ctx, &self.next_node_id);
@@ -437,14 +786,13 @@ impl RuntimeNode for LlmChatNode {
             let s = s_op.unwrap();
             // let ticket = String::new();
             let robot_id = req.robot_id.clone();
-            let prompt = self.prompt.clone();
             let connect_timeout = self.connect_timeout.clone();
             let read_timeout = self.read_timeout.clone();
             tokio::task::spawn(async move {
                 if let Err(e) = crate::ai::chat::chat(
                     &robot_id,
-                    &prompt,
-                    None,
+                    &system,
+                    &chat_history,
                     connect_timeout,
                     read_timeout,
                     ResultReceiver::SseSender(&s),
@@ -454,28 +802,36 @@ impl RuntimeNode for LlmChatNode {
                     log::info!("LlmChatNode response failed, err: {:?}", &e);
                 }
             });
-            false
+            // Waits for the next input like the non-streaming answer
+            true
         } else {
             let now = std::time::Instant::now();
             let mut s = String::with_capacity(1024);
-            if let Err(e) = tokio::task::block_in_place(|| {
-                // log::info!("prompt |{}|", &self.prompt);
-                let chat_history = if ctx.chat_history.is_empty() {
-                    None
-                } else {
-                    Some(ctx.chat_history.clone())
-                };
+            let r = if self.tools.is_empty() {
                 tokio::task::block_in_place(|| {
+                    // log::info!("prompt |{}|", &self.prompt);
                     tokio::runtime::Handle::current().block_on(crate::ai::chat::chat(
                         &req.robot_id,
-                        &self.prompt,
-                        chat_history,
+                        &system,
+                        &chat_history,
                         self.connect_timeout,
                         self.read_timeout,
                         ResultReceiver::StrBuf(&mut s),
                     ))
                 })
-            }) {
+            } else {
+                super::tool::answer(
+                    &self.tools,
+                    &system,
+                    &chat_history,
+                    self.connect_timeout,
+                    self.read_timeout,
+                    req,
+                    ctx,
+                )
+                .map(|a| s.push_str(&a))
+            };
+            if let Err(e) = r {
                 log::error!("LlmChatNode response failed, err: {:?}", &e);
                 match &self.answer_timeout_then {
                     LlmChatAnswerTimeoutThen::GotoAnotherNode => {
@@ -492,6 +848,7 @@ impl RuntimeNode for LlmChatNode {
                 response.answers.push(AnswerData {
                     text: s,
                     answer_type: AnswerType::TextPlain,
+                    rich_content: None,
                 });
             }
             log::info!("Llm response took {:?}", now.elapsed());
@@ -540,6 +897,84 @@ impl RuntimeNode for LlmChatNode {
     }
 }
 
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct ExtractField {
+    #[serde(rename = "varName")]
+    pub(crate) var_name: String,
+    #[serde(rename = "varType")]
+    pub(crate) var_type: VariableType,
+    // Tells the model what the field is, e.g. "Date of check-in"
+    #[serde(default)]
+    pub(crate) description: String,
+    #[serde(default)]
+    pub(crate) required: bool,
+}
+
+#[derive(Archive, Deserialize, Serialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct LlmExtractNode {
+    pub(super) instructions: String,
+    pub(super) fields: Vec<ExtractField>,
+    // Times of asking again when the reply is malformed
+    pub(super) retry_times: u8,
+    pub(super) connect_timeout: Option<u32>,
+    pub(super) read_timeout: Option<u32>,
+    pub(super) successful_node_id: String,
+    pub(super) missing_node_id: String,
+    pub(super) failed_node_id: Option<String>,
+}
+
+impl RuntimeNode for LlmExtractNode {
+    fn exec(&mut self, req: &Request, ctx: &mut Context, _response: &mut Response) -> bool {
+        let r = template::render(&self.instructions, req, ctx, false).and_then(|instructions| {
+            super::extractor::extract(
+                &req.robot_id,
+                &req.user_input,
+                &instructions,
+                &self.fields,
+                self.retry_times,
+                self.connect_timeout,
+                self.read_timeout,
+            )
+        });
+        let mut values = match r {
+            Ok(values) => values,
+            Err(e) => {
+                log::error!("Extracting fields failed: {:?}", &e);
+                // Asking for the fields again would fail the same way, so the flow stops
+                // when there is no failure branch
+                match self.failed_node_id.as_ref() {
+                    Some(id) => add_next_node(ctx, id),
+                    None => return true,
+                }
+                return false;
+            }
+        };
+        // Values told in previous turns are kept
+        let mut missing = Vec::new();
+        for f in self.fields.iter() {
+            match values.remove(&f.var_name) {
+                Some(v) => save_var(req, ctx, &f.var_name, v),
+                None if f.required && !ctx.vars.contains_key(&f.var_name) => {
+                    missing.push(VariableValue::Str(f.var_name.clone()))
+                }
+                None => {}
+            }
+        }
+        if missing.is_empty() {
+            add_next_node(ctx, &self.successful_node_id);
+        } else {
+            ctx.none_persistent_vars.insert(
+                String::from(super::extractor::MISSING_FIELDS_VAR_NAME),
+                VariableValue::Array(missing),
+            );
+            add_next_node(ctx, &self.missing_node_id);
+        }
+        false
+    }
+}
+
 #[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
 #[rkyv(compare(PartialEq))]
 pub(crate) enum KnowledgeBaseAnswerNoRecallThen {
@@ -554,12 +989,30 @@ pub(crate) enum KnowledgeBaseAnswerSource {
     Doc,
 }
 
+// Answers are generated by the chat provider from the retrieved QnA pairs and document chunks
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct KnowledgeBaseRag {
+    // Retrieved from each source
+    #[serde(rename = "topK")]
+    pub(crate) top_k: u8,
+    #[serde(default)]
+    pub(crate) instructions: String,
+    #[serde(rename = "responseStreaming", default)]
+    pub(crate) streaming: bool,
+    #[serde(rename = "connectTimeout", default)]
+    pub(crate) connect_timeout: Option<u32>,
+    #[serde(rename = "readTimeout", default)]
+    pub(crate) read_timeout: Option<u32>,
+}
+
 #[derive(Archive, Clone, Deserialize, Serialize)]
 #[rkyv(compare(PartialEq))]
 pub(crate) struct KnowledgeBaseAnswerNode {
     pub(super) recall_distance: f64,
     pub(super) retrieve_answer_sources: Vec<crate::flow::rt::node::KnowledgeBaseAnswerSource>,
     pub(super) no_recall_then: KnowledgeBaseAnswerNoRecallThen,
+    pub(super) rag: Option<KnowledgeBaseRag>,
     pub(super) next_node_id: String,
 }
 
@@ -579,7 +1032,12 @@ impl KnowledgeBaseAnswerNode {
                     self.recall_distance
                 );
                 if answer.is_some() && distance <= self.recall_distance {
-                    Some(answer.unwrap().answer)
+                    let answer = answer.unwrap();
+                    Some(String::from(crate::robot::i18n::select(
+                        answer.answer_i18n.iter(),
+                        &answer.answer,
+                        &req.locale,
+                    )))
                 } else {
                     None
                 }
@@ -593,7 +1051,103 @@ impl KnowledgeBaseAnswerNode {
     fn retrieve_doc_answer(&self, req: &Request) -> Option<String> {
         None
     }
-    fn fallback_answer(&self, ctx: &mut Context, response: &mut Response) -> bool {
+    // Refuses with the no recall answer when nothing is close enough to the input
+    fn rag_answer(
+        &self,
+        rag: &KnowledgeBaseRag,
+        req: &Request,
+        ctx: &mut Context,
+        response: &mut Response,
+    ) -> bool {
+        let passages = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(super::rag::retrieve(
+                &req.robot_id,
+                &req.user_input,
+                &req.locale,
+                &self.retrieve_answer_sources,
+                rag.top_k,
+                self.recall_distance,
+            ))
+        });
+        let passages = match passages {
+            Ok(p) if !p.is_empty() => p,
+            Ok(_) => return self.fallback_answer(req, ctx, response),
+            Err(e) => {
+                log::error!("KnowledgeBaseAnswerNode retrieve passages failed: {:?}", &e);
+                return self.fallback_answer(req, ctx, response);
+            }
+        };
+        let instructions = match template::render(&rag.instructions, req, ctx, false) {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("Rendering RAG instructions failed: {:?}", &e);
+                String::new()
+            }
+        };
+        let system = super::rag::system_prompt(&passages, &instructions);
+        let chat_history = vec![Prompt {
+            role: String::from("user"),
+            content: req.user_input.clone(),
+        }];
+        let sources = passages.into_iter().map(|p| p.source).collect();
+        if rag.streaming {
+            if let Ok(Some(s)) = super::facade::get_sender(&req.session_id) {
+                let robot_id = req.robot_id.clone();
+                let connect_timeout = rag.connect_timeout;
+                let read_timeout = rag.read_timeout;
+                tokio::task::spawn(async move {
+                    if let Err(e) = crate::ai::chat::chat(
+                        &robot_id,
+                        &system,
+                        &chat_history,
+                        connect_timeout,
+                        read_timeout,
+                        ResultReceiver::SseSender(&s),
+                    )
+                    .await
+                    {
+                        log::info!("KnowledgeBaseAnswerNode response failed, err: {:?}", &e);
+                    }
+                });
+                response.extra_data.sources = sources;
+                add_next_node(ctx, &self.next_node_id);
+                return false;
+            }
+        }
+        let mut s = String::with_capacity(1024);
+        let r = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(crate::ai::chat::chat(
+                &req.robot_id,
+                &system,
+                &chat_history,
+                rag.connect_timeout,
+                rag.read_timeout,
+                ResultReceiver::StrBuf(&mut s),
+            ))
+        });
+        if let Err(e) = r {
+            log::error!("KnowledgeBaseAnswerNode response failed, err: {:?}", &e);
+            return self.fallback_answer(req, ctx, response);
+        }
+        if s.is_empty() {
+            return self.fallback_answer(req, ctx, response);
+        }
+        response.answers.push(AnswerData {
+            text: s,
+            answer_type: AnswerType::TextPlain,
+            rich_content: None,
+        });
+        response.extra_data.sources = sources;
+        add_next_node(ctx, &self.next_node_id);
+        false
+    }
+    fn fallback_answer(&self, req: &Request, ctx: &mut Context, response: &mut Response) -> bool {
+        webhook::emit(
+            &req.robot_id,
+            &req.session_id,
+            WebhookEvent::FallbackTriggered,
+            serde_json::json!({"node": "KnowledgeBaseAnswerNode", "userInput": &req.user_input}),
+        );
         match &self.no_recall_then {
             KnowledgeBaseAnswerNoRecallThen::GotoAnotherNode => {
                 add_next_node(ctx, &self.next_node_id);
@@ -603,6 +1157,7 @@ impl KnowledgeBaseAnswerNode {
                 response.answers.push(AnswerData {
                     text: s.clone(),
                     answer_type: AnswerType::TextPlain,
+                    rich_content: None,
                 });
                 let r = RuntimeNnodeEnum::KnowledgeBaseAnswerNode(self.clone());
                 let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&r).unwrap();
@@ -616,6 +1171,9 @@ impl KnowledgeBaseAnswerNode {
 impl RuntimeNode for KnowledgeBaseAnswerNode {
     fn exec(&mut self, req: &Request, ctx: &mut Context, response: &mut Response) -> bool {
         // log::info!("Into LlmChaKnowledgeBaseAnswerNodetNode");
+        if let Some(rag) = &self.rag {
+            return self.rag_answer(rag, req, ctx, response);
+        }
         for answer_source in &self.retrieve_answer_sources {
             let r = match answer_source {
                 KnowledgeBaseAnswerSource::QnA => self.retrieve_qa_answer(req),
@@ -625,12 +1183,13 @@ impl RuntimeNode for KnowledgeBaseAnswerNode {
                 response.answers.push(AnswerData {
                     text: r.unwrap(),
                     answer_type: AnswerType::TextPlain,
+                    rich_content: None,
                 });
                 add_next_node(ctx, &self.next_node_id);
                 return false;
             }
         }
-        self.fallback_answer(ctx, response)
+        self.fallback_answer(req, ctx, response)
         /*
         let result = tokio::task::block_in_place(|| {
             tokio::runtime::Handle::current().block_on(crate::kb::qa::retrieve_answer(
@@ -649,22 +1208,116 @@ impl RuntimeNode for KnowledgeBaseAnswerNode {
                     response.answers.push(AnswerData {
                         text: answer.unwrap().answer,
                         answer_type: AnswerType::TextPlain,
+                        rich_content: None,
                     });
                     add_next_node(ctx, &self.next_node_id);
                     false
                 } else {
-                    self.fallback_answer(ctx, response)
+                    self.fallback_answer(req, ctx, response)
                 }
             }
             Err(e) => {
                 log::error!("KnowledgeBaseAnswerNode answer failed: {:?}", &e);
-                self.fallback_answer(ctx, response)
+                self.fallback_answer(req, ctx, response)
             }
         }
         */
     }
 }
 
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) enum VariableAssignmentScope {
+    // Saved in the session context
+    Session,
+    // Dropped when current request finished
+    Turn,
+}
+
+#[derive(Archive, Clone, Deserialize, Serialize, serde::Deserialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct VariableAssignment {
+    #[serde(rename = "varName")]
+    pub(crate) var_name: String,
+    pub(crate) expression: String,
+    pub(crate) scope: VariableAssignmentScope,
+}
+
+#[derive(Archive, Deserialize, Serialize)]
+#[rkyv(compare(PartialEq))]
+pub(crate) struct SetVariablesNode {
+    pub(super) assignments: Vec<VariableAssignment>,
+    pub(super) next_node_id: String,
+}
+
+impl SetVariablesNode {
+    fn eval(
+        a: &VariableAssignment,
+        var_type: Option<&VariableType>,
+        req: &Request,
+        ctx: &mut Context,
+    ) -> Result<VariableValue> {
+        let json = template::eval(&a.expression, req, ctx)?;
+        match var_type {
+            Some(t) => VariableValue::from_json(json, t),
+            None => {
+                let t = crate::variable::dto::json_type(&json);
+                VariableValue::from_json(json, &t)
+            }
+        }
+    }
+    fn assign(&self, a: &VariableAssignment, req: &Request, ctx: &mut Context) -> Result<()> {
+        let var = crate::variable::crud::get(&req.robot_id, &a.var_name)?;
+        if let Some(v) = var
+            .as_ref()
+            .filter(|v| !matches!(v.var_scope, VariableScope::Session))
+        {
+            // Evaluated while holding the update lock, so `counter + 1` is atomic
+            let value = store::update(
+                &req.robot_id,
+                &v.var_scope,
+                &req.user_id,
+                &a.var_name,
+                |current| {
+                    match current {
+                        Some(c) => ctx.none_persistent_vars.insert(a.var_name.clone(), c),
+                        None => ctx.none_persistent_vars.remove(&a.var_name),
+                    };
+                    Self::eval(a, Some(&v.var_type), req, ctx).map(Some)
+                },
+            )?;
+            if let Some(value) = value {
+                ctx.none_persistent_vars.insert(a.var_name.clone(), value);
+            }
+            return Ok(());
+        }
+        let value = Self::eval(a, var.as_ref().map(|v| &v.var_type), req, ctx)?;
+        match a.scope {
+            VariableAssignmentScope::Session => {
+                ctx.none_persistent_vars.remove(&a.var_name);
+                ctx.assigned_vars.insert(a.var_name.clone());
+                ctx.vars.insert(a.var_name.clone(), value);
+            }
+            VariableAssignmentScope::Turn => {
+                ctx.none_persistent_vars.insert(a.var_name.clone(), value);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RuntimeNode for SetVariablesNode {
+    fn exec(&mut self, req: &Request, ctx: &mut Context, _response: &mut Response) -> bool {
+        for a in self.assignments.iter() {
+            if let Err(e) = self.assign(a, req, ctx) {
+                log::error!("Assigning variable {} failed: {:?}", &a.var_name, &e);
+            }
+        }
+        add_next_node(ctx, &self.next_node_id);
+        false
+    }
+}
+
 pub(crate) fn deser_node(bytes: &[u8]) -> Result<RuntimeNnodeEnum> {
     // let now = std::time::Instant::now();
     let mut v = AlignedVec::<256>::with_capacity(bytes.len());
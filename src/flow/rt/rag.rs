@@ -0,0 +1,94 @@
+use super::dto::AnswerSource;
+use super::node::KnowledgeBaseAnswerSource;
+use crate::ai::embedding::embedding;
+use crate::kb::{doc, qa};
+use crate::result::{Error, Result};
+
+pub(super) struct Passage {
+    pub(super) source: AnswerSource,
+    text: String,
+}
+
+// Top `k` of each source which are within `recall_distance`, nearest first
+pub(super) async fn retrieve(
+    robot_id: &str,
+    question: &str,
+    locale: &str,
+    sources: &[KnowledgeBaseAnswerSource],
+    k: u8,
+    recall_distance: f64,
+) -> Result<Vec<Passage>> {
+    let vectors = embedding(robot_id, question).await?;
+    if vectors.0.is_empty() {
+        return Err(Error::ErrorWithMessage(format!(
+            "{} embedding data is empty",
+            question
+        )));
+    }
+    let mut passages: Vec<Passage> = Vec::with_capacity(k as usize * sources.len());
+    for s in sources.iter() {
+        match s {
+            KnowledgeBaseAnswerSource::QnA => {
+                for (d, distance) in qa::search(robot_id, &vectors.0, k).await? {
+                    log::info!("QnA {} distance {}", d.question_text(), distance);
+                    if distance > recall_distance {
+                        continue;
+                    }
+                    let answer =
+                        crate::robot::i18n::select(d.answer_i18n.iter(), &d.answer, locale);
+                    passages.push(Passage {
+                        text: String::from(answer),
+                        source: AnswerSource {
+                            index: 0,
+                            source_type: String::from("QnA"),
+                            id: d.id.clone().unwrap_or_default(),
+                            title: String::from(d.question_text()),
+                            distance,
+                        },
+                    });
+                }
+            }
+            KnowledgeBaseAnswerSource::Doc => {
+                for c in doc::search(robot_id, &vectors.0, k).await? {
+                    log::info!("Doc {} distance {}", &c.file_name, c.distance);
+                    if c.distance > recall_distance {
+                        continue;
+                    }
+                    passages.push(Passage {
+                        text: c.content,
+                        source: AnswerSource {
+                            index: 0,
+                            source_type: String::from("Doc"),
+                            id: c.doc_id.to_string(),
+                            title: c.file_name,
+                            distance: c.distance,
+                        },
+                    });
+                }
+            }
+        }
+    }
+    passages.sort_by(|a, b| a.source.distance.total_cmp(&b.source.distance));
+    for (i, p) in passages.iter_mut().enumerate() {
+        p.source.index = i + 1;
+    }
+    Ok(passages)
+}
+
+pub(super) fn system_prompt(passages: &[Passage], instructions: &str) -> String {
+    let mut s = String::from(
+        "Answer the question of the user with only the numbered sources below. Cite the sources you use with their numbers in square brackets, e.g. [1]. If the sources do not contain the answer, say that you do not know instead of guessing.",
+    );
+    if !instructions.is_empty() {
+        s.push_str("\n\n");
+        s.push_str(instructions);
+    }
+    s.push_str("\n\nSources:");
+    for p in passages.iter() {
+        s.push_str(&format!(
+            "\n\n[{}] {}\n{}",
+            p.source.index, &p.source.title, &p.text
+        ));
+    }
+    s
+}
@@ -0,0 +1,486 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use minijinja::value::Kwargs;
+use minijinja::{AutoEscape, Environment, ErrorKind, Value};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::context::Context;
+use super::dto::Request;
+use crate::result::{Error, Result};
+use crate::variable::crud as variable;
+use crate::variable::dto::{parse_datetime, VariableValue};
+
+const LEGACY_VAR_WRAP_SYMBOL: char = '`';
+// Legacy names which are not identifiers, e.g. `order id`, are looked up with {{ __vars["order id"] }}
+const LEGACY_VARS: &str = "__vars";
+const DEFAULT_DATE_FORMAT: &str = "[year]-[month]-[day] [hour]:[minute]:[second]";
+
+static PLAIN_ENV: LazyLock<Environment<'static>> = LazyLock::new(|| new_env(false));
+static HTML_ENV: LazyLock<Environment<'static>> = LazyLock::new(|| new_env(true));
+// Unreserved characters of RFC 3986, others are percent-encoded in values of URLs
+const URL_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn new_env(escape_html: bool) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(move |_| {
+        if escape_html {
+            AutoEscape::Html
+        } else {
+            AutoEscape::None
+        }
+    });
+    env.add_filter("number", number);
+    env.add_filter("currency", currency);
+    env.add_filter("date", date);
+    env.add_function("now", now);
+    env.add_function("timestamp", timestamp);
+    env.add_function("date_add", date_add);
+    env
+}
+
+fn to_f64(v: &Value) -> std::result::Result<f64, minijinja::Error> {
+    if let Some(s) = v.as_str() {
+        return s.trim().parse::<f64>().map_err(|_| {
+            minijinja::Error::new(ErrorKind::InvalidOperation, "value is not a number")
+        });
+    }
+    f64::try_from(v.clone())
+}
+
+fn group_thousands(n: f64, decimals: usize) -> String {
+    let s = format!("{:.*}", decimals, n.abs());
+    let (int_part, frac_part) = match s.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (s.as_str(), None),
+    };
+    let mut r = String::with_capacity(s.len() + 8);
+    if n < 0f64 {
+        r.push('-');
+    }
+    for (i, c) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            r.push(',');
+        }
+        r.push(c);
+    }
+    if let Some(f) = frac_part {
+        r.push('.');
+        r.push_str(f);
+    }
+    r
+}
+
+// {{ amount|number }} {{ amount|number(2) }}
+fn number(v: Value, decimals: Option<usize>) -> std::result::Result<String, minijinja::Error> {
+    let n = to_f64(&v)?;
+    let decimals = decimals.unwrap_or(if n.fract() == 0f64 { 0 } else { 2 });
+    Ok(group_thousands(n, decimals))
+}
+
+// {{ amount|currency }} {{ amount|currency("EUR") }} {{ amount|currency("JPY", decimals=0) }}
+fn currency(
+    v: Value,
+    code: Option<String>,
+    kwargs: Kwargs,
+) -> std::result::Result<String, minijinja::Error> {
+    let n = to_f64(&v)?;
+    let code = code.unwrap_or_else(|| String::from("USD")).to_uppercase();
+    let decimals: usize = kwargs.get::<Option<usize>>("decimals")?.unwrap_or(2);
+    kwargs.assert_all_used()?;
+    let amount = group_thousands(n, decimals);
+    let symbol = match code.as_str() {
+        "USD" => "$",
+        "EUR" => "€",
+        "GBP" => "£",
+        "CNY" | "JPY" => "¥",
+        "KRW" => "₩",
+        "INR" => "₹",
+        _ => return Ok(format!("{} {}", amount, code)),
+    };
+    if let Some(a) = amount.strip_prefix('-') {
+        Ok(format!("-{}{}", symbol, a))
+    } else {
+        Ok(format!("{}{}", symbol, amount))
+    }
+}
+
+// Accepts unix timestamps in seconds or RFC 3339 strings,
+// the format uses the `time` crate description syntax, e.g. "[year]/[month]/[day]"
+fn date(v: Value, format: Option<String>) -> std::result::Result<String, minijinja::Error> {
+    let dt = if let Some(s) = v.as_str() {
+        time::OffsetDateTime::parse(s, &time::format_description::well_known::Rfc3339)
+            .map_err(|e| minijinja::Error::new(ErrorKind::InvalidOperation, format!("{}", e)))?
+    } else {
+        let secs = to_f64(&v)? as i64;
+        time::OffsetDateTime::from_unix_timestamp(secs)
+            .map_err(|e| minijinja::Error::new(ErrorKind::InvalidOperation, format!("{}", e)))?
+    };
+    let format = format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT);
+    let desc = time::format_description::parse_borrowed::<2>(format)
+        .map_err(|e| minijinja::Error::new(ErrorKind::InvalidOperation, format!("{}", e)))?;
+    dt.format(&desc)
+        .map_err(|e| minijinja::Error::new(ErrorKind::InvalidOperation, format!("{}", e)))
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// timestamp("2024-01-31T08:00:00Z") returns unix seconds, so dates can be subtracted
+fn timestamp(v: Value) -> std::result::Result<i64, minijinja::Error> {
+    if let Some(s) = v.as_str() {
+        parse_datetime(s).ok_or_else(|| {
+            minijinja::Error::new(ErrorKind::InvalidOperation, "value is not a date time")
+        })
+    } else {
+        Ok(to_f64(&v)? as i64)
+    }
+}
+
+// date_add(order_date, days=7, hours=1) returns unix seconds
+fn date_add(v: Value, kwargs: Kwargs) -> std::result::Result<i64, minijinja::Error> {
+    let mut t = timestamp(v)?;
+    for (name, secs) in [
+        ("days", 86400),
+        ("hours", 3600),
+        ("minutes", 60),
+        ("seconds", 1),
+    ] {
+        if let Some(n) = kwargs.get::<Option<i64>>(name)? {
+            t += n * secs;
+        }
+    }
+    kwargs.assert_all_used()?;
+    Ok(t)
+}
+
+// Keeps the old `varName` substitution working by rewriting it to a lookup of LEGACY_VARS,
+// e.g. {{ __vars["varName"] }}, and returns the names which were rewritten
+fn convert_legacy(text: &str) -> (Cow<'_, str>, Vec<&str>) {
+    let mut legacy_names = Vec::new();
+    if !text.contains(LEGACY_VAR_WRAP_SYMBOL) {
+        return (Cow::Borrowed(text), legacy_names);
+    }
+    let mut new_str = String::with_capacity(text.len() + 16);
+    let mut rest = text;
+    while let Some(begin) = rest.find(LEGACY_VAR_WRAP_SYMBOL) {
+        let Some(len) = rest[begin + 1..].find(LEGACY_VAR_WRAP_SYMBOL) else {
+            break;
+        };
+        let end = begin + 1 + len;
+        let name = &rest[begin + 1..end];
+        new_str.push_str(&rest[..begin]);
+        if is_legacy_name(name) {
+            // A JSON string is also a valid template string literal
+            new_str.push_str("{{ ");
+            new_str.push_str(LEGACY_VARS);
+            new_str.push('[');
+            new_str.push_str(&serde_json::Value::from(name).to_string());
+            new_str.push_str("] }}");
+            legacy_names.push(name);
+            rest = &rest[end + 1..];
+        } else {
+            new_str.push(LEGACY_VAR_WRAP_SYMBOL);
+            rest = &rest[begin + 1..];
+        }
+    }
+    new_str.push_str(rest);
+    (Cow::Owned(new_str), legacy_names)
+}
+
+// Variable names could contain spaces, dashes and so on, but not line breaks
+fn is_legacy_name(s: &str) -> bool {
+    !s.trim().is_empty() && !s.chars().any(char::is_control)
+}
+
+// Names which are not variables are kept as they were written, like the old substitution did
+fn legacy_value(name: &str, value: Option<Value>) -> Value {
+    value.unwrap_or_else(|| {
+        Value::from(format!(
+            "{}{}{}",
+            LEGACY_VAR_WRAP_SYMBOL, name, LEGACY_VAR_WRAP_SYMBOL
+        ))
+    })
+}
+
+fn needs_render(text: &str) -> bool {
+    text.contains(LEGACY_VAR_WRAP_SYMBOL) || text.contains("{{") || text.contains("{%")
+}
+
+fn env(escape_html: bool) -> &'static Environment<'static> {
+    if escape_html {
+        &HTML_ENV
+    } else {
+        &PLAIN_ENV
+    }
+}
+
+pub(crate) fn to_value(v: &VariableValue) -> Value {
+    match v {
+        VariableValue::Str(s) => Value::from(s.as_str()),
+        VariableValue::Num(n) => {
+            if n.fract() == 0f64 && n.abs() < i64::MAX as f64 {
+                Value::from(*n as i64)
+            } else {
+                Value::from(*n)
+            }
+        }
+        VariableValue::Array(arr) => Value::from(arr.iter().map(to_value).collect::<Vec<_>>()),
+        VariableValue::Bool(b) => Value::from(*b),
+        VariableValue::DateTime(_) => Value::from(v.val_to_string()),
+        VariableValue::Object(o) => Value::from_serialize(o),
+    }
+}
+
+fn template_err(e: minijinja::Error) -> Error {
+    Error::ErrorWithMessage(format!("Template error: {}", e))
+}
+
+// Used when releasing flows, so broken templates never reach the runtime
+pub(crate) fn check(text: &str) -> Result<()> {
+    if !needs_render(text) {
+        return Ok(());
+    }
+    let (source, _) = convert_legacy(text);
+    PLAIN_ENV
+        .template_from_str(&source)
+        .map(|_| ())
+        .map_err(template_err)
+}
+
+fn lookup_value(name: &str, req: &Request, ctx: &mut Context) -> Result<Option<Value>> {
+    let value = if let Some(v) = variable::get(&req.robot_id, name)? {
+        v.get_value(req, ctx).map(to_value)
+    } else {
+        ctx.none_persistent_vars
+            .get(name)
+            .or_else(|| ctx.vars.get(name))
+            .map(to_value)
+    };
+    Ok(value)
+}
+
+// Values assigned in the flow do not have to be declared as robot variables
+fn lookup_values(
+    mut names: std::collections::HashSet<String>,
+    legacy_names: &[&str],
+    req: &Request,
+    ctx: &mut Context,
+) -> Result<HashMap<String, Value>> {
+    let mut values: HashMap<String, Value> = HashMap::with_capacity(names.len());
+    if names.remove(LEGACY_VARS) {
+        let mut legacy: HashMap<&str, Value> = HashMap::with_capacity(legacy_names.len());
+        for &name in legacy_names.iter() {
+            let value = lookup_value(name, req, ctx)?;
+            legacy.insert(name, legacy_value(name, value));
+        }
+        values.insert(String::from(LEGACY_VARS), Value::from(legacy));
+    }
+    for name in names.into_iter() {
+        if let Some(value) = lookup_value(&name, req, ctx)? {
+            values.insert(name, value);
+        }
+    }
+    Ok(values)
+}
+
+pub(crate) fn check_expression(expr: &str) -> Result<()> {
+    PLAIN_ENV
+        .compile_expression(expr)
+        .map(|_| ())
+        .map_err(template_err)
+}
+
+// Evaluates expressions like `counter + 1` or `first_name ~ " " ~ last_name`
+pub(crate) fn eval(expr: &str, req: &Request, ctx: &mut Context) -> Result<serde_json::Value> {
+    let expr = PLAIN_ENV.compile_expression(expr).map_err(template_err)?;
+    let values = lookup_values(expr.undeclared_variables(false), &[], req, ctx)?;
+    let v = expr.eval(values).map_err(template_err)?;
+    if v.is_undefined() || v.is_none() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Expression result has no value",
+        )));
+    }
+    Ok(serde_json::to_value(&v)?)
+}
+
+pub(crate) fn render(
+    text: &str,
+    req: &Request,
+    ctx: &mut Context,
+    escape_html: bool,
+) -> Result<String> {
+    if !needs_render(text) {
+        return Ok(String::from(text));
+    }
+    let (source, legacy_names) = convert_legacy(text);
+    let tpl = env(escape_html)
+        .template_from_str(&source)
+        .map_err(template_err)?;
+    let values = lookup_values(tpl.undeclared_variables(false), &legacy_names, req, ctx)?;
+    tpl.render(values).map_err(template_err)
+}
+
+pub(crate) fn render_with_vars(
+    text: &str,
+    vars: &HashMap<String, VariableValue>,
+    escape_html: bool,
+) -> Result<String> {
+    if !needs_render(text) {
+        return Ok(String::from(text));
+    }
+    let (source, legacy_names) = convert_legacy(text);
+    let tpl = env(escape_html)
+        .template_from_str(&source)
+        .map_err(template_err)?;
+    let mut values: HashMap<&str, Value> = vars
+        .iter()
+        .map(|(k, v)| (k.as_str(), to_value(v)))
+        .collect();
+    if !legacy_names.is_empty() {
+        let legacy: HashMap<&str, Value> = legacy_names
+            .iter()
+            .map(|&n| (n, legacy_value(n, vars.get(n).map(to_value))))
+            .collect();
+        values.insert(LEGACY_VARS, Value::from(legacy));
+    }
+    tpl.render(values).map_err(template_err)
+}
+
+// Definitions of HTTP APIs were sent as written before, so only {{ expression }} placeholders
+// are replaced in them. Backticks, {% and braces which are not expressions are kept
+fn placeholders(text: &str) -> Vec<(usize, usize, minijinja::Expression<'static, 'static>)> {
+    let mut r = Vec::new();
+    let mut from = 0;
+    while let Some(begin) = text[from..].find("{{").map(|i| from + i) {
+        let Some(len) = text[begin + 2..].find("}}") else {
+            break;
+        };
+        let end = begin + 2 + len + 2;
+        match PLAIN_ENV.compile_expression_owned(text[begin + 2..end - 2].to_string()) {
+            Ok(expr) => {
+                r.push((begin, end, expr));
+                from = end;
+            }
+            Err(_) => from = begin + 2,
+        }
+    }
+    r
+}
+
+// Variables used by placeholders of the text, e.g. `order` of {{ order.id }}
+pub(crate) fn placeholder_names(text: &str) -> std::collections::HashSet<String> {
+    placeholders(text)
+        .iter()
+        .flat_map(|(_, _, expr)| expr.undeclared_variables(false))
+        .collect()
+}
+
+fn render_placeholders_with(
+    text: &str,
+    vars: &HashMap<String, VariableValue>,
+    encode: bool,
+) -> Result<String> {
+    let placeholders = placeholders(text);
+    if placeholders.is_empty() {
+        return Ok(String::from(text));
+    }
+    let values: HashMap<&str, Value> = vars
+        .iter()
+        .map(|(k, v)| (k.as_str(), to_value(v)))
+        .collect();
+    let mut r = String::with_capacity(text.len() + 64);
+    let mut last = 0;
+    for (begin, end, expr) in placeholders.iter() {
+        r.push_str(&text[last..*begin]);
+        let v = expr.eval(&values).map_err(template_err)?;
+        // Variables without values are left empty like in templates
+        if !v.is_undefined() && !v.is_none() {
+            if encode && !v.is_safe() {
+                r.extend(utf8_percent_encode(&v.to_string(), URL_VALUE));
+            } else {
+                r.push_str(&v.to_string());
+            }
+        }
+        last = *end;
+    }
+    r.push_str(&text[last..]);
+    Ok(r)
+}
+
+// Renders bodies, headers and parameters of HTTP APIs
+pub(crate) fn render_placeholders(
+    text: &str,
+    vars: &HashMap<String, VariableValue>,
+) -> Result<String> {
+    render_placeholders_with(text, vars, false)
+}
+
+// Values can not change the path or query of URLs, unless marked like {{ base_url|safe }}/orders/{{ id }}
+pub(crate) fn render_url(text: &str, vars: &HashMap<String, VariableValue>) -> Result<String> {
+    render_placeholders_with(text, vars, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_names() -> Result<()> {
+        let vars = HashMap::from([
+            (
+                String::from("name"),
+                VariableValue::Str(String::from("Bob")),
+            ),
+            (String::from("order id"), VariableValue::Num(7f64)),
+        ]);
+        assert_eq!(
+            render_with_vars("Hi `name`, order `order id`", &vars, false)?,
+            "Hi Bob, order 7"
+        );
+        // Text which is not a variable is kept as written
+        assert_eq!(
+            render_with_vars("type `help` to restart, or `start over`", &vars, false)?,
+            "type `help` to restart, or `start over`"
+        );
+        assert_eq!(
+            render_with_vars("`name`: {{ name|upper }}", &vars, false)?,
+            "Bob: BOB"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn placeholders() -> Result<()> {
+        let vars = HashMap::from([
+            (
+                String::from("id"),
+                VariableValue::Str(String::from("a/b c")),
+            ),
+            (String::from("n"), VariableValue::Num(2f64)),
+        ]);
+        // Backticks, blocks and braces which are not expressions are sent as written
+        let body =
+            r#"{"q": "`code` {% raw %}", "n": {{ n }}, "o": {{"k": 1}}, "m": {{ missing }}}"#;
+        assert_eq!(
+            render_placeholders(body, &vars)?,
+            r#"{"q": "`code` {% raw %}", "n": 2, "o": {{"k": 1}}, "m": }"#
+        );
+        assert_eq!(
+            render_url("{{ \"h/v1\"|safe }}/items/{{ id }}?n={{ n }}", &vars)?,
+            "h/v1/items/a%2Fb%20c?n=2"
+        );
+        let names = placeholder_names(body);
+        assert_eq!(names.len(), 2);
+        assert!(names.contains("n") && names.contains("missing"));
+        Ok(())
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::BTreeSet;
+
+use serde_json::{json, Map, Value};
+
+use super::context::Context;
+use super::dto::Request;
+use super::node::{ExternalHttpCallNode, HttpResponseMapping, LlmTool};
+use super::template;
+use crate::ai::chat::{ToolChat, ToolChatTurn};
+use crate::ai::completion::Prompt;
+use crate::external::http::client as http;
+use crate::external::http::dto::{HttpAuth, HttpReqInfo, ResponseData, ValueSource};
+use crate::result::{Error, Result};
+use crate::variable::crud as variable;
+use crate::variable::dto::{Variable, VariableType, VariableValue, VariableValueSource};
+
+// Rounds of tool calls before the model has to answer
+const MAX_TOOL_ROUNDS: usize = 5;
+// Longer outputs are cut before being sent to the model
+const MAX_OUTPUT_LEN: usize = 8192;
+const MAX_NAME_LEN: usize = 64;
+
+enum Target<'t> {
+    HttpApi(Box<HttpReqInfo>, &'t [HttpResponseMapping]),
+    Variable(Variable),
+}
+
+struct Tool<'t> {
+    name: String,
+    description: String,
+    params: Vec<(String, VariableType)>,
+    target: Target<'t>,
+}
+
+// Names of functions can only contain a-z, A-Z, 0-9, _ and -
+fn tool_name(prefix: &str, name: &str, id: &str, tools: &[Tool]) -> String {
+    let sanitize = |s: &str| -> String {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    };
+    let mut n = sanitize(name);
+    if !n.chars().any(|c| c.is_ascii_alphanumeric()) {
+        n = sanitize(id);
+    }
+    let mut n = format!("{}_{}", prefix, n);
+    n.truncate(MAX_NAME_LEN - 3);
+    let mut r = n.clone();
+    let mut i = 1;
+    while tools.iter().any(|t| t.name == r) {
+        i += 1;
+        r = format!("{}_{}", &n, i);
+    }
+    r
+}
+
+// Variables used by the request which the node allows the model to fill in,
+// except credentials and constants which are never up to the model
+fn http_params(
+    robot_id: &str,
+    info: &HttpReqInfo,
+    allowed: &[String],
+) -> Result<Vec<(String, VariableType)>> {
+    let mut names = BTreeSet::new();
+    names.extend(template::placeholder_names(&info.address));
+    names.extend(template::placeholder_names(&info.request_body));
+    for p in info
+        .headers
+        .iter()
+        .chain(info.query_params.iter())
+        .chain(info.form_data.iter())
+    {
+        match p.value_source {
+            ValueSource::Val => names.extend(template::placeholder_names(&p.value)),
+            ValueSource::Var => {
+                names.insert(p.value.clone());
+            }
+        }
+    }
+    match &info.auth {
+        HttpAuth::None | HttpAuth::OAuth2ClientCredentials { .. } => {}
+        HttpAuth::Basic { username, password } => {
+            for n in template::placeholder_names(username)
+                .iter()
+                .chain(template::placeholder_names(password).iter())
+            {
+                names.remove(n);
+            }
+        }
+        HttpAuth::Bearer { var_name } => {
+            names.remove(var_name);
+        }
+        HttpAuth::ApiKey { value, .. } => {
+            for n in template::placeholder_names(value).iter() {
+                names.remove(n);
+            }
+        }
+    }
+    names.retain(|n| allowed.contains(n));
+    let mut params = Vec::with_capacity(names.len());
+    for name in names.into_iter() {
+        match variable::get(robot_id, &name)? {
+            Some(v) if matches!(v.var_val_source, VariableValueSource::Constant) => {}
+            Some(v) => params.push((name, v.var_type)),
+            None => params.push((name, VariableType::Str)),
+        }
+    }
+    Ok(params)
+}
+
+fn tools<'t>(defs: &'t [LlmTool], robot_id: &str) -> Result<Vec<Tool<'t>>> {
+    let mut tools: Vec<Tool> = Vec::with_capacity(defs.len());
+    for d in defs.iter() {
+        let tool = match d {
+            LlmTool::HttpApi {
+                http_api_id,
+                params,
+                response_mappings,
+            } => {
+                let info = crate::external::http::crud::get_detail(robot_id, http_api_id)?
+                    .ok_or_else(|| {
+                        Error::ErrorWithMessage(format!("HTTP API {} not found", http_api_id))
+                    })?;
+                Tool {
+                    name: tool_name("http", &info.name, &info.id, &tools),
+                    description: if info.description.is_empty() {
+                        info.name.clone()
+                    } else {
+                        info.description.clone()
+                    },
+                    params: http_params(robot_id, &info, params)?,
+                    target: Target::HttpApi(Box::new(info), response_mappings),
+                }
+            }
+            LlmTool::Variable { var_name } => {
+                let v = variable::get(robot_id, var_name)?.ok_or_else(|| {
+                    Error::ErrorWithMessage(format!("Variable {} not found", var_name))
+                })?;
+                Tool {
+                    name: tool_name("get", var_name, var_name, &tools),
+                    description: format!("Returns the value of {}", var_name),
+                    params: Vec::new(),
+                    target: Target::Variable(v),
+                }
+            }
+        };
+        tools.push(tool);
+    }
+    Ok(tools)
+}
+
+// Parameters having values in the context can be left out by the model
+fn parameters(t: &Tool, ctx: &Context) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for (name, var_type) in t.params.iter() {
+        properties.insert(name.clone(), var_type.json_schema());
+        if !ctx.vars.contains_key(name) {
+            required.push(Value::from(name.as_str()));
+        }
+    }
+    json!({"type": "object", "properties": properties, "required": required})
+}
+
+fn cut(mut s: String) -> String {
+    if s.len() > MAX_OUTPUT_LEN {
+        let mut end = MAX_OUTPUT_LEN;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+    }
+    s
+}
+
+fn call(t: &Tool, args: &Map<String, Value>, req: &Request, ctx: &mut Context) -> Result<String> {
+    match &t.target {
+        Target::HttpApi(info, mappings) => {
+            let mut vars = ctx.vars.clone();
+            for (name, var_type) in t.params.iter() {
+                if let Some(v) = args.get(name).filter(|v| !v.is_null()) {
+                    let v = VariableValue::from_json(v.clone(), var_type).map_err(|e| {
+                        Error::ErrorWithMessage(format!("Argument {}: {:?}", name, e))
+                    })?;
+                    vars.insert(name.clone(), v);
+                }
+            }
+            let res = tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(http::send(&req.robot_id, info, &vars))
+            })?;
+            for m in mappings.iter() {
+                match ExternalHttpCallNode::mapped_value(m, &res, &req.robot_id) {
+                    Ok(v) => super::node::save_var(req, ctx, &m.var_name, v),
+                    Err(e) => log::warn!("Mapping variable {} failed: {:?}", &m.var_name, e),
+                }
+            }
+            let body = match res.data {
+                ResponseData::Str(s) => s,
+                ResponseData::Bin(b) => format!("<{} bytes binary data>", b.len()),
+                ResponseData::None => String::new(),
+            };
+            Ok(format!("HTTP status: {}\n{}", res.status, body))
+        }
+        Target::Variable(v) => Ok(v
+            .get_value(req, ctx)
+            .map_or_else(|| String::from("No value"), |v| v.val_to_string())),
+    }
+}
+
+// Lets the model call tools until it answers
+pub(super) fn answer(
+    defs: &[LlmTool],
+    system: &str,
+    chat_history: &[Prompt],
+    connect_timeout: Option<u32>,
+    read_timeout: Option<u32>,
+    req: &Request,
+    ctx: &mut Context,
+) -> Result<String> {
+    let tools = tools(defs, &req.robot_id)?;
+    let functions = tools
+        .iter()
+        .map(|t| (t.name.clone(), t.description.clone(), parameters(t, ctx)))
+        .collect();
+    let mut chat = ToolChat::new(
+        &req.robot_id,
+        system,
+        chat_history,
+        functions,
+        connect_timeout,
+        read_timeout,
+    )?;
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let calls = match tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(chat.next())
+        })? {
+            ToolChatTurn::Answer(s) => return Ok(s),
+            ToolChatTurn::ToolCalls(calls) => calls,
+        };
+        for c in calls.iter() {
+            let output = match tools.iter().find(|t| t.name == c.name) {
+                Some(t) => call(t, &c.arguments, req, ctx).unwrap_or_else(|e| {
+                    log::warn!("Calling tool {} failed: {:?}", &c.name, &e);
+                    format!("Calling tool failed: {:?}", e)
+                }),
+                None => format!("Tool {} does not exist", &c.name),
+            };
+            let output = cut(output);
+            log::debug!("Tool {} returned {}", &c.name, &output);
+            chat.add_tool_result(c, &output);
+        }
+    }
+    Err(Error::ErrorWithMessage(String::from(
+        "The model kept calling tools without answering",
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::db;
+    use crate::man::settings::{self, Settings};
+
+    // Answers one HTTP request per connection with each JSON body, returns the request lines and bodies
+    async fn stub(replies: Vec<Value>) -> (u16, tokio::task::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::with_capacity(replies.len());
+            for reply in replies {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (r, mut w) = socket.into_split();
+                let mut r = BufReader::new(r);
+                let mut request_line = String::new();
+                r.read_line(&mut request_line).await.unwrap();
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    r.read_line(&mut line).await.unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            len = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0u8; len];
+                r.read_exact(&mut body).await.unwrap();
+                requests.push((
+                    String::from(request_line.trim_end()),
+                    String::from_utf8(body).unwrap(),
+                ));
+                let reply = reply.to_string();
+                let res = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                );
+                w.write_all(res.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (port, handle)
+    }
+
+    fn tool_calls(name: &str, arguments: &str) -> Value {
+        json!({"choices": [{"message": {"role": "assistant", "content": null, "tool_calls": [
+            {"id": "call_1", "type": "function", "function": {"name": name, "arguments": arguments}}
+        ]}}]})
+    }
+
+    // The database is opened in the working directory
+    fn setup(robot_id: &str, chat_port: u16) -> Request {
+        static DATA_DIR: std::sync::Once = std::sync::Once::new();
+        DATA_DIR.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("dialogflow-tool-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::env::set_current_dir(&dir).unwrap();
+        });
+        let mut s = Settings::default();
+        s.chat_provider.provider = crate::ai::chat::ChatProvider::OpenAI(String::from("gpt-4o"));
+        s.chat_provider.api_url = format!("http://127.0.0.1:{}/v1/chat/completions", chat_port);
+        s.text_generation_provider.provider =
+            crate::ai::completion::TextGenerationProvider::OpenAI(String::from("gpt-4o"));
+        s.sentence_embedding_provider.provider =
+            crate::ai::embedding::SentenceEmbeddingProvider::OpenAI(String::from("embedding"));
+        settings::save_settings(robot_id, s).unwrap();
+        variable::init(robot_id, true).unwrap();
+        serde_json::from_value(json!({
+            "robotId": robot_id,
+            "mainFlowId": "",
+            "sessionId": format!("{}-session", robot_id),
+            "userInputResult": "Successful",
+            "userInput": "How warm is it in Paris?",
+            "importVariables": [],
+            "userInputIntent": null,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn call_http_api() -> Result<()> {
+        let (api_port, api) = stub(vec![json!({"temp": 21})]).await;
+        let (chat_port, chat) = stub(vec![
+            tool_calls("http_weather", r#"{"city":"Paris"}"#),
+            json!({"choices": [{"message": {"role": "assistant", "content": "It is 21 degrees in Paris."}}]}),
+        ])
+        .await;
+        let req = setup("toolTestHttp", chat_port);
+        let info: HttpReqInfo = serde_json::from_value(json!({
+            "id": "weatherApi",
+            "name": "weather",
+            "description": "Current temperature of a city",
+            "protocol": "HTTP",
+            "method": "GET",
+            "address": format!("127.0.0.1:{}/weather?city={{{{ city }}}}", api_port),
+            "timeoutMilliseconds": 2000,
+            "postContentType": "JSON",
+            "headers": [],
+            "queryParams": [],
+            "formData": [],
+            "requestBody": "",
+            "userAgent": "",
+            "asyncReq": false,
+        }))?;
+        crate::db_executor!(
+            db::write,
+            &req.robot_id,
+            crate::external::http::crud::TABLE_SUFFIX,
+            &info.id,
+            &info
+        )?;
+        let defs: Vec<LlmTool> = serde_json::from_value(json!([{"HttpApi": {
+            "httpApiId": "weatherApi",
+            "params": ["city"],
+            "responseMappings": [
+                {"varName": "temp", "source": "Body", "expressionType": "JsonPointer", "expression": "/temp"}
+            ],
+        }}]))?;
+        let mut ctx = Context::get(&req.robot_id, &req.session_id);
+        let history = vec![Prompt {
+            role: String::from("user"),
+            content: req.user_input.clone(),
+        }];
+        let r = answer(
+            &defs,
+            "You answer questions about weather.",
+            &history,
+            None,
+            None,
+            &req,
+            &mut ctx,
+        )?;
+        assert_eq!(r, "It is 21 degrees in Paris.");
+        assert!(matches!(ctx.vars.get("temp"), Some(VariableValue::Num(n)) if *n == 21.0));
+
+        let api = api.await.unwrap();
+        assert!(
+            api[0].0.starts_with("GET /weather?city=Paris "),
+            "{}",
+            &api[0].0
+        );
+        let chat = chat.await.unwrap();
+        assert_eq!(chat.len(), 2);
+        let first: Value = serde_json::from_str(&chat[0].1)?;
+        assert_eq!(first["tools"][0]["function"]["name"], "http_weather");
+        assert_eq!(
+            first["tools"][0]["function"]["parameters"]["required"],
+            json!(["city"])
+        );
+        let second: Value = serde_json::from_str(&chat[1].1)?;
+        let messages = second["messages"].as_array().unwrap();
+        let output = messages.last().unwrap();
+        assert_eq!(output["role"], "tool");
+        assert_eq!(output["tool_call_id"], "call_1");
+        assert_eq!(output["content"], "HTTP status: 200\n{\"temp\":21}");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stop_calling_tools() -> Result<()> {
+        let (chat_port, chat) = stub(vec![tool_calls("http_missing", "{}"); MAX_TOOL_ROUNDS]).await;
+        let req = setup("toolTestRounds", chat_port);
+        let mut ctx = Context::get(&req.robot_id, &req.session_id);
+        let history = vec![Prompt {
+            role: String::from("user"),
+            content: req.user_input.clone(),
+        }];
+        let r = answer(&[], "", &history, None, None, &req, &mut ctx);
+        assert!(
+            matches!(&r, Err(Error::ErrorWithMessage(m)) if m == "The model kept calling tools without answering")
+        );
+        assert_eq!(chat.await.unwrap().len(), MAX_TOOL_ROUNDS);
+        Ok(())
+    }
+}
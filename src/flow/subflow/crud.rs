@@ -5,13 +5,14 @@ use axum::http::{header::HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 // use redb::TableDefinition;
+use utoipa::TupleUnit;
 
 use super::dto::{SubFlowDetail, SubFlowFormData};
 use crate::db;
 use crate::db_executor;
 use crate::flow::demo;
 use crate::result::{Error, Result};
-use crate::web::server::{self, to_res};
+use crate::web::server::{self, to_res, ResponseData};
 
 pub(crate) const TABLE_SUFFIX: &str = "subflows";
 // pub(crate) const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("subflows");
@@ -27,6 +28,14 @@ static LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
 //     db::write(TABLE, mainflow_id, &flow)
 // }
 
+#[utoipa::path(
+    get,
+    path = "/subflow",
+    tag = "flow",
+    summary = "List sub flows of a main flow",
+    params(("robotId" = String, Query), ("mainFlowId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<SubFlowDetail>>))
+)]
 pub(crate) async fn list(headers: HeaderMap, Query(q): Query<SubFlowFormData>) -> Response {
     let is_en = server::is_en(&headers);
     let template = demo::get_demo(is_en, &q.main_flow_id);
@@ -48,6 +57,14 @@ pub(crate) async fn list(headers: HeaderMap, Query(q): Query<SubFlowFormData>) -
     // to_res(r)
 }
 
+#[utoipa::path(
+    get,
+    path = "/subflow/simple",
+    tag = "flow",
+    summary = "List sub flows without their canvas",
+    params(("robotId" = String, Query), ("mainFlowId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<SubFlowDetail>>))
+)]
 pub(crate) async fn simple_list(Query(q): Query<SubFlowFormData>) -> Response {
     // let r: Result<Option<Vec<SubFlowDetail>>> = db::query(TABLE, q.main_flow_id.as_str());
     let r: Result<Option<Vec<SubFlowDetail>>> = db_executor!(
@@ -94,10 +111,27 @@ pub(crate) fn new_subflow(
         })
 }
 
+#[utoipa::path(
+    post,
+    path = "/subflow/new",
+    tag = "flow",
+    summary = "Create a sub flow named `data`",
+    params(("robotId" = String, Query), ("mainFlowId" = String, Query), ("data" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<SubFlowDetail>>))
+)]
 pub(crate) async fn new(Query(form): Query<SubFlowFormData>) -> impl IntoResponse {
     to_res(new_subflow(&form.robot_id, &form.main_flow_id, &form.data))
 }
 
+#[utoipa::path(
+    post,
+    path = "/subflow",
+    tag = "flow",
+    summary = "Save the sub flow at index `data`",
+    params(("robotId" = String, Query), ("mainFlowId" = String, Query), ("data" = String, Query)),
+    request_body = SubFlowDetail,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn save(
     Query(q): Query<SubFlowFormData>,
     Json(data): Json<SubFlowDetail>,
@@ -134,6 +168,14 @@ pub(crate) async fn save(
     to_res(r)
 }
 
+#[utoipa::path(
+    delete,
+    path = "/subflow",
+    tag = "flow",
+    summary = "Delete the sub flow at index `data`",
+    params(("robotId" = String, Query), ("mainFlowId" = String, Query), ("data" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn delete(Query(q): Query<SubFlowFormData>) -> impl IntoResponse {
     let r = q
         .data
@@ -165,6 +207,14 @@ pub(crate) async fn delete(Query(q): Query<SubFlowFormData>) -> impl IntoRespons
     to_res(r)
 }
 
+#[utoipa::path(
+    get,
+    path = "/mainflow/release",
+    tag = "flow",
+    summary = "Release a main flow",
+    params(("robotId" = String, Query), ("mainFlowId" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn release(
     headers: HeaderMap,
     Query(q): Query<SubFlowFormData>,
@@ -6,18 +6,20 @@ use serde_json::Value;
 
 use crate::flow::rt::collector::CollectType;
 use crate::flow::rt::condition::{CompareType, ConditionType, TargetDataVariant};
+use crate::flow::rt::node::LlmTool;
 use crate::result::{Error, Result};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub(crate) struct SubFlowFormData {
     #[serde(rename = "robotId")]
     pub(crate) robot_id: String,
     #[serde(rename = "mainFlowId")]
     pub(crate) main_flow_id: String,
+    #[serde(default)]
     pub(crate) data: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct SubFlowDetail {
     pub(crate) id: String,
     pub(crate) name: String,
@@ -62,8 +64,11 @@ pub(crate) enum Node {
     GotoNode(GotoNode),
     ExternalHttpNode(ExternalHttpNode),
     SendEmailNode(SendEmailNode),
+    SendNotificationNode(SendNotificationNode),
     EndNode(EndNode),
     KnowledgeBaseAnswerNode(KnowledgeBaseAnswerNode),
+    SetVariablesNode(SetVariablesNode),
+    LlmExtractNode(LlmExtractNode),
 }
 
 impl Node {
@@ -79,7 +84,70 @@ impl Node {
         Err(Error::ErrorWithMessage(message))
     }
 
-    pub(crate) fn is_valid(&self, f: &SubFlowDetail) -> Result<()> {
+    fn check_templates(&self, f: &SubFlowDetail) -> Result<()> {
+        // Prompts are rendered after being unwrapped from their JSON messages
+        let system_prompt: String;
+        let (t, node_name, texts): (&str, &str, Vec<&String>) = match self {
+            Node::DialogNode(n) => (
+                "Dialog",
+                &n.node_name,
+                std::iter::once(&n.dialog_text)
+                    .chain(n.dialog_text_i18n.values())
+                    .collect(),
+            ),
+            Node::LlmChatNode(n) => {
+                system_prompt = crate::ai::history::system_prompt(&n.prompt);
+                ("LLM chat", &n.node_name, vec![&system_prompt])
+            }
+            Node::LlmExtractNode(n) => ("LLM extraction", &n.node_name, vec![&n.instructions]),
+            Node::KnowledgeBaseAnswerNode(n) => match &n.rag {
+                Some(r) => ("Knowledge answer", &n.node_name, vec![&r.instructions]),
+                None => return Ok(()),
+            },
+            Node::CollectNode(n) => (
+                "Collect",
+                &n.node_name,
+                std::iter::once(&n.collect_prompt)
+                    .chain(n.collect_prompt_i18n.values())
+                    .collect(),
+            ),
+            Node::SendEmailNode(n) => (
+                "Send email",
+                &n.node_name,
+                [&n.from, &n.subject, &n.content, &n.plain_text_content]
+                    .into_iter()
+                    .chain(n.to_recipients.iter())
+                    .chain(n.cc_recipients.iter())
+                    .chain(n.bcc_recipients.iter())
+                    .collect(),
+            ),
+            Node::SendNotificationNode(n) => (
+                "Send notification",
+                &n.node_name,
+                [&n.subject, &n.content]
+                    .into_iter()
+                    .chain(n.recipients.iter())
+                    .collect(),
+            ),
+            Node::EndNode(n) => (
+                "End email",
+                &n.node_name,
+                std::iter::once(&n.ending_text)
+                    .chain(n.ending_text_i18n.values())
+                    .collect(),
+            ),
+            _ => return Ok(()),
+        };
+        for text in texts {
+            if let Err(Error::ErrorWithMessage(m)) = crate::flow::rt::template::check(text) {
+                return Self::err(f, t, node_name, &m);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_valid(&self, robot_id: &str, f: &SubFlowDetail) -> Result<()> {
+        self.check_templates(f)?;
         // println!("{}", std::any::type_name_of_val(&self));
         match self {
             Node::DialogNode(n) => {
@@ -104,6 +172,26 @@ impl Node {
                     Self::err(f, t, &n.node_name, "node name not filled in")
                 } else if n.prompt.is_empty() {
                     Self::err(f, t, &n.node_name, "No prompt filled in")
+                } else if n.tools.iter().any(|tool| match tool {
+                    LlmTool::HttpApi {
+                        http_api_id,
+                        params,
+                        response_mappings,
+                    } => {
+                        http_api_id.is_empty()
+                            || params.iter().any(|p| p.is_empty())
+                            || response_mappings.iter().any(|m| m.var_name.is_empty())
+                    }
+                    LlmTool::Variable { var_name } => var_name.is_empty(),
+                }) {
+                    Self::err(f, t, &n.node_name, "Tool is not configured")
+                } else if !n.tools.is_empty() && !crate::ai::chat::supports_tools(robot_id)? {
+                    Self::err(
+                        f,
+                        t,
+                        &n.node_name,
+                        "tools need OpenAI or Ollama chat provider",
+                    )
                 } else if n.branches.len() != 1 {
                     Self::err(f, t, &n.node_name, "Branch information is incorrect")
                 } else {
@@ -171,14 +259,59 @@ impl Node {
                     Self::err(f, t, &n.node_name, "verification failed")
                 } else if n.node_name.is_empty() {
                     Self::err(f, t, &n.node_name, "node name not filled in")
-                } else if n.branches.len() != 1 {
+                } else if n.branches.len() != if n.branch_on_status { 4 } else { 1 } {
                     Self::err(f, t, &n.node_name, "Branch information is incorrect")
+                } else if n.http_api_id.is_empty() {
+                    Self::err(f, t, &n.node_name, "No HTTP interface selected")
                 } else {
-                    if n.http_api_id.is_empty() {
-                        Self::err(f, t, &n.node_name, "No HTTP interface selected")
-                    } else {
-                        Ok(())
+                    for m in n.response_mappings.iter() {
+                        if m.var_name.is_empty() {
+                            return Self::err(
+                                f,
+                                t,
+                                &n.node_name,
+                                "response mapping variable not filled in",
+                            );
+                        }
+                        let r = match m.source {
+                            crate::flow::rt::node::HttpResponseSource::Header
+                                if m.expression.is_empty() =>
+                            {
+                                Err(Error::ErrorWithMessage(String::from(
+                                    "header name not filled in",
+                                )))
+                            }
+                            crate::flow::rt::node::HttpResponseSource::Header => Ok(()),
+                            crate::flow::rt::node::HttpResponseSource::Body => {
+                                crate::variable::extract::check(&m.expression_type, &m.expression)
+                            }
+                        };
+                        if let Err(Error::ErrorWithMessage(e)) = r {
+                            return Self::err(
+                                f,
+                                t,
+                                &n.node_name,
+                                &format!("response mapping of {}: {}", m.var_name, e),
+                            );
+                        }
+                    }
+                    // Asynchronous requests are sent in the background, there is no response to use
+                    let is_async =
+                        crate::external::http::crud::get_detail(robot_id, &n.http_api_id)?
+                            .is_some_and(|api| api.async_req);
+                    if is_async
+                        && (n.branch_on_status
+                            || !n.response_mappings.is_empty()
+                            || !n.status_code_var_name.is_empty())
+                    {
+                        return Self::err(
+                            f,
+                            t,
+                            &n.node_name,
+                            "can not map the response or branch on status of an asynchronous HTTP interface",
+                        );
                     }
+                    Ok(())
                 }
             }
             Node::SendEmailNode(n) => {
@@ -203,6 +336,27 @@ impl Node {
                     }
                 }
             }
+            Node::SendNotificationNode(n) => {
+                let t = "Send notification";
+                if !n.valid {
+                    Self::err(f, t, &n.node_name, "verification failed")
+                } else if n.node_name.is_empty() {
+                    Self::err(f, t, &n.node_name, "node name not filled in")
+                } else if n.provider_id.is_empty() {
+                    Self::err(f, t, &n.node_name, "No notification provider selected")
+                } else if n.content.is_empty() {
+                    Self::err(
+                        f,
+                        t,
+                        &n.node_name,
+                        "need to fill in the notification content",
+                    )
+                } else if n.branches.len() != if n.async_send { 1 } else { 2 } {
+                    Self::err(f, t, &n.node_name, "Branch information is incorrect")
+                } else {
+                    Ok(())
+                }
+            }
             Node::EndNode(n) => {
                 let t = "End email";
                 if !n.valid {
@@ -215,6 +369,48 @@ impl Node {
                     Ok(())
                 }
             }
+            Node::SetVariablesNode(n) => {
+                let t = "Set variables";
+                if !n.valid {
+                    Self::err(f, t, &n.node_name, "verification failed")
+                } else if n.node_name.is_empty() {
+                    Self::err(f, t, &n.node_name, "node name not filled in")
+                } else if n.assignments.is_empty() {
+                    Self::err(f, t, &n.node_name, "No assignment added")
+                } else if n.branches.len() != 1 {
+                    Self::err(f, t, &n.node_name, "Branch information is incorrect")
+                } else {
+                    for a in n.assignments.iter() {
+                        if a.var_name.is_empty() {
+                            return Self::err(f, t, &n.node_name, "variable name not filled in");
+                        }
+                        if let Err(Error::ErrorWithMessage(m)) =
+                            crate::flow::rt::template::check_expression(&a.expression)
+                        {
+                            return Self::err(f, t, &n.node_name, &m);
+                        }
+                    }
+                    Ok(())
+                }
+            }
+            Node::LlmExtractNode(n) => {
+                let t = "LLM extraction";
+                if !n.valid {
+                    Self::err(f, t, &n.node_name, "verification failed")
+                } else if n.node_name.is_empty() {
+                    Self::err(f, t, &n.node_name, "node name not filled in")
+                } else if n.fields.is_empty() {
+                    Self::err(f, t, &n.node_name, "No field added")
+                } else if n.fields.iter().any(|field| field.var_name.is_empty()) {
+                    Self::err(f, t, &n.node_name, "variable name not filled in")
+                } else if !crate::ai::chat::supports_tools(robot_id)? {
+                    Self::err(f, t, &n.node_name, "needs OpenAI or Ollama chat provider")
+                } else if n.branches.len() != 2 && n.branches.len() != 3 {
+                    Self::err(f, t, &n.node_name, "Branch information is incorrect")
+                } else {
+                    Ok(())
+                }
+            }
             Node::KnowledgeBaseAnswerNode(n) => {
                 let t = "Knowledge answer";
                 if !n.valid {
@@ -228,6 +424,8 @@ impl Node {
                         &n.node_name,
                         "recall thresholds must between 1 and 100",
                     )
+                } else if n.rag.as_ref().is_some_and(|r| r.top_k < 1 || r.top_k > 20) {
+                    Self::err(f, t, &n.node_name, "top k must between 1 and 20")
                 } else {
                     Ok(())
                 }
@@ -244,8 +442,11 @@ impl Node {
             Self::GotoNode(n) => n.node_id.clone(),
             Self::ExternalHttpNode(n) => n.node_id.clone(),
             Self::SendEmailNode(n) => n.node_id.clone(),
+            Self::SendNotificationNode(n) => n.node_id.clone(),
             Self::EndNode(n) => n.node_id.clone(),
             Self::KnowledgeBaseAnswerNode(n) => n.node_id.clone(),
+            Self::SetVariablesNode(n) => n.node_id.clone(),
+            Self::LlmExtractNode(n) => n.node_id.clone(),
         }
     }
 
@@ -283,11 +484,26 @@ impl Node {
                     .iter()
                     .for_each(|b| ids.push(b.target_node_id.clone()));
             }
+            Self::SendNotificationNode(n) => {
+                n.branches
+                    .iter()
+                    .for_each(|b| ids.push(b.target_node_id.clone()));
+            }
             Self::KnowledgeBaseAnswerNode(n) => {
                 n.branches
                     .iter()
                     .for_each(|b| ids.push(b.target_node_id.clone()));
             }
+            Self::SetVariablesNode(n) => {
+                n.branches
+                    .iter()
+                    .for_each(|b| ids.push(b.target_node_id.clone()));
+            }
+            Self::LlmExtractNode(n) => {
+                n.branches
+                    .iter()
+                    .for_each(|b| ids.push(b.target_node_id.clone()));
+            }
         };
         ids
     }
@@ -301,7 +517,10 @@ impl Node {
             Self::CollectNode(n) => Some(&mut n.branches),
             Self::ExternalHttpNode(n) => Some(&mut n.branches),
             Self::SendEmailNode(n) => Some(&mut n.branches),
+            Self::SendNotificationNode(n) => Some(&mut n.branches),
             Self::KnowledgeBaseAnswerNode(n) => Some(&mut n.branches),
+            Self::SetVariablesNode(n) => Some(&mut n.branches),
+            Self::LlmExtractNode(n) => Some(&mut n.branches),
         }
     }
 }
@@ -312,6 +531,7 @@ pub(crate) enum BranchType {
     Condition,
     InfoCollectedSuccessfully,
     EmailSentSuccessfully,
+    NotificationSentSuccessfully,
 }
 
 #[derive(Deserialize)]
@@ -353,8 +573,12 @@ pub(crate) struct DialogNode {
     pub(crate) node_name: String,
     #[serde(rename = "dialogText")]
     pub(crate) dialog_text: String,
+    #[serde(rename = "dialogTextI18n", default)]
+    pub(crate) dialog_text_i18n: HashMap<String, String>,
     #[serde(rename = "dialogTextType")]
     pub(crate) dialog_text_type: crate::flow::rt::dto::AnswerType,
+    #[serde(rename = "richContent", default)]
+    pub(crate) rich_content: Option<crate::flow::rt::dto::RichContent>,
     #[serde(rename = "nextStep")]
     pub(crate) next_step: NextActionType,
     pub(crate) branches: Vec<Branch>,
@@ -382,6 +606,8 @@ pub(crate) struct LlmChatNode {
     pub(crate) connect_timeout: Option<u32>,
     #[serde(rename = "readTimeout")]
     pub(crate) read_timeout: Option<u32>,
+    #[serde(default)]
+    pub(crate) tools: Vec<LlmTool>,
 }
 
 #[derive(Deserialize)]
@@ -404,10 +630,14 @@ pub(crate) struct CollectNode {
     pub(crate) collect_type: CollectType,
     #[serde(rename = "collectSaveVarName")]
     pub(crate) collect_save_var_name: String,
+    #[serde(rename = "collectPrompt", default)]
+    pub(crate) collect_prompt: String,
+    #[serde(rename = "collectPromptI18n", default)]
+    pub(crate) collect_prompt_i18n: HashMap<String, String>,
     pub(crate) branches: Vec<Branch>,
 }
 
-#[derive(Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Deserialize, PartialEq, Eq, Serialize, utoipa::ToSchema)]
 pub(crate) enum NextActionType {
     None,
     GotoMainFlow,
@@ -446,6 +676,13 @@ pub(crate) struct ExternalHttpNode {
     pub(crate) node_name: String,
     #[serde(rename = "httpApiId")]
     pub(crate) http_api_id: String,
+    #[serde(rename = "responseMappings", default)]
+    pub(crate) response_mappings: Vec<crate::flow::rt::node::HttpResponseMapping>,
+    #[serde(rename = "statusCodeVarName", default)]
+    pub(crate) status_code_var_name: String,
+    // Branches are: successful, client error, server error or other failures, and timeout
+    #[serde(rename = "branchOnStatus", default)]
+    pub(crate) branch_on_status: bool,
     pub(crate) branches: Vec<Branch>,
 }
 
@@ -468,6 +705,32 @@ pub(crate) struct SendEmailNode {
     pub(crate) content: String,
     #[serde(rename = "contentType")]
     pub(crate) content_type: String,
+    #[serde(rename = "plainTextContent", default)]
+    pub(crate) plain_text_content: String,
+    #[serde(default)]
+    pub(crate) attachments: Vec<crate::flow::rt::node::EmailAttachment>,
+    pub(crate) branches: Vec<Branch>,
+    #[serde(rename = "asyncSend")]
+    pub(crate) async_send: bool,
+}
+
+// Providers are configured in robot settings
+#[derive(Deserialize)]
+pub(crate) struct SendNotificationNode {
+    pub(crate) valid: bool,
+    #[serde(rename = "nodeId")]
+    pub(crate) node_id: String,
+    #[serde(rename = "nodeName")]
+    pub(crate) node_name: String,
+    #[serde(rename = "providerId")]
+    pub(crate) provider_id: String,
+    // Phone numbers, email addresses or chat user ids, depends on the provider
+    #[serde(default)]
+    pub(crate) recipients: Vec<String>,
+    // Only used by email providers
+    #[serde(default)]
+    pub(crate) subject: String,
+    pub(crate) content: String,
     pub(crate) branches: Vec<Branch>,
     #[serde(rename = "asyncSend")]
     pub(crate) async_send: bool,
@@ -482,6 +745,11 @@ pub(crate) struct EndNode {
     pub(crate) node_name: String,
     #[serde(rename = "endingText")]
     pub(crate) ending_text: String,
+    #[serde(rename = "endingTextI18n", default)]
+    pub(crate) ending_text_i18n: HashMap<String, String>,
+    // Transfer the conversation to a human agent
+    #[serde(rename = "handOff", default)]
+    pub(crate) hand_off: bool,
 }
 
 #[derive(Deserialize)]
@@ -498,4 +766,38 @@ pub(crate) struct KnowledgeBaseAnswerNode {
     pub(crate) no_answer_then: crate::flow::rt::node::KnowledgeBaseAnswerNoRecallThen,
     #[serde(rename = "retrieveAnswerSources")]
     pub(crate) retrieve_answer_sources: Vec<crate::flow::rt::node::KnowledgeBaseAnswerSource>,
+    // Answers are returned verbatim when not set
+    #[serde(default)]
+    pub(crate) rag: Option<crate::flow::rt::node::KnowledgeBaseRag>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SetVariablesNode {
+    pub(crate) valid: bool,
+    #[serde(rename = "nodeId")]
+    pub(crate) node_id: String,
+    #[serde(rename = "nodeName")]
+    pub(crate) node_name: String,
+    pub(crate) assignments: Vec<crate::flow::rt::node::VariableAssignment>,
+    pub(crate) branches: Vec<Branch>,
+}
+
+// Branches are all required fields extracted, some of them missing, and optionally extracting failed
+#[derive(Deserialize)]
+pub(crate) struct LlmExtractNode {
+    pub(crate) valid: bool,
+    #[serde(rename = "nodeId")]
+    pub(crate) node_id: String,
+    #[serde(rename = "nodeName")]
+    pub(crate) node_name: String,
+    #[serde(default)]
+    pub(crate) instructions: String,
+    pub(crate) fields: Vec<crate::flow::rt::node::ExtractField>,
+    #[serde(rename = "retryTimes", default)]
+    pub(crate) retry_times: u8,
+    #[serde(rename = "connectTimeout", default)]
+    pub(crate) connect_timeout: Option<u32>,
+    #[serde(rename = "readTimeout", default)]
+    pub(crate) read_timeout: Option<u32>,
+    pub(crate) branches: Vec<Branch>,
 }
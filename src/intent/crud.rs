@@ -4,13 +4,14 @@ use std::vec::Vec;
 use axum::extract::Query;
 use axum::response::IntoResponse;
 use axum::Json;
+use utoipa::TupleUnit;
 
 use super::detector;
 use super::dto::{IntentDetail, IntentFormData, IntentPhraseData};
 use crate::db;
 use crate::db_executor;
 use crate::result::{Error, Result};
-use crate::web::server::to_res;
+use crate::web::server::{to_res, ResponseData};
 
 // pub(crate) const TABLE: redb::TableDefinition<&str, &[u8]> =
 //     redb::TableDefinition::new(INTENT_LIST_KEY);
@@ -141,6 +142,14 @@ pub(crate) fn init(robot_id: &str, is_en: bool) -> Result<()> {
     Ok(())
 }
 
+#[utoipa::path(
+    get,
+    path = "/intent",
+    tag = "intent",
+    summary = "List intents",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<IntentDetail>>))
+)]
 pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
     // let r: Result<Option<Vec<Intent>>> = db::query(TABLE, INTENT_LIST_KEY);
     if let Some(robot_id) = q.get("robotId") {
@@ -153,6 +162,14 @@ pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoR
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/intent",
+    tag = "intent",
+    summary = "Add an intent named `data`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn add(Json(params): Json<IntentFormData>) -> impl IntoResponse {
     let r = add_intent(&params.robot_id, params.data.as_str());
     to_res(r)
@@ -172,6 +189,14 @@ fn add_intent(robot_id: &str, intent_name: &str) -> Result<()> {
     )
 }
 
+#[utoipa::path(
+    delete,
+    path = "/intent",
+    tag = "intent",
+    summary = "Delete the intent `id`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn remove(Json(params): Json<IntentFormData>) -> impl IntoResponse {
     let r = super::phrase::remove_by_intent_id(&params.robot_id, params.id.as_str())
         .await
@@ -201,6 +226,14 @@ pub(in crate::intent) fn get_detail_by_id(
     db_executor!(db::query, robot_id, TABLE_SUFFIX, intent_id)
 }
 
+#[utoipa::path(
+    get,
+    path = "/intent/detail",
+    tag = "intent",
+    summary = "Get intent detail",
+    params(("robotId" = String, Query), ("id" = String, Query)),
+    responses((status = 200, body = ResponseData<IntentDetail>))
+)]
 pub(crate) async fn detail(Query(params): Query<IntentFormData>) -> impl IntoResponse {
     // let mut od: Option<IntentDetail> = None;
     // let r = db::process_data(dbg!(params.id.as_str()), |d: &mut IntentDetail| {
@@ -213,6 +246,14 @@ pub(crate) async fn detail(Query(params): Query<IntentFormData>) -> impl IntoRes
     to_res(r)
 }
 
+#[utoipa::path(
+    post,
+    path = "/intent/keyword",
+    tag = "intent",
+    summary = "Add the keyword `data` to the intent `id`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn add_keyword(Json(params): Json<IntentFormData>) -> impl IntoResponse {
     let key = params.id.as_str();
     // let r: Result<Option<IntentDetail>> = db::query(TABLE, key);
@@ -229,6 +270,14 @@ pub(crate) async fn add_keyword(Json(params): Json<IntentFormData>) -> impl Into
     to_res(r)
 }
 
+#[utoipa::path(
+    delete,
+    path = "/intent/keyword",
+    tag = "intent",
+    summary = "Remove the keyword at index `data` from the intent `id`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn remove_keyword(Json(params): Json<IntentFormData>) -> impl IntoResponse {
     let r = params
         .data
@@ -254,6 +303,14 @@ pub(crate) async fn remove_keyword(Json(params): Json<IntentFormData>) -> impl I
     to_res(r)
 }
 
+#[utoipa::path(
+    post,
+    path = "/intent/regex",
+    tag = "intent",
+    summary = "Add the regular expression `data` to the intent `id`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn add_regex(Json(params): Json<IntentFormData>) -> impl IntoResponse {
     let key = params.id.as_str();
     let r: Result<Option<IntentDetail>> =
@@ -270,6 +327,14 @@ pub(crate) async fn add_regex(Json(params): Json<IntentFormData>) -> impl IntoRe
     to_res(r)
 }
 
+#[utoipa::path(
+    delete,
+    path = "/intent/regex",
+    tag = "intent",
+    summary = "Remove the regular expression at index `data` from the intent `id`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn remove_regex(Json(params): Json<IntentFormData>) -> impl IntoResponse {
     let r = params
         .data
@@ -296,6 +361,14 @@ pub(crate) async fn remove_regex(Json(params): Json<IntentFormData>) -> impl Int
 }
 
 #[axum::debug_handler]
+#[utoipa::path(
+    post,
+    path = "/intent/phrase",
+    tag = "intent",
+    summary = "Add the phrase `data` to the intent `id`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn add_phrase(
     Query(query): Query<IntentFormData>,
     Json(params): Json<IntentFormData>,
@@ -332,6 +405,7 @@ pub(crate) async fn add_phrase(
                     d.phrases.push(IntentPhraseData {
                         id: vec_row_id,
                         phrase: String::from(params.data.as_str()),
+                        locale: params.locale.clone(),
                     });
                     db_executor!(db::write, &params.robot_id, TABLE_SUFFIX, intent_id, &d)
                 })
@@ -383,6 +457,14 @@ pub(crate) async fn add_phrase(
 }
 */
 
+#[utoipa::path(
+    delete,
+    path = "/intent/phrase",
+    tag = "intent",
+    summary = "Remove the phrase at index `data` from the intent `id`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn remove_phrase(Json(params): Json<IntentFormData>) -> impl IntoResponse {
     let r = params.data.parse::<usize>();
     let phrase_idx = match r {
@@ -438,10 +520,26 @@ pub(crate) async fn remove_phrase(Json(params): Json<IntentFormData>) -> impl In
     // to_res(r)
 }
 
+#[utoipa::path(
+    post,
+    path = "/intent/detect",
+    tag = "intent",
+    summary = "Detect the intent of the text `data`",
+    request_body = IntentFormData,
+    responses((status = 200, body = ResponseData<Option<String>>))
+)]
 pub(crate) async fn detect(Json(params): Json<IntentFormData>) -> impl IntoResponse {
-    to_res(detector::detect(&params.robot_id, &params.data).await)
+    to_res(detector::detect(&params.robot_id, &params.data, &params.locale).await)
 }
 
+#[utoipa::path(
+    get,
+    path = "/intent/phrase/regenerate-all",
+    tag = "intent",
+    summary = "Regenerate embeddings of all phrases of an intent",
+    params(("robotId" = String, Query), ("id" = String, Query), ("data" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn regenerate_embeddings(
     Query(params): Query<IntentFormData>,
 ) -> impl IntoResponse {
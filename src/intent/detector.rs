@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use regex::Regex;
 
 use super::dto::IntentDetail;
@@ -6,8 +8,12 @@ use crate::ai::embedding::embedding;
 use crate::db;
 use crate::db_executor;
 use crate::result::{Error, Result};
+use crate::robot::i18n;
+
+// Nearest phrases considered, so that a phrase of the request language can win over a slightly nearer one of another language
+const PHRASE_CANDIDATES: u8 = 5;
 
-pub(crate) async fn detect(robot_id: &str, s: &str) -> Result<Option<String>> {
+pub(crate) async fn detect(robot_id: &str, s: &str, locale: &str) -> Result<Option<String>> {
     // let now = std::time::Instant::now();
     let r: Result<Vec<IntentDetail>> =
         db_executor!(db::get_all, robot_id, super::crud::TABLE_SUFFIX,);
@@ -63,16 +69,28 @@ pub(crate) async fn detect(robot_id: &str, s: &str) -> Result<Option<String>> {
     // let now = std::time::Instant::now();
     let search_vector: Vec<f32> = embedding.0.into();
     let similarity_threshold = embedding.1 as f64;
-    let mut result = phrase::search(robot_id, &search_vector).await?;
+    let result = phrase::search(robot_id, &search_vector, PHRASE_CANDIDATES).await?;
     // log::info!("Searching vector took {:?}", now.elapsed());
-    if !result.is_empty() {
-        if let Some(record) = result.get_mut(0) {
-            // log::info!("Record distance: {}", record.1);
-            if (1f64 - record.1) >= similarity_threshold {
-                let s = std::mem::replace(&mut record.0, String::new());
-                return Ok(Some(s));
-            }
-        }
+    let phrase_locales: HashMap<i64, &str> = intents
+        .iter()
+        .flat_map(|d| d.phrases.iter())
+        .filter(|p| !p.locale.is_empty())
+        .map(|p| (p.id, p.locale.as_str()))
+        .collect();
+    let other_language = |id: &i64| {
+        !locale.is_empty()
+            && phrase_locales
+                .get(id)
+                .is_some_and(|l| !i18n::language_matches(l, locale))
+    };
+    // Results are sorted by distance, so this is the nearest phrase in the request language if there is one
+    if let Some(record) = result
+        .into_iter()
+        .filter(|r| (1f64 - r.2) >= similarity_threshold)
+        .min_by_key(|r| other_language(&r.0))
+    {
+        // log::info!("Record distance: {}", record.2);
+        return Ok(Some(record.1));
     }
     Ok(None)
 }
@@ -1,11 +1,15 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
 pub(crate) struct IntentFormData {
     #[serde(rename = "robotId")]
     pub(crate) robot_id: String,
+    #[serde(default)]
     pub(crate) id: String,
+    #[serde(default)]
     pub(crate) data: String,
+    #[serde(default)]
+    pub(crate) locale: String,
 }
 
 // #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -46,13 +50,15 @@ pub(crate) struct IntentFormData {
 //     }
 // }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, utoipa::ToSchema)]
 pub(crate) struct IntentPhraseData {
     pub(crate) id: i64,
     pub(crate) phrase: String,
+    #[serde(default)]
+    pub(crate) locale: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, utoipa::ToSchema)]
 pub(crate) struct IntentDetail {
     pub(crate) intent_id: String,
     pub(crate) intent_name: String,
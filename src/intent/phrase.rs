@@ -79,18 +79,24 @@ pub(crate) async fn init_tables(robot_id: &str) -> Result<()> {
     Ok(())
 }
 
-pub(crate) async fn search(robot_id: &str, vectors: &Vec<f32>) -> Result<Vec<(String, f64)>> {
+// Returns id of the phrase, name of the intent and distance of the nearest `k` phrases
+pub(crate) async fn search(
+    robot_id: &str,
+    vectors: &Vec<f32>,
+    k: u8,
+) -> Result<Vec<(i64, String, f64)>> {
     let sql = format!(
-        "SELECT intent_id, intent_name, distance FROM {} WHERE phrase_vec MATCH ? ORDER BY distance ASC LIMIT 1",
+        "SELECT id, intent_name, distance FROM {} WHERE phrase_vec MATCH ? ORDER BY distance ASC LIMIT ?",
         robot_id
     );
     let results = sqlx::query::<Sqlite>(&sql)
         .bind(serde_json::to_string(vectors)?)
+        .bind(k as i64)
         .fetch_all(DATA_SOURCE.get().unwrap())
         .await?;
     let mut names = Vec::with_capacity(results.len());
     for r in results.iter() {
-        names.push((r.try_get(1)?, r.try_get(2)?));
+        names.push((r.try_get(0)?, r.try_get(1)?, r.try_get(2)?));
     }
     Ok(names)
 }
@@ -259,6 +265,10 @@ pub(crate) async fn remove_by_intent_id(robot_id: &str, intent_id: &str) -> Resu
 }
 
 pub(crate) async fn remove_tables(robot_id: &str) -> Result<()> {
+    let sql = format!("DROP TABLE IF EXISTS {}_idt", robot_id);
+    sqlx::query::<Sqlite>(&sql)
+        .execute(DATA_SOURCE.get().unwrap())
+        .await?;
     let sql = format!("DROP TABLE {}", robot_id);
     match sqlx::query::<Sqlite>(&sql)
         .execute(DATA_SOURCE.get().unwrap())
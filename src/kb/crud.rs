@@ -6,18 +6,37 @@ use axum::{
     response::IntoResponse,
     Json,
 };
+use utoipa::TupleUnit;
 
 use super::doc;
-use super::dto::QuestionAnswerPair;
+use super::dto::{DocData, QuestionAnswerPair};
 use crate::result::{Error, Result};
 use crate::robot::dto::RobotQuery;
-use crate::web::server::to_res;
+use crate::web::openapi::FileUpload;
+use crate::web::server::{to_res, ResponseData};
 
+#[utoipa::path(
+    get,
+    path = "/kb/doc",
+    tag = "kb",
+    summary = "List documents",
+    params(RobotQuery),
+    responses((status = 200, body = ResponseData<Vec<DocData>>))
+)]
 pub(crate) async fn list_doc(Query(q): Query<RobotQuery>) -> impl IntoResponse {
     let r = super::doc::list(&q.robot_id).await;
     to_res(r)
 }
 
+#[utoipa::path(
+    post,
+    path = "/kb/doc/upload",
+    tag = "kb",
+    summary = "Upload a document",
+    params(RobotQuery),
+    request_body(content = inline(FileUpload), content_type = "multipart/form-data"),
+    responses((status = 200, body = ResponseData<String>))
+)]
 pub(crate) async fn upload_doc(
     Query(q): Query<RobotQuery>,
     multipart: Multipart,
@@ -73,11 +92,28 @@ async fn upload_doc_inner(robot_id: &str, mut multipart: Multipart) -> Result<St
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/kb/qa",
+    tag = "kb",
+    summary = "List question answer pairs",
+    params(RobotQuery),
+    responses((status = 200, body = ResponseData<Vec<QuestionAnswerPair>>))
+)]
 pub(crate) async fn list_qa(Query(q): Query<RobotQuery>) -> impl IntoResponse {
     let r = super::qa::list(&q.robot_id).await;
     to_res(r)
 }
 
+#[utoipa::path(
+    post,
+    path = "/kb/qa",
+    tag = "kb",
+    summary = "Create or update a question answer pair",
+    params(RobotQuery),
+    request_body = QuestionAnswerPair,
+    responses((status = 200, body = ResponseData<String>))
+)]
 pub(crate) async fn save_qa(
     Query(q): Query<RobotQuery>,
     Json(d): Json<QuestionAnswerPair>,
@@ -87,6 +123,15 @@ pub(crate) async fn save_qa(
     to_res(r)
 }
 
+#[utoipa::path(
+    delete,
+    path = "/kb/qa",
+    tag = "kb",
+    summary = "Delete a question answer pair",
+    params(RobotQuery),
+    request_body = QuestionAnswerPair,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn delete_qa(
     Query(q): Query<RobotQuery>,
     Json(d): Json<QuestionAnswerPair>,
@@ -95,6 +140,14 @@ pub(crate) async fn delete_qa(
     to_res(r)
 }
 
+#[utoipa::path(
+    get,
+    path = "/kb/qa/dryrun",
+    tag = "kb",
+    summary = "Retrieve the nearest question answer pair and its distance",
+    params(("robotId" = String, Query), ("text" = String, Query)),
+    responses((status = 200, body = ResponseData<serde_json::Value>))
+)]
 pub(crate) async fn qa_dryrun(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
     let r = q.get("robotId");
     let t = q.get("text");
@@ -14,13 +14,17 @@ use quick_xml::Reader;
 use sqlx::{pool::PoolOptions, Row, Sqlite};
 use zip::ZipArchive;
 
-use super::dto::DocData;
+use super::dto::{DocChunk, DocData};
+use crate::ai::embedding::embedding;
 use crate::result::{Error, Result};
+use crate::robot::dto::RobotData;
 
 type SqliteConnPool = sqlx::Pool<Sqlite>;
 
 // static DATA_SOURCE: OnceCell<SqliteConnPool> = OnceCell::new();
 static DATA_SOURCE: OnceLock<SqliteConnPool> = OnceLock::new();
+// Characters of a document chunk which is embedded for retrieval
+const CHUNK_LEN: usize = 800;
 // static DATA_SOURCES: OnceLock<Mutex<HashMap<String, SqliteConnPool>>> = OnceLock::new();
 
 fn get_sqlite_path() -> std::path::PathBuf {
@@ -108,7 +112,21 @@ pub(crate) async fn init_tables(robot_id: &str) -> Result<()> {
 //     }
 // }
 
-pub(super) async fn list(robot_id: &str) -> Result<Vec<DocData>> {
+pub(crate) async fn remove_tables(robot_id: &str) -> Result<()> {
+    let sql = format!(
+        "DROP TABLE IF EXISTS {}_doc_vec;
+        DROP TABLE IF EXISTS {}_doc_chunk;
+        DROP TABLE IF EXISTS {}_doc;
+        DROP TABLE IF EXISTS {}_vec_row_id;",
+        robot_id, robot_id, robot_id, robot_id
+    );
+    sqlx::raw_sql(&sql)
+        .execute(DATA_SOURCE.get().unwrap())
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn list(robot_id: &str) -> Result<Vec<DocData>> {
     let sql = format!("SELECT * FROM {}_doc ORDER BY created_at DESC", robot_id);
     let results = sqlx::query_as::<Sqlite, DocData>(&sql)
         .fetch_all(DATA_SOURCE.get().unwrap())
@@ -116,48 +134,208 @@ pub(super) async fn list(robot_id: &str) -> Result<Vec<DocData>> {
     Ok(results)
 }
 
-pub(super) async fn save(
+fn create_chunk_table_sql(robot_id: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {}_doc_chunk (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            doc_id INTEGER NOT NULL,
+            content TEXT NOT NULL
+        )",
+        robot_id
+    )
+}
+
+// Documents are kept when embedding fails, e.g. no embedding provider is set up yet,
+// they are indexed later by `index_missing`
+pub(crate) async fn save(
     robot_id: &str,
     file_name: &str,
     file_size: usize,
     doc_content: &str,
 ) -> Result<()> {
+    let sql = format!(
+        "INSERT INTO {}_doc(file_name, file_size, doc_content, created_at)VALUES(?, ?, ?, unixepoch())",
+        robot_id
+    );
+    let doc_id = sqlx::query::<Sqlite>(&sql)
+        .bind(file_name)
+        .bind(file_size as i64)
+        .bind(doc_content)
+        .execute(DATA_SOURCE.get().unwrap())
+        .await?
+        .last_insert_rowid();
+    if let Err(e) = index(robot_id, doc_id, doc_content).await {
+        log::warn!("Indexing document {} failed, err: {:?}", file_name, &e);
+    }
+    Ok(())
+}
+
+// Chunks are embedded before the transaction, so slow embedding does not hold the write lock
+async fn index(robot_id: &str, doc_id: i64, doc_content: &str) -> Result<()> {
     async fn inner(
         robot_id: &str,
-        file_name: &str,
-        file_size: usize,
-        doc_content: &str,
+        doc_id: i64,
+        chunks: &[(String, Vec<f32>)],
         transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
     ) -> Result<()> {
+        sqlx::query::<Sqlite>(&create_chunk_table_sql(robot_id))
+            .execute(&mut **transaction)
+            .await?;
+        // Uploads and `index_missing` can index the same document at once
         let sql = format!(
-            "INSERT INTO {}_doc(file_name, file_size, doc_content, created_at)VALUES(?, ?, ?, unixepoch())",
+            "SELECT COUNT(*) FROM {}_doc_chunk WHERE doc_id = ?",
             robot_id
         );
-        sqlx::query::<Sqlite>(&sql)
-            .bind(file_name)
-            .bind(file_size as i64)
-            .bind(doc_content)
-            .execute(&mut **transaction)
+        let indexed: i64 = sqlx::query_scalar::<Sqlite, i64>(&sql)
+            .bind(doc_id)
+            .fetch_one(&mut **transaction)
             .await?;
+        if indexed > 0 {
+            return Ok(());
+        }
+        for (chunk, vectors) in chunks.iter() {
+            let sql = format!(
+                "INSERT INTO {}_doc_chunk(doc_id, content)VALUES(?, ?)",
+                robot_id
+            );
+            let chunk_id = sqlx::query::<Sqlite>(&sql)
+                .bind(doc_id)
+                .bind(chunk)
+                .execute(&mut **transaction)
+                .await?
+                .last_insert_rowid();
+            let sql = format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS {}_doc_vec USING vec0 (
+                vectors float[{}]
+            );
+            INSERT INTO {}_doc_vec (rowid, vectors)VALUES(?, ?)",
+                robot_id,
+                vectors.len(),
+                robot_id
+            );
+            sqlx::query::<Sqlite>(&sql)
+                .bind(chunk_id)
+                .bind(serde_json::to_string(vectors)?)
+                .execute(&mut **transaction)
+                .await?;
+        }
         Ok(())
     }
+    let mut chunks = Vec::new();
+    for chunk in split_chunks(doc_content).into_iter() {
+        let vectors = embedding(robot_id, &chunk).await?;
+        if vectors.0.is_empty() {
+            return Err(Error::ErrorWithMessage(String::from(
+                "Embedding data is empty",
+            )));
+        }
+        chunks.push((chunk, vectors.0));
+    }
+    if chunks.is_empty() {
+        return Ok(());
+    }
     let mut transaction = DATA_SOURCE.get().unwrap().begin().await?;
-    let r = inner(
-        robot_id,
-        file_name,
-        file_size,
-        doc_content,
-        &mut transaction,
-    )
-    .await;
+    let r = inner(robot_id, doc_id, &chunks, &mut transaction).await;
     if r.is_ok() {
         transaction.commit().await?;
     } else {
         transaction.rollback().await?;
     }
+    r
+}
+
+// Documents uploaded before they were split into chunks, or while embedding failed
+pub(crate) async fn index_missing(robot_id: &str) -> Result<()> {
+    sqlx::query::<Sqlite>(&create_chunk_table_sql(robot_id))
+        .execute(DATA_SOURCE.get().unwrap())
+        .await?;
+    let sql = format!(
+        "SELECT id, file_name, doc_content FROM {}_doc WHERE id NOT IN (SELECT doc_id FROM {}_doc_chunk)",
+        robot_id, robot_id
+    );
+    let docs = sqlx::query_as::<Sqlite, (i64, String, String)>(&sql)
+        .fetch_all(DATA_SOURCE.get().unwrap())
+        .await?;
+    for (doc_id, file_name, doc_content) in docs.iter() {
+        log::info!("Indexing document {} of robot {}", file_name, robot_id);
+        // Embedding fails the same way for the rest of documents
+        index(robot_id, *doc_id, doc_content).await?;
+    }
     Ok(())
 }
 
+pub(crate) async fn index_all_missing() {
+    let robots: Vec<RobotData> = match crate::db::get_all(crate::robot::crud::TABLE) {
+        Ok(robots) => robots,
+        Err(e) => {
+            log::error!("Listing robots failed, err: {:?}", &e);
+            return;
+        }
+    };
+    for robot in robots.iter() {
+        if let Err(e) = index_missing(&robot.robot_id).await {
+            log::warn!(
+                "Indexing documents of robot {} failed, err: {:?}",
+                &robot.robot_id,
+                &e
+            );
+        }
+    }
+}
+
+// Paragraphs are merged into chunks of up to `CHUNK_LEN` characters, longer ones are cut
+fn split_chunks(text: &str) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::with_capacity(16);
+    let mut chunk = String::with_capacity(CHUNK_LEN * 4);
+    let mut chunk_len = 0usize;
+    for p in text.lines().map(str::trim).filter(|p| !p.is_empty()) {
+        let mut chars: Vec<char> = p.chars().collect();
+        while !chars.is_empty() {
+            if chunk_len > 0 && chunk_len + 1 + chars.len() > CHUNK_LEN {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_len = 0;
+            }
+            let len = chars.len().min(CHUNK_LEN - chunk_len);
+            if chunk_len > 0 {
+                chunk.push('\n');
+                chunk_len += 1;
+            }
+            chunk.extend(chars.drain(..len));
+            chunk_len += len;
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// Nearest `k` chunks of uploaded documents
+pub(crate) async fn search(robot_id: &str, vectors: &[f32], k: u8) -> Result<Vec<DocChunk>> {
+    let sql = "SELECT COUNT(*) FROM sqlite_master WHERE name = ?";
+    let tables: i64 = sqlx::query_scalar::<Sqlite, i64>(sql)
+        .bind(format!("{}_doc_vec", robot_id))
+        .fetch_one(DATA_SOURCE.get().unwrap())
+        .await?;
+    if tables == 0 {
+        return Ok(Vec::new());
+    }
+    let sql = format!(
+        "
+        SELECT c.doc_id, d.file_name, c.content, v.distance FROM {}_doc_chunk c INNER JOIN
+        (SELECT rowid, distance FROM {}_doc_vec WHERE vectors MATCH ? ORDER BY distance ASC LIMIT ?) v
+        ON c.id = v.rowid INNER JOIN {}_doc d ON d.id = c.doc_id ORDER BY v.distance ASC
+        ",
+        robot_id, robot_id, robot_id
+    );
+    let results = sqlx::query_as::<Sqlite, DocChunk>(&sql)
+        .bind(serde_json::to_string(vectors)?)
+        .bind(k as i64)
+        .fetch_all(DATA_SOURCE.get().unwrap())
+        .await?;
+    Ok(results)
+}
+
 pub(super) fn parse_docx(b: Vec<u8>) -> Result<String> {
     // let mut file = File::open("./numbering.docx")?;
     // let mut buf = Vec::with_capacity(3096);
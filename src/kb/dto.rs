@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::vec::Vec;
 
 use serde::{Deserialize, Serialize};
@@ -9,22 +10,38 @@ use serde::{Deserialize, Serialize};
 //     pub(super) qa_data: QuestionAnswerPair,
 // }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct QuestionAnswerPair {
-    pub(super) id: Option<String>,
+    pub(crate) id: Option<String>,
     pub(super) question: QuestionData,
     #[serde(rename = "similarQuestions")]
     pub(super) similar_questions: Vec<QuestionData>,
     pub(crate) answer: String,
+    #[serde(rename = "answerI18n", default)]
+    pub(crate) answer_i18n: HashMap<String, String>,
 }
 
-#[derive(Deserialize, Serialize)]
+impl QuestionAnswerPair {
+    pub(crate) fn question_text(&self) -> &str {
+        &self.question.question
+    }
+
+    pub(crate) fn reset_ids(&mut self) {
+        self.id = None;
+        self.question.vec_row_id = None;
+        for q in self.similar_questions.iter_mut() {
+            q.vec_row_id = None;
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct QuestionData {
     pub(super) question: String,
     pub(super) vec_row_id: Option<i64>,
 }
 
-#[derive(Serialize, sqlx::FromRow)]
+#[derive(Deserialize, Serialize, sqlx::FromRow, utoipa::ToSchema)]
 pub(crate) struct DocData {
     pub(crate) id: i64,
     #[serde(rename = "fileName")]
@@ -34,3 +51,11 @@ pub(crate) struct DocData {
     #[serde(rename = "docContent")]
     pub(crate) doc_content: String,
 }
+
+#[derive(sqlx::FromRow)]
+pub(crate) struct DocChunk {
+    pub(crate) doc_id: i64,
+    pub(crate) file_name: String,
+    pub(crate) content: String,
+    pub(crate) distance: f64,
+}
@@ -87,6 +87,19 @@ pub(crate) async fn init_tables(robot_id: &str) -> Result<()> {
 //     }
 // );
 
+pub(crate) async fn remove_tables(robot_id: &str) -> Result<()> {
+    let sql = format!(
+        "DROP TABLE IF EXISTS {};
+        DROP TABLE IF EXISTS {}_qa;
+        DROP TABLE IF EXISTS {}_question_vec_row_id;",
+        robot_id, robot_id, robot_id
+    );
+    sqlx::raw_sql(&sql)
+        .execute(DATA_SOURCE.get().unwrap())
+        .await?;
+    Ok(())
+}
+
 pub(crate) async fn list(robot_id: &str) -> Result<Vec<QuestionAnswerPair>> {
     let sql = format!(
         "SELECT qa_data FROM {}_qa ORDER BY created_at DESC",
@@ -259,24 +272,37 @@ pub(crate) async fn retrieve_answer(
         log::warn!("{}", &err);
         return Err(Error::ErrorWithMessage(err));
     }
+    let mut results = search(robot_id, &vectors.0, 1).await?;
+    if results.is_empty() {
+        return Ok((None, 1.0));
+    }
+    let (d, distance) = results.remove(0);
+    Ok((Some(d), distance))
+}
 
+// Nearest `k` pairs, a pair is matched by its question or any of its similar questions
+pub(crate) async fn search(
+    robot_id: &str,
+    vectors: &[f32],
+    k: u8,
+) -> Result<Vec<(QuestionAnswerPair, f64)>> {
     let sql = format!(
         "
-        SELECT qa_data, v.distance FROM {}_qa q INNER JOIN
-        (SELECT qa_id, distance FROM {} WHERE vectors MATCH ? ORDER BY distance ASC LIMIT 1) v
-        ON q.id = v.qa_id
+        SELECT qa_data, MIN(v.distance) AS d FROM {}_qa q INNER JOIN
+        (SELECT qa_id, distance FROM {} WHERE vectors MATCH ? ORDER BY distance ASC LIMIT ?) v
+        ON q.id = v.qa_id GROUP BY q.id ORDER BY d ASC LIMIT ?
         ",
         robot_id, robot_id
     );
     let results = sqlx::query::<Sqlite>(&sql)
-        .bind(serde_json::to_string(&vectors.0)?)
+        .bind(serde_json::to_string(vectors)?)
+        .bind(k as i64 * 4)
+        .bind(k as i64)
         .fetch_all(DATA_SOURCE.get().unwrap())
         .await?;
-    if results.len() > 0 {
-        return Ok((
-            Some(serde_json::from_str(results[0].try_get(0)?)?),
-            results[0].try_get(1)?,
-        ));
+    let mut d = Vec::with_capacity(results.len());
+    for r in results.iter() {
+        d.push((serde_json::from_str(r.try_get(0)?)?, r.try_get(1)?));
     }
-    Ok((None, 1.0))
+    Ok(d)
 }
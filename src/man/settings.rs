@@ -9,13 +9,17 @@ use axum::response::IntoResponse;
 use axum::Json;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use utoipa::TupleUnit;
 
-use crate::ai::huggingface::HuggingFaceModel;
+use crate::ai::huggingface::{DownloadStatus, HuggingFaceModel};
 use crate::ai::{asr, chat, completion, embedding, huggingface, tts};
 use crate::db;
+use crate::external::email::dto::EmailChannel;
+use crate::external::notification::dto::NotificationProvider;
 use crate::result::{Error, Result};
 use crate::robot::dto::RobotQuery;
-use crate::web::server::{self, to_res};
+use crate::web::server::{self, to_res, ResponseData};
+use crate::web::widget::ChatWidget;
 
 pub(crate) const TABLE: redb::TableDefinition<&str, &[u8]> = redb::TableDefinition::new("settings");
 pub(crate) const SETTINGS_KEY: &str = "global-settings";
@@ -23,7 +27,7 @@ pub(crate) const SETTINGS_KEY: &str = "global-settings";
 static SETTINGS_CACHE: LazyLock<Mutex<HashMap<String, Settings>>> =
     LazyLock::new(|| Mutex::new(HashMap::with_capacity(32)));
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct HfModelDownload {
     #[serde(rename = "connectTimeoutMillis")]
     pub(crate) connect_timeout_millis: u32,
@@ -33,7 +37,7 @@ pub(crate) struct HfModelDownload {
     pub(crate) access_token: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct GlobalSettings {
     pub(crate) ip: String,
     pub(crate) port: u16,
@@ -43,7 +47,7 @@ pub(crate) struct GlobalSettings {
     pub(crate) hf_model_download: HfModelDownload,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct Settings {
     settings_version: u8,
     #[serde(rename = "maxSessionIdleSec")]
@@ -68,6 +72,12 @@ pub(crate) struct Settings {
     pub(crate) smtp_timeout_sec: u16,
     #[serde(rename = "emailVerificationRegex")]
     pub(crate) email_verification_regex: String,
+    #[serde(rename = "notificationProviders", default)]
+    pub(crate) notification_providers: Vec<NotificationProvider>,
+    #[serde(rename = "emailChannel", default)]
+    pub(crate) email_channel: EmailChannel,
+    #[serde(rename = "chatWidget", default)]
+    pub(crate) chat_widget: ChatWidget,
 }
 
 // #[test]
@@ -103,7 +113,7 @@ pub(crate) struct Settings {
 //     assert_eq!(v["embeddingProvider"]["provider"], "HuggingFace");
 // }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct ChatProvider {
     pub(crate) provider: chat::ChatProvider,
     #[serde(rename = "apiUrl")]
@@ -117,11 +127,21 @@ pub(crate) struct ChatProvider {
     pub(crate) read_timeout_millis: u32,
     #[serde(rename = "maxResponseTokenLength")]
     pub(crate) max_response_token_length: u32,
+    // Context window of the model, chat history is cut or summarized to fit in it
+    #[serde(
+        rename = "maxContextTokenLength",
+        default = "default_max_context_token_length"
+    )]
+    pub(crate) max_context_token_length: u32,
     #[serde(rename = "proxyUrl")]
     pub(crate) proxy_url: String,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+fn default_max_context_token_length() -> u32 {
+    4096
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct TextGenerationProvider {
     pub(crate) provider: completion::TextGenerationProvider,
     #[serde(rename = "apiUrl")]
@@ -139,7 +159,7 @@ pub(crate) struct TextGenerationProvider {
     pub(crate) proxy_url: String,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct SentenceEmbeddingProvider {
     pub(crate) provider: embedding::SentenceEmbeddingProvider,
     #[serde(rename = "similarityThreshold")]
@@ -157,7 +177,7 @@ pub(crate) struct SentenceEmbeddingProvider {
     pub(crate) proxy_url: String,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct AsrProvider {
     pub(crate) provider: asr::AsrProvider,
     #[serde(rename = "apiUrl")]
@@ -173,7 +193,7 @@ pub(crate) struct AsrProvider {
     pub(crate) proxy_url: String,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct TtsProvider {
     pub(crate) provider: tts::TtsProvider,
     #[serde(rename = "apiUrl")]
@@ -219,6 +239,7 @@ impl Default for Settings {
                 connect_timeout_millis: 5000,
                 read_timeout_millis: 10000,
                 max_response_token_length: 1000,
+                max_context_token_length: default_max_context_token_length(),
                 proxy_url: String::new(),
             },
             text_generation_provider: TextGenerationProvider {
@@ -272,6 +293,9 @@ impl Default for Settings {
             smtp_password: String::new(),
             smtp_timeout_sec: 60u16,
             email_verification_regex: String::new(),
+            notification_providers: Vec::new(),
+            email_channel: EmailChannel::default(),
+            chat_widget: ChatWidget::default(),
         }
     }
 }
@@ -307,6 +331,13 @@ pub(crate) fn get_global_settings() -> Result<Option<GlobalSettings>> {
     db::query(TABLE, SETTINGS_KEY)
 }
 
+#[utoipa::path(
+    get,
+    path = "/management/global-settings",
+    tag = "settings",
+    summary = "Get global settings",
+    responses((status = 200, body = ResponseData<GlobalSettings>))
+)]
 pub(crate) async fn rest_get_global_settings() -> impl IntoResponse {
     to_res(get_global_settings())
 }
@@ -320,15 +351,42 @@ pub(crate) fn get_settings(robot_id: &str) -> Result<Option<Settings>> {
     db::query(TABLE, robot_id)
 }
 
+#[utoipa::path(
+    get,
+    path = "/management/settings",
+    tag = "settings",
+    summary = "Get robot settings",
+    params(RobotQuery),
+    responses((status = 200, body = ResponseData<Settings>))
+)]
 pub(crate) async fn get(Query(q): Query<RobotQuery>) -> impl IntoResponse {
     to_res::<Option<Settings>>(get_settings(&q.robot_id))
 }
 
+#[utoipa::path(
+    post,
+    path = "/management/settings",
+    tag = "settings",
+    summary = "Save robot settings",
+    params(RobotQuery),
+    request_body = Settings,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn save(
     Query(q): Query<RobotQuery>,
     Json(data): Json<Settings>,
 ) -> impl IntoResponse {
-    to_res(save_settings(&q.robot_id, data))
+    let r = save_settings(&q.robot_id, data);
+    if r.is_ok() {
+        // Documents uploaded without an embedding provider can be indexed now
+        let robot_id = q.robot_id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::kb::doc::index_missing(&robot_id).await {
+                log::warn!("Indexing documents failed, err: {:?}", &e);
+            }
+        });
+    }
+    to_res(r)
 }
 
 pub(crate) fn save_global_settings(data: &GlobalSettings) -> Result<()> {
@@ -340,6 +398,14 @@ pub(crate) fn save_global_settings(data: &GlobalSettings) -> Result<()> {
     db::write(TABLE, SETTINGS_KEY, &data)
 }
 
+#[utoipa::path(
+    post,
+    path = "/management/global-settings",
+    tag = "settings",
+    summary = "Save global settings",
+    request_body = GlobalSettings,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn rest_save_global_settings(
     Json(data): Json<GlobalSettings>,
 ) -> impl IntoResponse {
@@ -347,6 +413,15 @@ pub(crate) async fn rest_save_global_settings(
 }
 
 pub(crate) fn save_settings(robot_id: &str, data: Settings) -> Result<()> {
+    crate::external::notification::sender::check_providers(&data.notification_providers)?;
+    if data.email_channel.enabled
+        && (data.email_channel.imap_host.is_empty() || data.email_channel.main_flow_id.is_empty())
+    {
+        return Err(Error::ErrorWithMessage(String::from(
+            "IMAP host and main flow of the email channel are required",
+        )));
+    }
+    crate::web::widget::check(&data.chat_widget)?;
     if let completion::TextGenerationProvider::HuggingFace(m) =
         &data.text_generation_provider.provider
     {
@@ -388,6 +463,14 @@ pub(crate) fn save_settings(robot_id: &str, data: Settings) -> Result<()> {
     Ok(())
 }
 
+#[utoipa::path(
+    post,
+    path = "/management/settings/smtp/test",
+    tag = "settings",
+    summary = "Test SMTP settings",
+    request_body = Settings,
+    responses((status = 200, body = ResponseData<bool>))
+)]
 pub(crate) async fn smtp_test(Json(settings): Json<Settings>) -> impl IntoResponse {
     to_res(check_smtp_settings(&settings))
 }
@@ -395,13 +478,20 @@ pub(crate) async fn smtp_test(Json(settings): Json<Settings>) -> impl IntoRespon
 pub(crate) fn check_smtp_settings(settings: &Settings) -> Result<bool> {
     use lettre::transport::smtp::authentication::Credentials;
     use lettre::SmtpTransport;
-    let creds = Credentials::new(
-        settings.smtp_username.to_owned(),
-        settings.smtp_password.to_owned(),
-    );
+    // Same host formats as the email sending transport
+    let mut builder = if settings.smtp_host.contains("://") {
+        SmtpTransport::from_url(&settings.smtp_host)?
+    } else {
+        SmtpTransport::relay(&settings.smtp_host)?
+    };
+    if !settings.smtp_username.is_empty() {
+        builder = builder.credentials(Credentials::new(
+            settings.smtp_username.to_owned(),
+            settings.smtp_password.to_owned(),
+        ));
+    }
 
-    let mailer = SmtpTransport::relay(&settings.smtp_host)?
-        .credentials(creds)
+    let mailer = builder
         .timeout(Some(core::time::Duration::from_secs(
             settings.smtp_timeout_sec as u64,
         )))
@@ -410,6 +500,14 @@ pub(crate) fn check_smtp_settings(settings: &Settings) -> Result<bool> {
     Ok(mailer.test_connection()?)
 }
 
+#[utoipa::path(
+    post,
+    path = "/management/settings/model/download",
+    tag = "settings",
+    summary = "Download files of a Hugging Face model",
+    request_body = HuggingFaceModel,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn download_model_files(Json(m): Json<HuggingFaceModel>) -> impl IntoResponse {
     let global_settings = get_global_settings();
     if global_settings.is_err() {
@@ -445,11 +543,26 @@ pub(crate) async fn download_model_files(Json(m): Json<HuggingFaceModel>) -> imp
     to_res(Ok(()))
 }
 
+#[utoipa::path(
+    get,
+    path = "/management/settings/model/download/progress",
+    tag = "settings",
+    summary = "Get model download progress",
+    responses((status = 200, body = ResponseData<DownloadStatus>))
+)]
 pub(crate) async fn download_model_progress() -> impl IntoResponse {
     let r = huggingface::get_download_status();
     to_res(Ok(r))
 }
 
+#[utoipa::path(
+    post,
+    path = "/management/settings/model/check/files",
+    tag = "settings",
+    summary = "Check whether files of models are downloaded",
+    request_body = Vec<HuggingFaceModel>,
+    responses((status = 200, body = ResponseData<HashMap<String, bool>>))
+)]
 pub(crate) async fn check_model_files(bytes: Bytes) -> impl IntoResponse {
     match serde_json::from_slice::<Vec<HuggingFaceModel>>(bytes.as_ref()) {
         Ok(repositories) => {
@@ -478,6 +591,14 @@ pub(crate) async fn check_model_files(bytes: Bytes) -> impl IntoResponse {
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/management/settings/model/check/embedding",
+    tag = "settings",
+    summary = "Check the embedding model of a robot",
+    params(RobotQuery),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn check_embedding_model(Query(q): Query<RobotQuery>) -> impl IntoResponse {
     let r = if let Ok(r) = get_settings(&q.robot_id) {
         if let Some(settings) = r {
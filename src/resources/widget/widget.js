@@ -0,0 +1,86 @@
+// Adds the chat widget of a robot to a page:
+// <script src="https://your.server/widget.js" data-robot-id="ROBOT_ID" async></script>
+(function () {
+  'use strict';
+  var script = document.currentScript;
+  if (!script || window.__dialogflowChatWidget) {
+    return;
+  }
+  var robotId = script.getAttribute('data-robot-id');
+  if (!robotId) {
+    console.warn('Chat widget: data-robot-id is missing');
+    return;
+  }
+  window.__dialogflowChatWidget = true;
+  var base = new URL(script.src, location.href).origin;
+  var query = '?robotId=' + encodeURIComponent(robotId);
+
+  var ICON_CHAT =
+    '<svg viewBox="0 0 24 24" width="28" height="28" fill="currentColor" aria-hidden="true"><path d="M4 4h16a2 2 0 0 1 2 2v10a2 2 0 0 1-2 2H8l-4 4V6a2 2 0 0 1 2-2z"/></svg>';
+  var ICON_CLOSE =
+    '<svg viewBox="0 0 24 24" width="24" height="24" fill="currentColor" aria-hidden="true"><path d="M18.3 5.7 12 12l6.3 6.3-1.4 1.4L10.6 13.4 4.3 19.7 2.9 18.3 9.2 12 2.9 5.7 4.3 4.3l6.3 6.3 6.3-6.3z"/></svg>';
+
+  function mount(config) {
+    var side = config.position === 'Left' ? 'left' : 'right';
+    var host = document.createElement('div');
+    // Styles of the site and the widget never affect each other
+    var root = host.attachShadow ? host.attachShadow({ mode: 'open' }) : host;
+    var style = document.createElement('style');
+    style.textContent =
+      '.launcher{position:fixed;bottom:20px;' + side + ':20px;width:56px;height:56px;border:none;border-radius:50%;' +
+      'background:' + config.primaryColor + ';color:#fff;cursor:pointer;box-shadow:0 4px 14px rgba(0,0,0,.25);' +
+      'display:flex;align-items:center;justify-content:center;z-index:2147483000;padding:0}' +
+      '.frame{position:fixed;bottom:88px;' + side + ':20px;width:370px;height:560px;max-width:calc(100vw - 24px);' +
+      'max-height:calc(100vh - 108px);border:none;border-radius:12px;box-shadow:0 8px 30px rgba(0,0,0,.25);' +
+      'background:#fff;z-index:2147483000;display:none}' +
+      '.frame.open{display:block}' +
+      '@media (max-width:480px){.frame{' + side + ':12px;bottom:84px}}';
+    var frame = document.createElement('iframe');
+    frame.className = 'frame';
+    frame.title = config.title;
+    frame.setAttribute('allow', 'geolocation');
+    var launcher = document.createElement('button');
+    launcher.className = 'launcher';
+    launcher.type = 'button';
+    launcher.setAttribute('aria-label', config.title);
+    launcher.innerHTML = ICON_CHAT;
+    root.appendChild(style);
+    root.appendChild(frame);
+    root.appendChild(launcher);
+    document.body.appendChild(host);
+
+    var opened = false;
+    function toggle(open) {
+      opened = open;
+      // The conversation starts when the widget is opened for the first time
+      if (open && !frame.src) {
+        frame.src = base + '/widget/chat.html' + query;
+      }
+      frame.classList.toggle('open', open);
+      launcher.innerHTML = open ? ICON_CLOSE : ICON_CHAT;
+    }
+    launcher.addEventListener('click', function () {
+      toggle(!opened);
+    });
+    window.addEventListener('message', function (e) {
+      if (e.origin === base && e.data && e.data.type === 'dialogflow-widget-close') {
+        toggle(false);
+      }
+    });
+  }
+
+  fetch(base + '/widget/config' + query)
+    .then(function (r) {
+      return r.json();
+    })
+    .then(function (r) {
+      if (r.status === 200 && r.data) {
+        mount(r.data);
+      } else {
+        console.warn('Chat widget is unavailable:', r.err && r.err.message);
+      }
+    })
+    .catch(function (e) {
+      console.warn('Chat widget is unavailable:', e);
+    });
+})();
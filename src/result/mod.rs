@@ -197,7 +197,7 @@ impl From<axum::extract::multipart::MultipartError> for Error {
 
 impl From<zip::result::ZipError> for Error {
     fn from(err: zip::result::ZipError) -> Self {
-        Error::ErrorWithMessage(format!("Read docx file failed: {:?}", err))
+        Error::ErrorWithMessage(format!("Read or write zip archive failed: {:?}", err))
     }
 }
 
@@ -0,0 +1,631 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Write};
+use std::vec::Vec;
+
+use axum::body::Bytes;
+use axum::extract::Query;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use super::dto::{RobotData, RobotQuery};
+use crate::db;
+use crate::db_executor;
+use crate::external::http::dto::HttpReqInfo;
+use crate::flow::mainflow::dto::MainFlowDetail;
+use crate::flow::subflow::dto::SubFlowDetail;
+use crate::intent::dto::IntentDetail;
+use crate::kb::dto::{DocData, QuestionAnswerPair};
+use crate::man::settings::{self, Settings};
+use crate::result::{Error, Result};
+use crate::variable::dto::Variable;
+use crate::web::openapi::Binary;
+use crate::web::server::{self, to_res, ResponseData};
+
+pub(crate) const BUNDLE_VERSION: u32 = 1;
+
+const MANIFEST: &str = "manifest.json";
+const ROBOT: &str = "robot.json";
+const SETTINGS: &str = "settings.json";
+const MAIN_FLOWS: &str = "mainflows.json";
+const INTENTS: &str = "intents.json";
+const VARIABLES: &str = "variables.json";
+const EXTERNAL_HTTP_APIS: &str = "externalHttpApis.json";
+const KB_QA: &str = "kb/qa.json";
+const KB_DOCS: &str = "kb/docs.json";
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Manifest {
+    #[serde(rename = "bundleVersion")]
+    pub(crate) bundle_version: u32,
+    #[serde(rename = "appVersion")]
+    pub(crate) app_version: String,
+    #[serde(rename = "robotId")]
+    pub(crate) robot_id: String,
+    #[serde(rename = "exportedAt")]
+    pub(crate) exported_at: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct MainFlowEntry {
+    #[serde(rename = "mainFlow")]
+    pub(crate) main_flow: MainFlowDetail,
+    #[serde(rename = "subFlows")]
+    pub(crate) sub_flows: Vec<SubFlowDetail>,
+}
+
+pub(crate) struct Bundle {
+    pub(crate) manifest: Manifest,
+    pub(crate) robot: RobotData,
+    pub(crate) settings: Option<Settings>,
+    pub(crate) main_flows: Vec<MainFlowEntry>,
+    pub(crate) intents: Vec<IntentDetail>,
+    pub(crate) variables: Vec<Variable>,
+    pub(crate) http_apis: Vec<HttpReqInfo>,
+    pub(crate) qa: Vec<QuestionAnswerPair>,
+    pub(crate) docs: Vec<DocData>,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ImportQuery {
+    #[serde(rename = "robotId", default)]
+    pub(crate) robot_id: String,
+    #[serde(rename = "robotName", default)]
+    pub(crate) robot_name: String,
+    #[serde(rename = "dryRun", default)]
+    pub(crate) dry_run: bool,
+    #[serde(default)]
+    pub(crate) overwrite: bool,
+}
+
+#[derive(Default, Serialize, utoipa::ToSchema)]
+pub(crate) struct ImportReport {
+    #[serde(rename = "dryRun")]
+    pub(crate) dry_run: bool,
+    #[serde(rename = "bundleVersion")]
+    pub(crate) bundle_version: u32,
+    #[serde(rename = "sourceRobotId")]
+    pub(crate) source_robot_id: String,
+    #[serde(rename = "targetRobotId")]
+    pub(crate) target_robot_id: String,
+    #[serde(rename = "robotExists")]
+    pub(crate) robot_exists: bool,
+    #[serde(rename = "mainFlows")]
+    pub(crate) main_flows: usize,
+    #[serde(rename = "subFlows")]
+    pub(crate) sub_flows: usize,
+    pub(crate) intents: usize,
+    pub(crate) phrases: usize,
+    pub(crate) variables: usize,
+    #[serde(rename = "externalHttpApis")]
+    pub(crate) http_apis: usize,
+    #[serde(rename = "qaPairs")]
+    pub(crate) qa_pairs: usize,
+    pub(crate) docs: usize,
+    // Old main flow id -> new main flow id
+    #[serde(rename = "remappedIds")]
+    pub(crate) remapped_ids: HashMap<String, String>,
+    pub(crate) warnings: Vec<String>,
+    pub(crate) errors: Vec<String>,
+}
+
+pub(crate) async fn collect(robot_id: &str) -> Result<Bundle> {
+    let robot: Option<RobotData> = db::query(super::crud::TABLE, robot_id)?;
+    let Some(robot) = robot else {
+        return Err(Error::ErrorWithMessage(format!(
+            "Robot {} was not found.",
+            robot_id
+        )));
+    };
+    let main_flows: Vec<MainFlowDetail> = db_executor!(
+        db::get_all,
+        robot_id,
+        crate::flow::mainflow::crud::TABLE_SUFFIX,
+    )?;
+    let mut entries = Vec::with_capacity(main_flows.len());
+    for main_flow in main_flows.into_iter() {
+        let sub_flows: Option<Vec<SubFlowDetail>> = db_executor!(
+            db::query,
+            robot_id,
+            crate::flow::subflow::crud::TABLE_SUFFIX,
+            main_flow.id.as_str()
+        )?;
+        entries.push(MainFlowEntry {
+            main_flow,
+            sub_flows: sub_flows.unwrap_or_default(),
+        });
+    }
+    Ok(Bundle {
+        manifest: Manifest {
+            bundle_version: BUNDLE_VERSION,
+            app_version: String::from(server::VERSION),
+            robot_id: String::from(robot_id),
+            exported_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        },
+        robot,
+        settings: settings::get_settings(robot_id)?,
+        main_flows: entries,
+        intents: db_executor!(db::get_all, robot_id, crate::intent::crud::TABLE_SUFFIX,)?,
+        variables: db_executor!(db::get_all, robot_id, crate::variable::crud::TABLE_SUFFIX,)?,
+        http_apis: db_executor!(
+            db::get_all,
+            robot_id,
+            crate::external::http::crud::TABLE_SUFFIX,
+        )?,
+        qa: crate::kb::qa::list(robot_id).await?,
+        docs: crate::kb::doc::list(robot_id).await?,
+    })
+}
+
+fn write_entry<D: Serialize>(
+    zip: &mut ZipWriter<Cursor<Vec<u8>>>,
+    name: &str,
+    d: &D,
+) -> Result<()> {
+    zip.start_file(name, SimpleFileOptions::default())?;
+    zip.write_all(serde_json::to_string_pretty(d)?.as_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn pack(b: &Bundle) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::with_capacity(65536)));
+    write_entry(&mut zip, MANIFEST, &b.manifest)?;
+    write_entry(&mut zip, ROBOT, &b.robot)?;
+    write_entry(&mut zip, SETTINGS, &b.settings)?;
+    write_entry(&mut zip, MAIN_FLOWS, &b.main_flows)?;
+    write_entry(&mut zip, INTENTS, &b.intents)?;
+    write_entry(&mut zip, VARIABLES, &b.variables)?;
+    write_entry(&mut zip, EXTERNAL_HTTP_APIS, &b.http_apis)?;
+    write_entry(&mut zip, KB_QA, &b.qa)?;
+    write_entry(&mut zip, KB_DOCS, &b.docs)?;
+    let cursor = zip.finish()?;
+    Ok(cursor.into_inner())
+}
+
+fn read_entry<D: serde::de::DeserializeOwned>(
+    zip: &mut ZipArchive<Cursor<&[u8]>>,
+    name: &str,
+) -> Result<D> {
+    let mut f = zip.by_name(name).map_err(|e| {
+        Error::ErrorWithMessage(format!("Invalid bundle, {} is missing: {:?}", name, e))
+    })?;
+    let mut s = String::with_capacity(f.size() as usize);
+    f.read_to_string(&mut s)?;
+    Ok(serde_json::from_str(&s)?)
+}
+
+pub(crate) fn unpack(data: &[u8]) -> Result<Bundle> {
+    let mut zip = ZipArchive::new(Cursor::new(data))?;
+    let manifest: Manifest = read_entry(&mut zip, MANIFEST)?;
+    if manifest.bundle_version > BUNDLE_VERSION {
+        return Err(Error::ErrorWithMessage(format!(
+            "Bundle version {} is newer than supported version {}, please upgrade this application.",
+            manifest.bundle_version, BUNDLE_VERSION
+        )));
+    }
+    Ok(Bundle {
+        manifest,
+        robot: read_entry(&mut zip, ROBOT)?,
+        settings: read_entry(&mut zip, SETTINGS)?,
+        main_flows: read_entry(&mut zip, MAIN_FLOWS)?,
+        intents: read_entry(&mut zip, INTENTS)?,
+        variables: read_entry(&mut zip, VARIABLES)?,
+        http_apis: read_entry(&mut zip, EXTERNAL_HTTP_APIS)?,
+        qa: read_entry(&mut zip, KB_QA)?,
+        docs: read_entry(&mut zip, KB_DOCS)?,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/robot/export",
+    tag = "robot",
+    summary = "Export a robot as a zip bundle",
+    params(RobotQuery),
+    responses((status = 200, content_type = "application/zip", body = inline(Binary)))
+)]
+pub(crate) async fn export(Query(q): Query<RobotQuery>) -> Response {
+    let r = match collect(&q.robot_id).await {
+        Ok(b) => pack(&b),
+        Err(e) => Err(e),
+    };
+    match r {
+        Ok(data) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, "application/zip".parse().unwrap());
+            let disposition = format!("attachment; filename=\"{}.zip\"", &q.robot_id);
+            if let Ok(v) = disposition.parse() {
+                headers.insert(header::CONTENT_DISPOSITION, v);
+            }
+            (StatusCode::OK, headers, data).into_response()
+        }
+        Err(e) => to_res::<()>(Err(e)).into_response(),
+    }
+}
+
+fn existing_main_flow_ids(except_robot_id: &str) -> Result<HashSet<String>> {
+    let robots: Vec<RobotData> = db::get_all(super::crud::TABLE)?;
+    let mut ids = HashSet::with_capacity(32);
+    for r in robots.iter() {
+        if r.robot_id.eq(except_robot_id) {
+            continue;
+        }
+        let main_flows: Vec<MainFlowDetail> = db_executor!(
+            db::get_all,
+            &r.robot_id,
+            crate::flow::mainflow::crud::TABLE_SUFFIX,
+        )?;
+        for f in main_flows.into_iter() {
+            ids.insert(f.id);
+        }
+    }
+    Ok(ids)
+}
+
+// Entries with an empty or duplicate id would overwrite each other
+fn check_ids<'a>(kind: &str, ids: impl Iterator<Item = &'a str>, errors: &mut Vec<String>) {
+    let mut seen = HashSet::with_capacity(32);
+    for id in ids {
+        if id.is_empty() {
+            errors.push(format!("Invalid bundle, a {} has an empty id.", kind));
+        } else if !seen.insert(id) {
+            errors.push(format!("Invalid bundle, duplicate {} id: {}", kind, id));
+        }
+    }
+}
+
+fn remapped_ids(b: &Bundle, robot_id: &str) -> Result<HashMap<String, String>> {
+    let existing = existing_main_flow_ids(robot_id)?;
+    let mut ids = HashMap::with_capacity(b.main_flows.len());
+    for (idx, f) in b.main_flows.iter().enumerate() {
+        if existing.contains(&f.main_flow.id) {
+            let new_id = format!("{}{}", idx + 1, scru128::new_string());
+            ids.insert(f.main_flow.id.clone(), new_id);
+        }
+    }
+    Ok(ids)
+}
+
+pub(crate) fn check(b: &Bundle, q: &ImportQuery) -> Result<ImportReport> {
+    let mut report = ImportReport {
+        dry_run: q.dry_run,
+        bundle_version: b.manifest.bundle_version,
+        source_robot_id: b.manifest.robot_id.clone(),
+        target_robot_id: if q.robot_id.is_empty() {
+            super::crud::get_robot_id()
+        } else {
+            q.robot_id.clone()
+        },
+        main_flows: b.main_flows.len(),
+        sub_flows: b.main_flows.iter().map(|f| f.sub_flows.len()).sum(),
+        intents: b.intents.len(),
+        phrases: b.intents.iter().map(|i| i.phrases.len()).sum(),
+        variables: b.variables.len(),
+        http_apis: b.http_apis.len(),
+        qa_pairs: b.qa.len(),
+        docs: b.docs.len(),
+        ..Default::default()
+    };
+    if !super::crud::is_robot_id(&report.target_robot_id) {
+        report
+            .errors
+            .push(format!("Invalid robot id: {}", &report.target_robot_id));
+        return Ok(report);
+    }
+    check_ids(
+        "main flow",
+        b.main_flows.iter().map(|f| f.main_flow.id.as_str()),
+        &mut report.errors,
+    );
+    check_ids(
+        "intent",
+        b.intents.iter().map(|i| i.intent_id.as_str()),
+        &mut report.errors,
+    );
+    check_ids(
+        "variable",
+        b.variables.iter().map(|v| v.var_name.as_str()),
+        &mut report.errors,
+    );
+    check_ids(
+        "HTTP API",
+        b.http_apis.iter().map(|h| h.id.as_str()),
+        &mut report.errors,
+    );
+    let exists: Option<RobotData> = db::query(super::crud::TABLE, report.target_robot_id.as_str())?;
+    report.robot_exists = exists.is_some();
+    if report.robot_exists && !q.overwrite {
+        report.errors.push(format!(
+            "Robot {} already exists, set overwrite to replace it.",
+            &report.target_robot_id
+        ));
+    }
+    if b.settings.is_none() {
+        report
+            .warnings
+            .push(String::from("Settings are missing, defaults will be used."));
+    }
+    report.remapped_ids = remapped_ids(b, &report.target_robot_id)?;
+    if report.phrases > 0 || report.qa_pairs > 0 {
+        report.warnings.push(String::from(
+            "Embeddings of intent phrases and questions will be regenerated by the embedding provider in settings.",
+        ));
+    }
+    Ok(report)
+}
+
+fn remap(s: &str, ids: &HashMap<String, String>) -> String {
+    let mut s = String::from(s);
+    for (old, new) in ids.iter() {
+        if s.contains(old.as_str()) {
+            s = s.replace(old.as_str(), new);
+        }
+    }
+    s
+}
+
+pub(crate) async fn restore(b: Bundle, q: &ImportQuery, is_en: bool) -> Result<ImportReport> {
+    let mut report = check(&b, q)?;
+    if report.dry_run || !report.errors.is_empty() {
+        return Ok(report);
+    }
+    let robot_id = report.target_robot_id.clone();
+    if !report.robot_exists {
+        let ids = report.remapped_ids.clone();
+        write_or_purge(b, &robot_id, q, &ids, is_en, &mut report.warnings).await?;
+        return Ok(report);
+    }
+    // The bundle is imported under a fresh id first, so a failure leaves the existing robot untouched.
+    // Tables of embeddings can not be renamed, so it is imported again under the id of the existing robot.
+    let staging_id = super::crud::get_robot_id();
+    let staging = unpack(&pack(&b)?)?;
+    let ids = remapped_ids(&staging, &staging_id)?;
+    write_or_purge(staging, &staging_id, q, &ids, is_en, &mut Vec::new()).await?;
+    // Kept until the bundle replaced the existing robot, so it can be put back
+    let backup = pack(&collect(&robot_id).await?)?;
+    super::crud::purge(&robot_id).await?;
+    let ids = report.remapped_ids.clone();
+    if let Err(e) = write_or_purge(b, &robot_id, q, &ids, is_en, &mut report.warnings).await {
+        let restored = match unpack(&backup) {
+            Ok(backup) => {
+                let q = ImportQuery {
+                    robot_id: robot_id.clone(),
+                    robot_name: String::new(),
+                    dry_run: false,
+                    overwrite: true,
+                };
+                write_or_purge(
+                    backup,
+                    &robot_id,
+                    &q,
+                    &HashMap::new(),
+                    is_en,
+                    &mut Vec::new(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        return Err(match restored {
+            Ok(_) => Error::ErrorWithMessage(format!(
+                "Importing robot {} failed: {:?}, the robot was restored and the bundle was imported as robot {}.",
+                &robot_id, e, &staging_id
+            )),
+            Err(restore_err) => {
+                let path = std::path::Path::new(".")
+                    .join("data")
+                    .join(format!("{}-backup.zip", &robot_id));
+                if let Err(e) = std::fs::write(&path, &backup) {
+                    log::error!("Saving backup of robot {} failed: {:?}", &robot_id, &e);
+                }
+                Error::ErrorWithMessage(format!(
+                    "Importing robot {} failed: {:?}, restoring it failed too: {:?}, its export was saved to {} and the bundle was imported as robot {}.",
+                    &robot_id, e, restore_err, path.display(), &staging_id
+                ))
+            }
+        });
+    }
+    if let Err(e) = super::crud::purge(&staging_id).await {
+        log::error!("Removing staging robot {} failed: {:?}", &staging_id, &e);
+    }
+    Ok(report)
+}
+
+// Removes what was written when importing fails
+async fn write_or_purge(
+    b: Bundle,
+    robot_id: &str,
+    q: &ImportQuery,
+    ids: &HashMap<String, String>,
+    is_en: bool,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let r = write(b, robot_id, q, ids, is_en, warnings).await;
+    if r.is_err() {
+        if let Err(e) = super::crud::purge(robot_id).await {
+            log::error!(
+                "Removing partly imported robot {} failed: {:?}",
+                robot_id,
+                &e
+            );
+        }
+    }
+    r
+}
+
+async fn write(
+    mut b: Bundle,
+    robot_id: &str,
+    q: &ImportQuery,
+    ids: &HashMap<String, String>,
+    is_en: bool,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    // Tables first, so that `purge` can remove a partly imported robot
+    crate::intent::phrase::init_tables(robot_id).await?;
+    crate::kb::qa::init_tables(robot_id).await?;
+    crate::kb::doc::init_tables(robot_id).await?;
+    db_executor!(
+        db::init_table,
+        robot_id,
+        crate::flow::mainflow::crud::TABLE_SUFFIX,
+    )?;
+    db_executor!(
+        db::init_table,
+        robot_id,
+        crate::flow::subflow::crud::TABLE_SUFFIX,
+    )?;
+    db_executor!(
+        db::init_table,
+        robot_id,
+        crate::external::http::crud::TABLE_SUFFIX,
+    )?;
+    b.robot.robot_id = String::from(robot_id);
+    if !q.robot_name.is_empty() {
+        b.robot.robot_name = q.robot_name.clone();
+    }
+    super::crud::persist(&b.robot)?;
+    match b.settings.take() {
+        Some(s) => settings::save_settings(robot_id, s)?,
+        None => {
+            settings::init(robot_id)?;
+        }
+    }
+
+    // Flows
+    let mut main_flow_ids = Vec::with_capacity(b.main_flows.len());
+    for mut f in b.main_flows.into_iter() {
+        f.main_flow.id = remap(&f.main_flow.id, ids);
+        for s in f.sub_flows.iter_mut() {
+            s.id = remap(&s.id, ids);
+            s.canvas = remap(&s.canvas, ids);
+        }
+        db_executor!(
+            db::write,
+            robot_id,
+            crate::flow::mainflow::crud::TABLE_SUFFIX,
+            f.main_flow.id.as_str(),
+            &f.main_flow
+        )?;
+        db_executor!(
+            db::write,
+            robot_id,
+            crate::flow::subflow::crud::TABLE_SUFFIX,
+            f.main_flow.id.as_str(),
+            &f.sub_flows
+        )?;
+        // Flows that were never designed can not be released
+        if f.sub_flows.iter().any(|s| !s.canvas.is_empty()) {
+            main_flow_ids.push(f.main_flow.id);
+        }
+    }
+
+    // Intents, phrase embeddings are regenerated
+    for mut i in b.intents.into_iter() {
+        let mut phrases = Vec::with_capacity(i.phrases.len());
+        for mut p in std::mem::take(&mut i.phrases).into_iter() {
+            match crate::intent::phrase::add(
+                robot_id,
+                None,
+                &i.intent_id,
+                &i.intent_name,
+                &p.phrase,
+            )
+            .await
+            {
+                Ok(id) => {
+                    p.id = id;
+                    phrases.push(p);
+                }
+                Err(e) => warnings.push(format!(
+                    "Phrase \"{}\" of intent {} was skipped: {:?}",
+                    &p.phrase, &i.intent_name, e
+                )),
+            }
+        }
+        i.phrases = phrases;
+        db_executor!(
+            db::write,
+            robot_id,
+            crate::intent::crud::TABLE_SUFFIX,
+            i.intent_id.as_str(),
+            &i
+        )?;
+    }
+
+    for v in b.variables.iter() {
+        db_executor!(
+            db::write,
+            robot_id,
+            crate::variable::crud::TABLE_SUFFIX,
+            v.var_name.as_str(),
+            v
+        )?;
+    }
+    for h in b.http_apis.iter() {
+        db_executor!(
+            db::write,
+            robot_id,
+            crate::external::http::crud::TABLE_SUFFIX,
+            h.id.as_str(),
+            h
+        )?;
+    }
+
+    // Knowledge base, question embeddings are regenerated
+    for mut d in b.qa.into_iter() {
+        d.reset_ids();
+        let answer = d.answer.clone();
+        if let Err(e) = crate::kb::qa::save(robot_id, d).await {
+            warnings.push(format!(
+                "Question answer pair \"{}\" was skipped: {:?}",
+                answer, e
+            ));
+        }
+    }
+    for d in b.docs.iter() {
+        crate::kb::doc::save(robot_id, &d.file_name, d.file_size as usize, &d.doc_content).await?;
+    }
+
+    for id in main_flow_ids.iter() {
+        if let Err(e) = crate::flow::rt::convertor::convert_flow(is_en, robot_id, id) {
+            warnings.push(format!("Main flow {} was not released: {:?}", id, e));
+        }
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/robot/import",
+    tag = "robot",
+    summary = "Import a robot from a zip bundle",
+    params(ImportQuery),
+    request_body(content = inline(Binary), content_type = "application/zip"),
+    responses((status = 200, body = ResponseData<ImportReport>))
+)]
+pub(crate) async fn import(
+    headers: HeaderMap,
+    Query(q): Query<ImportQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let is_en = server::is_en(&headers);
+    let r = match unpack(body.as_ref()) {
+        Ok(b) => restore(b, &q, is_en).await,
+        Err(e) => Err(e),
+    };
+    let r = r.and_then(|report| {
+        if report.errors.is_empty() || report.dry_run {
+            Ok(report)
+        } else {
+            Err(Error::ErrorWithMessage(report.errors.join(" ")))
+        }
+    });
+    to_res(r)
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::DirEntry;
 use std::path::Path;
 use std::vec::Vec;
@@ -5,6 +6,7 @@ use std::vec::Vec;
 use axum::extract::Query;
 use axum::{response::IntoResponse, Json};
 use redb::TableDefinition;
+use utoipa::TupleUnit;
 
 use super::dto::{RobotData, RobotQuery, RobotType};
 use crate::db_executor;
@@ -15,11 +17,24 @@ use crate::man::settings;
 use crate::result::{Error, Result};
 use crate::variable::crud as variable;
 use crate::web::server;
+use crate::web::server::ResponseData;
 use crate::{db, web::server::to_res};
 
-const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("robots");
+pub(crate) const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("robots");
 
-fn get_robot_id() -> String {
+// Robot ids are used in table names and file paths
+pub(crate) fn is_robot_id(id: &str) -> bool {
+    id.len() == 26 && id.starts_with('r') && is_scru128(&id[1..])
+}
+
+pub(crate) fn is_scru128(id: &str) -> bool {
+    id.len() == 25
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || b.is_ascii_lowercase())
+}
+
+pub(super) fn get_robot_id() -> String {
     let mut id = String::with_capacity(32);
     id.push('r');
     let gen = scru128::new_string();
@@ -38,10 +53,22 @@ pub(crate) async fn init(is_en: bool) -> Result<()> {
         robot_id: get_robot_id(),
         robot_name: String::from(name),
         robot_type: RobotType::TextBot,
+        locales: vec![],
+        default_locale: String::from(if is_en { "en" } else { "zh" }),
+        auto_detect_locale: false,
+        template_id: None,
     };
     new(&d, is_en).await
 }
 
+#[utoipa::path(
+    post,
+    path = "/robot",
+    tag = "robot",
+    summary = "Create or update a robot",
+    request_body = RobotData,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn save(
     headers: axum::http::HeaderMap,
     Json(mut d): Json<RobotData>,
@@ -49,7 +76,11 @@ pub(crate) async fn save(
     if d.robot_id.is_empty() {
         let is_en = server::is_en(&headers);
         d.robot_id = get_robot_id();
-        if let Err(e) = new(&d, is_en).await {
+        let r = match d.template_id.take() {
+            Some(t) if !t.is_empty() => super::template::create_robot(&d, &t, is_en).await,
+            _ => new(&d, is_en).await,
+        };
+        if let Err(e) = r {
             return to_res(Err(Error::ErrorWithMessage(format!(
                 "Failed to create robot, error detail was: {:?}",
                 &e
@@ -78,19 +109,88 @@ async fn new(d: &RobotData, is_en: bool) -> Result<()> {
     Ok(())
 }
 
-fn persist(d: &RobotData) -> Result<()> {
+pub(super) fn persist(d: &RobotData) -> Result<()> {
     db::write(TABLE, d.robot_id.as_str(), &d)?;
-    Ok(())
+    super::i18n::remove_cache(&d.robot_id)
 }
 
+#[utoipa::path(
+    get,
+    path = "/robot",
+    tag = "robot",
+    summary = "List robots",
+    responses((status = 200, body = ResponseData<Vec<RobotData>>))
+)]
 pub(crate) async fn list() -> impl IntoResponse {
     to_res::<Vec<RobotData>>(db::get_all(TABLE))
 }
 
+#[utoipa::path(
+    get,
+    path = "/robot/detail",
+    tag = "robot",
+    summary = "Get robot detail",
+    params(RobotQuery),
+    responses((status = 200, body = ResponseData<RobotData>))
+)]
 pub(crate) async fn detail(Query(q): Query<RobotQuery>) -> impl IntoResponse {
     to_res::<Option<RobotData>>(db::query(TABLE, q.robot_id.as_str()))
 }
 
+#[utoipa::path(
+    post,
+    path = "/robot/clone",
+    tag = "robot",
+    summary = "Clone a robot with flows, intents, variables, HTTP APIs and knowledge base",
+    params(("robotId" = String, Query), ("robotName" = Option<String>, Query)),
+    responses((status = 200, body = ResponseData<RobotData>))
+)]
+pub(crate) async fn clone(
+    headers: axum::http::HeaderMap,
+    Query(q): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let Some(robot_id) = q.get("robotId") else {
+        return to_res(Err(Error::ErrorWithMessage(String::from(
+            "Parameter: robotId is missing.",
+        ))));
+    };
+    let is_en = server::is_en(&headers);
+    let robot_name = q.get("robotName").map(|s| s.as_str()).unwrap_or("");
+    to_res(clone_robot(robot_id, robot_name, is_en).await)
+}
+
+async fn clone_robot(robot_id: &str, robot_name: &str, is_en: bool) -> Result<RobotData> {
+    let mut b = super::bundle::collect(robot_id).await?;
+    let q = super::bundle::ImportQuery {
+        robot_id: get_robot_id(),
+        robot_name: if robot_name.is_empty() {
+            format!("{} (copy)", &b.robot.robot_name)
+        } else {
+            String::from(robot_name)
+        },
+        dry_run: false,
+        overwrite: false,
+    };
+    b.robot.template_id = None;
+    let report = super::bundle::restore(b, &q, is_en).await?;
+    if !report.errors.is_empty() {
+        return Err(Error::ErrorWithMessage(report.errors.join(" ")));
+    }
+    for w in report.warnings.iter() {
+        log::warn!("Cloning robot {}: {}", robot_id, w);
+    }
+    let r: Option<RobotData> = db::query(TABLE, q.robot_id.as_str())?;
+    r.ok_or_else(|| Error::ErrorWithMessage(String::from("Cloned robot was not found.")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/robot",
+    tag = "robot",
+    summary = "Delete a robot and all its data",
+    params(RobotQuery),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn delete(Query(q): Query<RobotQuery>) -> impl IntoResponse {
     to_res(purge(&q.robot_id).await)
 }
@@ -118,8 +218,10 @@ fn delete_dirs(dir: &Path, cb: &dyn Fn(&DirEntry) -> Result<()>) -> Result<()> {
     Ok(())
 }
 
-async fn purge(robot_id: &str) -> Result<()> {
+pub(super) async fn purge(robot_id: &str) -> Result<()> {
     crate::intent::phrase::remove_tables(robot_id).await?;
+    crate::kb::qa::remove_tables(robot_id).await?;
+    crate::kb::doc::remove_tables(robot_id).await?;
     // let root = &format!("{}{}", crate::intent::detector::SAVING_PATH_ROOT, robot_id);
     // let path = Path::new(&root);
     // if path.exists() {
@@ -146,6 +248,10 @@ async fn purge(robot_id: &str) -> Result<()> {
         robot_id,
         crate::variable::crud::TABLE_SUFFIX,
     )?;
+    crate::variable::store::delete_tables(robot_id)?;
+    crate::external::webhook::crud::delete_tables(robot_id)?;
+    crate::external::email::crud::delete_all(robot_id)?;
+    crate::external::channel::crud::delete_tables(robot_id)?;
     db_executor!(
         db::delete_table,
         robot_id,
@@ -169,5 +275,6 @@ async fn purge(robot_id: &str) -> Result<()> {
         robot_id,
         crate::flow::mainflow::crud::TABLE_SUFFIX,
     )?;
-    db::remove(TABLE, robot_id)
+    db::remove(TABLE, robot_id)?;
+    super::i18n::remove_cache(robot_id)
 }
@@ -1,19 +1,20 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub(crate) struct RobotQuery {
     #[serde(rename = "robotId")]
     pub(crate) robot_id: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) enum RobotType {
     InboundCallBot,
     OutboundCallBot,
     TextBot,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct RobotData {
     #[serde(rename = "robotId")]
     pub(crate) robot_id: String,
@@ -21,4 +22,12 @@ pub(crate) struct RobotData {
     pub(crate) robot_name: String,
     #[serde(rename = "robotType")]
     pub(crate) robot_type: RobotType,
+    #[serde(default)]
+    pub(crate) locales: Vec<String>,
+    #[serde(rename = "defaultLocale", default)]
+    pub(crate) default_locale: String,
+    #[serde(rename = "autoDetectLocale", default)]
+    pub(crate) auto_detect_locale: bool,
+    #[serde(rename = "templateId", default, skip_serializing)]
+    pub(crate) template_id: Option<String>,
 }
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::vec::Vec;
+
+use axum::extract::Query;
+use axum::response::IntoResponse;
+use serde::Serialize;
+
+use super::dto::{RobotData, RobotQuery};
+use crate::db;
+use crate::db_executor;
+use crate::flow::mainflow::dto::MainFlowDetail;
+use crate::flow::rt::context::Context;
+use crate::flow::rt::dto::Request;
+use crate::flow::subflow::dto::{CanvasCells, Node, SubFlowDetail};
+use crate::intent::dto::IntentDetail;
+use crate::result::{Error, Result};
+use crate::web::server::{to_res, ResponseData};
+
+pub(crate) const AUTO_DETECT: &str = "auto";
+
+// Locale settings of robots, read on every request
+static LOCALES_CACHE: LazyLock<Mutex<HashMap<String, RobotLocales>>> =
+    LazyLock::new(|| Mutex::new(HashMap::with_capacity(32)));
+
+#[derive(Clone)]
+struct RobotLocales {
+    // Locales of the robot including the default one
+    candidates: Vec<String>,
+    default_locale: String,
+    auto_detect_locale: bool,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct MissingTranslation {
+    pub(crate) locale: String,
+    pub(crate) kind: String,
+    pub(crate) location: String,
+    pub(crate) text: String,
+}
+
+fn primary_tag(locale: &str) -> &str {
+    locale.split(['-', '_']).next().unwrap_or(locale)
+}
+
+fn locale_matches(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+pub(crate) fn language_matches(a: &str, b: &str) -> bool {
+    primary_tag(a).eq_ignore_ascii_case(primary_tag(b))
+}
+
+// Picks the variant of `locale`, then the variant of the same language,
+// otherwise falls back to the text of the default locale
+pub(crate) fn select<'a, I>(variants: I, default: &'a str, locale: &str) -> &'a str
+where
+    I: IntoIterator<Item = (&'a String, &'a String)>,
+{
+    if locale.is_empty() {
+        return default;
+    }
+    let mut same_language: Option<&'a str> = None;
+    for (l, t) in variants.into_iter() {
+        if t.is_empty() {
+            continue;
+        }
+        if locale_matches(l, locale) {
+            return t;
+        }
+        if same_language.is_none() && language_matches(l, locale) {
+            same_language = Some(t);
+        }
+    }
+    same_language.unwrap_or(default)
+}
+
+fn iso_639_1(lang: whatlang::Lang) -> &'static str {
+    use whatlang::Lang;
+    match lang {
+        Lang::Eng => "en",
+        Lang::Cmn => "zh",
+        Lang::Spa => "es",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Jpn => "ja",
+        Lang::Kor => "ko",
+        Lang::Rus => "ru",
+        Lang::Por => "pt",
+        Lang::Ita => "it",
+        Lang::Ara => "ar",
+        Lang::Hin => "hi",
+        Lang::Tha => "th",
+        Lang::Vie => "vi",
+        Lang::Ind => "id",
+        Lang::Nld => "nl",
+        Lang::Tur => "tr",
+        Lang::Pol => "pl",
+        Lang::Ukr => "uk",
+        Lang::Heb => "he",
+        Lang::Ell => "el",
+        Lang::Swe => "sv",
+        l => l.code(),
+    }
+}
+
+pub(crate) fn detect(text: &str, candidates: &[String]) -> Option<String> {
+    let info = whatlang::detect(text)?;
+    // Chinese characters are not ambiguous even in very short inputs
+    if !info.is_reliable() && info.lang() != whatlang::Lang::Cmn {
+        return None;
+    }
+    let lang = iso_639_1(info.lang());
+    candidates
+        .iter()
+        .find(|c| language_matches(c, lang))
+        .cloned()
+}
+
+fn robot_locales(robot_id: &str) -> Result<Option<RobotLocales>> {
+    if let Some(l) = LOCALES_CACHE.lock()?.get(robot_id) {
+        return Ok(Some(l.clone()));
+    }
+    let robot: Option<RobotData> = db::query(super::crud::TABLE, robot_id)?;
+    let Some(robot) = robot else {
+        return Ok(None);
+    };
+    let mut candidates = robot.locales;
+    if !robot.default_locale.is_empty() && !candidates.contains(&robot.default_locale) {
+        candidates.push(robot.default_locale.clone());
+    }
+    let l = RobotLocales {
+        candidates,
+        default_locale: robot.default_locale,
+        auto_detect_locale: robot.auto_detect_locale,
+    };
+    LOCALES_CACHE
+        .lock()?
+        .insert(String::from(robot_id), l.clone());
+    Ok(Some(l))
+}
+
+// Called whenever the robot is saved or removed
+pub(crate) fn remove_cache(robot_id: &str) -> Result<()> {
+    LOCALES_CACHE.lock()?.remove(robot_id);
+    Ok(())
+}
+
+// Decides which locale the current turn uses and remembers it in the session
+pub(crate) fn resolve_locale(req: &mut Request, ctx: &mut Context) -> Result<()> {
+    let Some(robot) = robot_locales(&req.robot_id)? else {
+        return Ok(());
+    };
+    let candidates = &robot.candidates;
+    let auto = req.locale.eq(AUTO_DETECT) || (req.locale.is_empty() && robot.auto_detect_locale);
+    if auto {
+        req.locale.clear();
+        if !req.user_input.is_empty() {
+            if let Some(l) = detect(&req.user_input, candidates) {
+                req.locale.push_str(&l);
+            }
+        }
+    }
+    if req.locale.is_empty() {
+        if ctx.locale.is_empty() {
+            req.locale.push_str(&robot.default_locale);
+        } else {
+            req.locale.push_str(&ctx.locale);
+        }
+    }
+    if !ctx.locale.eq(&req.locale) {
+        ctx.locale.clear();
+        ctx.locale.push_str(&req.locale);
+    }
+    Ok(())
+}
+
+fn has_variant(variants: &HashMap<String, String>, locale: &str) -> bool {
+    variants
+        .iter()
+        .any(|(l, t)| !t.is_empty() && language_matches(l, locale))
+}
+
+fn check_text(
+    report: &mut Vec<MissingTranslation>,
+    locales: &[&String],
+    kind: &str,
+    location: &str,
+    text: &str,
+    variants: &HashMap<String, String>,
+) {
+    if text.is_empty() {
+        return;
+    }
+    for l in locales.iter() {
+        if !has_variant(variants, l) {
+            report.push(MissingTranslation {
+                locale: String::from(*l),
+                kind: String::from(kind),
+                location: String::from(location),
+                text: String::from(text),
+            });
+        }
+    }
+}
+
+pub(crate) async fn missing_translations(robot_id: &str) -> Result<Vec<MissingTranslation>> {
+    let robot: Option<RobotData> = db::query(super::crud::TABLE, robot_id)?;
+    let Some(robot) = robot else {
+        return Err(Error::ErrorWithMessage(format!(
+            "Robot {} was not found.",
+            robot_id
+        )));
+    };
+    let locales: Vec<&String> = robot
+        .locales
+        .iter()
+        .filter(|l| !language_matches(l, &robot.default_locale))
+        .collect();
+    let mut report = Vec::with_capacity(32);
+    if locales.is_empty() {
+        return Ok(report);
+    }
+    let main_flows: Vec<MainFlowDetail> = db_executor!(
+        db::get_all,
+        robot_id,
+        crate::flow::mainflow::crud::TABLE_SUFFIX,
+    )?;
+    for main_flow in main_flows.iter() {
+        let sub_flows: Option<Vec<SubFlowDetail>> = db_executor!(
+            db::query,
+            robot_id,
+            crate::flow::subflow::crud::TABLE_SUFFIX,
+            main_flow.id.as_str()
+        )?;
+        for sub_flow in sub_flows.unwrap_or_default().iter() {
+            if sub_flow.canvas.is_empty() {
+                continue;
+            }
+            let cells: CanvasCells = match serde_json::from_str(&sub_flow.canvas) {
+                Ok(c) => c,
+                Err(e) => {
+                    log::warn!("Parsing canvas of {} failed: {:?}", &sub_flow.name, e);
+                    continue;
+                }
+            };
+            for cell in cells.cells.iter() {
+                let Some(node) = cell.data.as_ref() else {
+                    continue;
+                };
+                match node {
+                    Node::DialogNode(n) => check_text(
+                        &mut report,
+                        &locales,
+                        "dialogText",
+                        &format!(
+                            "{} / {} / {}",
+                            &main_flow.name, &sub_flow.name, &n.node_name
+                        ),
+                        &n.dialog_text,
+                        &n.dialog_text_i18n,
+                    ),
+                    Node::EndNode(n) => check_text(
+                        &mut report,
+                        &locales,
+                        "endingText",
+                        &format!(
+                            "{} / {} / {}",
+                            &main_flow.name, &sub_flow.name, &n.node_name
+                        ),
+                        &n.ending_text,
+                        &n.ending_text_i18n,
+                    ),
+                    Node::CollectNode(n) => check_text(
+                        &mut report,
+                        &locales,
+                        "collectPrompt",
+                        &format!(
+                            "{} / {} / {}",
+                            &main_flow.name, &sub_flow.name, &n.node_name
+                        ),
+                        &n.collect_prompt,
+                        &n.collect_prompt_i18n,
+                    ),
+                    _ => {}
+                }
+            }
+        }
+    }
+    let intents: Vec<IntentDetail> =
+        db_executor!(db::get_all, robot_id, crate::intent::crud::TABLE_SUFFIX,)?;
+    for intent in intents.iter() {
+        if intent.phrases.is_empty() {
+            continue;
+        }
+        for l in locales.iter() {
+            if !intent
+                .phrases
+                .iter()
+                .any(|p| language_matches(&p.locale, l))
+            {
+                report.push(MissingTranslation {
+                    locale: String::from(*l),
+                    kind: String::from("intentPhrase"),
+                    location: intent.intent_name.clone(),
+                    text: String::new(),
+                });
+            }
+        }
+    }
+    for qa in crate::kb::qa::list(robot_id).await?.iter() {
+        check_text(
+            &mut report,
+            &locales,
+            "kbAnswer",
+            qa.question_text(),
+            &qa.answer,
+            &qa.answer_i18n,
+        );
+    }
+    Ok(report)
+}
+
+#[utoipa::path(
+    get,
+    path = "/robot/i18n/missing",
+    tag = "robot",
+    summary = "Report texts without translations for the robot locales",
+    params(RobotQuery),
+    responses((status = 200, body = ResponseData<Vec<MissingTranslation>>))
+)]
+pub(crate) async fn report(Query(q): Query<RobotQuery>) -> impl IntoResponse {
+    to_res(missing_translations(&q.robot_id).await)
+}
@@ -1,2 +1,5 @@
+pub(crate) mod bundle;
 pub(crate) mod crud;
 pub(crate) mod dto;
+pub(crate) mod i18n;
+pub(crate) mod template;
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+use axum::extract::Query;
+use axum::response::IntoResponse;
+use axum::Json;
+use redb::TableDefinition;
+use serde::{Deserialize, Serialize};
+use utoipa::TupleUnit;
+
+use super::bundle;
+use super::dto::{RobotData, RobotQuery};
+use crate::db;
+use crate::result::{Error, Result};
+use crate::web::server::{self, to_res, ResponseData};
+
+const TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("robotTemplates");
+
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct RobotTemplate {
+    #[serde(default)]
+    pub(crate) id: String,
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) description: String,
+    #[serde(default)]
+    pub(crate) language: String,
+    #[serde(rename = "createdAt", default)]
+    pub(crate) created_at: u64,
+}
+
+pub(crate) fn init_table() -> Result<()> {
+    db::init_table(TABLE)
+}
+
+// Paths are built from stored templates only, ids in requests never reach the file system
+fn get_bundle_path(t: &RobotTemplate) -> Result<PathBuf> {
+    let p = Path::new(".").join("data").join("templates");
+    if !p.exists() {
+        std::fs::create_dir_all(&p)?;
+    }
+    Ok(p.join(format!("{}.zip", &t.id)))
+}
+
+pub(crate) fn get(template_id: &str) -> Result<Option<RobotTemplate>> {
+    if !super::crud::is_scru128(template_id) {
+        return Err(Error::ErrorWithMessage(format!(
+            "Invalid template id: {}",
+            template_id
+        )));
+    }
+    db::query(TABLE, template_id)
+}
+
+fn find(template_id: &str) -> Result<RobotTemplate> {
+    get(template_id)?
+        .ok_or_else(|| Error::ErrorWithMessage(format!("Template {} was not found.", template_id)))
+}
+
+pub(crate) fn read_bundle(template_id: &str) -> Result<bundle::Bundle> {
+    let t = find(template_id)?;
+    let data = std::fs::read(get_bundle_path(&t)?)?;
+    bundle::unpack(&data)
+}
+
+async fn save_template(robot_id: &str, mut t: RobotTemplate, is_en: bool) -> Result<RobotTemplate> {
+    if t.name.trim().is_empty() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Template name is missing.",
+        )));
+    }
+    let mut b = bundle::collect(robot_id).await?;
+    // Settings contain credentials, templates use default settings instead
+    b.settings = None;
+    let data = bundle::pack(&b)?;
+    t.id = scru128::new_string();
+    if t.language.is_empty() {
+        t.language = String::from(if is_en { "en" } else { "zh" });
+    }
+    t.created_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    std::fs::write(get_bundle_path(&t)?, data)?;
+    db::write(TABLE, t.id.as_str(), &t)?;
+    Ok(t)
+}
+
+fn remove_template(template_id: &str) -> Result<()> {
+    let t = find(template_id)?;
+    let p = get_bundle_path(&t)?;
+    if p.exists() {
+        std::fs::remove_file(p)?;
+    }
+    db::remove(TABLE, t.id.as_str())
+}
+
+#[utoipa::path(
+    get,
+    path = "/robot/template",
+    tag = "robot",
+    summary = "List robot templates",
+    params(("language" = Option<String>, Query)),
+    responses((status = 200, body = ResponseData<Vec<RobotTemplate>>))
+)]
+pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    let r: Result<Vec<RobotTemplate>> = db::get_all(TABLE).map(|mut v: Vec<RobotTemplate>| {
+        if let Some(lang) = q.get("language") {
+            v.retain(|t| t.language.eq(lang));
+        }
+        v
+    });
+    to_res(r)
+}
+
+#[utoipa::path(
+    post,
+    path = "/robot/template",
+    tag = "robot",
+    summary = "Save a robot as a template",
+    params(RobotQuery),
+    request_body = RobotTemplate,
+    responses((status = 200, body = ResponseData<RobotTemplate>))
+)]
+pub(crate) async fn save(
+    headers: axum::http::HeaderMap,
+    Query(q): Query<RobotQuery>,
+    Json(t): Json<RobotTemplate>,
+) -> impl IntoResponse {
+    let is_en = server::is_en(&headers);
+    to_res(save_template(&q.robot_id, t, is_en).await)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/robot/template",
+    tag = "robot",
+    summary = "Delete a robot template",
+    params(("id" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn delete(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    if let Some(id) = q.get("id") {
+        to_res(remove_template(id))
+    } else {
+        to_res(Err(Error::ErrorWithMessage(String::from(
+            "Parameter: id is missing.",
+        ))))
+    }
+}
+
+pub(crate) async fn create_robot(d: &RobotData, template_id: &str, is_en: bool) -> Result<()> {
+    let b = read_bundle(template_id)?;
+    let q = bundle::ImportQuery {
+        robot_id: d.robot_id.clone(),
+        robot_name: d.robot_name.clone(),
+        dry_run: false,
+        overwrite: false,
+    };
+    let report = bundle::restore(b, &q, is_en).await?;
+    if !report.errors.is_empty() {
+        return Err(Error::ErrorWithMessage(report.errors.join(" ")));
+    }
+    for w in report.warnings.iter() {
+        log::warn!("Creating robot from template {}: {}", template_id, w);
+    }
+    Ok(())
+}
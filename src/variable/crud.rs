@@ -5,15 +5,19 @@ use std::vec::Vec;
 use axum::extract::Query;
 use axum::response::IntoResponse;
 use axum::Json;
+use utoipa::TupleUnit;
 
 use super::dto::Variable;
-use super::dto::{VariableObtainValueExpressionType, VariableType, VariableValueSource};
+use super::dto::{split_var_path, VariableValue};
+use super::dto::{
+    VariableObtainValueExpressionType, VariableScope, VariableType, VariableValueSource,
+};
 use crate::db;
 use crate::db_executor;
 use crate::flow::rt::context::Context;
 use crate::flow::rt::dto::Request;
 use crate::result::{Error, Result};
-use crate::web::server::to_res;
+use crate::web::server::{to_res, ResponseData};
 
 // const TABLE: redb::TableDefinition<&str, &[u8]> = redb::TableDefinition::new("variables");
 // pub(crate) const VARIABLE_LIST_KEY: &str = "variables";
@@ -47,6 +51,7 @@ pub(crate) fn init(robot_id: &str, is_en: bool) -> Result<()> {
         obtain_value_expression_type: VariableObtainValueExpressionType::None,
         obtain_value_expression: String::new(),
         cach_enabled: true,
+        var_scope: VariableScope::Session,
     };
     // let result = db_executor!(db::write, robot_id, &v.var_name, &v);
     // let table_name = get_table_name(robot_id);
@@ -55,6 +60,14 @@ pub(crate) fn init(robot_id: &str, is_en: bool) -> Result<()> {
     db_executor!(db::write, robot_id, TABLE_SUFFIX, &v.var_name, &v)
 }
 
+#[utoipa::path(
+    get,
+    path = "/variable",
+    tag = "variable",
+    summary = "List variables",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<Vec<Variable>>))
+)]
 pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
     // let result:Result<Vec<Variable>> = db_executor!(db::get_all, "robot_id",);
     // to_res::<Vec<Variable>>(db::get_all(TABLE))
@@ -67,6 +80,15 @@ pub(crate) async fn list(Query(q): Query<HashMap<String, String>>) -> impl IntoR
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/variable",
+    tag = "variable",
+    summary = "Create or update a variable",
+    params(("robotId" = String, Query)),
+    request_body = Variable,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn add(
     Query(q): Query<HashMap<String, String>>,
     Json(v): Json<Variable>,
@@ -96,6 +118,11 @@ pub(crate) async fn add(
     to_res(r)
     */
     // to_res(db::write(TABLE, &v.var_name, &v))
+    if matches!(v.var_val_source, VariableValueSource::Constant) {
+        if let Err(e) = VariableValue::new(&v.var_constant_value, &v.var_type) {
+            return to_res(Err(e));
+        }
+    }
     if let Some(robot_id) = q.get("robotId") {
         to_res(db_executor!(
             db::write,
@@ -120,6 +147,15 @@ pub(crate) async fn add(
 //     r
 // }
 
+#[utoipa::path(
+    delete,
+    path = "/variable",
+    tag = "variable",
+    summary = "Delete a variable",
+    params(("robotId" = String, Query)),
+    request_body = Variable,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
 pub(crate) async fn delete(
     Query(q): Query<HashMap<String, String>>,
     Json(v): Json<Variable>,
@@ -172,13 +208,21 @@ pub(crate) fn get(robot_id: &str, name: &str) -> Result<Option<Variable>> {
     db_executor!(db::query, robot_id, TABLE_SUFFIX, name)
 }
 
-pub(crate) fn get_value(name: &str, req: &Request, ctx: &mut Context) -> String {
-    if let Ok(r) = get(&req.robot_id, name) {
-        if let Some(v) = r {
-            if let Some(val) = v.get_value(req, ctx) {
-                return val.val_to_string();
-            }
+// Supports paths inside object and array variables, e.g. `order.items[0].name`
+pub(crate) fn get_path_value(
+    name: &str,
+    req: &Request,
+    ctx: &mut Context,
+) -> Option<VariableValue> {
+    let (var_name, path) = split_var_path(name);
+    if let Ok(Some(v)) = get(&req.robot_id, var_name) {
+        if let Some(val) = v.get_value(req, ctx) {
+            return val.get_path(path);
         }
     }
-    String::new()
+    None
+}
+
+pub(crate) fn get_value(name: &str, req: &Request, ctx: &mut Context) -> String {
+    get_path_value(name, req, ctx).map_or(String::new(), |v| v.val_to_string())
 }
@@ -5,8 +5,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::flow::rt::context::Context;
 use crate::flow::rt::dto::Request;
+use crate::result::{Error, Result};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct SimpleVariable {
     #[serde(rename = "varName")]
     pub(crate) var_name: String,
@@ -16,7 +17,7 @@ pub(crate) struct SimpleVariable {
     pub(crate) var_val: String,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) struct Variable {
     // #[serde(rename(deserialize = "varName"))]
     #[serde(rename = "varName")]
@@ -35,74 +36,29 @@ pub(crate) struct Variable {
     pub(crate) obtain_value_expression: String,
     #[serde(rename = "cacheEnabled")]
     pub(crate) cach_enabled: bool,
+    #[serde(rename = "varScope", default)]
+    pub(crate) var_scope: VariableScope,
 }
 
 impl Variable {
-    fn get_data_from_scraper(&self, s: &str) -> Option<String> {
-        let parts = &self
-            .obtain_value_expression
-            .split_whitespace()
-            .collect::<Vec<&str>>();
-        let mut selectors = Vec::with_capacity(parts.len());
-        for part in parts {
-            if let Ok(selector) = scraper::Selector::parse(part) {
-                selectors.push(selector);
-            } else {
-                return None;
-            }
-        }
-        let doc = scraper::Html::parse_document(s);
-        let mut ele_ref_op = doc.select(&selectors.pop().unwrap()).next();
-        for sel in selectors.iter() {
-            if let Some(ele_ref) = ele_ref_op {
-                ele_ref_op = ele_ref.select(sel).next();
-            } else {
-                return None;
-            }
-        }
-        if let Some(ele_ref) = ele_ref_op {
-            if let Some(res) = ele_ref.text().next() {
-                return Some(String::from(res));
-            }
-        }
-        None
-    }
     fn get_data_from_res<'a, 'b>(
         &'b self,
         ctx: &'a mut Context,
         s: &'b str,
     ) -> Option<&'a VariableValue> {
-        let mut str_store: Option<String> = None;
-        let r = match self.obtain_value_expression_type {
-            VariableObtainValueExpressionType::JsonPointer => {
-                if let Ok(v) = serde_json::from_str::<serde_json::Value>(s) {
-                    if let Some(r) = v.pointer(&self.obtain_value_expression) {
-                        if r.is_string() {
-                            str_store = Some(String::from(r.as_str().unwrap()));
-                            str_store.as_ref().unwrap()
-                        } else {
-                            s
-                        }
-                    } else {
-                        s
-                    }
-                } else {
-                    s
-                }
-            }
-            VariableObtainValueExpressionType::HtmlScrape => {
-                str_store = self.get_data_from_scraper(s);
-                if str_store.is_some() {
-                    str_store.as_ref().unwrap()
-                } else {
-                    s
-                }
+        let r = super::extract::extract(
+            &self.obtain_value_expression_type,
+            &self.obtain_value_expression,
+            s,
+        )
+        .and_then(|values| super::extract::to_variable_value(values, &self.var_type));
+        let v = match r {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Variable {} got an invalid value: {:?}", &self.var_name, e);
+                return None;
             }
-            VariableObtainValueExpressionType::JavaScript => s,
-            VariableObtainValueExpressionType::None => s,
         };
-        // println!("{}", r);
-        let v = VariableValue::new(r, &self.var_type);
         if self.cach_enabled {
             ctx.vars.insert(self.var_name.clone(), v);
             return ctx.vars.get(&self.var_name);
@@ -116,7 +72,23 @@ impl Variable {
         req: &'b Request,
         ctx: &'b mut Context,
     ) -> Option<&'b VariableValue> {
-        if self.cach_enabled && ctx.vars.contains_key(&self.var_name) {
+        if !matches!(self.var_scope, VariableScope::Session)
+            && !ctx.none_persistent_vars.contains_key(&self.var_name)
+        {
+            match super::store::get(&req.robot_id, &self.var_scope, &req.user_id, &self.var_name) {
+                Ok(Some(v)) => {
+                    ctx.none_persistent_vars.insert(self.var_name.clone(), v);
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Reading variable {} failed: {:?}", &self.var_name, e),
+            }
+        }
+        // Assigned for the current turn only, or loaded from robot and user scopes
+        if ctx.none_persistent_vars.contains_key(&self.var_name) {
+            ctx.none_persistent_vars.get(&self.var_name)
+        } else if (self.cach_enabled || ctx.assigned_vars.contains(&self.var_name))
+            && ctx.vars.contains_key(&self.var_name)
+        {
             // println!("get from cache");
             ctx.vars.get(&self.var_name)
         } else {
@@ -144,12 +116,18 @@ impl Variable {
                 ctx.vars.get(&self.var_name)
             }
             VariableValueSource::UserInput => {
-                let v = VariableValue::new(&req.user_input, &self.var_type);
+                let v = VariableValue::new(&req.user_input, &self.var_type).ok()?;
                 ctx.vars.insert(self.var_name.clone(), v);
                 ctx.vars.get(&self.var_name)
             }
             VariableValueSource::Constant => {
-                let v = VariableValue::new(&self.var_constant_value, &self.var_type);
+                let v = match VariableValue::new(&self.var_constant_value, &self.var_type) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        log::warn!("Variable {} got an invalid value: {:?}", &self.var_name, e);
+                        return None;
+                    }
+                };
                 ctx.vars.insert(self.var_name.clone(), v);
                 ctx.vars.get(&self.var_name)
             }
@@ -172,9 +150,14 @@ impl Variable {
                     if let Some(api) = op {
                         return tokio::task::block_in_place(
                             /*move*/
-                            || match tokio::runtime::Handle::current()
-                                .block_on(crate::external::http::client::req(api, &ctx.vars, false))
-                            {
+                            || match tokio::runtime::Handle::current().block_on(
+                                crate::external::http::client::req(
+                                    &req.robot_id,
+                                    api,
+                                    &ctx.vars,
+                                    false,
+                                ),
+                            ) {
                                 Ok(r) => match r {
                                     crate::external::http::dto::ResponseData::Str(s) => {
                                         // 下面这句，需要在get_data_from_res的上方，否则会报*ctx可变借用了两次，因为返回值，对ctx有引用
@@ -203,15 +186,139 @@ pub(crate) enum VariableValue {
     Str(String),
     Num(f64),
     Array(Vec<VariableValue>),
+    Bool(bool),
+    // Unix timestamp in seconds
+    DateTime(i64),
+    Object(serde_json::Map<String, serde_json::Value>),
+}
+
+// Accepts RFC 3339, "2024-01-31 08:00:00", "2024-01-31" (as UTC) and unix timestamps
+pub(crate) fn parse_datetime(s: &str) -> Option<i64> {
+    use time::format_description::well_known::Rfc3339;
+    use time::macros::format_description;
+    let s = s.trim();
+    if let Ok(n) = s.parse::<i64>() {
+        return Some(n);
+    }
+    if let Ok(d) = time::OffsetDateTime::parse(s, &Rfc3339) {
+        return Some(d.unix_timestamp());
+    }
+    if let Ok(d) = time::PrimitiveDateTime::parse(
+        s,
+        format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+    ) {
+        return Some(d.assume_utc().unix_timestamp());
+    }
+    if let Ok(d) = time::Date::parse(s, format_description!("[year]-[month]-[day]")) {
+        return Some(d.midnight().assume_utc().unix_timestamp());
+    }
+    None
+}
+
+pub(crate) fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" | "on" => Some(true),
+        "false" | "no" | "n" | "0" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn invalid_value(v: &str, t: &VariableType) -> Error {
+    Error::ErrorWithMessage(format!("'{}' is not a valid {:?} value", v, t))
 }
 
 impl VariableValue {
-    pub(crate) fn new(v: &str, t: &VariableType) -> Self {
+    pub(crate) fn new(v: &str, t: &VariableType) -> Result<Self> {
         match t {
-            VariableType::Str => VariableValue::Str(String::from(v)),
-            VariableType::Num => VariableValue::Num(v.parse::<f64>().unwrap_or(0f64)),
+            VariableType::Str => Ok(VariableValue::Str(String::from(v))),
+            VariableType::Num => v
+                .trim()
+                .parse::<f64>()
+                .map(VariableValue::Num)
+                .map_err(|_| invalid_value(v, t)),
+            VariableType::Bool => parse_bool(v)
+                .map(VariableValue::Bool)
+                .ok_or_else(|| invalid_value(v, t)),
+            VariableType::DateTime => parse_datetime(v)
+                .map(VariableValue::DateTime)
+                .ok_or_else(|| invalid_value(v, t)),
+            VariableType::Object | VariableType::Array(_) => {
+                let json: serde_json::Value =
+                    serde_json::from_str(v).map_err(|_| invalid_value(v, t))?;
+                Self::from_json(json, t)
+            }
         }
     }
+
+    pub(crate) fn from_json(v: serde_json::Value, t: &VariableType) -> Result<Self> {
+        match (v, t) {
+            (serde_json::Value::String(s), _) => Self::new(&s, t),
+            (serde_json::Value::Number(n), VariableType::Num) => n
+                .as_f64()
+                .map(VariableValue::Num)
+                .ok_or_else(|| invalid_value(&n.to_string(), t)),
+            (serde_json::Value::Number(n), VariableType::DateTime) => n
+                .as_i64()
+                .map(VariableValue::DateTime)
+                .ok_or_else(|| invalid_value(&n.to_string(), t)),
+            (serde_json::Value::Bool(b), VariableType::Bool) => Ok(VariableValue::Bool(b)),
+            (serde_json::Value::Object(o), VariableType::Object) => Ok(VariableValue::Object(o)),
+            (serde_json::Value::Array(arr), VariableType::Array(item_type)) => arr
+                .into_iter()
+                .map(|item| Self::from_json(item, item_type))
+                .collect::<Result<Vec<_>>>()
+                .map(VariableValue::Array),
+            (v, t) => Err(invalid_value(&v.to_string(), t)),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            VariableValue::Str(s) => serde_json::Value::from(s.as_str()),
+            VariableValue::Num(n) => serde_json::Value::from(*n),
+            VariableValue::Array(arr) => {
+                serde_json::Value::Array(arr.iter().map(|v| v.to_json()).collect())
+            }
+            VariableValue::Bool(b) => serde_json::Value::Bool(*b),
+            VariableValue::DateTime(_) => serde_json::Value::from(self.val_to_string()),
+            VariableValue::Object(o) => serde_json::Value::Object(o.clone()),
+        }
+    }
+
+    // Resolves paths like `items[0].name` inside objects and arrays
+    pub(crate) fn get_path(&self, path: &str) -> Option<VariableValue> {
+        if path.is_empty() {
+            return Some(self.clone());
+        }
+        let mut pointer = String::with_capacity(path.len() + 8);
+        for part in path.split('.').filter(|p| !p.is_empty()) {
+            let mut segments = part.split('[');
+            if let Some(key) = segments.next() {
+                if !key.is_empty() {
+                    pointer.push('/');
+                    pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                }
+            }
+            for idx in segments {
+                pointer.push('/');
+                pointer.push_str(idx.trim_end_matches(']'));
+            }
+        }
+        let json = self.to_json();
+        match json.pointer(&pointer)? {
+            serde_json::Value::String(s) => Some(VariableValue::Str(s.clone())),
+            serde_json::Value::Number(n) => n.as_f64().map(VariableValue::Num),
+            serde_json::Value::Bool(b) => Some(VariableValue::Bool(*b)),
+            serde_json::Value::Object(o) => Some(VariableValue::Object(o.clone())),
+            serde_json::Value::Array(arr) => Some(VariableValue::Array(
+                arr.iter()
+                    .filter_map(|v| VariableValue::from_json(v.clone(), &json_type(v)).ok())
+                    .collect(),
+            )),
+            serde_json::Value::Null => None,
+        }
+    }
+
     // pub(crate) fn val_to_string(&self) -> Cow<'_, str> {
     pub(crate) fn val_to_string(&self) -> String {
         match self {
@@ -228,18 +335,95 @@ impl VariableValue {
                 s.push(']');
                 s
             }
+            VariableValue::Bool(b) => b.to_string(),
+            VariableValue::DateTime(t) => time::OffsetDateTime::from_unix_timestamp(*t)
+                .ok()
+                .and_then(|d| {
+                    d.format(&time::format_description::well_known::Rfc3339)
+                        .ok()
+                })
+                .unwrap_or_else(|| t.to_string()),
+            VariableValue::Object(o) => serde_json::to_string(o).unwrap_or_default(),
         }
         // Cow::Owned(s)
     }
 }
 
-#[derive(Clone, Deserialize, Serialize, PartialEq)]
+pub(crate) fn json_type(v: &serde_json::Value) -> VariableType {
+    match v {
+        serde_json::Value::Number(_) => VariableType::Num,
+        serde_json::Value::Bool(_) => VariableType::Bool,
+        serde_json::Value::Object(_) => VariableType::Object,
+        serde_json::Value::Array(arr) => {
+            VariableType::Array(Box::new(arr.first().map_or(VariableType::Str, json_type)))
+        }
+        _ => VariableType::Str,
+    }
+}
+
+// Splits `order.items[0].name` into the variable name and the path inside it
+pub(crate) fn split_var_path(name: &str) -> (&str, &str) {
+    match name.find(['.', '[']) {
+        Some(idx) => (&name[..idx], name[idx..].trim_start_matches('.')),
+        None => (name, ""),
+    }
+}
+
+#[derive(
+    Clone,
+    Debug,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    utoipa::ToSchema,
+)]
+// Bounds are required by rkyv for the recursive `Array`
+#[rkyv(serialize_bounds(__S: rkyv::ser::Writer + rkyv::ser::Allocator, __S::Error: rkyv::rancor::Source))]
+#[rkyv(deserialize_bounds(__D::Error: rkyv::rancor::Source))]
+#[rkyv(compare(PartialEq))]
+#[rkyv(bytecheck(bounds(__C: rkyv::validation::ArchiveContext, __C::Error: rkyv::rancor::Source)))]
 pub(crate) enum VariableType {
     Str,
     Num,
+    Bool,
+    DateTime,
+    Object,
+    // Typed array, e.g. {"Array": "Num"}
+    #[schema(no_recursion)]
+    Array(#[rkyv(omit_bounds)] Box<VariableType>),
+}
+
+impl VariableType {
+    // Describes values of the type to LLMs
+    pub(crate) fn json_schema(&self) -> serde_json::Value {
+        match self {
+            VariableType::Str => serde_json::json!({"type": "string"}),
+            VariableType::Num => serde_json::json!({"type": "number"}),
+            VariableType::Bool => serde_json::json!({"type": "boolean"}),
+            VariableType::DateTime => serde_json::json!({"type": "string", "format": "date-time"}),
+            VariableType::Object => serde_json::json!({"type": "object"}),
+            VariableType::Array(t) => {
+                serde_json::json!({"type": "array", "items": t.json_schema()})
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) enum VariableScope {
+    // Lives in the session context
+    #[default]
+    Session,
+    // Shared by all sessions of the robot
+    Robot,
+    // Keyed by `userId` of the request, remembered across sessions
+    User,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
 pub(crate) enum VariableValueSource {
     Import,
     Collect,
@@ -248,10 +432,22 @@ pub(crate) enum VariableValueSource {
     ExternalHttp,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(
+    Clone,
+    Deserialize,
+    Serialize,
+    rkyv::Archive,
+    rkyv::Deserialize,
+    rkyv::Serialize,
+    utoipa::ToSchema,
+)]
+#[rkyv(compare(PartialEq))]
 pub(crate) enum VariableObtainValueExpressionType {
     None,
     JsonPointer,
     HtmlScrape,
     JavaScript,
+    JsonPath,
+    XPath,
+    Regex,
 }
@@ -0,0 +1,609 @@
+use axum::response::IntoResponse;
+use axum::Json;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+
+use super::dto::{VariableObtainValueExpressionType, VariableType, VariableValue};
+use crate::result::{Error, Result};
+use crate::web::server::{to_res, ResponseData};
+
+// Returns every value matched by the expression, texts are returned as JSON strings
+pub(crate) fn extract(
+    expr_type: &VariableObtainValueExpressionType,
+    expr: &str,
+    s: &str,
+) -> Result<Vec<serde_json::Value>> {
+    match expr_type {
+        VariableObtainValueExpressionType::None | VariableObtainValueExpressionType::JavaScript => {
+            Ok(vec![serde_json::Value::from(s)])
+        }
+        VariableObtainValueExpressionType::JsonPointer => {
+            let v = serde_json::from_str::<serde_json::Value>(s)?;
+            Ok(v.pointer(expr).into_iter().cloned().collect())
+        }
+        VariableObtainValueExpressionType::JsonPath => {
+            let path = serde_json_path::JsonPath::parse(expr)
+                .map_err(|e| Error::ErrorWithMessage(format!("Invalid JSONPath: {}", e)))?;
+            let v = serde_json::from_str::<serde_json::Value>(s)?;
+            Ok(path.query(&v).all().into_iter().cloned().collect())
+        }
+        VariableObtainValueExpressionType::HtmlScrape => html(expr, s),
+        VariableObtainValueExpressionType::XPath => xpath(expr, s),
+        VariableObtainValueExpressionType::Regex => regex(expr, s),
+    }
+}
+
+// Catches malformed expressions when saving flows
+pub(crate) fn check(expr_type: &VariableObtainValueExpressionType, expr: &str) -> Result<()> {
+    let empty = expr.trim().is_empty();
+    match expr_type {
+        VariableObtainValueExpressionType::None | VariableObtainValueExpressionType::JavaScript => {
+            return Ok(())
+        }
+        _ if empty => {
+            return Err(Error::ErrorWithMessage(String::from(
+                "expression not filled in",
+            )))
+        }
+        VariableObtainValueExpressionType::JsonPath => {
+            serde_json_path::JsonPath::parse(expr)
+                .map_err(|e| Error::ErrorWithMessage(format!("Invalid JSONPath: {}", e)))?;
+        }
+        VariableObtainValueExpressionType::Regex => {
+            regex::Regex::new(expr)?;
+        }
+        VariableObtainValueExpressionType::XPath => {
+            parse_xpath(expr.trim())?;
+        }
+        VariableObtainValueExpressionType::HtmlScrape => {
+            html(expr, "")?;
+        }
+        VariableObtainValueExpressionType::JsonPointer => {}
+    }
+    Ok(())
+}
+
+// Matched values become an array for array variables, otherwise the first one is taken
+pub(crate) fn to_variable_value(
+    mut values: Vec<serde_json::Value>,
+    t: &VariableType,
+) -> Result<VariableValue> {
+    if values.is_empty() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Expression matched nothing",
+        )));
+    }
+    match t {
+        VariableType::Array(item_type) => {
+            if values.len() == 1 && values[0].is_array() {
+                return from_json(values.remove(0), t);
+            }
+            values
+                .into_iter()
+                .map(|v| from_json(v, item_type))
+                .collect::<Result<Vec<_>>>()
+                .map(VariableValue::Array)
+        }
+        _ => from_json(values.swap_remove(0), t),
+    }
+}
+
+// Numbers and booleans may still be read into string variables
+fn from_json(v: serde_json::Value, t: &VariableType) -> Result<VariableValue> {
+    if v.is_string() || v.is_object() || v.is_array() {
+        return VariableValue::from_json(v, t);
+    }
+    VariableValue::from_json(v.clone(), t).or_else(|_| VariableValue::new(&v.to_string(), t))
+}
+
+// CSS selector, optionally followed by `@attr` to read an attribute, e.g. `div.price a @href`
+fn html(expr: &str, s: &str) -> Result<Vec<serde_json::Value>> {
+    let expr = expr.trim();
+    let (selector, attr) = match expr.rsplit_once(char::is_whitespace) {
+        Some((sel, attr)) if attr.starts_with('@') => (sel.trim(), Some(&attr[1..])),
+        _ => (expr, None),
+    };
+    let selector = scraper::Selector::parse(selector)
+        .map_err(|e| Error::ErrorWithMessage(format!("Invalid CSS selector: {}", e)))?;
+    let doc = scraper::Html::parse_document(s);
+    let values = doc
+        .select(&selector)
+        .filter_map(|ele| match attr {
+            Some(a) => ele.value().attr(a).map(String::from),
+            None => Some(ele.text().collect::<String>().trim().to_string()),
+        })
+        .map(serde_json::Value::from)
+        .collect();
+    Ok(values)
+}
+
+// Named groups produce objects, otherwise the first group (or the whole match) of every match
+fn regex(expr: &str, s: &str) -> Result<Vec<serde_json::Value>> {
+    let re = regex::Regex::new(expr)?;
+    let names: Vec<&str> = re.capture_names().flatten().collect();
+    let values = re
+        .captures_iter(s)
+        .filter_map(|caps| {
+            if !names.is_empty() {
+                let mut o = serde_json::Map::with_capacity(names.len());
+                for name in names.iter() {
+                    if let Some(m) = caps.name(name) {
+                        o.insert(String::from(*name), serde_json::Value::from(m.as_str()));
+                    }
+                }
+                Some(serde_json::Value::Object(o))
+            } else {
+                caps.get(if caps.len() > 1 { 1 } else { 0 })
+                    .map(|m| serde_json::Value::from(m.as_str()))
+            }
+        })
+        .collect();
+    Ok(values)
+}
+
+struct XmlElement {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<XmlChild>,
+}
+
+enum XmlChild {
+    Element(XmlElement),
+    Text(String),
+}
+
+impl XmlElement {
+    fn new(e: &BytesStart) -> Result<Self> {
+        let mut attrs = Vec::new();
+        for a in e.attributes() {
+            let a = a.map_err(quick_xml::errors::Error::from)?;
+            let key = String::from_utf8_lossy(a.key.local_name().as_ref()).to_string();
+            attrs.push((key, a.unescape_value()?.to_string()));
+        }
+        Ok(XmlElement {
+            name: String::from_utf8_lossy(e.local_name().as_ref()).to_string(),
+            attrs,
+            children: Vec::new(),
+        })
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &XmlElement> {
+        self.children.iter().filter_map(|c| match c {
+            XmlChild::Element(e) => Some(e),
+            XmlChild::Text(_) => None,
+        })
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn string_value(&self) -> String {
+        let mut s = String::new();
+        self.push_text(&mut s);
+        s
+    }
+
+    fn push_text(&self, s: &mut String) {
+        for c in self.children.iter() {
+            match c {
+                XmlChild::Element(e) => e.push_text(s),
+                XmlChild::Text(t) => s.push_str(t),
+            }
+        }
+    }
+
+    fn push_descendants_or_self<'a>(&'a self, nodes: &mut Vec<&'a XmlElement>) {
+        nodes.push(self);
+        for e in self.elements() {
+            e.push_descendants_or_self(nodes);
+        }
+    }
+}
+
+fn parse_xml(s: &str) -> Result<XmlElement> {
+    let mut reader = Reader::from_str(s);
+    reader.config_mut().trim_text(true);
+    // The document node, its children are the root elements
+    let mut stack = vec![XmlElement {
+        name: String::new(),
+        attrs: Vec::new(),
+        children: Vec::new(),
+    }];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => stack.push(XmlElement::new(&e)?),
+            Event::Empty(e) => {
+                let ele = XmlElement::new(&e)?;
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(XmlChild::Element(ele));
+                }
+            }
+            Event::End(_) if stack.len() > 1 => {
+                let ele = stack.pop().unwrap();
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(XmlChild::Element(ele));
+                }
+            }
+            Event::Text(e) => {
+                let t = e.unescape()?.to_string();
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(XmlChild::Text(t));
+                }
+            }
+            Event::CData(e) => {
+                let t = String::from_utf8_lossy(&e.into_inner()).to_string();
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(XmlChild::Text(t));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    while stack.len() > 1 {
+        let ele = stack.pop().unwrap();
+        if let Some(parent) = stack.last_mut() {
+            parent.children.push(XmlChild::Element(ele));
+        }
+    }
+    Ok(stack.pop().unwrap())
+}
+
+fn xpath_err(expr: &str) -> Error {
+    Error::ErrorWithMessage(format!("Unsupported XPath expression: {}", expr))
+}
+
+// Splits on `/` outside of predicates, an empty step stands for `//`
+fn split_steps(expr: &str) -> Vec<&str> {
+    let mut steps = Vec::new();
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut begin = 0usize;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '\'' | '"' if quote == Some(c) => quote = None,
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            '[' if quote.is_none() => depth += 1,
+            ']' if quote.is_none() => depth = depth.saturating_sub(1),
+            '/' if quote.is_none() && depth == 0 => {
+                steps.push(&expr[begin..i]);
+                begin = i + 1;
+            }
+            _ => {}
+        }
+    }
+    steps.push(&expr[begin..]);
+    steps
+}
+
+// Splits `name[1][@id='a']` into the node test and its predicates
+fn split_predicates(step: &str) -> Option<(&str, Vec<&str>)> {
+    let Some(begin) = step.find('[') else {
+        return Some((step, Vec::new()));
+    };
+    let mut predicates = Vec::new();
+    let mut rest = &step[begin..];
+    while !rest.is_empty() {
+        rest = rest.strip_prefix('[')?;
+        let mut quote: Option<char> = None;
+        let mut end = None;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '\'' | '"' if quote == Some(c) => quote = None,
+                '\'' | '"' if quote.is_none() => quote = Some(c),
+                ']' if quote.is_none() => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let end = end?;
+        predicates.push(rest[..end].trim());
+        rest = &rest[end + 1..];
+    }
+    Some((&step[..begin], predicates))
+}
+
+fn unquote(s: &str) -> Option<&str> {
+    let s = s.trim();
+    s.strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .or_else(|| s.strip_prefix('"').and_then(|s| s.strip_suffix('"')))
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'))
+}
+
+enum Predicate<'e> {
+    Position(usize),
+    Last,
+    HasAttr(&'e str),
+    HasChild(&'e str),
+    AttrEq(&'e str, &'e str),
+    TextEq(&'e str),
+    ChildEq(&'e str, &'e str),
+}
+
+impl<'e> Predicate<'e> {
+    fn parse(predicate: &'e str) -> Option<Self> {
+        if let Ok(n) = predicate.parse::<usize>() {
+            return Some(Predicate::Position(n));
+        }
+        if predicate == "last()" {
+            return Some(Predicate::Last);
+        }
+        match predicate.split_once('=') {
+            Some((left, right)) => {
+                let expected = unquote(right)?;
+                let left = left.trim();
+                if let Some(attr) = left.strip_prefix('@') {
+                    is_name(attr).then_some(Predicate::AttrEq(attr, expected))
+                } else if left == "text()" || left == "." {
+                    Some(Predicate::TextEq(expected))
+                } else {
+                    is_name(left).then_some(Predicate::ChildEq(left, expected))
+                }
+            }
+            None => {
+                if let Some(attr) = predicate.strip_prefix('@') {
+                    is_name(attr).then_some(Predicate::HasAttr(attr))
+                } else {
+                    is_name(predicate).then_some(Predicate::HasChild(predicate))
+                }
+            }
+        }
+    }
+
+    fn matches(&self, e: &XmlElement, pos: usize, count: usize) -> bool {
+        match self {
+            Predicate::Position(n) => pos == *n,
+            Predicate::Last => pos == count,
+            Predicate::HasAttr(attr) => e.attr(attr).is_some(),
+            Predicate::HasChild(name) => e.elements().any(|c| c.name == *name),
+            Predicate::AttrEq(attr, expected) => e.attr(attr) == Some(*expected),
+            Predicate::TextEq(expected) => e.string_value() == *expected,
+            Predicate::ChildEq(name, expected) => e
+                .elements()
+                .any(|c| c.name == *name && c.string_value() == *expected),
+        }
+    }
+}
+
+enum Step<'e> {
+    // `//`, the following step also matches descendants
+    Descendant,
+    Current,
+    Elements(&'e str, Vec<Predicate<'e>>),
+    // Attributes and texts end the path
+    Attr(&'e str),
+    Text,
+}
+
+// The whole expression is parsed up front, so unsupported parts are reported
+// even if no element of the document reaches them
+fn parse_xpath(expr: &str) -> Result<Vec<Step<'_>>> {
+    let mut parts = split_steps(expr);
+    if expr.starts_with('/') {
+        parts.remove(0);
+    }
+    let last = parts.len().saturating_sub(1);
+    let mut steps = Vec::with_capacity(parts.len());
+    for (idx, part) in parts.into_iter().enumerate() {
+        let part = part.trim();
+        let step = if part.is_empty() {
+            Step::Descendant
+        } else if part == "." {
+            Step::Current
+        } else if let Some(attr) = part.strip_prefix('@') {
+            if idx != last || (attr != "*" && !is_name(attr)) {
+                return Err(xpath_err(expr));
+            }
+            Step::Attr(attr)
+        } else if part == "text()" {
+            if idx != last {
+                return Err(xpath_err(expr));
+            }
+            Step::Text
+        } else {
+            let (name, predicates) = split_predicates(part).ok_or_else(|| xpath_err(expr))?;
+            if name != "*" && !is_name(name) {
+                return Err(xpath_err(expr));
+            }
+            let predicates = predicates
+                .into_iter()
+                .map(Predicate::parse)
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| xpath_err(expr))?;
+            Step::Elements(name, predicates)
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+// A subset of XPath 1.0: `/` and `//` steps, `*`, `@attr`, `text()`,
+// and predicates like `[2]`, `[last()]`, `[@id]`, `[@id='a']` and `[name='b']`
+fn xpath(expr: &str, s: &str) -> Result<Vec<serde_json::Value>> {
+    let steps = parse_xpath(expr.trim())?;
+    let doc = parse_xml(s)?;
+    let mut nodes: Vec<&XmlElement> = vec![&doc];
+    let mut descendant = false;
+    for step in steps.iter() {
+        if let Step::Descendant = step {
+            descendant = true;
+            continue;
+        }
+        if descendant {
+            let mut expanded = Vec::new();
+            for n in nodes.iter() {
+                n.push_descendants_or_self(&mut expanded);
+            }
+            nodes = expanded;
+            descendant = false;
+        }
+        match step {
+            Step::Descendant | Step::Current => {}
+            Step::Attr(attr) => {
+                let values = nodes
+                    .iter()
+                    .flat_map(|n| {
+                        n.attrs
+                            .iter()
+                            .filter(|(k, _)| *attr == "*" || k == attr)
+                            .map(|(_, v)| serde_json::Value::from(v.as_str()))
+                    })
+                    .collect();
+                return Ok(values);
+            }
+            Step::Text => {
+                let values = nodes
+                    .iter()
+                    .flat_map(|n| {
+                        n.children.iter().filter_map(|c| match c {
+                            XmlChild::Text(t) => Some(serde_json::Value::from(t.as_str())),
+                            XmlChild::Element(_) => None,
+                        })
+                    })
+                    .collect();
+                return Ok(values);
+            }
+            Step::Elements(name, predicates) => {
+                let mut next = Vec::new();
+                for n in nodes.iter() {
+                    let mut candidates: Vec<&XmlElement> = n
+                        .elements()
+                        .filter(|c| *name == "*" || c.name == *name)
+                        .collect();
+                    for p in predicates.iter() {
+                        let count = candidates.len();
+                        candidates = candidates
+                            .into_iter()
+                            .enumerate()
+                            .filter(|(i, c)| p.matches(c, i + 1, count))
+                            .map(|(_, c)| c)
+                            .collect();
+                    }
+                    next.extend(candidates);
+                }
+                nodes = next;
+            }
+        }
+    }
+    Ok(nodes
+        .into_iter()
+        .map(|n| serde_json::Value::from(n.string_value()))
+        .collect())
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub(crate) struct ExtractTestData {
+    #[serde(rename = "obtainValueExpressionType")]
+    expr_type: VariableObtainValueExpressionType,
+    #[serde(rename = "obtainValueExpression")]
+    expr: String,
+    #[serde(rename = "varType")]
+    var_type: VariableType,
+    sample: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct ExtractTestResult {
+    matches: Vec<serde_json::Value>,
+    value: serde_json::Value,
+}
+
+fn try_extract(d: ExtractTestData) -> Result<ExtractTestResult> {
+    let matches = extract(&d.expr_type, &d.expr, &d.sample)?;
+    let value = to_variable_value(matches.clone(), &d.var_type)?;
+    Ok(ExtractTestResult {
+        matches,
+        value: value.to_json(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/variable/extract/test",
+    tag = "variable",
+    summary = "Try a variable extraction on a sample",
+    request_body = ExtractTestData,
+    responses((status = 200, body = ResponseData<ExtractTestResult>))
+)]
+pub(crate) async fn dry_run(Json(d): Json<ExtractTestData>) -> impl IntoResponse {
+    to_res(try_extract(d))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOKS: &str = r#"<shelf>
+        <book id="a" lang="en"><title>Dune</title><price>9</price></book>
+        <book id="b"><title>Emma</title><price>7</price></book>
+        <box><book id="c"><title>Ulysses</title></book></box>
+    </shelf>"#;
+
+    fn texts(expr: &str) -> Vec<String> {
+        xpath(expr, BOOKS)
+            .unwrap()
+            .into_iter()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn xpath_steps() {
+        assert_eq!(texts("/shelf/book/title"), ["Dune", "Emma"]);
+        assert_eq!(texts("//book/title/text()"), ["Dune", "Emma", "Ulysses"]);
+        assert_eq!(texts("/shelf/*/book/@id"), ["c"]);
+        assert_eq!(texts("//book[1]/@*"), ["a", "en", "c"]);
+        assert_eq!(texts("/shelf/./book[last()]/title"), ["Emma"]);
+        assert!(texts("/shelf/missing/title").is_empty());
+    }
+
+    #[test]
+    fn xpath_predicates() {
+        assert_eq!(texts("//book[@lang]/title"), ["Dune"]);
+        assert_eq!(texts("//book[@id='b']/title"), ["Emma"]);
+        assert_eq!(texts("//book[price=\"7\"]/@id"), ["b"]);
+        assert_eq!(texts("//book[price]/title[. = 'Dune']"), ["Dune"]);
+        assert_eq!(texts("//book[title][2]/@id"), ["b"]);
+        assert_eq!(texts("//title[text()='Ulysses']"), ["Ulysses"]);
+    }
+
+    #[test]
+    fn unsupported_xpath() {
+        for expr in [
+            "//book[@id!='a']",
+            "//book[contains(@id,'a')]",
+            "//book[position()>1]",
+            "//book[@id=a]",
+            "//book[1",
+            "//book/@id/title",
+            "//book/text()/title",
+            "//node()",
+            "//book|//box",
+        ] {
+            let e = xpath(expr, BOOKS).err();
+            assert!(
+                matches!(&e, Some(Error::ErrorWithMessage(m)) if m.starts_with("Unsupported XPath")),
+                "{}",
+                expr
+            );
+            assert!(
+                check(&VariableObtainValueExpressionType::XPath, expr).is_err(),
+                "{}",
+                expr
+            );
+        }
+    }
+}
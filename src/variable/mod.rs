@@ -1,2 +1,4 @@
 pub(crate) mod crud;
 pub(crate) mod dto;
+pub(crate) mod extract;
+pub(crate) mod store;
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use axum::extract::Query;
+use axum::response::IntoResponse;
+use axum::Json;
+use utoipa::TupleUnit;
+
+use super::dto::{SimpleVariable, VariableScope, VariableValue};
+use crate::db;
+use crate::db_executor;
+use crate::flow::rt::dto::Request;
+use crate::result::{Error, Result};
+use crate::web::server::{to_res, ResponseData};
+
+pub(crate) const GLOBAL_TABLE_SUFFIX: &str = "globalVars";
+pub(crate) const USER_TABLE_SUFFIX: &str = "userVars";
+
+// Serializes read-modify-write updates of a record, so concurrent sessions never lose an update,
+// while updates of other records, which may call HTTP APIs, do not wait for each other
+static UPDATE_LOCKS: LazyLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::with_capacity(32)));
+
+// Robot variables are stored one per record, user variables in one record per user
+fn lock_key(robot_id: &str, scope: &VariableScope, user_id: &str, name: &str) -> String {
+    match scope {
+        VariableScope::User => format!("{}:user:{}", robot_id, user_id),
+        _ => format!("{}:robot:{}", robot_id, name),
+    }
+}
+
+fn with_lock<T>(key: String, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let lock = UPDATE_LOCKS.lock()?.entry(key.clone()).or_default().clone();
+    let r = {
+        let _guard = lock.lock()?;
+        f()
+    };
+    drop(lock);
+    let mut locks = UPDATE_LOCKS.lock()?;
+    if locks.get(&key).is_some_and(|l| Arc::strong_count(l) == 1) {
+        locks.remove(&key);
+    }
+    r
+}
+
+fn none_if_table_missing<D>(r: Result<Option<D>>) -> Result<Option<D>> {
+    match r {
+        Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Ok(None),
+        r => r,
+    }
+}
+
+fn get_user_vars(robot_id: &str, user_id: &str) -> Result<HashMap<String, VariableValue>> {
+    let r: Option<HashMap<String, VariableValue>> = none_if_table_missing(db_executor!(
+        db::query,
+        robot_id,
+        USER_TABLE_SUFFIX,
+        user_id
+    ))?;
+    Ok(r.unwrap_or_default())
+}
+
+fn check_user_id(user_id: &str) -> Result<()> {
+    if user_id.is_empty() {
+        Err(Error::ErrorWithMessage(String::from(
+            "User scoped variables need the userId of request.",
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn get(
+    robot_id: &str,
+    scope: &VariableScope,
+    user_id: &str,
+    name: &str,
+) -> Result<Option<VariableValue>> {
+    match scope {
+        VariableScope::Session => Ok(None),
+        VariableScope::Robot => {
+            let r: Option<(String, VariableValue)> = none_if_table_missing(db_executor!(
+                db::query,
+                robot_id,
+                GLOBAL_TABLE_SUFFIX,
+                name
+            ))?;
+            Ok(r.map(|(_, v)| v))
+        }
+        VariableScope::User => {
+            check_user_id(user_id)?;
+            Ok(get_user_vars(robot_id, user_id)?.remove(name))
+        }
+    }
+}
+
+// `f` is called while holding the lock of the record, returning None removes the value
+pub(crate) fn update<F>(
+    robot_id: &str,
+    scope: &VariableScope,
+    user_id: &str,
+    name: &str,
+    f: F,
+) -> Result<Option<VariableValue>>
+where
+    F: FnOnce(Option<VariableValue>) -> Result<Option<VariableValue>>,
+{
+    match scope {
+        VariableScope::Session => Err(Error::ErrorWithMessage(String::from(
+            "Session variables are not stored.",
+        ))),
+        VariableScope::Robot => with_lock(lock_key(robot_id, scope, user_id, name), || {
+            let current = get(robot_id, scope, user_id, name)?;
+            let v = f(current)?;
+            match &v {
+                Some(v) => {
+                    db_executor!(db::write, robot_id, GLOBAL_TABLE_SUFFIX, name, &(name, v))?
+                }
+                None => db_executor!(db::remove, robot_id, GLOBAL_TABLE_SUFFIX, name)?,
+            };
+            Ok(v)
+        }),
+        VariableScope::User => {
+            check_user_id(user_id)?;
+            with_lock(lock_key(robot_id, scope, user_id, name), || {
+                let mut vars = get_user_vars(robot_id, user_id)?;
+                let v = f(vars.remove(name))?;
+                if let Some(v) = &v {
+                    vars.insert(String::from(name), v.clone());
+                }
+                if vars.is_empty() {
+                    db_executor!(db::remove, robot_id, USER_TABLE_SUFFIX, user_id)?;
+                } else {
+                    db_executor!(db::write, robot_id, USER_TABLE_SUFFIX, user_id, &vars)?;
+                }
+                Ok(v)
+            })
+        }
+    }
+}
+
+// Saves the value when the variable was declared with robot or user scope
+pub(crate) fn persist_scoped(req: &Request, name: &str, v: &VariableValue) -> Result<bool> {
+    if let Some(var) = super::crud::get(&req.robot_id, name)? {
+        if !matches!(var.var_scope, VariableScope::Session) {
+            update(&req.robot_id, &var.var_scope, &req.user_id, name, |_| {
+                Ok(Some(v.clone()))
+            })?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub(crate) fn delete_tables(robot_id: &str) -> Result<()> {
+    db_executor!(db::delete_table, robot_id, GLOBAL_TABLE_SUFFIX,)?;
+    db_executor!(db::delete_table, robot_id, USER_TABLE_SUFFIX,)
+}
+
+fn to_json_map(vars: HashMap<String, VariableValue>) -> HashMap<String, serde_json::Value> {
+    vars.into_iter().map(|(k, v)| (k, v.to_json())).collect()
+}
+
+fn list_values(
+    q: &HashMap<String, String>,
+    scope: VariableScope,
+) -> Result<HashMap<String, VariableValue>> {
+    let robot_id = q
+        .get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))?;
+    match scope {
+        VariableScope::Robot => {
+            let r: Result<Vec<(String, VariableValue)>> =
+                db_executor!(db::get_all, robot_id, GLOBAL_TABLE_SUFFIX,);
+            match r {
+                Ok(v) => Ok(v.into_iter().collect()),
+                Err(Error::DbError(redb::Error::TableDoesNotExist(_))) => Ok(HashMap::new()),
+                Err(e) => Err(e),
+            }
+        }
+        _ => {
+            let user_id = q.get("userId").map_or("", |s| s.as_str());
+            check_user_id(user_id)?;
+            get_user_vars(robot_id, user_id)
+        }
+    }
+}
+
+fn set_value(
+    q: &HashMap<String, String>,
+    scope: VariableScope,
+    v: SimpleVariable,
+) -> Result<Option<VariableValue>> {
+    let robot_id = q
+        .get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))?;
+    let user_id = q.get("userId").map_or("", |s| s.as_str());
+    // Declared type wins over the type sent by the client
+    let var_type = match super::crud::get(robot_id, &v.var_name)? {
+        Some(d) => d.var_type,
+        None => v.var_type,
+    };
+    let value = VariableValue::new(&v.var_val, &var_type)?;
+    update(robot_id, &scope, user_id, &v.var_name, |_| Ok(Some(value)))
+}
+
+fn remove_value(q: &HashMap<String, String>, scope: VariableScope) -> Result<()> {
+    let robot_id = q
+        .get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))?;
+    let user_id = q.get("userId").map_or("", |s| s.as_str());
+    match q.get("varName") {
+        Some(name) => update(robot_id, &scope, user_id, name, |_| Ok(None)).map(|_| ()),
+        // Forgets everything of the user
+        None if matches!(scope, VariableScope::User) => {
+            check_user_id(user_id)?;
+            with_lock(lock_key(robot_id, &scope, user_id, ""), || {
+                db_executor!(db::remove, robot_id, USER_TABLE_SUFFIX, user_id)
+            })
+        }
+        None => Err(Error::ErrorWithMessage(String::from(
+            "Parameter: varName is missing.",
+        ))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/variable/global",
+    tag = "variable",
+    summary = "List values of robot scope variables",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<serde_json::Value>))
+)]
+pub(crate) async fn list_global(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(list_values(&q, VariableScope::Robot).map(to_json_map))
+}
+
+#[utoipa::path(
+    post,
+    path = "/variable/global",
+    tag = "variable",
+    summary = "Set the value of a robot scope variable",
+    params(("robotId" = String, Query)),
+    request_body = SimpleVariable,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn save_global(
+    Query(q): Query<HashMap<String, String>>,
+    Json(v): Json<SimpleVariable>,
+) -> impl IntoResponse {
+    to_res(set_value(&q, VariableScope::Robot, v).map(|_| ()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/variable/global",
+    tag = "variable",
+    summary = "Delete the value of a robot scope variable",
+    params(("robotId" = String, Query), ("varName" = String, Query)),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn delete_global(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(remove_value(&q, VariableScope::Robot))
+}
+
+#[utoipa::path(
+    get,
+    path = "/variable/user",
+    tag = "variable",
+    summary = "List values of user scope variables of a user",
+    params(("robotId" = String, Query), ("userId" = String, Query)),
+    responses((status = 200, body = ResponseData<serde_json::Value>))
+)]
+pub(crate) async fn list_user(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(list_values(&q, VariableScope::User).map(to_json_map))
+}
+
+#[utoipa::path(
+    post,
+    path = "/variable/user",
+    tag = "variable",
+    summary = "Set the value of a user scope variable",
+    params(("robotId" = String, Query), ("userId" = String, Query)),
+    request_body = SimpleVariable,
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn save_user(
+    Query(q): Query<HashMap<String, String>>,
+    Json(v): Json<SimpleVariable>,
+) -> impl IntoResponse {
+    to_res(set_value(&q, VariableScope::User, v).map(|_| ()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/variable/user",
+    tag = "variable",
+    summary = "Delete a value of a user, or all values when varName is absent",
+    params(
+        ("robotId" = String, Query),
+        ("userId" = String, Query),
+        ("varName" = Option<String>, Query),
+    ),
+    responses((status = 200, body = ResponseData<TupleUnit>))
+)]
+pub(crate) async fn delete_user(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(remove_value(&q, VariableScope::User))
+}
@@ -1,5 +1,7 @@
 pub(crate) mod asset;
+pub(crate) mod openapi;
 pub mod server;
+pub(crate) mod widget;
 
 // pub use crate::flow::rt::context::clean_expired_session;
 // pub use crate::flow::rt::convertor::t1;
@@ -0,0 +1,111 @@
+use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, SchemaFormat, Type};
+use utoipa::openapi::{RefOr, Schema};
+use utoipa::{OpenApi, PartialSchema, ToSchema};
+
+// Paths are collected from the `#[utoipa::path]` annotations of the handlers by `server::gen_router`
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Dialog flow chat bot"),
+    components(schemas(
+        crate::robot::dto::RobotData,
+        crate::robot::dto::RobotQuery,
+        crate::robot::bundle::ImportReport,
+        crate::robot::template::RobotTemplate,
+        crate::robot::i18n::MissingTranslation,
+        crate::intent::dto::IntentFormData,
+        crate::intent::dto::IntentDetail,
+        crate::variable::dto::Variable,
+        crate::variable::dto::SimpleVariable,
+        crate::variable::extract::ExtractTestData,
+        crate::flow::mainflow::dto::MainFlowDetail,
+        crate::flow::subflow::dto::SubFlowDetail,
+        crate::flow::subflow::dto::SubFlowFormData,
+        crate::external::http::dto::HttpReqInfo,
+        crate::external::http::dto::HttpTestData,
+        crate::external::http::dto::HttpTestResult,
+        crate::external::webhook::dto::Webhook,
+        crate::external::webhook::dto::WebhookDelivery,
+        crate::external::email::dto::EmailOutboxEntry,
+        crate::external::email::dto::EmailStatus,
+        crate::external::email::dto::UploadedAttachment,
+        crate::external::email::dto::EmailChannel,
+        crate::external::email::dto::EmailPollResult,
+        crate::external::channel::dto::ChannelAdapter,
+        crate::external::channel::dto::ChannelPlatform,
+        crate::external::channel::dto::TelegramSettings,
+        crate::external::channel::dto::SlackSettings,
+        crate::external::channel::dto::GenericJsonSettings,
+        crate::external::notification::dto::NotificationProvider,
+        crate::external::notification::dto::NotificationChannel,
+        crate::external::notification::dto::HttpNotificationTemplate,
+        crate::external::notification::dto::NotificationHeader,
+        crate::man::settings::GlobalSettings,
+        crate::man::settings::Settings,
+        crate::web::widget::ChatWidget,
+        crate::web::widget::WidgetPosition,
+        crate::ai::huggingface::HuggingFaceModel,
+        crate::ai::huggingface::DownloadStatus,
+        crate::ai::crud::Request,
+        crate::kb::dto::QuestionAnswerPair,
+        crate::kb::dto::DocData,
+        crate::flow::rt::dto::Request,
+        crate::flow::rt::dto::Response,
+    ))
+)]
+pub(crate) struct ApiDoc;
+
+fn binary() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .format(Some(SchemaFormat::KnownFormat(KnownFormat::Binary)))
+}
+
+// Raw content of a file, as a request body or a download
+pub(crate) enum Binary {}
+
+impl PartialSchema for Binary {
+    fn schema() -> RefOr<Schema> {
+        binary().into()
+    }
+}
+
+impl ToSchema for Binary {}
+
+// `multipart/form-data` body with a single `file` part
+pub(crate) enum FileUpload {}
+
+impl PartialSchema for FileUpload {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .property("file", binary())
+            .required("file")
+            .into()
+    }
+}
+
+impl ToSchema for FileUpload {}
+
+pub(crate) fn error_schema() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .property("message", ObjectBuilder::new().schema_type(Type::String))
+        .description(Some("Present when status is not 200"))
+}
+
+// Handlers of different modules share names like `list`, which are not unique operation ids
+pub(crate) fn without_operation_ids(mut api: utoipa::openapi::OpenApi) -> utoipa::openapi::OpenApi {
+    for item in api.paths.paths.values_mut() {
+        for op in [
+            &mut item.get,
+            &mut item.post,
+            &mut item.put,
+            &mut item.delete,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            op.operation_id = None;
+        }
+    }
+    api
+}
@@ -5,16 +5,24 @@ use std::vec::Vec;
 use axum::extract::DefaultBodyLimit;
 use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{get, post};
 use axum::Router;
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
 use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::limit::RequestBodyLimitLayer;
+use utoipa::OpenApi;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+use utoipa_swagger_ui::SwaggerUi;
 
 use super::asset::ASSETS_MAP;
+use super::openapi::ApiDoc;
 use crate::ai::crud as ai;
+use crate::external::channel::crud as channel;
+use crate::external::email::crud as email;
 use crate::external::http::crud as http;
+use crate::external::http::import as http_import;
+use crate::external::webhook::crud as webhook;
 use crate::flow::mainflow::crud as mainflow;
 use crate::flow::rt::facade as rt;
 use crate::flow::subflow::crud as subflow;
@@ -22,8 +30,13 @@ use crate::intent::crud as intent;
 use crate::kb::crud as kb;
 use crate::man::settings;
 use crate::result::Error;
+use crate::robot::bundle;
 use crate::robot::crud as robot;
+use crate::robot::i18n;
+use crate::robot::template;
 use crate::variable::crud as variable;
+use crate::variable::extract as variable_extract;
+use crate::variable::store as variable_store;
 
 //https://stackoverflow.com/questions/27840394/how-can-a-rust-program-access-metadata-from-its-cargo-package
 pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -105,8 +118,19 @@ pub async fn start_app() {
         listening_ip.push_str(&settings.ip);
     }
 
+    tokio::spawn(crate::kb::doc::index_all_missing());
     let (sender, recv) = tokio::sync::oneshot::channel::<()>();
     tokio::spawn(crate::flow::rt::context::clean_expired_session(recv));
+    let (webhook_sender, webhook_recv) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(crate::external::webhook::delivery::deliver_pending(
+        webhook_recv,
+    ));
+    let (email_sender, email_recv) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(crate::external::email::outbox::send_pending(email_recv));
+    let (inbound_sender, inbound_recv) = tokio::sync::oneshot::channel::<()>();
+    tokio::spawn(crate::external::email::inbound::poll_mailboxes(
+        inbound_recv,
+    ));
 
     let r: Router = gen_router();
     let app = r.fallback(fallback);
@@ -162,104 +186,99 @@ pub async fn start_app() {
     // let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     // let addr = SocketAddr::from((settings.ip, settings.port));
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(sender))
+        .with_graceful_shutdown(shutdown_signal(
+            sender,
+            vec![webhook_sender, email_sender, inbound_sender],
+        ))
         .await
         .unwrap();
 }
 
 fn gen_router() -> Router {
-    Router::new()
-        .route(
-            "/robot",
-            get(robot::list).post(robot::save).delete(robot::delete),
-        )
-        .route("/robot/detail", get(robot::detail))
-        .route(
-            "/intent",
-            get(intent::list).post(intent::add).delete(intent::remove),
-        )
-        .route("/intent/detect", post(intent::detect))
-        .route("/intent/detail", get(intent::detail))
-        .route(
-            "/intent/keyword",
-            post(intent::add_keyword).delete(intent::remove_keyword),
-        )
-        .route(
-            "/intent/regex",
-            post(intent::add_regex).delete(intent::remove_regex),
-        )
-        .route(
-            "/intent/phrase",
-            post(intent::add_phrase).delete(intent::remove_phrase),
-        )
-        .route(
-            "/intent/phrase/regenerate-all",
-            get(intent::regenerate_embeddings),
-        )
-        .route(
-            "/variable",
-            get(variable::list)
-                .post(variable::add)
-                .delete(variable::delete),
-        )
-        .route(
-            "/mainflow",
-            get(mainflow::list)
-                .post(mainflow::new)
-                .put(mainflow::save)
-                .delete(mainflow::delete),
-        )
-        .route("/mainflow/release", get(subflow::release))
-        .route(
-            "/subflow",
-            get(subflow::list)
-                .post(subflow::save)
-                .delete(subflow::delete),
-        )
-        .route("/subflow/simple", get(subflow::simple_list))
-        .route("/subflow/new", post(subflow::new))
-        .route("/external/http", get(http::list))
-        .route(
-            "/external/http/{id}",
-            get(http::detail).post(http::save).delete(http::remove),
-        )
-        .route(
-            "/management/global-settings",
-            get(settings::rest_get_global_settings).post(settings::rest_save_global_settings),
-        )
-        .route(
-            "/management/settings",
-            get(settings::get).post(settings::save),
-        )
-        .route(
-            "/management/settings/model/download",
-            post(settings::download_model_files),
-        )
-        .route(
-            "/management/settings/model/download/progress",
-            get(settings::download_model_progress),
-        )
-        .route(
-            "/management/settings/model/check/files",
-            post(settings::check_model_files),
-        )
-        .route(
-            "/management/settings/model/check/embedding",
-            get(settings::check_embedding_model),
-        )
-        .route(
-            "/kb/qa",
-            get(kb::list_qa).post(kb::save_qa).delete(kb::delete_qa),
+    let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(robot::list, robot::save, robot::delete))
+        .routes(routes!(robot::detail))
+        .routes(routes!(robot::clone))
+        .routes(routes!(template::list, template::save, template::delete))
+        .routes(routes!(i18n::report))
+        .routes(routes!(bundle::export))
+        .routes(routes!(bundle::import))
+        .routes(routes!(intent::list, intent::add, intent::remove))
+        .routes(routes!(intent::detect))
+        .routes(routes!(intent::detail))
+        .routes(routes!(intent::add_keyword, intent::remove_keyword))
+        .routes(routes!(intent::add_regex, intent::remove_regex))
+        .routes(routes!(intent::add_phrase, intent::remove_phrase))
+        .routes(routes!(intent::regenerate_embeddings))
+        .routes(routes!(variable::list, variable::add, variable::delete))
+        .routes(routes!(variable_extract::dry_run))
+        .routes(routes!(
+            variable_store::list_global,
+            variable_store::save_global,
+            variable_store::delete_global
+        ))
+        .routes(routes!(
+            variable_store::list_user,
+            variable_store::save_user,
+            variable_store::delete_user
+        ))
+        .routes(routes!(
+            mainflow::list,
+            mainflow::new,
+            mainflow::save,
+            mainflow::delete
+        ))
+        .routes(routes!(subflow::release))
+        .routes(routes!(subflow::list, subflow::save, subflow::delete))
+        .routes(routes!(subflow::simple_list))
+        .routes(routes!(subflow::new))
+        .routes(routes!(http::list))
+        .routes(routes!(http::test))
+        .routes(routes!(http_import::import_openapi))
+        .routes(routes!(http_import::import_curl))
+        .routes(routes!(http::detail, http::save, http::remove))
+        .routes(routes!(webhook::list, webhook::save))
+        .routes(routes!(webhook::remove))
+        .routes(routes!(webhook::deliveries))
+        .routes(routes!(webhook::redeliver))
+        .routes(routes!(
+            email::list_attachments,
+            email::upload_attachment,
+            email::delete_attachment
+        ))
+        .routes(routes!(email::outbox))
+        .routes(routes!(email::poll))
+        .routes(routes!(channel::list, channel::save))
+        .routes(routes!(channel::remove))
+        .routes(routes!(channel::receive))
+        .routes(routes!(
+            settings::rest_get_global_settings,
+            settings::rest_save_global_settings
+        ))
+        .routes(routes!(settings::get, settings::save))
+        .routes(routes!(settings::download_model_files))
+        .routes(routes!(settings::download_model_progress))
+        .routes(routes!(settings::check_model_files))
+        .routes(routes!(settings::check_embedding_model))
+        .routes(routes!(kb::list_qa, kb::save_qa, kb::delete_qa))
+        .routes(routes!(kb::list_doc))
+        .routes(routes!(kb::qa_dryrun))
+        .routes(routes!(kb::upload_doc))
+        .routes(routes!(settings::smtp_test))
+        .routes(routes!(rt::answer))
+        .routes(routes!(rt::answer_sse))
+        .routes(routes!(super::widget::script))
+        .routes(routes!(super::widget::page))
+        .routes(routes!(super::widget::config))
+        .routes(routes!(ai::gen_text))
+        .routes(routes!(version))
+        .routes(routes!(check_new_version))
+        .split_for_parts();
+    router
+        .merge(
+            SwaggerUi::new("/openapi/ui")
+                .url("/openapi.json", super::openapi::without_operation_ids(api)),
         )
-        .route("/kb/doc", get(kb::list_doc))
-        .route("/kb/qa/dryrun", get(kb::qa_dryrun))
-        .route("/kb/doc/upload", post(kb::upload_doc))
-        .route("/management/settings/smtp/test", post(settings::smtp_test))
-        .route("/flow/answer", post(rt::answer))
-        .route("/flow/answer/sse", post(rt::answer_sse))
-        .route("/ai/text/generation", post(ai::gen_text))
-        .route("/version.json", get(version))
-        .route("/check-new-version.json", get(check_new_version))
         // .route("/o", get(subflow::output))
         .layer(DefaultBodyLimit::disable())
         .layer(RequestBodyLimitLayer::new(
@@ -315,6 +334,13 @@ fn convert_version(ver: &str) -> u64 {
     v.parse().expect("Wrong version")
 }
 
+#[utoipa::path(
+    get,
+    path = "/version.json",
+    tag = "version",
+    summary = "Get current version",
+    responses((status = 200, content_type = "application/json", body = String))
+)]
 async fn version() -> impl IntoResponse {
     let mut v = String::with_capacity(15);
     let _ = v.push('"');
@@ -323,6 +349,13 @@ async fn version() -> impl IntoResponse {
     v
 }
 
+#[utoipa::path(
+    get,
+    path = "/check-new-version.json",
+    tag = "version",
+    summary = "Check new version",
+    responses((status = 200, body = ResponseData<serde_json::Value>))
+)]
 async fn check_new_version() -> impl IntoResponse {
     let r = reqwest::get("https://dialogflowchatbot.github.io/check-new-version.json").await;
     if let Err(e) = r {
@@ -350,7 +383,11 @@ async fn check_new_version() -> impl IntoResponse {
     )
 }
 
-async fn shutdown_signal(sender: tokio::sync::oneshot::Sender<()>) {
+async fn shutdown_signal(
+    sender: tokio::sync::oneshot::Sender<()>,
+    // Background workers like webhook deliveries
+    worker_senders: Vec<tokio::sync::oneshot::Sender<()>>,
+) {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -377,6 +414,9 @@ async fn shutdown_signal(sender: tokio::sync::oneshot::Sender<()>) {
         Ok(_) => {}
         Err(_) => log::info!("中断 ctx 失败"),
     };
+    for s in worker_senders {
+        let _ = s.send(());
+    }
 
     crate::intent::phrase::shutdown_db().await;
     crate::kb::qa::shutdown_db().await;
@@ -390,10 +430,11 @@ async fn shutdown_signal(sender: tokio::sync::oneshot::Sender<()>) {
     log::info!("{}", m);
 }
 
-#[derive(Serialize)]
-struct ResponseData<D> {
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct ResponseData<D> {
     pub(crate) status: u16,
     pub(crate) data: Option<D>,
+    #[schema(schema_with = super::openapi::error_schema)]
     pub(crate) err: Option<Error>,
 }
 
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use axum::extract::Query;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+
+use crate::man::settings;
+use crate::result::{Error, Result};
+use crate::web::openapi::Binary;
+use crate::web::server::{to_res, ResponseData};
+
+const SCRIPT: &str = include_str!("../resources/widget/widget.js");
+const PAGE: &str = include_str!("../resources/widget/chat.html");
+// Replaced by the configuration of the robot
+const CONFIG_PLACEHOLDER: &str = "/*WIDGET_CONFIG*/null";
+
+#[derive(Clone, Copy, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) enum WidgetPosition {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub(crate) struct ChatWidget {
+    pub(crate) enabled: bool,
+    #[serde(rename = "mainFlowId")]
+    pub(crate) main_flow_id: String,
+    pub(crate) title: String,
+    #[serde(rename = "primaryColor")]
+    pub(crate) primary_color: String,
+    pub(crate) position: WidgetPosition,
+    pub(crate) placeholder: String,
+    #[serde(rename = "avatarUrl", default)]
+    pub(crate) avatar_url: String,
+    // Sites which can embed the chat page, any site can when it is empty
+    #[serde(rename = "allowedOrigins", default)]
+    pub(crate) allowed_origins: Vec<String>,
+}
+
+impl Default for ChatWidget {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            main_flow_id: String::new(),
+            title: String::from("Chat"),
+            primary_color: String::from("#1677ff"),
+            position: WidgetPosition::Right,
+            placeholder: String::from("Type a message"),
+            avatar_url: String::new(),
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WidgetConfig<'a> {
+    #[serde(rename = "robotId")]
+    robot_id: &'a str,
+    #[serde(flatten)]
+    widget: &'a ChatWidget,
+}
+
+pub(crate) fn check(w: &ChatWidget) -> Result<()> {
+    if w.enabled && w.main_flow_id.is_empty() {
+        return Err(Error::ErrorWithMessage(String::from(
+            "Main flow of the chat widget is required",
+        )));
+    }
+    let color = w.primary_color.strip_prefix('#').unwrap_or_default();
+    if ![3, 6, 8].contains(&color.len()) || !color.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::ErrorWithMessage(format!(
+            "Invalid color: {}, e.g. #1677ff",
+            &w.primary_color
+        )));
+    }
+    // Origins are put into the `Content-Security-Policy` header
+    for o in w.allowed_origins.iter() {
+        if !(o.starts_with("http://") || o.starts_with("https://"))
+            || o.chars()
+                .any(|c| c.is_whitespace() || c == ';' || c == ',' || c == '\'')
+        {
+            return Err(Error::ErrorWithMessage(format!(
+                "Invalid origin: {}, e.g. https://www.example.com",
+                o
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn enabled_widget(q: &HashMap<String, String>) -> Result<(&String, ChatWidget)> {
+    let robot_id = q
+        .get("robotId")
+        .ok_or_else(|| Error::ErrorWithMessage(String::from("Parameter: robotId is missing.")))?;
+    match settings::get_settings(robot_id)? {
+        Some(s) if s.chat_widget.enabled => Ok((robot_id, s.chat_widget)),
+        _ => Err(Error::ErrorWithMessage(String::from(
+            "Chat widget is not enabled",
+        ))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/widget.js",
+    tag = "widget",
+    summary = "Script which adds the chat widget of a robot to a page",
+    responses((status = 200, content_type = "text/javascript", body = inline(Binary)))
+)]
+pub(crate) async fn script() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/javascript; charset=utf-8"),
+            (header::CACHE_CONTROL, "public, max-age=3600"),
+        ],
+        SCRIPT,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/widget/config",
+    tag = "widget",
+    summary = "Get the chat widget settings of a robot",
+    params(("robotId" = String, Query)),
+    responses((status = 200, body = ResponseData<ChatWidget>))
+)]
+pub(crate) async fn config(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    to_res(enabled_widget(&q).map(|(_, w)| w))
+}
+
+fn render_page(robot_id: &str, w: &ChatWidget) -> Result<String> {
+    let config = serde_json::to_string(&WidgetConfig {
+        robot_id,
+        widget: w,
+    })?;
+    // Keeps `</script>` in texts from closing the script element
+    Ok(PAGE.replace(CONFIG_PLACEHOLDER, &config.replace('<', "\\u003c")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/widget/chat.html",
+    tag = "widget",
+    summary = "Chat page of a robot, embedded by the widget script",
+    params(("robotId" = String, Query)),
+    responses((status = 200, content_type = "text/html", body = inline(Binary)))
+)]
+pub(crate) async fn page(Query(q): Query<HashMap<String, String>>) -> impl IntoResponse {
+    let (robot_id, w) = match enabled_widget(&q) {
+        Ok(r) => r,
+        Err(e) => return (StatusCode::NOT_FOUND, format!("{:?}", e)).into_response(),
+    };
+    let ancestors = if w.allowed_origins.is_empty() {
+        String::from("*")
+    } else {
+        format!("'self' {}", w.allowed_origins.join(" "))
+    };
+    match render_page(robot_id, &w) {
+        Ok(html) => (
+            [
+                (
+                    header::CONTENT_TYPE,
+                    String::from("text/html; charset=utf-8"),
+                ),
+                (
+                    header::CONTENT_SECURITY_POLICY,
+                    format!("frame-ancestors {}", ancestors),
+                ),
+                (header::CACHE_CONTROL, String::from("no-cache")),
+            ],
+            html,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", e)).into_response(),
+    }
+}